@@ -0,0 +1,71 @@
+//! One bit per day membership index, an alternative to the binary search for `contains` checks.
+
+/// Longest span (in days) covered by a bitmap, ~89 years.
+pub const MAX_BITMAP_DAYS: usize = 32 * 1024;
+const BITMAP_WORDS: usize = MAX_BITMAP_DAYS / 64;
+
+#[derive(Default, Clone, Debug)]
+pub struct MembershipBitmap {
+    first_date: i32,
+    words: heapless::Vec<u64, BITMAP_WORDS>,
+}
+
+impl MembershipBitmap {
+    /// Builds the bitmap of sorted `dates`, `None` when there are no dates or they span more
+    /// than `MAX_BITMAP_DAYS`.
+    pub fn from_dates(dates: &[i32]) -> Option<MembershipBitmap> {
+        let (first_date, last_date) = (*dates.first()?, *dates.last()?);
+        let days = (last_date as i64 - first_date as i64 + 1) as usize;
+        if days > MAX_BITMAP_DAYS {
+            return None;
+        }
+        let mut bitmap = MembershipBitmap {
+            first_date,
+            words: heapless::Vec::new(),
+        };
+        bitmap.words.resize(days.div_ceil(64), 0).ok()?;
+        for date in dates {
+            let day = (date - first_date) as usize;
+            bitmap.words[day / 64] |= 1 << (day % 64);
+        }
+        Some(bitmap)
+    }
+
+    pub fn contains(&self, date: i32) -> bool {
+        let day = date as i64 - self.first_date as i64;
+        if day < 0 {
+            return false;
+        }
+        let day = day as usize;
+        self.words
+            .get(day / 64)
+            .is_some_and(|word| word & (1 << (day % 64)) != 0)
+    }
+
+    /// Bytes used by the bits of this calendar.
+    pub fn size(&self) -> usize {
+        self.words.len() * std::mem::size_of::<u64>()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn bitmap_matches_dates() {
+        let dates = [-100, -1, 0, 63, 64, 500];
+        let bitmap = MembershipBitmap::from_dates(&dates).unwrap();
+        for date in -200..600 {
+            assert_eq!(bitmap.contains(date), dates.contains(&date), "date {date}");
+        }
+        assert_eq!(bitmap.size(), 10 * 8);
+    }
+
+    #[test]
+    fn bitmap_is_bounded() {
+        assert!(MembershipBitmap::from_dates(&[]).is_none());
+        assert!(MembershipBitmap::from_dates(&[0, MAX_BITMAP_DAYS as i32]).is_none());
+        assert!(MembershipBitmap::from_dates(&[0, MAX_BITMAP_DAYS as i32 - 1]).is_some());
+    }
+}
@@ -0,0 +1,221 @@
+//! Date component of cron expressions, used to expand schedules into calendar entries.
+//!
+//! The usual five fields (`minute hour day-of-month month day-of-week`) are accepted with `*`,
+//! lists, ranges, steps and month/weekday names. Minutes and hours are validated but only decide
+//! the time of day, so they do not change the dates. As in Vixie cron, a day matches when both
+//! day fields match, or either of them when neither starts with `*`.
+
+use std::fmt;
+
+use crate::date;
+
+const MONTH_NAMES: [&str; 12] = [
+    "JAN", "FEB", "MAR", "APR", "MAY", "JUN", "JUL", "AUG", "SEP", "OCT", "NOV", "DEC",
+];
+const WEEKDAY_NAMES: [&str; 7] = ["SUN", "MON", "TUE", "WED", "THU", "FRI", "SAT"];
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum CronError {
+    FieldCount { found: usize },
+    InvalidField { field: &'static str, value: String },
+}
+
+impl fmt::Display for CronError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            CronError::FieldCount { found } => {
+                write!(f, "cron expression must have 5 fields, found {found}")
+            }
+            CronError::InvalidField { field, value } => {
+                write!(f, "invalid {field} field \"{value}\"")
+            }
+        }
+    }
+}
+
+impl std::error::Error for CronError {}
+
+/// Days matched by a cron expression, as bit sets indexed by the field values.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct CronSchedule {
+    days_of_month: u64,
+    months: u64,
+    /// 0 = Sunday, 7 is folded into 0.
+    days_of_week: u64,
+    day_of_month_star: bool,
+    day_of_week_star: bool,
+}
+
+impl CronSchedule {
+    pub fn parse(expression: &str) -> Result<CronSchedule, CronError> {
+        let expression = match expression.trim().to_ascii_lowercase().as_str() {
+            "@yearly" | "@annually" => "0 0 1 1 *".to_string(),
+            "@monthly" => "0 0 1 * *".to_string(),
+            "@weekly" => "0 0 * * 0".to_string(),
+            "@daily" | "@midnight" => "0 0 * * *".to_string(),
+            _ => expression.to_string(),
+        };
+        let fields: Vec<&str> = expression.split_whitespace().collect();
+        let [minute, hour, day_of_month, month, day_of_week] = fields[..] else {
+            return Err(CronError::FieldCount {
+                found: fields.len(),
+            });
+        };
+        parse_field("minute", minute, 0, 59, &[])?;
+        parse_field("hour", hour, 0, 23, &[])?;
+        let days_of_week = parse_field("day-of-week", day_of_week, 0, 7, &WEEKDAY_NAMES)?;
+        Ok(CronSchedule {
+            days_of_month: parse_field("day-of-month", day_of_month, 1, 31, &[])?,
+            months: parse_field("month", month, 1, 12, &MONTH_NAMES)?,
+            days_of_week: (days_of_week | days_of_week >> 7) & 0x7f,
+            day_of_month_star: day_of_month.starts_with('*'),
+            day_of_week_star: day_of_week.starts_with('*'),
+        })
+    }
+
+    /// True when the schedule runs at least once on the PG epoch day `day`.
+    pub fn matches(&self, day: i32) -> bool {
+        let (_, month, day_of_month) = date::to_ymd(day);
+        if self.months & (1 << month) == 0 {
+            return false;
+        }
+        let day_of_month_match = self.days_of_month & (1 << day_of_month) != 0;
+        let day_of_week_match = self.days_of_week & (1 << (date::iso_weekday(day) % 7)) != 0;
+        if self.day_of_month_star || self.day_of_week_star {
+            day_of_month_match && day_of_week_match
+        } else {
+            day_of_month_match || day_of_week_match
+        }
+    }
+
+    /// Days between `from` and `to` (inclusive PG epoch days) the schedule runs on, ascending.
+    pub fn dates(&self, from: i32, to: i32) -> Vec<i32> {
+        (from..=to).filter(|day| self.matches(*day)).collect()
+    }
+}
+
+/// Parses a comma separated list of `*`, `value`, `first-last`, each optionally followed by
+/// `/step`, into a bit set of the values between `min` and `max`. `names` are accepted instead
+/// of numbers, the first name stands for `min`.
+fn parse_field(
+    field: &'static str,
+    value: &str,
+    min: u32,
+    max: u32,
+    names: &[&str],
+) -> Result<u64, CronError> {
+    let invalid = || CronError::InvalidField {
+        field,
+        value: value.to_string(),
+    };
+    let number = |text: &str| -> Result<u32, CronError> {
+        let number = match names
+            .iter()
+            .position(|name| name.eq_ignore_ascii_case(text))
+        {
+            Some(position) => min + position as u32,
+            None => text.parse::<u32>().map_err(|_| invalid())?,
+        };
+        match (min..=max).contains(&number) {
+            true => Ok(number),
+            false => Err(invalid()),
+        }
+    };
+
+    let mut bits = 0u64;
+    for item in value.split(',') {
+        let (range, step) = match item.split_once('/') {
+            Some((range, step)) => (range, step.parse::<u32>().map_err(|_| invalid())?),
+            None => (item, 1),
+        };
+        let (first, last) = match range.split_once('-') {
+            _ if range == "*" => (min, max),
+            Some((first, last)) => (number(first)?, number(last)?),
+            // "5/10" runs from 5 to the end of the range
+            None if step > 1 => (number(range)?, max),
+            None => (number(range)?, number(range)?),
+        };
+        if step == 0 || first > last {
+            return Err(invalid());
+        }
+        for number in (first..=last).step_by(step as usize) {
+            bits |= 1 << number;
+        }
+    }
+    Ok(bits)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn dates(expression: &str, from: (i32, u32, u32), to: (i32, u32, u32)) -> Vec<(i32, u32, u32)> {
+        CronSchedule::parse(expression)
+            .unwrap()
+            .dates(
+                date::from_ymd(from.0, from.1, from.2),
+                date::from_ymd(to.0, to.1, to.2),
+            )
+            .into_iter()
+            .map(date::to_ymd)
+            .collect()
+    }
+
+    #[test]
+    fn days_of_month_and_months() {
+        assert_eq!(
+            dates("0 0 1,15 * *", (2024, 1, 1), (2024, 2, 29)),
+            vec![(2024, 1, 1), (2024, 1, 15), (2024, 2, 1), (2024, 2, 15)]
+        );
+        assert_eq!(
+            dates("30 2 31 jan-mar/2 *", (2024, 1, 1), (2024, 12, 31)),
+            vec![(2024, 1, 31), (2024, 3, 31)]
+        );
+        assert_eq!(
+            dates("@yearly", (2023, 6, 1), (2025, 6, 1)),
+            vec![(2024, 1, 1), (2025, 1, 1)]
+        );
+    }
+
+    #[test]
+    fn days_of_week() {
+        // Saturdays and Sundays, 7 is Sunday too
+        assert_eq!(
+            dates("0 0 * * 6-7", (2024, 1, 1), (2024, 1, 14)),
+            vec![(2024, 1, 6), (2024, 1, 7), (2024, 1, 13), (2024, 1, 14)]
+        );
+        assert_eq!(
+            dates("0 0 */2 * MON", (2024, 1, 1), (2024, 1, 31)),
+            vec![(2024, 1, 1), (2024, 1, 15), (2024, 1, 29)]
+        );
+        // both day fields restricted: either one matches
+        assert_eq!(
+            dates("0 0 13 * fri", (2024, 10, 1), (2024, 10, 31)),
+            vec![
+                (2024, 10, 4),
+                (2024, 10, 11),
+                (2024, 10, 13),
+                (2024, 10, 18),
+                (2024, 10, 25)
+            ]
+        );
+    }
+
+    #[test]
+    fn invalid_expressions() {
+        assert_eq!(
+            CronSchedule::parse("0 0 1 *"),
+            Err(CronError::FieldCount { found: 4 })
+        );
+        assert_eq!(
+            CronSchedule::parse("0 0 32 * *"),
+            Err(CronError::InvalidField {
+                field: "day-of-month",
+                value: "32".to_string()
+            })
+        );
+        assert!(CronSchedule::parse("0 24 * * *").is_err());
+        assert!(CronSchedule::parse("0 0 * * */0").is_err());
+        assert!(CronSchedule::parse("0 0 10-5 * *").is_err());
+    }
+}
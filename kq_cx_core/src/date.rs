@@ -0,0 +1,89 @@
+//! Conversions between PostgreSQL epoch days (0 = 2000-01-01) and civil dates, using the
+//! proleptic Gregorian calendar like PostgreSQL does.
+
+/// Days between 0000-03-01 and 2000-01-01, shifts the era based algorithm to the PG epoch.
+const PG_EPOCH_SHIFT: i64 = 730_425;
+
+/// Returns the PostgreSQL epoch day of the given civil date.
+pub fn from_ymd(year: i32, month: u32, day: u32) -> i32 {
+    let year = year as i64 - if month <= 2 { 1 } else { 0 };
+    let era = year.div_euclid(400);
+    let year_of_era = year - era * 400;
+    let month_index = (month as i64 + 9) % 12; // March = 0
+    let day_of_year = (153 * month_index + 2) / 5 + day as i64 - 1;
+    let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+    (era * 146_097 + day_of_era - PG_EPOCH_SHIFT) as i32
+}
+
+/// Returns the `(year, month, day)` of a PostgreSQL epoch day.
+pub fn to_ymd(days: i32) -> (i32, u32, u32) {
+    let days = days as i64 + PG_EPOCH_SHIFT;
+    let era = days.div_euclid(146_097);
+    let day_of_era = days - era * 146_097;
+    let year_of_era =
+        (day_of_era - day_of_era / 1460 + day_of_era / 36524 - day_of_era / 146_096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let month_index = (5 * day_of_year + 2) / 153;
+    let day = (day_of_year - (153 * month_index + 2) / 5 + 1) as u32;
+    let month = if month_index < 10 {
+        month_index + 3
+    } else {
+        month_index - 9
+    } as u32;
+    let year = year_of_era + era * 400 + if month <= 2 { 1 } else { 0 };
+    (year as i32, month, day)
+}
+
+/// Parses a `YYYY-MM-DD` date into a PostgreSQL epoch day, `None` when it is not a valid date.
+pub fn parse_iso(text: &str) -> Option<i32> {
+    let mut parts = text.trim().splitn(3, '-');
+    let year: i32 = parts.next()?.parse().ok()?;
+    let month: u32 = parts.next()?.parse().ok()?;
+    let day: u32 = parts.next()?.parse().ok()?;
+    if !(1..=12).contains(&month) || !(1..=31).contains(&day) {
+        return None;
+    }
+    let days = from_ymd(year, month, day);
+    // Feb 30 and the like roll over into the next month
+    (to_ymd(days) == (year, month, day)).then_some(days)
+}
+
+/// ISO day of the week, 1 = Monday ... 7 = Sunday.
+pub fn iso_weekday(days: i32) -> u32 {
+    // 2000-01-01 was a Saturday
+    ((days as i64 + 5).rem_euclid(7) + 1) as u32
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn pg_epoch_round_trip() {
+        assert_eq!(from_ymd(2000, 1, 1), 0);
+        assert_eq!(from_ymd(1970, 1, 1), -10957);
+        assert_eq!(from_ymd(2199, 1, 1), 72684);
+        for days in -200_000..200_000 {
+            let (year, month, day) = to_ymd(days);
+            assert_eq!(from_ymd(year, month, day), days);
+        }
+    }
+
+    #[test]
+    fn parse_iso_dates() {
+        assert_eq!(parse_iso("1970-01-01"), Some(-10957));
+        assert_eq!(parse_iso(" 9999-12-31 "), Some(from_ymd(9999, 12, 31)));
+        assert_eq!(parse_iso("2024-02-29"), Some(from_ymd(2024, 2, 29)));
+        assert_eq!(parse_iso("2023-02-29"), None);
+        assert_eq!(parse_iso("2024-13-01"), None);
+        assert_eq!(parse_iso("2024-01"), None);
+        assert_eq!(parse_iso("tomorrow"), None);
+    }
+
+    #[test]
+    fn weekdays() {
+        assert_eq!(iso_weekday(from_ymd(2000, 1, 1)), 6);
+        assert_eq!(iso_weekday(from_ymd(2024, 1, 1)), 1);
+        assert_eq!(iso_weekday(from_ymd(1999, 12, 26)), 7);
+    }
+}
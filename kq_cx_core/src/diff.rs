@@ -0,0 +1,51 @@
+//! Differences between the entries of two calendars.
+
+use std::cmp::Ordering;
+
+/// Result of `diff_dates`, the dates are in ascending order.
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct DatesDiff {
+    pub only_in_a: Vec<i32>,
+    pub only_in_b: Vec<i32>,
+    pub common: usize,
+}
+
+/// Compares two sorted lists of entries in a single pass.
+pub fn diff_dates(a: &[i32], b: &[i32]) -> DatesDiff {
+    let mut diff = DatesDiff::default();
+    let (mut a_index, mut b_index) = (0, 0);
+    while a_index < a.len() && b_index < b.len() {
+        match a[a_index].cmp(&b[b_index]) {
+            Ordering::Less => {
+                diff.only_in_a.push(a[a_index]);
+                a_index += 1;
+            }
+            Ordering::Greater => {
+                diff.only_in_b.push(b[b_index]);
+                b_index += 1;
+            }
+            Ordering::Equal => {
+                diff.common += 1;
+                a_index += 1;
+                b_index += 1;
+            }
+        }
+    }
+    diff.only_in_a.extend_from_slice(&a[a_index..]);
+    diff.only_in_b.extend_from_slice(&b[b_index..]);
+    diff
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn diff_splits_entries() {
+        let diff = diff_dates(&[1, 3, 5, 7], &[3, 4, 7, 9, 10]);
+        assert_eq!(diff.only_in_a, [1, 5]);
+        assert_eq!(diff.only_in_b, [4, 9, 10]);
+        assert_eq!(diff.common, 2);
+        assert_eq!(diff_dates(&[], &[]), DatesDiff::default());
+    }
+}
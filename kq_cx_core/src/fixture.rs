@@ -0,0 +1,150 @@
+//! Deterministic calendars for test suites, populated instead of running the loader queries.
+//!
+//! A fixture file has one entry per line, `calendar_id,xuid,YYYY-MM-DD`, in any order. Blank
+//! lines and lines starting with `#` are ignored:
+//!
+//! ```text
+//! # calendar_id,xuid,entry_date
+//! 1,month,2024-01-01
+//! 1,month,2024-02-01
+//! ```
+
+use std::collections::{BTreeMap, BTreeSet};
+
+use crate::date;
+use crate::format::ImageCalendar;
+use crate::synthetic::{generate_dates, Pattern};
+
+/// Name of the dataset bundled with the extension, used instead of a file path.
+pub const BUILTIN: &str = "builtin";
+
+/// The bundled dataset: `day`, `week` (Mondays), `month`, `quarter` and `year` calendars, ids 1
+/// to 5, from 2020 to 2039. Never changes, tests may rely on its dates.
+pub fn builtin() -> Vec<ImageCalendar> {
+    let first_date = date::from_ymd(2020, 1, 1);
+    let months = generate_dates(Pattern::Monthly, first_date, 240, 0);
+    let calendar = |calendar_id, xuid: &str, dates| ImageCalendar {
+        calendar_id,
+        namespace: String::new(),
+        xuid: xuid.to_string(),
+        parent_id: None,
+        dates,
+    };
+    vec![
+        calendar(
+            1,
+            "day",
+            generate_dates(Pattern::Daily, first_date, 7305, 0),
+        ),
+        // 2019-12-30 is the Monday of the first week of 2020
+        calendar(
+            2,
+            "week",
+            generate_dates(Pattern::Weekly, date::from_ymd(2019, 12, 30), 1044, 0),
+        ),
+        calendar(3, "month", months.clone()),
+        calendar(4, "quarter", months.iter().step_by(3).copied().collect()),
+        calendar(5, "year", months.iter().step_by(12).copied().collect()),
+    ]
+}
+
+/// Parses a fixture file into calendars in id order, with their entries sorted and deduplicated.
+/// Errors name the first invalid line.
+pub fn parse(text: &str) -> Result<Vec<ImageCalendar>, String> {
+    let mut calendars: BTreeMap<i64, (String, BTreeSet<i32>)> = BTreeMap::new();
+    for (index, line) in text.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let line_error = |message: &str| format!("line {}: {message}", index + 1);
+        let fields: Vec<&str> = line.split(',').map(str::trim).collect();
+        let [calendar_id, xuid, entry_date] = fields[..] else {
+            return Err(line_error("expected calendar_id,xuid,entry_date"));
+        };
+        let calendar_id: i64 = calendar_id
+            .parse()
+            .map_err(|_| line_error(&format!("\"{calendar_id}\" is not a calendar id")))?;
+        let entry_date = date::parse_iso(entry_date)
+            .ok_or_else(|| line_error(&format!("\"{entry_date}\" is not a YYYY-MM-DD date")))?;
+        let (calendar_xuid, dates) = calendars
+            .entry(calendar_id)
+            .or_insert_with(|| (xuid.to_string(), BTreeSet::new()));
+        if calendar_xuid != xuid {
+            return Err(line_error(&format!(
+                "calendar_id = {calendar_id} is already xuid = {calendar_xuid}"
+            )));
+        }
+        dates.insert(entry_date);
+    }
+    Ok(calendars
+        .into_iter()
+        .map(|(calendar_id, (xuid, dates))| ImageCalendar {
+            calendar_id,
+            namespace: String::new(),
+            xuid,
+            parent_id: None,
+            dates: dates.into_iter().collect(),
+        })
+        .collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{Calendar, MAX_ENTRIES_PER_CALENDAR};
+
+    #[test]
+    fn builtin_calendars_fit_the_cache() {
+        let calendars = builtin();
+        assert_eq!(calendars.len(), 5);
+        for calendar in &calendars {
+            assert!(calendar.dates.len() <= MAX_ENTRIES_PER_CALENDAR);
+            assert!(Calendar::from_dates(&calendar.dates).is_ok());
+        }
+        assert_eq!(
+            calendars[0].dates.last(),
+            Some(&date::from_ymd(2039, 12, 31))
+        );
+        assert_eq!(date::iso_weekday(calendars[1].dates[0]), 1);
+        assert_eq!(calendars[3].dates[1], date::from_ymd(2020, 4, 1));
+        assert_eq!(calendars[4].dates.len(), 20);
+    }
+
+    #[test]
+    fn parses_fixture_files() {
+        let calendars = parse(
+            "# calendar_id,xuid,entry_date\n\
+             2, quarter, 2024-04-01\n\
+             \n\
+             1,month,2024-02-01\n\
+             1,month,2024-01-01\n\
+             1,month,2024-01-01\n",
+        )
+        .unwrap();
+        assert_eq!(calendars.len(), 2);
+        assert_eq!(calendars[0].xuid, "month");
+        assert_eq!(
+            calendars[0].dates,
+            vec![date::from_ymd(2024, 1, 1), date::from_ymd(2024, 2, 1)]
+        );
+        assert_eq!(calendars[1].calendar_id, 2);
+    }
+
+    #[test]
+    fn rejects_invalid_lines() {
+        assert_eq!(
+            parse("1,month\n"),
+            Err("line 1: expected calendar_id,xuid,entry_date".to_string())
+        );
+        assert_eq!(
+            parse("1,month,2024-01-01\nx,month,2024-02-01"),
+            Err("line 2: \"x\" is not a calendar id".to_string())
+        );
+        assert!(parse("1,month,2024-02-30").is_err());
+        assert_eq!(
+            parse("1,month,2024-01-01\n1,year,2024-01-01"),
+            Err("line 2: calendar_id = 1 is already xuid = month".to_string())
+        );
+    }
+}
@@ -0,0 +1,264 @@
+//! Versioned binary format for exporting and importing the calendar cache.
+//!
+//! All integers are little-endian, strings are a `u16` byte length followed by UTF-8 bytes.
+//!
+//! ```text
+//! header:   magic "KQCXCACH", format version (u16), extension version (string),
+//!           max entries per calendar (u32), max pages per calendar (u32), calendar count (u32)
+//! calendar: id (i64), namespace (string), xuid (string), has parent (u8), parent id (i64),
+//!           checksum (u64), entry count (u32), entries (i32 each)
+//! ```
+//!
+//! A new format version must be used for any layout change, `decode` refuses versions it does
+//! not know instead of guessing.
+
+use std::fmt;
+
+use crate::{dates_checksum, MAX_ENTRIES_PER_CALENDAR, MAX_PAGES_PER_CALENDAR};
+
+pub const MAGIC: &[u8; 8] = b"KQCXCACH";
+pub const FORMAT_VERSION: u16 = 1;
+
+/// Calendar as stored in a cache image.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ImageCalendar {
+    pub calendar_id: i64,
+    pub namespace: String,
+    pub xuid: String,
+    pub parent_id: Option<i64>,
+    pub dates: Vec<i32>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CacheImage {
+    /// Version of the extension that wrote the image, informational only.
+    pub extension_version: String,
+    pub calendars: Vec<ImageCalendar>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum FormatError {
+    /// The data does not start with `MAGIC`.
+    BadMagic,
+    UnsupportedVersion {
+        found: u16,
+    },
+    /// The image was written with larger capacities than this build supports.
+    CapacityExceeded {
+        max_entries: u32,
+        max_pages: u32,
+    },
+    ChecksumMismatch {
+        calendar_id: i64,
+    },
+    Truncated,
+    TrailingBytes,
+    InvalidString,
+}
+
+impl fmt::Display for FormatError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            FormatError::BadMagic => write!(f, "not a kq_cx cache image"),
+            FormatError::UnsupportedVersion { found } => write!(
+                f,
+                "cache image format version {found} is not supported, expected {FORMAT_VERSION}"
+            ),
+            FormatError::CapacityExceeded {
+                max_entries,
+                max_pages,
+            } => write!(
+                f,
+                "cache image was written with {max_entries} entries and {max_pages} pages per \
+                 calendar, this build supports {MAX_ENTRIES_PER_CALENDAR} and \
+                 {MAX_PAGES_PER_CALENDAR}"
+            ),
+            FormatError::ChecksumMismatch { calendar_id } => {
+                write!(f, "checksum mismatch for calendar_id = {calendar_id}")
+            }
+            FormatError::Truncated => write!(f, "cache image is truncated"),
+            FormatError::TrailingBytes => write!(f, "unexpected data after the last calendar"),
+            FormatError::InvalidString => write!(f, "cache image contains invalid UTF-8"),
+        }
+    }
+}
+
+impl std::error::Error for FormatError {}
+
+pub fn encode(image: &CacheImage) -> Vec<u8> {
+    let mut bytes = Vec::new();
+    bytes.extend_from_slice(MAGIC);
+    bytes.extend_from_slice(&FORMAT_VERSION.to_le_bytes());
+    put_str(&mut bytes, &image.extension_version);
+    bytes.extend_from_slice(&(MAX_ENTRIES_PER_CALENDAR as u32).to_le_bytes());
+    bytes.extend_from_slice(&(MAX_PAGES_PER_CALENDAR as u32).to_le_bytes());
+    bytes.extend_from_slice(&(image.calendars.len() as u32).to_le_bytes());
+    for calendar in &image.calendars {
+        bytes.extend_from_slice(&calendar.calendar_id.to_le_bytes());
+        put_str(&mut bytes, &calendar.namespace);
+        put_str(&mut bytes, &calendar.xuid);
+        bytes.push(calendar.parent_id.is_some() as u8);
+        bytes.extend_from_slice(&calendar.parent_id.unwrap_or_default().to_le_bytes());
+        bytes.extend_from_slice(&dates_checksum(&calendar.dates).to_le_bytes());
+        bytes.extend_from_slice(&(calendar.dates.len() as u32).to_le_bytes());
+        for date in &calendar.dates {
+            bytes.extend_from_slice(&date.to_le_bytes());
+        }
+    }
+    bytes
+}
+
+pub fn decode(bytes: &[u8]) -> Result<CacheImage, FormatError> {
+    let mut reader = Reader { bytes };
+    if reader
+        .take(MAGIC.len())
+        .map_err(|_| FormatError::BadMagic)?
+        != MAGIC
+    {
+        return Err(FormatError::BadMagic);
+    }
+    let found = u16::from_le_bytes(reader.array()?);
+    if found != FORMAT_VERSION {
+        return Err(FormatError::UnsupportedVersion { found });
+    }
+    let extension_version = reader.string()?;
+    let max_entries = u32::from_le_bytes(reader.array()?);
+    let max_pages = u32::from_le_bytes(reader.array()?);
+    if max_entries as usize > MAX_ENTRIES_PER_CALENDAR
+        || max_pages as usize > MAX_PAGES_PER_CALENDAR
+    {
+        return Err(FormatError::CapacityExceeded {
+            max_entries,
+            max_pages,
+        });
+    }
+
+    let calendar_count = u32::from_le_bytes(reader.array()?);
+    let mut calendars = Vec::new();
+    for _ in 0..calendar_count {
+        let calendar_id = i64::from_le_bytes(reader.array()?);
+        let namespace = reader.string()?;
+        let xuid = reader.string()?;
+        let has_parent = reader.array::<1>()?[0] != 0;
+        let parent_id = i64::from_le_bytes(reader.array()?);
+        let checksum = u64::from_le_bytes(reader.array()?);
+        let entry_count = u32::from_le_bytes(reader.array()?) as usize;
+        let dates = reader
+            .take(entry_count.checked_mul(4).ok_or(FormatError::Truncated)?)?
+            .chunks_exact(4)
+            .map(|chunk| i32::from_le_bytes(chunk.try_into().unwrap()))
+            .collect::<Vec<_>>();
+        if dates_checksum(&dates) != checksum {
+            return Err(FormatError::ChecksumMismatch { calendar_id });
+        }
+        calendars.push(ImageCalendar {
+            calendar_id,
+            namespace,
+            xuid,
+            parent_id: has_parent.then_some(parent_id),
+            dates,
+        });
+    }
+    if !reader.bytes.is_empty() {
+        return Err(FormatError::TrailingBytes);
+    }
+
+    Ok(CacheImage {
+        extension_version,
+        calendars,
+    })
+}
+
+fn put_str(bytes: &mut Vec<u8>, value: &str) {
+    bytes.extend_from_slice(&(value.len() as u16).to_le_bytes());
+    bytes.extend_from_slice(value.as_bytes());
+}
+
+struct Reader<'a> {
+    bytes: &'a [u8],
+}
+
+impl<'a> Reader<'a> {
+    fn take(&mut self, len: usize) -> Result<&'a [u8], FormatError> {
+        if self.bytes.len() < len {
+            return Err(FormatError::Truncated);
+        }
+        let (head, tail) = self.bytes.split_at(len);
+        self.bytes = tail;
+        Ok(head)
+    }
+
+    fn array<const N: usize>(&mut self) -> Result<[u8; N], FormatError> {
+        Ok(self.take(N)?.try_into().unwrap())
+    }
+
+    fn string(&mut self) -> Result<String, FormatError> {
+        let len = u16::from_le_bytes(self.array()?) as usize;
+        String::from_utf8(self.take(len)?.to_vec()).map_err(|_| FormatError::InvalidString)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn image() -> CacheImage {
+        CacheImage {
+            extension_version: "1.0.1".to_string(),
+            calendars: vec![
+                ImageCalendar {
+                    calendar_id: 1,
+                    namespace: String::new(),
+                    xuid: "month".to_string(),
+                    parent_id: None,
+                    dates: vec![8766, 8797, 8826],
+                },
+                ImageCalendar {
+                    calendar_id: 2,
+                    namespace: "acme".to_string(),
+                    xuid: "fiscal".to_string(),
+                    parent_id: Some(1),
+                    dates: vec![],
+                },
+            ],
+        }
+    }
+
+    #[test]
+    fn round_trip() {
+        assert_eq!(decode(&encode(&image())), Ok(image()));
+    }
+
+    #[test]
+    fn rejects_other_versions_and_magic() {
+        let mut bytes = encode(&image());
+        bytes[MAGIC.len()..MAGIC.len() + 2].copy_from_slice(&2u16.to_le_bytes());
+        assert_eq!(
+            decode(&bytes),
+            Err(FormatError::UnsupportedVersion { found: 2 })
+        );
+        assert_eq!(decode(b"PGDMP"), Err(FormatError::BadMagic));
+    }
+
+    #[test]
+    fn rejects_corrupted_and_truncated_images() {
+        let mut bytes = encode(&image());
+        let last = bytes.len() - 1;
+        // last byte of the last entry of calendar 1, calendar 2 is empty
+        let entry_byte = last - (8 + 2 + 4 + 2 + 6 + 1 + 8 + 8 + 4);
+        bytes[entry_byte] ^= 1;
+        assert_eq!(
+            decode(&bytes),
+            Err(FormatError::ChecksumMismatch { calendar_id: 1 })
+        );
+
+        let bytes = encode(&image());
+        assert_eq!(
+            decode(&bytes[..bytes.len() - 1]),
+            Err(FormatError::Truncated)
+        );
+        let mut bytes = bytes;
+        bytes.push(0);
+        assert_eq!(decode(&bytes), Err(FormatError::TrailingBytes));
+    }
+}
@@ -0,0 +1,295 @@
+//! Public holidays of a few jurisdictions, computed from their fixed-date, weekday and Easter
+//! rules. Holidays declared for a single year (jubilees, state funerals, ...) are not included.
+
+use crate::date;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Jurisdiction {
+    /// Federal holidays of the United States, with their observed dates.
+    UsFederal,
+    /// Bank holidays of England and Wales, with their substitute days.
+    GbEngland,
+    /// Public holidays observed in every German state.
+    DeNational,
+}
+
+impl Jurisdiction {
+    pub fn parse(jurisdiction: &str) -> Option<Jurisdiction> {
+        match jurisdiction.to_ascii_uppercase().as_str() {
+            "US_FEDERAL" => Some(Jurisdiction::UsFederal),
+            "GB_ENGLAND" => Some(Jurisdiction::GbEngland),
+            "DE_NATIONAL" => Some(Jurisdiction::DeNational),
+            _ => None,
+        }
+    }
+
+    fn holidays(&self) -> &'static [Holiday] {
+        match self {
+            Jurisdiction::UsFederal => US_FEDERAL,
+            Jurisdiction::GbEngland => GB_ENGLAND,
+            Jurisdiction::DeNational => DE_NATIONAL,
+        }
+    }
+
+    fn observance(&self) -> Observance {
+        match self {
+            Jurisdiction::UsFederal => Observance::NearestWeekday,
+            Jurisdiction::GbEngland => Observance::NextFreeWeekday,
+            Jurisdiction::DeNational => Observance::Actual,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+enum Rule {
+    /// Month and day.
+    Fixed(u32, u32),
+    /// `nth` ISO weekday (1 = Monday) of the month, -1 is the last one.
+    NthWeekday { month: u32, weekday: u32, nth: i32 },
+    /// Days after Easter Sunday.
+    Easter(i32),
+}
+
+/// What happens to a holiday falling on a weekend.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Observance {
+    Actual,
+    /// Saturday holidays are observed on Friday, Sunday holidays on Monday.
+    NearestWeekday,
+    /// Moved to the next weekday that is not already a holiday.
+    NextFreeWeekday,
+}
+
+struct Holiday {
+    name: &'static str,
+    rule: Rule,
+    /// First year the holiday was observed.
+    since: i32,
+}
+
+const fn holiday(name: &'static str, rule: Rule, since: i32) -> Holiday {
+    Holiday { name, rule, since }
+}
+
+const fn nth(month: u32, weekday: u32, nth: i32) -> Rule {
+    Rule::NthWeekday {
+        month,
+        weekday,
+        nth,
+    }
+}
+
+const MONDAY: u32 = 1;
+const THURSDAY: u32 = 4;
+
+static US_FEDERAL: &[Holiday] = &[
+    holiday("New Year's Day", Rule::Fixed(1, 1), 1870),
+    holiday("Martin Luther King Jr. Day", nth(1, MONDAY, 3), 1986),
+    holiday("Washington's Birthday", nth(2, MONDAY, 3), 1971),
+    holiday("Memorial Day", nth(5, MONDAY, -1), 1971),
+    holiday("Juneteenth", Rule::Fixed(6, 19), 2021),
+    holiday("Independence Day", Rule::Fixed(7, 4), 1870),
+    holiday("Labor Day", nth(9, MONDAY, 1), 1894),
+    holiday("Columbus Day", nth(10, MONDAY, 2), 1971),
+    holiday("Veterans Day", Rule::Fixed(11, 11), 1938),
+    holiday("Thanksgiving Day", nth(11, THURSDAY, 4), 1942),
+    holiday("Christmas Day", Rule::Fixed(12, 25), 1870),
+];
+
+static GB_ENGLAND: &[Holiday] = &[
+    holiday("New Year's Day", Rule::Fixed(1, 1), 1974),
+    holiday("Good Friday", Rule::Easter(-2), 1871),
+    holiday("Easter Monday", Rule::Easter(1), 1871),
+    holiday("Early May bank holiday", nth(5, MONDAY, 1), 1978),
+    holiday("Spring bank holiday", nth(5, MONDAY, -1), 1971),
+    holiday("Summer bank holiday", nth(8, MONDAY, -1), 1971),
+    holiday("Christmas Day", Rule::Fixed(12, 25), 1871),
+    holiday("Boxing Day", Rule::Fixed(12, 26), 1871),
+];
+
+static DE_NATIONAL: &[Holiday] = &[
+    holiday("Neujahr", Rule::Fixed(1, 1), 1949),
+    holiday("Karfreitag", Rule::Easter(-2), 1949),
+    holiday("Ostermontag", Rule::Easter(1), 1949),
+    holiday("Tag der Arbeit", Rule::Fixed(5, 1), 1949),
+    holiday("Christi Himmelfahrt", Rule::Easter(39), 1949),
+    holiday("Pfingstmontag", Rule::Easter(50), 1949),
+    holiday("Tag der Deutschen Einheit", Rule::Fixed(10, 3), 1990),
+    holiday("Erster Weihnachtstag", Rule::Fixed(12, 25), 1949),
+    holiday("Zweiter Weihnachtstag", Rule::Fixed(12, 26), 1949),
+];
+
+/// Easter Sunday of the Gregorian calendar (anonymous Gregorian algorithm), as a PG epoch day.
+pub fn easter_sunday(year: i32) -> i32 {
+    let a = year % 19;
+    let b = year / 100;
+    let c = year % 100;
+    let d = b / 4;
+    let e = b % 4;
+    let f = (b + 8) / 25;
+    let g = (b - f + 1) / 3;
+    let h = (19 * a + b - d - g + 15) % 30;
+    let i = c / 4;
+    let k = c % 4;
+    let l = (32 + 2 * e + 2 * i - h - k) % 7;
+    let m = (a + 11 * h + 22 * l) / 451;
+    let month = (h + l - 7 * m + 114) / 31;
+    let day = (h + l - 7 * m + 114) % 31 + 1;
+    date::from_ymd(year, month as u32, day as u32)
+}
+
+fn rule_date(rule: Rule, year: i32) -> i32 {
+    match rule {
+        Rule::Fixed(month, day) => date::from_ymd(year, month, day),
+        Rule::NthWeekday {
+            month,
+            weekday,
+            nth,
+        } if nth > 0 => {
+            let first = date::from_ymd(year, month, 1);
+            let offset = (weekday + 7 - date::iso_weekday(first)) % 7;
+            first + offset as i32 + 7 * (nth - 1)
+        }
+        Rule::NthWeekday {
+            month,
+            weekday,
+            nth,
+        } => {
+            let last = date::from_ymd(year + month as i32 / 12, month % 12 + 1, 1) - 1;
+            let offset = (date::iso_weekday(last) + 7 - weekday) % 7;
+            last - offset as i32 + 7 * (nth + 1)
+        }
+        Rule::Easter(days) => easter_sunday(year) + days,
+    }
+}
+
+fn is_weekend(day: i32) -> bool {
+    date::iso_weekday(day) >= 6
+}
+
+/// Observed holidays of one year, in rule order.
+fn year_holidays(jurisdiction: Jurisdiction, year: i32) -> Vec<(i32, &'static str)> {
+    let mut observed: Vec<(i32, &'static str)> = vec![];
+    for holiday in jurisdiction.holidays() {
+        if year < holiday.since {
+            continue;
+        }
+        let actual = rule_date(holiday.rule, year);
+        let day = match (jurisdiction.observance(), date::iso_weekday(actual)) {
+            (Observance::NearestWeekday, 6) => actual - 1,
+            (Observance::NearestWeekday, 7) => actual + 1,
+            (Observance::NextFreeWeekday, _) => {
+                let mut day = actual;
+                while is_weekend(day) || observed.iter().any(|(other, _)| *other == day) {
+                    day += 1;
+                }
+                day
+            }
+            _ => actual,
+        };
+        observed.push((day, holiday.name));
+    }
+    observed
+}
+
+/// Holidays observed between `from` and `to` (inclusive PG epoch days), sorted by date. A
+/// holiday observed on another day than its actual date is only listed on the observed one.
+pub fn holidays(jurisdiction: Jurisdiction, from: i32, to: i32) -> Vec<(i32, &'static str)> {
+    if from > to {
+        return vec![];
+    }
+    // observed dates can move into the neighbouring year (e.g. US New Year's Day on Dec 31)
+    let (first_year, _, _) = date::to_ymd(from);
+    let (last_year, _, _) = date::to_ymd(to);
+    let mut days: Vec<(i32, &'static str)> = (first_year - 1..=last_year + 1)
+        .flat_map(|year| year_holidays(jurisdiction, year))
+        .filter(|(day, _)| (from..=to).contains(day))
+        .collect();
+    days.sort_unstable();
+    days.dedup_by_key(|(day, _)| *day);
+    days
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn dates(jurisdiction: Jurisdiction, year: i32) -> Vec<(i32, u32, u32)> {
+        holidays(
+            jurisdiction,
+            date::from_ymd(year, 1, 1),
+            date::from_ymd(year, 12, 31),
+        )
+        .into_iter()
+        .map(|(day, _)| date::to_ymd(day))
+        .collect()
+    }
+
+    #[test]
+    fn easter_dates() {
+        for (year, month, day) in [(2000, 4, 23), (2019, 4, 21), (2024, 3, 31), (2038, 4, 25)] {
+            assert_eq!(easter_sunday(year), date::from_ymd(year, month, day));
+        }
+    }
+
+    #[test]
+    fn us_federal_observed_dates() {
+        assert_eq!(
+            dates(Jurisdiction::UsFederal, 2021),
+            vec![
+                (2021, 1, 1),
+                (2021, 1, 18),
+                (2021, 2, 15),
+                (2021, 5, 31),
+                (2021, 6, 18), // Juneteenth on a Saturday
+                (2021, 7, 5),  // Independence Day on a Sunday
+                (2021, 9, 6),
+                (2021, 10, 11),
+                (2021, 11, 11),
+                (2021, 11, 25),
+                (2021, 12, 24),
+                (2021, 12, 31), // New Year's Day 2022 on a Saturday
+            ]
+        );
+    }
+
+    #[test]
+    fn gb_england_substitute_days() {
+        assert_eq!(
+            dates(Jurisdiction::GbEngland, 2021),
+            vec![
+                (2021, 1, 1),
+                (2021, 4, 2),
+                (2021, 4, 5),
+                (2021, 5, 3),
+                (2021, 5, 31),
+                (2021, 8, 30),
+                (2021, 12, 27), // Christmas Day on a Saturday
+                (2021, 12, 28), // Boxing Day on a Sunday
+            ]
+        );
+    }
+
+    #[test]
+    fn de_national_dates() {
+        assert_eq!(
+            dates(Jurisdiction::DeNational, 2024),
+            vec![
+                (2024, 1, 1),
+                (2024, 3, 29),
+                (2024, 4, 1),
+                (2024, 5, 1),
+                (2024, 5, 9),
+                (2024, 5, 20),
+                (2024, 10, 3),
+                (2024, 12, 25),
+                (2024, 12, 26),
+            ]
+        );
+        assert_eq!(
+            Jurisdiction::parse("de_national"),
+            Some(Jurisdiction::DeNational)
+        );
+        assert_eq!(Jurisdiction::parse("XX"), None);
+    }
+}
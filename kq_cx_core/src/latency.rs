@@ -0,0 +1,63 @@
+//! Fixed-size latency histograms with power-of-two buckets.
+//!
+//! Bucket `i` counts the durations of `[2^(i-1), 2^i)` nanoseconds (bucket 0 only counts 0 ns),
+//! the last bucket also counts everything above. The counters can be kept in shared memory and
+//! percentiles are only precise to a factor of two, which is enough to spot lock waits.
+
+pub const LATENCY_BUCKETS: usize = 32;
+
+/// Bucket counting a duration of `ns` nanoseconds.
+pub fn bucket(ns: u64) -> usize {
+    ((u64::BITS - ns.leading_zeros()) as usize).min(LATENCY_BUCKETS - 1)
+}
+
+/// Upper bound in nanoseconds of the bucket holding the `percent` percentile of the counted
+/// durations, `None` when nothing was counted.
+pub fn percentile(buckets: &[u64; LATENCY_BUCKETS], percent: u64) -> Option<u64> {
+    let count: u64 = buckets.iter().sum();
+    if count == 0 {
+        return None;
+    }
+    // rank of the percentile, 1-based
+    let rank = (count * percent).div_ceil(100).max(1);
+    let mut seen = 0;
+    let index = buckets
+        .iter()
+        .position(|bucket_count| {
+            seen += bucket_count;
+            seen >= rank
+        })
+        .unwrap();
+    Some(match index {
+        0 => 0,
+        _ => (1u64 << index) - 1,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn durations_fall_in_power_of_two_buckets() {
+        assert_eq!(bucket(0), 0);
+        assert_eq!(bucket(1), 1);
+        assert_eq!(bucket(1023), 10);
+        assert_eq!(bucket(1024), 11);
+        assert_eq!(bucket(u64::MAX), LATENCY_BUCKETS - 1);
+    }
+
+    #[test]
+    fn percentiles_report_the_bucket_upper_bound() {
+        let mut buckets = [0; LATENCY_BUCKETS];
+        assert_eq!(percentile(&buckets, 50), None);
+
+        // 98 fast acquisitions and 2 slow ones
+        buckets[bucket(100)] += 98;
+        buckets[bucket(5_000_000)] += 2;
+        assert_eq!(percentile(&buckets, 50), Some(127));
+        assert_eq!(percentile(&buckets, 98), Some(127));
+        assert_eq!(percentile(&buckets, 99), Some(8_388_607));
+        assert_eq!(percentile(&buckets, 100), Some(8_388_607));
+    }
+}
@@ -0,0 +1,393 @@
+//! Calendar storage and arithmetic used by the `kq_cx` PostgreSQL extension.
+//!
+//! This crate has no PostgreSQL dependency, dates are plain `i32` day numbers relative to the
+//! PostgreSQL epoch (2000-01-01), so the search and arithmetic logic can be tested and
+//! benchmarked with `cargo` alone. The extension stores `Calendar` values in shared memory.
+
+pub mod bitmap;
+pub mod cron;
+pub mod date;
+pub mod diff;
+pub mod fixture;
+pub mod format;
+pub mod holidays;
+pub mod latency;
+pub mod math;
+pub mod names;
+pub mod skip;
+pub mod synthetic;
+
+use std::fmt;
+
+pub const MAX_ENTRIES_PER_CALENDAR: usize = 8 * 1024;
+pub const MAX_PAGES_PER_CALENDAR: usize = 512;
+
+pub type EntriesVec = heapless::Vec<i32, MAX_ENTRIES_PER_CALENDAR>;
+pub type PageMapVec = heapless::Vec<usize, MAX_PAGES_PER_CALENDAR>;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CalendarError {
+    /// The calendar already holds `MAX_ENTRIES_PER_CALENDAR` entries.
+    TooManyEntries,
+    /// The page map needs more than `MAX_PAGES_PER_CALENDAR` pages.
+    TooManyPages,
+}
+
+impl fmt::Display for CalendarError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            CalendarError::TooManyEntries => write!(
+                f,
+                "calendar cannot hold more than {MAX_ENTRIES_PER_CALENDAR} entries"
+            ),
+            CalendarError::TooManyPages => write!(
+                f,
+                "calendar page map cannot hold more than {MAX_PAGES_PER_CALENDAR} pages"
+            ),
+        }
+    }
+}
+
+impl std::error::Error for CalendarError {}
+
+/// How lookups locate the entry of a date, chosen from the spacing of the entries when the page
+/// map is built.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum SearchStrategy {
+    /// Page map, then binary search within the page.
+    #[default]
+    Binary,
+    /// Index guessed from the position of the date between the first and last entry, for nearly
+    /// uniformly spaced entries (weekly, monthly). Falls back to `Binary` when the guess misses.
+    Interpolation,
+}
+
+/// Sorted calendar entries plus the page map used to locate them.
+///
+/// The page map splits the date range in pages of `page_size` days, each page map entry holds the
+/// index of the first date that falls in that page.
+#[derive(Default, Clone, Debug)]
+pub struct Calendar {
+    pub(crate) dates: EntriesVec,
+    pub(crate) page_size: i32,
+    pub(crate) first_page_offset: i32,
+    pub(crate) page_map: PageMapVec,
+    pub(crate) checksum: u64,
+    /// Calendar used for dates outside of this one, see `covers`.
+    pub(crate) parent_id: Option<i64>,
+    pub(crate) search: SearchStrategy,
+    /// Results of the hot intervals, see `skip`.
+    pub(crate) skips: skip::SkipTables,
+}
+
+impl Calendar {
+    /// Builds a calendar (entries and page map) from dates already sorted in ascending order.
+    pub fn from_dates(dates: &[i32]) -> Result<Calendar, CalendarError> {
+        let mut calendar = Calendar::default();
+        for date in dates {
+            calendar.push_date(*date)?;
+        }
+        calendar.build_page_map()?;
+        Ok(calendar)
+    }
+
+    pub fn dates(&self) -> &[i32] {
+        &self.dates
+    }
+
+    pub fn page_size(&self) -> i32 {
+        self.page_size
+    }
+
+    pub fn first_page_offset(&self) -> i32 {
+        self.first_page_offset
+    }
+
+    pub fn page_map(&self) -> &[usize] {
+        &self.page_map
+    }
+
+    /// Stable content hash of the entries (see `dates_checksum`), calculated when the page map is
+    /// built. Two calendars with the same entries always have the same checksum.
+    pub fn checksum(&self) -> u64 {
+        self.checksum
+    }
+
+    pub fn parent_id(&self) -> Option<i64> {
+        self.parent_id
+    }
+
+    pub fn search_strategy(&self) -> SearchStrategy {
+        self.search
+    }
+
+    /// Bytes taken by the entries, the page map and the skip tables. The shared memory slot of a
+    /// calendar always has room for `MAX_ENTRIES_PER_CALENDAR` entries, this is the part of it in
+    /// use.
+    pub fn used_bytes(&self) -> usize {
+        std::mem::size_of_val(self.dates())
+            + std::mem::size_of_val(self.page_map())
+            + self.skips.used_bytes()
+    }
+
+    pub fn set_parent_id(&mut self, parent_id: Option<i64>) {
+        self.parent_id = parent_id;
+    }
+
+    /// True when `date` is between the first and the last entry (inclusive), lookups for other
+    /// dates are delegated to the parent calendar when there is one.
+    pub fn covers(&self, date: i32) -> bool {
+        match (self.dates.first(), self.dates.last()) {
+            (Some(first_date), Some(last_date)) => (*first_date..=*last_date).contains(&date),
+            _ => false,
+        }
+    }
+
+    /// Appends an entry, `build_page_map` must be called once all the entries are added.
+    pub fn push_date(&mut self, date: i32) -> Result<(), CalendarError> {
+        self.dates
+            .push(date)
+            .map_err(|_| CalendarError::TooManyEntries)
+    }
+
+    /// Sorts the entries pushed in any order and drops the repeated ones. Returns false when they
+    /// already were ascending and unique.
+    pub fn sort_dates(&mut self) -> bool {
+        if self.dates.windows(2).all(|pair| pair[0] < pair[1]) {
+            return false;
+        }
+        self.dates.sort_unstable();
+        let mut unique = 0;
+        for index in 0..self.dates.len() {
+            if unique == 0 || self.dates[index] != self.dates[unique - 1] {
+                self.dates[unique] = self.dates[index];
+                unique += 1;
+            }
+        }
+        self.dates.truncate(unique);
+        true
+    }
+
+    /// True when `date` is one of the entries.
+    pub fn contains(&self, date: i32) -> bool {
+        self.dates.binary_search(&date).is_ok()
+    }
+
+    /// Adds and removes entries (in any order), keeping the entries sorted and unique. Removing
+    /// wins when a date is in both lists. `build_page_map` must be called afterwards.
+    pub fn apply_exceptions(
+        &mut self,
+        added: &[i32],
+        removed: &[i32],
+    ) -> Result<(), CalendarError> {
+        let mut dates: Vec<i32> = self.dates.iter().chain(added).copied().collect();
+        dates.sort_unstable();
+        dates.dedup();
+        let mut removed = removed.to_vec();
+        removed.sort_unstable();
+        dates.retain(|date| removed.binary_search(date).is_err());
+
+        self.dates.clear();
+        for date in dates {
+            self.push_date(date)?;
+        }
+        Ok(())
+    }
+
+    /// Calculates the page size and (re)creates the page map from the current entries.
+    pub fn build_page_map(&mut self) -> Result<(), CalendarError> {
+        self.build_page_map_sized(None)
+    }
+
+    /// Same as `build_page_map` with a fixed (positive) page size instead of the one calculated
+    /// from the entry density. The page map may be left incomplete when it returns an error.
+    pub fn build_page_map_sized(&mut self, page_size: Option<i32>) -> Result<(), CalendarError> {
+        self.page_map.clear();
+        self.checksum = dates_checksum(&self.dates);
+        self.search = math::search_strategy(&self.dates);
+        // the skip tables refer to the entries, built again for the same intervals
+        let intervals: Vec<i32> = self.skips.intervals().to_vec();
+        self.build_skip_tables(&intervals);
+        let (Some(first_date), Some(last_date)) = (self.dates.first(), self.dates.last()) else {
+            self.page_size = 0;
+            self.first_page_offset = 0;
+            return Ok(());
+        };
+
+        let page_size = page_size.unwrap_or_else(|| {
+            math::calculate_page_size(
+                *first_date,
+                *last_date,
+                self.dates.len() as i64,
+                MAX_PAGES_PER_CALENDAR,
+            )
+        });
+        let first_page_offset = first_date / page_size;
+
+        self.page_size = page_size;
+        self.first_page_offset = first_page_offset;
+
+        self.page_map
+            .push(0)
+            .map_err(|_| CalendarError::TooManyPages)?;
+        let mut prev_page_index = 0;
+        for (calendar_date_index, date) in self.dates.iter().enumerate() {
+            let page_index = (date / page_size) - first_page_offset;
+            while prev_page_index < page_index {
+                prev_page_index += 1;
+                self.page_map
+                    .push(calendar_date_index)
+                    .map_err(|_| CalendarError::TooManyPages)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+const FNV_OFFSET_BASIS: u64 = 0xcbf2_9ce4_8422_2325;
+const FNV_PRIME: u64 = 0x0000_0100_0000_01b3;
+
+fn fnv1a(hash: u64, bytes: impl IntoIterator<Item = u8>) -> u64 {
+    bytes.into_iter().fold(hash, |hash, byte| {
+        (hash ^ byte as u64).wrapping_mul(FNV_PRIME)
+    })
+}
+
+/// 64-bit FNV-1a over the little-endian bytes of the dates.
+pub fn dates_checksum(dates: &[i32]) -> u64 {
+    fnv1a(
+        FNV_OFFSET_BASIS,
+        dates.iter().flat_map(|date| date.to_le_bytes()),
+    )
+}
+
+/// Whole-cache checksum from the `(calendar_id, checksum)` pairs of every calendar, the order
+/// of the pairs does not matter.
+pub fn cache_checksum(calendars: &[(i64, u64)]) -> u64 {
+    let mut calendars = calendars.to_vec();
+    calendars.sort_unstable();
+    fnv1a(
+        FNV_OFFSET_BASIS,
+        calendars.iter().flat_map(|(calendar_id, checksum)| {
+            calendar_id
+                .to_le_bytes()
+                .into_iter()
+                .chain(checksum.to_le_bytes())
+        }),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn page_map_points_to_first_entry_of_each_page() {
+        let calendar = Calendar::from_dates(&[0, 1, 2, 40, 41, 90]).unwrap();
+        let page_size = calendar.page_size();
+        assert_eq!(calendar.page_map().len(), (90 / page_size) as usize + 1);
+        for (page_index, first_index) in calendar.page_map().iter().enumerate() {
+            let page_start = page_index as i32 * page_size;
+            // every entry before the page start index belongs to a previous page
+            assert!(calendar.dates()[..*first_index]
+                .iter()
+                .all(|date| *date < page_start));
+        }
+    }
+
+    #[test]
+    fn page_size_override_keeps_lookups() {
+        let dates: Vec<i32> = (0..200).map(|day| day * 7).collect();
+        let mut calendar = Calendar::from_dates(&dates).unwrap();
+        let expected: Vec<i32> = (0..1500)
+            .map(|date| math::add_calendar_days(&calendar, date, 3))
+            .collect();
+
+        calendar.build_page_map_sized(Some(5)).unwrap();
+        assert_eq!(calendar.page_size(), 5);
+        let found: Vec<i32> = (0..1500)
+            .map(|date| math::add_calendar_days(&calendar, date, 3))
+            .collect();
+        assert_eq!(found, expected);
+
+        assert_eq!(
+            calendar.build_page_map_sized(Some(1)),
+            Err(CalendarError::TooManyPages)
+        );
+    }
+
+    #[test]
+    fn sort_dates_orders_and_dedups() {
+        let mut calendar = Calendar::default();
+        for date in [30, 10, 20, 10, 40] {
+            calendar.push_date(date).unwrap();
+        }
+        assert!(calendar.sort_dates());
+        assert_eq!(calendar.dates(), [10, 20, 30, 40]);
+        assert!(!calendar.sort_dates());
+    }
+
+    #[test]
+    fn used_bytes_counts_entries_and_pages() {
+        let calendar = Calendar::from_dates(&[0, 1, 2, 40, 41, 90]).unwrap();
+        assert_eq!(
+            calendar.used_bytes(),
+            6 * 4 + std::mem::size_of_val(calendar.page_map())
+        );
+        assert_eq!(Calendar::default().used_bytes(), 0);
+    }
+
+    #[test]
+    fn empty_calendar_has_no_pages() {
+        let calendar = Calendar::from_dates(&[]).unwrap();
+        assert!(calendar.page_map().is_empty());
+        assert_eq!(calendar.page_size(), 0);
+    }
+
+    #[test]
+    fn covers_is_inclusive_and_false_when_empty() {
+        let calendar = Calendar::from_dates(&[10, 20, 30]).unwrap();
+        assert!(calendar.covers(10) && calendar.covers(25) && calendar.covers(30));
+        assert!(!calendar.covers(9) && !calendar.covers(31));
+        assert!(!Calendar::default().covers(0));
+    }
+
+    #[test]
+    fn exceptions_add_and_remove_entries() {
+        let mut calendar = Calendar::from_dates(&[10, 20, 30]).unwrap();
+        calendar.apply_exceptions(&[25, 5, 20], &[30, 40]).unwrap();
+        calendar.build_page_map().unwrap();
+        assert_eq!(calendar.dates(), [5, 10, 20, 25]);
+        assert_eq!(calendar.checksum(), dates_checksum(&[5, 10, 20, 25]));
+    }
+
+    #[test]
+    fn too_many_entries_is_an_error() {
+        let dates: Vec<i32> = (0..=MAX_ENTRIES_PER_CALENDAR as i32).collect();
+        assert_eq!(
+            Calendar::from_dates(&dates).unwrap_err(),
+            CalendarError::TooManyEntries
+        );
+    }
+
+    #[test]
+    fn checksum_depends_on_content_and_order() {
+        let calendar = Calendar::from_dates(&[1, 2, 3]).unwrap();
+        assert_eq!(calendar.checksum(), dates_checksum(&[1, 2, 3]));
+        assert_ne!(calendar.checksum(), dates_checksum(&[1, 2, 4]));
+        assert_ne!(dates_checksum(&[1, 2]), dates_checksum(&[2, 1]));
+        assert_eq!(dates_checksum(&[]), 0xcbf2_9ce4_8422_2325);
+    }
+
+    #[test]
+    fn cache_checksum_ignores_calendar_order() {
+        assert_eq!(
+            cache_checksum(&[(1, 10), (2, 20)]),
+            cache_checksum(&[(2, 20), (1, 10)])
+        );
+        assert_ne!(
+            cache_checksum(&[(1, 10), (2, 20)]),
+            cache_checksum(&[(1, 20), (2, 10)])
+        );
+    }
+}
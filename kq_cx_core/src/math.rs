@@ -0,0 +1,1326 @@
+use std::cmp::Ordering;
+use std::ops::Bound;
+
+use crate::{date, Calendar, SearchStrategy};
+
+/// Target number of entries per page, the page size is the smallest power of two that keeps
+/// the average page at or above this density (so pages end up holding ~4-8 entries).
+const TARGET_ENTRIES_PER_PAGE: i64 = 4;
+
+/// Calculates the page size (in days) from the actual entry density of the calendar.
+///
+/// The original C heuristic only distinguished weekly (16) and monthly (32) calendars, which
+/// produced sparse pages for quarterly/yearly calendars and overfull pages for daily ones. The
+/// result is clamped to a power of two and grown until the page map fits in `max_pages`.
+pub fn calculate_page_size(
+    first_date: i32,
+    last_date: i32,
+    entry_count: i64,
+    max_pages: usize,
+) -> i32 {
+    let date_range = (last_date as i64 - first_date as i64) + 1;
+    let days_per_target = (date_range * TARGET_ENTRIES_PER_PAGE) / entry_count.max(1);
+
+    let mut page_size =
+        (days_per_target.clamp(1, i32::MAX as i64 / 2) as u32).next_power_of_two() as i32;
+    while page_count(first_date, last_date, page_size) > max_pages {
+        page_size *= 2;
+    }
+    page_size
+}
+
+/// Entries are searched by interpolation when the spread of their gaps (largest minus smallest)
+/// is at most this fraction of the smallest gap, e.g. 28 to 31 days for monthly calendars.
+const INTERPOLATION_MAX_GAP_SPREAD: (i64, i64) = (1, 4);
+
+/// Strategy for the entries, see `SearchStrategy`. Calendars with fewer than 3 entries always use
+/// the binary search.
+pub fn search_strategy(dates: &[i32]) -> SearchStrategy {
+    if dates.len() < 3 {
+        return SearchStrategy::Binary;
+    }
+    let (min_gap, max_gap) = dates
+        .windows(2)
+        .map(|pair| pair[1] as i64 - pair[0] as i64)
+        .fold((i64::MAX, i64::MIN), |(min_gap, max_gap), gap| {
+            (min_gap.min(gap), max_gap.max(gap))
+        });
+    let (numerator, denominator) = INTERPOLATION_MAX_GAP_SPREAD;
+    if (max_gap - min_gap) * denominator <= min_gap * numerator {
+        SearchStrategy::Interpolation
+    } else {
+        SearchStrategy::Binary
+    }
+}
+
+/// Number of page map entries needed to cover `[first_date, last_date]` with `page_size`.
+fn page_count(first_date: i32, last_date: i32, page_size: i32) -> usize {
+    ((last_date / page_size) - (first_date / page_size) + 1) as usize
+}
+
+/// One step of a calendar lookup, reported by `explain_closest_index_from_left`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LookupStep {
+    /// Page map entry computed from the date, see `page_map_index`.
+    PageIndex { page_map_index: i32 },
+    /// The date is before the first or after the last entry, no search is needed.
+    OutsideEntries { first_date: i32, last_date: i32 },
+    /// Range of `dates` (inclusive) covered by the page.
+    PageBounds { start_index: i32, end_index: i32 },
+    /// Binary search iteration comparing `dates[mid]` with the date.
+    Compare {
+        left: i32,
+        right: i32,
+        mid: i32,
+        mid_date: i32,
+    },
+    /// Index guessed from the position of the date, `hit` when it or one of its neighbours is
+    /// the closest index from the left.
+    Interpolation { guess: i32, hit: bool },
+    /// Closest index from the left, -1 when the date is before the first entry.
+    Found { index: i32 },
+}
+
+// Original C Source
+// int32 left_binary_search(const int32 *arr, int32 left, int32 right, int32 value) {
+//     while (left <= right) {
+//         int32 mid = left + (right - left) / 2;
+//         if (arr[mid] < value)
+//             left = mid + 1;
+//         else if (arr[mid] > value) {
+//             right = mid - 1;
+//         } else
+//             return mid;
+//     }
+//     return left - 1;
+// }
+fn left_binary_search(
+    arr: &[i32],
+    mut left: i32,
+    mut right: i32,
+    value: i32,
+    trace: &mut impl FnMut(LookupStep),
+) -> i32 {
+    while left <= right {
+        let mid = left + (right - left) / 2;
+        trace(LookupStep::Compare {
+            left,
+            right,
+            mid,
+            mid_date: arr[mid as usize],
+        });
+        match arr[mid as usize].cmp(&value) {
+            Ordering::Less => left = mid + 1,
+            Ordering::Greater => right = mid - 1,
+            Ordering::Equal => return mid,
+        }
+    }
+    left - 1
+}
+
+/// Closest index from the left by interpolation, `None` when neither the guessed index nor its
+/// neighbours are. `date` must be between the first and last entry, which must differ.
+fn interpolation_search(
+    dates: &[i32],
+    date: i32,
+    trace: &mut impl FnMut(LookupStep),
+) -> Option<i32> {
+    let first_date = dates[0] as i64;
+    let last_date = dates[dates.len() - 1] as i64;
+    let guess =
+        ((date as i64 - first_date) * (dates.len() - 1) as i64 / (last_date - first_date)) as usize;
+    let is_closest =
+        |index: usize| dates[index] <= date && dates.get(index + 1).is_none_or(|next| *next > date);
+    let index = [guess, guess.saturating_sub(1), guess + 1]
+        .into_iter()
+        .find(|index| *index < dates.len() && is_closest(*index));
+    trace(LookupStep::Interpolation {
+        guess: guess as i32,
+        hit: index.is_some(),
+    });
+    index.map(|index| index as i32)
+}
+
+/// Index of the page map entry for the page that contains `date`, it can be negative or beyond
+/// the page map when the date is outside of the calendar.
+pub fn page_map_index(date: i32, calendar: &Calendar) -> i32 {
+    (date / calendar.page_size) - calendar.first_page_offset
+}
+
+// Original C Source
+// int32 get_closest_index_from_left(int32 date_adt, Calendar calendar) {
+//     int32 page_map_index = (date_adt / calendar.page_size) - calendar.first_page_offset;
+//
+//     if (page_map_index >= calendar.page_map_size) {
+//         int32 ret_val = -1 * calendar.dates_size - 1;
+//         return ret_val;
+//     } else if (page_map_index < 0) {
+//         int32 ret_val = -1;
+//         return ret_val;
+//     }
+//
+//     int32 inclusive_start_index = calendar.page_map[page_map_index];
+//     int32 exclusive_end_index =
+//         page_map_index < calendar.page_map_size - 1 ?
+//     calendar.page_map[page_map_index + 1] :
+//     calendar.dates_size;
+//
+//     return left_binary_search(calendar.dates,
+//         inclusive_start_index,
+//         exclusive_end_index,
+//         date_adt);
+// }
+pub fn get_closest_index_from_left(date: i32, calendar: &Calendar) -> i32 {
+    closest_index_from_left_traced(date, calendar, &mut |_| {})
+}
+
+/// Same as `get_closest_index_from_left`, also returning every step taken by the lookup.
+pub fn explain_closest_index_from_left(date: i32, calendar: &Calendar) -> Vec<LookupStep> {
+    let mut steps = Vec::new();
+    let index = closest_index_from_left_traced(date, calendar, &mut |step| steps.push(step));
+    steps.push(LookupStep::Found { index });
+    steps
+}
+
+fn closest_index_from_left_traced(
+    date: i32,
+    calendar: &Calendar,
+    trace: &mut impl FnMut(LookupStep),
+) -> i32 {
+    // Checked against the first and last entry before the page map, so every date before the
+    // calendar is -1 and every date after it the last entry, whatever page it falls in. The C
+    // version returned a negative index past the last page, sending those dates to DATE_PAST.
+    let (Some(first_date), Some(last_date)) = (calendar.dates.first(), calendar.dates.last())
+    else {
+        return -1;
+    };
+    if date < *first_date || date > *last_date {
+        trace(LookupStep::OutsideEntries {
+            first_date: *first_date,
+            last_date: *last_date,
+        });
+        return if date < *first_date {
+            -1
+        } else {
+            calendar.dates.len() as i32 - 1
+        };
+    }
+    if calendar.search == SearchStrategy::Interpolation {
+        if let Some(index) = interpolation_search(&calendar.dates, date, trace) {
+            return index;
+        }
+    }
+    let page_map_index = page_map_index(date, calendar);
+
+    // debug1!("page_map_index: {}, date: {}, calendar.page_size: {}, calendar.first_page_offset: {}",
+    //     page_map_index, date, calendar.page_size, calendar.first_page_offset);
+
+    trace(LookupStep::PageIndex { page_map_index });
+
+    let (start_index, end_index) = page_bounds(page_map_index, calendar).unwrap();
+
+    // debug1!("get_closest_index_from_left: inclusive_start_index: {}, exclusive_end_index: {}", inclusive_start_index, exclusive_end_index);
+
+    trace(LookupStep::PageBounds {
+        start_index,
+        end_index,
+    });
+
+    left_binary_search(&calendar.dates, start_index, end_index, date, trace)
+}
+
+/// Range of `dates` (inclusive) covered by a page, `None` outside of the page map.
+fn page_bounds(page_map_index: i32, calendar: &Calendar) -> Option<(i32, i32)> {
+    if page_map_index < 0 || page_map_index >= calendar.page_map.len() as i32 {
+        return None;
+    }
+    let inclusive_start_index = calendar.page_map[page_map_index as usize];
+    let exclusive_end_index = if page_map_index < calendar.page_map.len() as i32 - 1 {
+        calendar.page_map[page_map_index as usize + 1]
+    } else {
+        calendar.dates.len()
+    };
+    Some((
+        inclusive_start_index as i32,
+        (exclusive_end_index - 1) as i32,
+    ))
+}
+
+/// Result of the last lookup on a calendar, lets consecutive lookups of nearby dates skip the
+/// page map and most of the binary search. Only valid for the calendar it was built from.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct LookupMemo {
+    /// Dates in `[period_start, period_end)` resolve to `hit_index`.
+    period_start: i32,
+    period_end: i32,
+    hit_index: i32,
+    page_map_index: i32,
+    /// Range of `dates` (inclusive) covered by the page.
+    start_index: i32,
+    end_index: i32,
+}
+
+impl LookupMemo {
+    fn set_hit(&mut self, calendar: &Calendar, hit_index: i32) {
+        self.hit_index = hit_index;
+        self.period_start = if hit_index < 0 {
+            i32::MIN
+        } else {
+            calendar.dates[hit_index as usize]
+        };
+        self.period_end = calendar
+            .dates
+            .get((hit_index + 1) as usize)
+            .copied()
+            .unwrap_or(i32::MAX);
+    }
+}
+
+/// Same as `get_closest_index_from_left`, reusing and updating the memo of the previous lookup.
+pub fn closest_index_from_left_memo(
+    date: i32,
+    calendar: &Calendar,
+    memo: &mut Option<LookupMemo>,
+) -> i32 {
+    let page_map_index = page_map_index(date, calendar);
+    if let Some(last) = memo {
+        if (last.period_start..last.period_end).contains(&date) {
+            return last.hit_index;
+        }
+        if !calendar.covers(date) {
+            return get_closest_index_from_left(date, calendar);
+        }
+        if page_map_index == last.page_map_index {
+            let index = left_binary_search(
+                &calendar.dates,
+                last.start_index,
+                last.end_index,
+                date,
+                &mut |_| {},
+            );
+            last.set_hit(calendar, index);
+            return index;
+        }
+    }
+
+    let index = get_closest_index_from_left(date, calendar);
+    // dates outside of the page map are cheap, the memo of the last page is kept
+    if let Some((start_index, end_index)) = page_bounds(page_map_index, calendar) {
+        let mut last = LookupMemo {
+            period_start: 0,
+            period_end: 0,
+            hit_index: 0,
+            page_map_index,
+            start_index,
+            end_index,
+        };
+        last.set_hit(calendar, index);
+        *memo = Some(last);
+    }
+    index
+}
+
+/// Entry found by the previous lookup of an ordered scan, see `closest_index_from_left_ordered`.
+/// Only valid for the calendar it was built from.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct OrderedCursor {
+    hit_index: usize,
+}
+
+/// Same as `get_closest_index_from_left` for dates read in order (ascending or descending): the
+/// search gallops from the entry of the previous date towards the new one, so consecutive dates
+/// cost O(log distance) instead of a full search. Unordered dates are still correct, only slower.
+pub fn closest_index_from_left_ordered(
+    date: i32,
+    calendar: &Calendar,
+    cursor: &mut Option<OrderedCursor>,
+) -> i32 {
+    let index = match cursor {
+        Some(last) if calendar.covers(date) => gallop(&calendar.dates, last.hit_index, date),
+        _ => get_closest_index_from_left(date, calendar),
+    };
+    // dates outside of the entries are cheap, the cursor stays on the last entry found
+    if calendar.covers(date) {
+        *cursor = Some(OrderedCursor {
+            hit_index: index as usize,
+        });
+    }
+    index
+}
+
+/// Closest index from left of `date` (between the first and last entry), searched from `from`
+/// with steps doubling until the entry is passed, then a binary search within the last step.
+fn gallop(dates: &[i32], from: usize, date: i32) -> i32 {
+    let (mut low, mut high) = (from, from);
+    let mut step = 1;
+    if dates[from] <= date {
+        // dates[low] <= date, the entry is in [low, high)
+        loop {
+            high = (low + step).min(dates.len());
+            if high == dates.len() || dates[high] > date {
+                break;
+            }
+            low = high;
+            step *= 2;
+        }
+    } else {
+        // dates[high] > date
+        loop {
+            low = high.saturating_sub(step);
+            if low == 0 || dates[low] <= date {
+                break;
+            }
+            high = low;
+            step *= 2;
+        }
+    }
+    (low + dates[low..high].partition_point(|entry| *entry <= date)) as i32 - 1
+}
+
+// Original C Source
+// int32 add_calendar_days(
+//     const IMCX *imcx,
+//     const Calendar *calendar,
+//     int32 input_date,
+//     int32 interval,
+//     int32 *result_date,
+//     int32 *first_date_idx,
+//     int32 *result_date_idx
+// ) {
+//     if (!imcx->cache_filled) {
+//         return RET_ERROR_NOT_READY;
+//     }
+//     // Find the interval -> the closest date index from the left of the input_date in the calendar
+//     int32 prev_date_index = get_closest_index_from_left(input_date, *calendar);
+//     // Now try to get the corresponding date of requested interval
+//     int32 result_date_index = prev_date_index + interval;
+//     // This can be useful for reporting or debugging.
+//     if (prev_date_index > 0 && first_date_idx != NULL) {
+//         *first_date_idx = prev_date_index;
+//     }
+//     if (result_date_index > 0 && result_date_idx != NULL) {
+//         *result_date_idx = result_date_index;
+//     }
+//     // Now check if inside boundaries.
+//     if (result_date_index >= 0) // If result_date_index is positive (negative interval is smaller than current index)
+//     {
+//         if (prev_date_index < 0) // Handle Negative OOB Indices (When interval is negative)
+//         {
+//             *result_date = calendar->dates[0]; // Returns first date of the calendar
+//             return RET_ADD_DAYS_NEGATIVE;
+//         }
+//         if (result_date_index >= calendar->dates_size) // Handle Positive OOB Indices (When interval is positive)
+//         {
+//             *result_date = PG_INT32_MAX; // Returns infinity+.
+//             return RET_ADD_DAYS_POSITIVE;
+//         }
+//         *result_date = calendar->dates[result_date_index];
+//         return RET_SUCCESS;
+//     } else {
+//         // Handle Negative OOB Indices (When interval is negative)
+//         *result_date = calendar->dates[0]; // Returns the first date of the calendar.
+//         return RET_SUCCESS;
+//     }
+// }
+
+pub const DATE_PAST: i32 = -10957; //1970-01-01
+pub const DATE_FUTURE: i32 = 72684; //2199-01-01
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum OutOfBounds {
+    /// The input date or the result is before the first entry.
+    Past,
+    /// The result is after the last entry.
+    Future,
+}
+
+/// How an `add_calendar_days` result was derived, the indexes are -1 when they do not apply.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Lookup {
+    pub result_date: i32,
+    /// Closest entry at or before the input date.
+    pub hit_index: i32,
+    pub result_index: i32,
+    pub page_map_index: i32,
+    pub out_of_bounds: Option<OutOfBounds>,
+}
+
+pub fn add_calendar_days(calendar: &Calendar, input_date: i32, interval: i32) -> i32 {
+    lookup_calendar_days(calendar, input_date, interval.into()).result_date
+}
+
+/// Same as `lookup_calendar_days`, see `closest_index_from_left_memo`.
+pub fn lookup_calendar_days_memo(
+    calendar: &Calendar,
+    input_date: i32,
+    interval: i64,
+    memo: &mut Option<LookupMemo>,
+) -> Lookup {
+    lookup_with(calendar, input_date, interval, |date| {
+        closest_index_from_left_memo(date, calendar, memo)
+    })
+}
+
+/// Same as `lookup_calendar_days`, see `closest_index_from_left_ordered`.
+pub fn lookup_calendar_days_ordered(
+    calendar: &Calendar,
+    input_date: i32,
+    interval: i64,
+    cursor: &mut Option<OrderedCursor>,
+) -> Lookup {
+    lookup_with(calendar, input_date, interval, |date| {
+        closest_index_from_left_ordered(date, calendar, cursor)
+    })
+}
+
+/// Same as `add_calendar_days` but returns the intermediate indexes of the lookup. The interval
+/// can be any `i64`, lookups beyond the entries are out of bounds instead of overflowing.
+pub fn lookup_calendar_days(calendar: &Calendar, input_date: i32, interval: i64) -> Lookup {
+    lookup_with(calendar, input_date, interval, |date| {
+        get_closest_index_from_left(date, calendar)
+    })
+}
+
+/// First and last days PostgreSQL accepts as dates (4714-11-24 BC and 5874897-12-31).
+pub const MIN_DATE: i32 = -2_451_545;
+pub const MAX_DATE: i32 = 2_145_031_948;
+
+/// Lookup on a calendar without entries: plain day arithmetic, out of bounds when the result is
+/// not a valid date.
+pub fn plain_arithmetic(input_date: i32, days: i64) -> Lookup {
+    let result_date = (input_date as i64).saturating_add(days);
+    let out_of_bounds = if result_date < MIN_DATE as i64 {
+        Some(OutOfBounds::Past)
+    } else if result_date > MAX_DATE as i64 {
+        Some(OutOfBounds::Future)
+    } else {
+        None
+    };
+    Lookup {
+        result_date: match out_of_bounds {
+            None => result_date as i32,
+            Some(OutOfBounds::Past) => DATE_PAST,
+            Some(OutOfBounds::Future) => DATE_FUTURE,
+        },
+        hit_index: -1,
+        result_index: -1,
+        page_map_index: -1,
+        out_of_bounds,
+    }
+}
+
+/// Index reported by `Lookup`, indexes beyond `i32` only happen out of bounds.
+fn reported_index(index: i64) -> i32 {
+    index.clamp(i32::MIN as i64, i32::MAX as i64) as i32
+}
+
+fn lookup_with(
+    calendar: &Calendar,
+    input_date: i32,
+    interval: i64,
+    closest_index_from_left: impl FnOnce(i32) -> i32,
+) -> Lookup {
+    if calendar.dates.is_empty() {
+        return plain_arithmetic(input_date, interval);
+    }
+
+    let prev_date_index = closest_index_from_left(input_date);
+    let result_date_index = (prev_date_index as i64).saturating_add(interval);
+    let mut lookup = Lookup {
+        result_date: DATE_PAST,
+        hit_index: prev_date_index,
+        result_index: reported_index(result_date_index),
+        page_map_index: page_map_index(input_date, calendar),
+        out_of_bounds: None,
+    };
+
+    if prev_date_index < 0 || result_date_index < 0 {
+        // Handle Negative OOB indices (When interval is negative)
+        lookup.out_of_bounds = Some(OutOfBounds::Past);
+    } else if result_date_index >= calendar.dates.len() as i64 {
+        // Returns infinity+
+        lookup.result_date = DATE_FUTURE;
+        lookup.out_of_bounds = Some(OutOfBounds::Future);
+    } else {
+        lookup.result_date = calendar
+            .skips
+            .result_date(prev_date_index, interval)
+            .unwrap_or_else(|| calendar.dates[result_date_index as usize]);
+    }
+    lookup
+}
+
+/// Direction of `offset_calendar_days`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Direction {
+    Forward,
+    Backward,
+}
+
+impl Direction {
+    pub fn parse(direction: &str) -> Option<Direction> {
+        match direction.to_ascii_lowercase().as_str() {
+            "forward" => Some(Direction::Forward),
+            "backward" => Some(Direction::Backward),
+            _ => None,
+        }
+    }
+}
+
+/// The `steps`-th entry strictly after (forward) or before (backward) `input_date`, with 0 steps
+/// the closest entry at or after (forward) or at or before (backward) it. Unlike
+/// `add_calendar_days`, stepping backward from a date between two entries counts the entry
+/// before it as the first step, so both directions are symmetric.
+pub fn offset_calendar_days(
+    calendar: &Calendar,
+    input_date: i32,
+    steps: u32,
+    direction: Direction,
+) -> Lookup {
+    let steps = steps as i64;
+    if calendar.dates.is_empty() {
+        return match direction {
+            Direction::Forward => plain_arithmetic(input_date, steps),
+            Direction::Backward => plain_arithmetic(input_date, -steps),
+        };
+    }
+
+    let hit_index = get_closest_index_from_left(input_date, calendar);
+    // entries at or before the input date, and strictly before it
+    let at_or_before = hit_index as i64 + 1;
+    let is_entry = hit_index >= 0 && calendar.dates[hit_index as usize] == input_date;
+    let before = if is_entry {
+        at_or_before - 1
+    } else {
+        at_or_before
+    };
+    let result_index = match (direction, steps) {
+        (Direction::Forward, 0) => before,
+        (Direction::Forward, _) => at_or_before + steps - 1,
+        (Direction::Backward, 0) => at_or_before - 1,
+        (Direction::Backward, _) => before - steps,
+    };
+
+    let mut lookup = Lookup {
+        result_date: DATE_PAST,
+        hit_index,
+        result_index: reported_index(result_index),
+        page_map_index: page_map_index(input_date, calendar),
+        out_of_bounds: None,
+    };
+    if result_index < 0 {
+        lookup.out_of_bounds = Some(OutOfBounds::Past);
+    } else if result_index >= calendar.dates.len() as i64 {
+        lookup.result_date = DATE_FUTURE;
+        lookup.out_of_bounds = Some(OutOfBounds::Future);
+    } else {
+        lookup.result_date = calendar.dates[result_index as usize];
+    }
+    lookup
+}
+
+/// Entries of the calendar between the two bounds.
+pub fn entries_between(calendar: &Calendar, lower: Bound<i32>, upper: Bound<i32>) -> &[i32] {
+    let dates = calendar.dates();
+    let start = match lower {
+        Bound::Included(date) => dates.partition_point(|entry| *entry < date),
+        Bound::Excluded(date) => dates.partition_point(|entry| *entry <= date),
+        Bound::Unbounded => 0,
+    };
+    let end = match upper {
+        Bound::Included(date) => dates.partition_point(|entry| *entry <= date),
+        Bound::Excluded(date) => dates.partition_point(|entry| *entry < date),
+        Bound::Unbounded => dates.len(),
+    };
+    &dates[start..end.max(start)]
+}
+
+/// Days of `[from, to]` that are not entries of the calendar, in ascending order. Only the entries
+/// inside the range are copied, the days are produced while iterating.
+pub fn non_entries_between(calendar: &Calendar, from: i32, to: i32) -> impl Iterator<Item = i32> {
+    let entries = entries_between(calendar, Bound::Included(from), Bound::Included(to)).to_vec();
+    let mut next_entry = 0;
+    (from..=to).filter(move |day| match entries.get(next_entry) == Some(day) {
+        true => {
+            next_entry += 1;
+            false
+        }
+        false => true,
+    })
+}
+
+/// Period containing `date`: from the closest entry at or before it until the next entry
+/// (exclusive), `None` as end for the last entry. `None` when the date is before the first entry.
+pub fn period_bounds(calendar: &Calendar, date: i32) -> Option<(i32, Option<i32>)> {
+    let index = get_closest_index_from_left(date, calendar);
+    if index < 0 {
+        return None;
+    }
+    let dates = calendar.dates();
+    Some((
+        dates[index as usize],
+        dates.get(index as usize + 1).copied(),
+    ))
+}
+
+/// Zero-based number of the period containing `date`, the index of the closest entry at or before
+/// it. `None` when the date is before the first entry.
+pub fn period_index(calendar: &Calendar, date: i32) -> Option<usize> {
+    usize::try_from(get_closest_index_from_left(date, calendar)).ok()
+}
+
+/// Start of the period of `periods` containing `date`, and the bounds of the period `shift`
+/// periods later (end `None` for the last one). `None` when the date is before the first entry
+/// or the shifted period is outside the entries.
+pub fn shifted_period(
+    periods: &Calendar,
+    date: i32,
+    shift: i64,
+) -> Option<(i32, i32, Option<i32>)> {
+    let index = period_index(periods, date)?;
+    let dates = periods.dates();
+    let target = usize::try_from(index as i64 + shift).ok()?;
+    Some((
+        dates[index],
+        *dates.get(target)?,
+        dates.get(target + 1).copied(),
+    ))
+}
+
+/// Entry of `calendar` in the period `[start, end)` at the position the entry of `date` has in
+/// its period starting at `period_start`, the last entry of the period when it has fewer.
+/// `None` when the period has no entries.
+pub fn entry_at_position(
+    calendar: &Calendar,
+    period_start: i32,
+    date: i32,
+    (start, end): (i32, Option<i32>),
+) -> Option<i32> {
+    let position = entries_between(
+        calendar,
+        Bound::Included(period_start),
+        Bound::Included(date),
+    )
+    .len()
+    .saturating_sub(1);
+    let end = end.map_or(Bound::Unbounded, Bound::Excluded);
+    let entries = entries_between(calendar, Bound::Included(start), end);
+    entries.get(position).or(entries.last()).copied()
+}
+
+/// Moves `date` by `shift` periods of `periods` (e.g. months) keeping its position in the period
+/// counted in entries of `calendar` (e.g. weeks), so irregular periods are jumped whole.
+pub fn add_periods(calendar: &Calendar, periods: &Calendar, date: i32, shift: i64) -> Option<i32> {
+    let (period_start, start, end) = shifted_period(periods, date, shift)?;
+    entry_at_position(calendar, period_start, date, (start, end))
+}
+
+/// Entries after `from` up to `to` (inclusive), negated when `to` is before `from`. Between two
+/// dates inside the entries it is the interval from the period of `from` to the period of `to`.
+pub fn entries_spanned(calendar: &Calendar, from: i32, to: i32) -> i64 {
+    match from <= to {
+        true => entries_between(calendar, Bound::Excluded(from), Bound::Included(to)).len() as i64,
+        false => -entries_spanned(calendar, to, from),
+    }
+}
+
+/// Days from `date` to the first entry at or after it, 0 when `date` is an entry. `None` after
+/// the last entry.
+pub fn days_until_next_entry(calendar: &Calendar, date: i32) -> Option<i64> {
+    let dates = calendar.dates();
+    let next_entry = dates.get(dates.partition_point(|entry| *entry < date))?;
+    Some(*next_entry as i64 - date as i64)
+}
+
+/// Page of the page map with the entries that fall in it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PageStats {
+    pub page_index: usize,
+    /// Index of the first entry of the page, also the page map value.
+    pub start_offset: usize,
+    pub entry_count: usize,
+    /// First day covered by the page, the page ends `page_size` days later (exclusive).
+    pub first_day: i32,
+}
+
+/// One `PageStats` per page map entry, in page order.
+pub fn page_stats(calendar: &Calendar) -> Vec<PageStats> {
+    (0..calendar.page_map.len())
+        .map(|page_index| {
+            let start_offset = calendar.page_map[page_index];
+            let end_offset = calendar
+                .page_map
+                .get(page_index + 1)
+                .copied()
+                .unwrap_or(calendar.dates.len());
+            PageStats {
+                page_index,
+                start_offset,
+                entry_count: end_offset - start_offset,
+                first_day: (calendar.first_page_offset + page_index as i32) * calendar.page_size,
+            }
+        })
+        .collect()
+}
+
+/// Complete periods `[entry, next entry)` overlapping `[from, to)`, the first one may start
+/// before `from` and the last one end after `to`. The last entry starts no complete period.
+pub fn periods_between(calendar: &Calendar, from: i32, to: i32) -> Vec<(i32, i32)> {
+    if from >= to {
+        return vec![];
+    }
+    let dates = calendar.dates();
+    let first = dates
+        .partition_point(|entry| *entry <= from)
+        .saturating_sub(1);
+    dates[first..]
+        .windows(2)
+        .map(|period| (period[0], period[1]))
+        .take_while(|(start, _)| *start < to)
+        .filter(|(_, end)| *end > from)
+        .collect()
+}
+
+/// Consecutive entries more than `min_gap` days apart, as `(entry, next entry)` pairs.
+pub fn gaps(calendar: &Calendar, min_gap: i32) -> Vec<(i32, i32)> {
+    calendar
+        .dates()
+        .windows(2)
+        .filter(|pair| pair[1] - pair[0] > min_gap)
+        .map(|pair| (pair[0], pair[1]))
+        .collect()
+}
+
+/// Period length of `entry_histogram`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum HistogramBucket {
+    Year,
+    Month,
+}
+
+impl HistogramBucket {
+    pub fn parse(bucket: &str) -> Option<HistogramBucket> {
+        match bucket.to_ascii_lowercase().as_str() {
+            "year" => Some(HistogramBucket::Year),
+            "month" => Some(HistogramBucket::Month),
+            _ => None,
+        }
+    }
+
+    /// First day of the bucket containing `day`.
+    fn start(&self, day: i32) -> i32 {
+        let (year, month, _) = date::to_ymd(day);
+        match self {
+            HistogramBucket::Year => date::from_ymd(year, 1, 1),
+            HistogramBucket::Month => date::from_ymd(year, month, 1),
+        }
+    }
+}
+
+/// Number of entries per year or month as `(first day of the bucket, entries)`, in date order.
+/// Buckets without entries are not listed.
+pub fn entry_histogram(calendar: &Calendar, bucket: HistogramBucket) -> Vec<(i32, usize)> {
+    let mut histogram: Vec<(i32, usize)> = vec![];
+    for day in calendar.dates() {
+        let start = bucket.start(*day);
+        match histogram.last_mut() {
+            Some((last_start, count)) if *last_start == start => *count += 1,
+            _ => histogram.push((start, 1)),
+        }
+    }
+    histogram
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::MAX_PAGES_PER_CALENDAR;
+    use proptest::prelude::*;
+
+    /// Straightforward linear-scan version of `add_calendar_days`, used as the oracle.
+    fn reference_add_days(dates: &[i32], input_date: i32, interval: i32) -> i32 {
+        if dates.is_empty() {
+            return input_date + interval;
+        }
+        let prev_index = dates.iter().filter(|date| **date <= input_date).count() as i64 - 1;
+        let result_index = prev_index + interval as i64;
+        if prev_index < 0 || result_index < 0 {
+            DATE_PAST
+        } else if result_index >= dates.len() as i64 {
+            DATE_FUTURE
+        } else {
+            dates[result_index as usize]
+        }
+    }
+
+    /// Sorted, unique calendars with nearly uniform gaps, mostly searched by interpolation.
+    fn uniform_calendar_dates() -> impl Strategy<Value = Vec<i32>> {
+        (
+            -12000..12000i32,
+            4..100i32,
+            prop::collection::vec(0..1000i32, 3..300),
+        )
+            .prop_map(|(start, gap, jitters)| {
+                jitters
+                    .iter()
+                    .scan(start, |date, jitter| {
+                        *date += gap + jitter % (gap / 4 + 1);
+                        Some(*date)
+                    })
+                    .collect()
+            })
+    }
+
+    /// Sorted, unique calendars with gaps between 1 day and ~1 year.
+    fn calendar_dates() -> impl Strategy<Value = Vec<i32>> {
+        (-12000..12000i32, prop::collection::vec(1..400i32, 1..300)).prop_map(|(start, gaps)| {
+            gaps.iter()
+                .scan(start, |date, gap| {
+                    *date += gap;
+                    Some(*date)
+                })
+                .collect()
+        })
+    }
+
+    proptest! {
+        #[test]
+        fn interpolation_matches_reference(
+            dates in uniform_calendar_dates(),
+            date_offset in -100..100i32,
+            position in 0.0..1.0f64,
+            interval in -60..60i32,
+        ) {
+            let calendar = Calendar::from_dates(&dates).unwrap();
+            let first_date = dates[0];
+            let last_date = dates[dates.len() - 1];
+            let input_date =
+                first_date + ((last_date - first_date) as f64 * position) as i32 + date_offset;
+
+            let mut binary = calendar.clone();
+            binary.search = SearchStrategy::Binary;
+            prop_assert_eq!(
+                get_closest_index_from_left(input_date, &calendar),
+                get_closest_index_from_left(input_date, &binary)
+            );
+            prop_assert_eq!(
+                add_calendar_days(&calendar, input_date, interval),
+                reference_add_days(&dates, input_date, interval)
+            );
+        }
+
+        #[test]
+        fn add_calendar_days_matches_reference(
+            dates in calendar_dates(),
+            date_offset in -500..500i32,
+            position in 0.0..1.0f64,
+            interval in -60..60i32,
+        ) {
+            let calendar = Calendar::from_dates(&dates).unwrap();
+            let first_date = dates[0];
+            let last_date = dates[dates.len() - 1];
+            // dates around the whole calendar, including before the first and after the last entry
+            let input_date =
+                first_date + ((last_date - first_date) as f64 * position) as i32 + date_offset;
+
+            prop_assert_eq!(
+                add_calendar_days(&calendar, input_date, interval),
+                reference_add_days(&dates, input_date, interval)
+            );
+        }
+
+        #[test]
+        fn add_calendar_days_on_boundaries(dates in calendar_dates(), interval in -3..3i32) {
+            let calendar = Calendar::from_dates(&dates).unwrap();
+            for input_date in [dates[0] - 1, dates[0], dates[dates.len() - 1], dates[dates.len() - 1] + 1] {
+                prop_assert_eq!(
+                    add_calendar_days(&calendar, input_date, interval),
+                    reference_add_days(&dates, input_date, interval)
+                );
+            }
+        }
+
+        #[test]
+        fn memoized_lookups_match_reference(
+            dates in calendar_dates(),
+            steps in prop::collection::vec(-40..40i32, 1..50),
+            interval in -5..5i32,
+        ) {
+            let calendar = Calendar::from_dates(&dates).unwrap();
+            let mut memo = None;
+            // a walk of nearby dates, hitting the memoized period, page and other pages
+            let mut input_date = dates[0] - 20;
+            for step in steps {
+                input_date += step;
+                prop_assert_eq!(
+                    lookup_calendar_days_memo(&calendar, input_date, interval.into(), &mut memo).result_date,
+                    reference_add_days(&dates, input_date, interval)
+                );
+            }
+        }
+
+        #[test]
+        fn ordered_lookups_match_reference(
+            dates in calendar_dates(),
+            steps in prop::collection::vec(0..400i32, 1..80),
+            descending in any::<bool>(),
+            interval in -5..5i32,
+        ) {
+            let calendar = Calendar::from_dates(&dates).unwrap();
+            let mut cursor = None;
+            // an ordered scan from before the first entry or from after the last one
+            let mut input_date = match descending {
+                false => dates[0] - 20,
+                true => dates[dates.len() - 1] + 20,
+            };
+            for step in steps {
+                input_date += if descending { -step } else { step };
+                prop_assert_eq!(
+                    lookup_calendar_days_ordered(&calendar, input_date, interval.into(), &mut cursor),
+                    lookup_calendar_days(&calendar, input_date, interval.into())
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn gallop_finds_the_closest_index_from_left() {
+        let dates = [10, 20, 30, 40, 50, 60, 70, 80];
+        for from in 0..dates.len() {
+            for date in 10..=85 {
+                let expected = dates.partition_point(|entry| *entry <= date) as i32 - 1;
+                assert_eq!(
+                    gallop(&dates, from, date),
+                    expected,
+                    "from {from}, date {date}"
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn page_size_follows_density() {
+        // ~22 years of daily entries must still fit in the page map
+        let page_size = calculate_page_size(0, 8035, 8036, MAX_PAGES_PER_CALENDAR);
+        assert_eq!(page_size.count_ones(), 1);
+        assert!((8035 / page_size) as usize <= MAX_PAGES_PER_CALENDAR);
+
+        // quarterly calendars get wider pages than weekly ones over the same range
+        assert!(
+            calculate_page_size(0, 728, 8, MAX_PAGES_PER_CALENDAR)
+                > calculate_page_size(0, 728, 105, MAX_PAGES_PER_CALENDAR)
+        );
+    }
+
+    #[test]
+    fn uniform_calendars_are_searched_by_interpolation() {
+        let weekly: Vec<i32> = (0..100).map(|week| week * 7).collect();
+        assert_eq!(search_strategy(&weekly), SearchStrategy::Interpolation);
+        // 2024-01-01 to 2024-06-01, 29 to 31 days apart
+        let monthly = [8766, 8797, 8826, 8857, 8887, 8918];
+        assert_eq!(search_strategy(&monthly), SearchStrategy::Interpolation);
+        // week days only, 1 to 3 days apart
+        let business_days = [0, 1, 2, 3, 4, 7, 8, 9, 10, 11];
+        assert_eq!(search_strategy(&business_days), SearchStrategy::Binary);
+        assert_eq!(search_strategy(&[0, 7]), SearchStrategy::Binary);
+
+        let calendar = Calendar::from_dates(&monthly).unwrap();
+        assert_eq!(calendar.search_strategy(), SearchStrategy::Interpolation);
+        let steps = explain_closest_index_from_left(8830, &calendar);
+        assert_eq!(
+            steps,
+            [
+                LookupStep::Interpolation {
+                    guess: 2,
+                    hit: true
+                },
+                LookupStep::Found { index: 2 }
+            ]
+        );
+    }
+
+    #[test]
+    fn left_binary_search_returns_closest_index_from_left() {
+        let dates = [10, 20, 30, 40];
+        assert_eq!(left_binary_search(&dates, 0, 3, 5, &mut |_| {}), -1);
+        assert_eq!(left_binary_search(&dates, 0, 3, 10, &mut |_| {}), 0);
+        assert_eq!(left_binary_search(&dates, 0, 3, 25, &mut |_| {}), 1);
+        assert_eq!(left_binary_search(&dates, 0, 3, 45, &mut |_| {}), 3);
+    }
+
+    #[test]
+    fn add_calendar_days_moves_between_entries() {
+        let calendar = Calendar::from_dates(&[8766, 8797, 8826, 8857]).unwrap();
+        assert_eq!(add_calendar_days(&calendar, 8766, 1), 8797);
+        assert_eq!(add_calendar_days(&calendar, 8780, 2), 8826);
+        assert_eq!(add_calendar_days(&calendar, 8826, -2), 8766);
+        assert_eq!(add_calendar_days(&calendar, 8826, 0), 8826);
+    }
+
+    #[test]
+    fn add_calendar_days_out_of_bounds() {
+        let calendar = Calendar::from_dates(&[8766, 8797, 8826, 8857]).unwrap();
+        assert_eq!(add_calendar_days(&calendar, 8797, -5), DATE_PAST);
+        assert_eq!(add_calendar_days(&calendar, 8700, 1), DATE_PAST);
+        assert_eq!(add_calendar_days(&calendar, 8797, 5), DATE_FUTURE);
+    }
+
+    #[test]
+    fn huge_intervals_are_out_of_bounds() {
+        let calendar = Calendar::from_dates(&[8766, 8797, 8826, 8857]).unwrap();
+        let future = lookup_calendar_days(&calendar, 8800, i64::MAX);
+        assert_eq!(future.out_of_bounds, Some(OutOfBounds::Future));
+        assert_eq!(future.result_date, DATE_FUTURE);
+        assert_eq!(future.result_index, i32::MAX);
+        let past = lookup_calendar_days(&calendar, 8800, i64::MIN);
+        assert_eq!(past.out_of_bounds, Some(OutOfBounds::Past));
+        assert_eq!(
+            lookup_calendar_days(&calendar, 8800, 1 << 40).out_of_bounds,
+            Some(OutOfBounds::Future)
+        );
+
+        let empty = Calendar::default();
+        assert_eq!(
+            lookup_calendar_days(&empty, 100, 1 << 40).result_date,
+            DATE_FUTURE
+        );
+        assert_eq!(
+            lookup_calendar_days(&empty, 100, i64::MIN).out_of_bounds,
+            Some(OutOfBounds::Past)
+        );
+        assert_eq!(lookup_calendar_days(&empty, 100, -5).result_date, 95);
+    }
+
+    #[test]
+    fn lookup_reports_indexes() {
+        let calendar = Calendar::from_dates(&[8766, 8797, 8826, 8857]).unwrap();
+        let lookup = lookup_calendar_days(&calendar, 8800, 1);
+        assert_eq!(lookup.hit_index, 1);
+        assert_eq!(lookup.result_index, 2);
+        assert_eq!(lookup.result_date, 8826);
+        assert_eq!(lookup.out_of_bounds, None);
+        assert_eq!(
+            lookup_calendar_days(&calendar, 8800, 3).out_of_bounds,
+            Some(OutOfBounds::Future)
+        );
+        assert_eq!(
+            lookup_calendar_days(&calendar, 8700, 0).out_of_bounds,
+            Some(OutOfBounds::Past)
+        );
+    }
+
+    #[test]
+    fn explain_lookup_ends_with_closest_index() {
+        let mut calendar = Calendar::from_dates(&[8766, 8797, 8826, 8857]).unwrap();
+        calendar.search = SearchStrategy::Binary;
+        let steps = explain_closest_index_from_left(8800, &calendar);
+        assert!(matches!(steps[0], LookupStep::PageIndex { .. }));
+        assert!(steps
+            .iter()
+            .any(|step| matches!(step, LookupStep::Compare { .. })));
+        assert_eq!(steps.last(), Some(&LookupStep::Found { index: 1 }));
+
+        let outside = LookupStep::OutsideEntries {
+            first_date: 8766,
+            last_date: 8857,
+        };
+        let steps = explain_closest_index_from_left(8000, &calendar);
+        assert_eq!(steps, [outside, LookupStep::Found { index: -1 }]);
+        // before the first entry but in the first page
+        let steps = explain_closest_index_from_left(8765, &calendar);
+        assert_eq!(steps, [outside, LookupStep::Found { index: -1 }]);
+        let steps = explain_closest_index_from_left(9000, &calendar);
+        assert_eq!(steps, [outside, LookupStep::Found { index: 3 }]);
+    }
+
+    #[test]
+    fn entries_between_honors_bounds() {
+        let calendar = Calendar::from_dates(&[10, 20, 30, 40]).unwrap();
+        assert_eq!(
+            entries_between(&calendar, Bound::Included(20), Bound::Excluded(40)),
+            [20, 30]
+        );
+        assert_eq!(
+            entries_between(&calendar, Bound::Excluded(20), Bound::Unbounded),
+            [30, 40]
+        );
+        assert!(entries_between(&calendar, Bound::Included(31), Bound::Excluded(35)).is_empty());
+        assert!(entries_between(&calendar, Bound::Included(35), Bound::Excluded(31)).is_empty());
+    }
+
+    #[test]
+    fn non_entries_between_is_the_complement() {
+        let calendar = Calendar::from_dates(&[10, 12, 13, 16]).unwrap();
+        let days: Vec<i32> = non_entries_between(&calendar, 11, 16).collect();
+        assert_eq!(days, [11, 14, 15]);
+        let days: Vec<i32> = non_entries_between(&calendar, 15, 18).collect();
+        assert_eq!(days, [15, 17, 18]);
+        assert_eq!(non_entries_between(&calendar, 16, 15).count(), 0);
+        assert_eq!(non_entries_between(&Calendar::default(), 1, 3).count(), 3);
+    }
+
+    #[test]
+    fn period_bounds_spans_to_next_entry() {
+        let calendar = Calendar::from_dates(&[10, 20, 30]).unwrap();
+        assert_eq!(period_bounds(&calendar, 25), Some((20, Some(30))));
+        assert_eq!(period_bounds(&calendar, 20), Some((20, Some(30))));
+        assert_eq!(period_bounds(&calendar, 35), Some((30, None)));
+        assert_eq!(period_bounds(&calendar, 5), None);
+    }
+
+    #[test]
+    fn entries_spanned_is_the_interval_between_periods() {
+        let calendar = Calendar::from_dates(&[10, 20, 30, 40]).unwrap();
+        assert_eq!(entries_spanned(&calendar, 15, 35), 2);
+        assert_eq!(entries_spanned(&calendar, 35, 15), -2);
+        assert_eq!(entries_spanned(&calendar, 20, 20), 0);
+        assert_eq!(entries_spanned(&calendar, 5, 50), 4);
+        for (from, to) in [(10, 40), (12, 31), (25, 29)] {
+            let interval = entries_spanned(&calendar, from, to) as i32;
+            assert_eq!(
+                add_calendar_days(&calendar, from, interval),
+                add_calendar_days(&calendar, to, 0)
+            );
+        }
+    }
+
+    #[test]
+    fn period_index_counts_from_the_first_entry() {
+        let calendar = Calendar::from_dates(&[10, 20, 30]).unwrap();
+        assert_eq!(period_index(&calendar, 9), None);
+        assert_eq!(period_index(&calendar, 10), Some(0));
+        assert_eq!(period_index(&calendar, 29), Some(1));
+        assert_eq!(period_index(&calendar, 400), Some(2));
+    }
+
+    #[test]
+    fn add_periods_keeps_the_position_in_the_period() {
+        // weeks in 4-4-5 months
+        let weeks: Vec<i32> = (0..26).map(|week| week * 7).collect();
+        let months = Calendar::from_dates(&[0, 28, 56, 91, 119, 147]).unwrap();
+        let weeks = Calendar::from_dates(&weeks).unwrap();
+        // third week of the first month
+        assert_eq!(add_periods(&weeks, &months, 15, 1), Some(42));
+        assert_eq!(add_periods(&weeks, &months, 15, 2), Some(70));
+        // fifth week of a 5-week month, clamped in a 4-week one
+        assert_eq!(add_periods(&weeks, &months, 84, 1), Some(112));
+        assert_eq!(add_periods(&weeks, &months, 84, -2), Some(21));
+        assert_eq!(add_periods(&weeks, &months, 15, -1), None);
+        assert_eq!(add_periods(&weeks, &months, -1, 1), None);
+    }
+
+    #[test]
+    fn days_until_next_entry_is_zero_on_entries() {
+        let calendar = Calendar::from_dates(&[10, 20, 30]).unwrap();
+        assert_eq!(days_until_next_entry(&calendar, 20), Some(0));
+        assert_eq!(days_until_next_entry(&calendar, 21), Some(9));
+        assert_eq!(days_until_next_entry(&calendar, -5), Some(15));
+        assert_eq!(days_until_next_entry(&calendar, 31), None);
+    }
+
+    #[test]
+    fn page_stats_count_every_entry_once() {
+        let dates: Vec<i32> = (0..400).map(|day| 8766 + day * 3).collect();
+        let calendar = Calendar::from_dates(&dates).unwrap();
+        let stats = page_stats(&calendar);
+        assert_eq!(stats.len(), calendar.page_map().len());
+        assert_eq!(
+            stats.iter().map(|page| page.entry_count).sum::<usize>(),
+            dates.len()
+        );
+        for page in &stats {
+            let page_dates = &dates[page.start_offset..page.start_offset + page.entry_count];
+            assert!(page_dates.iter().all(|date| (page.first_day
+                ..page.first_day + calendar.page_size())
+                .contains(date)));
+        }
+        assert!(page_stats(&Calendar::default()).is_empty());
+    }
+
+    #[test]
+    fn periods_between_cover_the_range() {
+        let calendar = Calendar::from_dates(&[10, 20, 30, 40]).unwrap();
+        assert_eq!(periods_between(&calendar, 15, 30), [(10, 20), (20, 30)]);
+        assert_eq!(periods_between(&calendar, 20, 31), [(20, 30), (30, 40)]);
+        assert_eq!(
+            periods_between(&calendar, 0, 100),
+            [(10, 20), (20, 30), (30, 40)]
+        );
+        assert!(periods_between(&calendar, 40, 100).is_empty());
+        assert!(periods_between(&calendar, 25, 25).is_empty());
+        assert!(periods_between(&Calendar::default(), 0, 100).is_empty());
+    }
+
+    #[test]
+    fn gaps_exceed_min_gap() {
+        let calendar = Calendar::from_dates(&[10, 17, 24, 45, 52, 80]).unwrap();
+        assert_eq!(gaps(&calendar, 7), [(24, 45), (52, 80)]);
+        assert_eq!(gaps(&calendar, 21), [(52, 80)]);
+        assert!(gaps(&calendar, 28).is_empty());
+        assert!(gaps(&Calendar::default(), 0).is_empty());
+    }
+
+    #[test]
+    fn entry_histogram_by_year_and_month() {
+        let days = [
+            date::from_ymd(2023, 12, 31),
+            date::from_ymd(2024, 1, 1),
+            date::from_ymd(2024, 1, 15),
+            date::from_ymd(2024, 3, 1),
+        ];
+        let calendar = Calendar::from_dates(&days).unwrap();
+        assert_eq!(
+            entry_histogram(&calendar, HistogramBucket::Year),
+            [
+                (date::from_ymd(2023, 1, 1), 1),
+                (date::from_ymd(2024, 1, 1), 3)
+            ]
+        );
+        assert_eq!(
+            entry_histogram(&calendar, HistogramBucket::Month),
+            [
+                (date::from_ymd(2023, 12, 1), 1),
+                (date::from_ymd(2024, 1, 1), 2),
+                (date::from_ymd(2024, 3, 1), 1)
+            ]
+        );
+        assert_eq!(
+            HistogramBucket::parse("Month"),
+            Some(HistogramBucket::Month)
+        );
+        assert_eq!(HistogramBucket::parse("week"), None);
+    }
+
+    #[test]
+    fn offset_calendar_days_is_symmetric() {
+        let calendar = Calendar::from_dates(&[10, 20, 30, 40]).unwrap();
+        let offset = |date, steps, direction| {
+            offset_calendar_days(&calendar, date, steps, direction).result_date
+        };
+        // on an entry
+        assert_eq!(offset(20, 1, Direction::Forward), 30);
+        assert_eq!(offset(20, 1, Direction::Backward), 10);
+        assert_eq!(offset(20, 0, Direction::Backward), 20);
+        // between entries, add_calendar_days(25, -1) would return 10
+        assert_eq!(offset(25, 1, Direction::Forward), 30);
+        assert_eq!(offset(25, 1, Direction::Backward), 20);
+        assert_eq!(offset(25, 0, Direction::Forward), 30);
+        assert_eq!(offset(25, 0, Direction::Backward), 20);
+        assert_eq!(offset(5, 1, Direction::Forward), 10);
+
+        let past = offset_calendar_days(&calendar, 15, 2, Direction::Backward);
+        assert_eq!(past.out_of_bounds, Some(OutOfBounds::Past));
+        let future = offset_calendar_days(&calendar, 25, 3, Direction::Forward);
+        assert_eq!(future.out_of_bounds, Some(OutOfBounds::Future));
+        assert_eq!(
+            offset_calendar_days(&Calendar::default(), 100, 5, Direction::Backward).result_date,
+            95
+        );
+    }
+
+    #[test]
+    fn add_calendar_days_on_empty_calendar_is_plain_arithmetic() {
+        let calendar = Calendar::default();
+        assert_eq!(add_calendar_days(&calendar, 100, 5), 105);
+    }
+}
@@ -0,0 +1,54 @@
+//! Suggestions for mistyped calendar xuids.
+
+/// Levenshtein distance between `a` and `b`, ignoring ASCII case.
+pub fn edit_distance(a: &str, b: &str) -> usize {
+    let a: Vec<char> = a.chars().map(|c| c.to_ascii_lowercase()).collect();
+    let b: Vec<char> = b.chars().map(|c| c.to_ascii_lowercase()).collect();
+    let mut previous: Vec<usize> = (0..=b.len()).collect();
+    let mut current = vec![0; b.len() + 1];
+    for (i, a_char) in a.iter().enumerate() {
+        current[0] = i + 1;
+        for (j, b_char) in b.iter().enumerate() {
+            let substitution = previous[j] + usize::from(a_char != b_char);
+            current[j + 1] = substitution.min(previous[j + 1] + 1).min(current[j] + 1);
+        }
+        std::mem::swap(&mut previous, &mut current);
+    }
+    previous[b.len()]
+}
+
+/// Closest candidate to `name`, `None` when none is within a third of its length (at least 2
+/// edits), so unrelated names are not suggested. Ties go to the first candidate.
+pub fn closest<'a>(name: &str, candidates: impl IntoIterator<Item = &'a str>) -> Option<&'a str> {
+    let max_distance = (name.chars().count() / 3).max(2);
+    candidates
+        .into_iter()
+        .map(|candidate| (edit_distance(name, candidate), candidate))
+        .filter(|(distance, _)| *distance <= max_distance)
+        .min_by_key(|(distance, _)| *distance)
+        .map(|(_, candidate)| candidate)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn distances_count_single_character_edits() {
+        assert_eq!(edit_distance("", ""), 0);
+        assert_eq!(edit_distance("month", ""), 5);
+        assert_eq!(edit_distance("FISCAL_445", "fiscal_445"), 0);
+        assert_eq!(edit_distance("FISCAL_454", "FISCAL_445"), 2);
+        assert_eq!(edit_distance("quater", "quarter"), 1);
+        assert_eq!(edit_distance("kitten", "sitting"), 3);
+    }
+
+    #[test]
+    fn closest_skips_unrelated_names() {
+        let candidates = ["month", "quarter", "FISCAL_445"];
+        assert_eq!(closest("quater", candidates), Some("quarter"));
+        assert_eq!(closest("fiscal-445", candidates), Some("FISCAL_445"));
+        assert_eq!(closest("week", candidates), None);
+        assert_eq!(closest("month", []), None);
+    }
+}
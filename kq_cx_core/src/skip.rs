@@ -0,0 +1,176 @@
+//! Precomputed lookups for the intervals most lookups use.
+//!
+//! For each hot interval the table holds the result date of every entry of the calendar, so once
+//! the entry of the input date is located a lookup with that interval is one read. The tables
+//! take `entries * intervals` slots, calendars with too many entries go without them.
+
+use crate::math::{DATE_FUTURE, DATE_PAST};
+use crate::{Calendar, MAX_ENTRIES_PER_CALENDAR};
+
+pub const MAX_SKIP_INTERVALS: usize = 8;
+/// Slots of all the tables of a calendar, as many as its entries.
+pub const MAX_SKIP_TARGETS: usize = MAX_ENTRIES_PER_CALENDAR;
+
+#[derive(Default, Clone, Debug)]
+pub struct SkipTables {
+    intervals: heapless::Vec<i32, MAX_SKIP_INTERVALS>,
+    /// Entries covered by each table. 0 when the tables are not built.
+    entries: usize,
+    /// Result date of every (interval, entry), the entries of the first interval first. Out of
+    /// bounds results hold `DATE_PAST` or `DATE_FUTURE`.
+    targets: heapless::Vec<i32, MAX_SKIP_TARGETS>,
+}
+
+impl SkipTables {
+    /// Intervals requested for the calendar, kept when the tables do not fit so they are built
+    /// again with the entries.
+    pub fn intervals(&self) -> &[i32] {
+        &self.intervals
+    }
+
+    /// True when the tables are built and answer the lookups.
+    pub fn is_built(&self) -> bool {
+        self.entries > 0
+    }
+
+    pub fn used_bytes(&self) -> usize {
+        std::mem::size_of_val(self.targets.as_slice())
+    }
+
+    /// Result date of a lookup with `interval` from the entry at `hit_index`, `None` when the
+    /// interval is not a hot one or there is no such entry (or the tables are not built).
+    pub fn result_date(&self, hit_index: i32, interval: i64) -> Option<i32> {
+        if hit_index < 0 || hit_index as usize >= self.entries {
+            return None;
+        }
+        let table = self
+            .intervals
+            .iter()
+            .position(|hot| *hot as i64 == interval)?;
+        Some(self.targets[table * self.entries + hit_index as usize])
+    }
+}
+
+/// Parses a comma separated list of intervals, e.g. `1,-1,5,-5`. Repeated intervals are kept
+/// once.
+pub fn parse_intervals(list: &str) -> Result<Vec<i32>, String> {
+    let mut intervals = vec![];
+    for item in list
+        .split(',')
+        .map(str::trim)
+        .filter(|item| !item.is_empty())
+    {
+        let interval: i32 = item
+            .parse()
+            .map_err(|_| format!("\"{item}\" is not an interval"))?;
+        if !intervals.contains(&interval) {
+            intervals.push(interval);
+        }
+    }
+    if intervals.len() > MAX_SKIP_INTERVALS {
+        return Err(format!(
+            "at most {MAX_SKIP_INTERVALS} intervals, got {}",
+            intervals.len()
+        ));
+    }
+    Ok(intervals)
+}
+
+impl Calendar {
+    pub fn skip_tables(&self) -> &SkipTables {
+        &self.skips
+    }
+
+    /// (Re)builds the skip tables of `intervals` (at most `MAX_SKIP_INTERVALS`) from the current
+    /// entries. Returns false when they need more than `MAX_SKIP_TARGETS` slots, the lookups then
+    /// read the entries as usual.
+    pub fn build_skip_tables(&mut self, intervals: &[i32]) -> bool {
+        self.skips = SkipTables::default();
+        for interval in intervals.iter().take(MAX_SKIP_INTERVALS) {
+            self.skips.intervals.push(*interval).unwrap();
+        }
+        if self.dates.is_empty() || intervals.is_empty() {
+            return intervals.is_empty();
+        }
+        let entries = self.dates.len();
+        if entries.saturating_mul(self.skips.intervals.len()) > MAX_SKIP_TARGETS {
+            return false;
+        }
+
+        for interval in &self.skips.intervals {
+            for hit_index in 0..entries {
+                let result_index = hit_index as i64 + *interval as i64;
+                let result_date = match result_index {
+                    index if index < 0 => DATE_PAST,
+                    index if index >= entries as i64 => DATE_FUTURE,
+                    index => self.dates[index as usize],
+                };
+                self.skips.targets.push(result_date).unwrap();
+            }
+        }
+        self.skips.entries = entries;
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::math;
+    use proptest::prelude::*;
+
+    #[test]
+    fn parses_interval_lists() {
+        assert_eq!(parse_intervals(" 1, -1,5,,1"), Ok(vec![1, -1, 5]));
+        assert_eq!(parse_intervals(""), Ok(vec![]));
+        assert!(parse_intervals("1,x").is_err());
+        assert!(parse_intervals("1,2,3,4,5,6,7,8,9").is_err());
+    }
+
+    #[test]
+    fn large_calendars_go_without_tables() {
+        let dates: Vec<i32> = (0..MAX_SKIP_TARGETS as i32 / 2 + 1).collect();
+        let mut calendar = Calendar::from_dates(&dates).unwrap();
+        assert!(calendar.build_skip_tables(&[1]));
+        assert!(!calendar.build_skip_tables(&[1, -1]));
+        assert!(!calendar.skip_tables().is_built());
+        assert_eq!(calendar.skip_tables().intervals(), [1, -1]);
+        assert_eq!(calendar.skip_tables().result_date(0, 1), None);
+    }
+
+    #[test]
+    fn long_sparse_calendars_get_tables() {
+        // monthly entries over 40 years, far more days than slots
+        let dates: Vec<i32> = (0..480).map(|month| month * 30).collect();
+        let mut calendar = Calendar::from_dates(&dates).unwrap();
+        assert!(calendar.build_skip_tables(&[1, -1, 5, -5, 20, -20]));
+        assert_eq!(calendar.skip_tables().result_date(10, 5), Some(450));
+        assert_eq!(calendar.skip_tables().result_date(10, -20), Some(DATE_PAST));
+    }
+
+    #[test]
+    fn page_map_rebuild_keeps_the_intervals() {
+        let mut calendar = Calendar::from_dates(&[0, 7, 14]).unwrap();
+        assert!(calendar.build_skip_tables(&[1]));
+        calendar.apply_exceptions(&[21], &[]).unwrap();
+        calendar.build_page_map().unwrap();
+        assert_eq!(calendar.skip_tables().result_date(2, 1), Some(21));
+        assert_eq!(math::add_calendar_days(&calendar, 15, 1), 21);
+    }
+
+    proptest! {
+        #[test]
+        fn skip_tables_match_the_search(
+            dates in proptest::collection::btree_set(0i32..600, 1..80),
+            date in -20i32..620,
+            interval in -30i32..30,
+        ) {
+            let dates: Vec<i32> = dates.into_iter().collect();
+            let mut calendar = Calendar::from_dates(&dates).unwrap();
+            let expected = math::lookup_calendar_days(&calendar, date, interval.into());
+            prop_assert!(calendar.build_skip_tables(&[interval, 1, -1]));
+            let found = math::lookup_calendar_days(&calendar, date, interval.into());
+            prop_assert_eq!(found, expected);
+        }
+    }
+}
@@ -0,0 +1,104 @@
+//! Deterministic synthetic data, used to benchmark and load test the cache without customer data.
+
+use crate::date;
+
+/// Small xorshift generator, sequences must be reproducible across runs and versions.
+pub struct XorShift64(u64);
+
+impl XorShift64 {
+    pub fn new(seed: u64) -> XorShift64 {
+        // xorshift gets stuck on 0
+        XorShift64(seed.max(1))
+    }
+
+    pub fn next_u64(&mut self) -> u64 {
+        self.0 ^= self.0 << 13;
+        self.0 ^= self.0 >> 7;
+        self.0 ^= self.0 << 17;
+        self.0
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Pattern {
+    Daily,
+    Weekly,
+    Monthly,
+    /// Random gaps of 1 to 10 days.
+    Random,
+}
+
+impl Pattern {
+    pub fn parse(pattern: &str) -> Option<Pattern> {
+        match pattern.to_ascii_lowercase().as_str() {
+            "daily" => Some(Pattern::Daily),
+            "weekly" => Some(Pattern::Weekly),
+            "monthly" => Some(Pattern::Monthly),
+            "random" => Some(Pattern::Random),
+            _ => None,
+        }
+    }
+}
+
+/// Generates `count` ascending entries starting at `first_date` (pg epoch days). The same
+/// arguments always produce the same dates.
+pub fn generate_dates(pattern: Pattern, first_date: i32, count: usize, seed: u64) -> Vec<i32> {
+    let mut rng = XorShift64::new(seed);
+    let (first_year, first_month, _) = date::to_ymd(first_date);
+    (0..count)
+        .scan(first_date, |date, index| {
+            let current = match pattern {
+                Pattern::Daily => first_date + index as i32,
+                Pattern::Weekly => first_date + 7 * index as i32,
+                Pattern::Monthly => {
+                    let month_index = first_month as i32 - 1 + index as i32;
+                    date::from_ymd(
+                        first_year + month_index.div_euclid(12),
+                        (month_index.rem_euclid(12) + 1) as u32,
+                        1,
+                    )
+                }
+                Pattern::Random => {
+                    if index > 0 {
+                        *date += 1 + (rng.next_u64() % 10) as i32;
+                    }
+                    *date
+                }
+            };
+            Some(current)
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn generated_dates_are_sorted_and_deterministic() {
+        for pattern in [
+            Pattern::Daily,
+            Pattern::Weekly,
+            Pattern::Monthly,
+            Pattern::Random,
+        ] {
+            let dates = generate_dates(pattern, 7305, 500, 42);
+            assert_eq!(dates.len(), 500);
+            assert!(dates.windows(2).all(|pair| pair[0] < pair[1]));
+            assert_eq!(dates, generate_dates(pattern, 7305, 500, 42));
+        }
+    }
+
+    #[test]
+    fn monthly_pattern_uses_first_day_of_month() {
+        let dates = generate_dates(Pattern::Monthly, date::from_ymd(2024, 11, 1), 3, 0);
+        assert_eq!(
+            dates,
+            vec![
+                date::from_ymd(2024, 11, 1),
+                date::from_ymd(2024, 12, 1),
+                date::from_ymd(2025, 1, 1)
+            ]
+        );
+    }
+}
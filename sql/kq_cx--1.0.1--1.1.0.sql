@@ -0,0 +1,802 @@
+-- Moves the extension from the schema it was created in to the kq_cx schema, where 1.1.0 creates its
+-- objects, and restricts the functions to the kq_cx_admin and kq_cx_reader roles. The 1.0.1 functions
+-- are dropped and created again in kq_cx: views and indexes calling them must be dropped first.
+
+DO $$
+BEGIN
+    IF to_regnamespace('kq_cx') IS NULL THEN
+        CREATE SCHEMA kq_cx;
+        -- as created by CREATE EXTENSION, the schema is not a member of the extension
+        ALTER EXTENSION kq_cx DROP SCHEMA kq_cx;
+    END IF;
+END
+$$;
+
+DROP FUNCTION @extschema@.kq_cx_cache_info();
+DROP FUNCTION @extschema@.kq_cx_info();
+DROP FUNCTION @extschema@.kq_cx_display_cache();
+DROP FUNCTION @extschema@.kq_cx_display_page_map();
+DROP FUNCTION @extschema@.kq_cx_invalidate_cache();
+DROP FUNCTION @extschema@.kq_cx_add_days(date, integer, bigint);
+DROP FUNCTION @extschema@.kq_cx_add_days_xuid(date, integer, text);
+DROP FUNCTION @extschema@.kq_cx_populate_cache();
+
+UPDATE pg_catalog.pg_depend
+SET refobjid = 'kq_cx'::regnamespace
+WHERE classid = 'pg_catalog.pg_extension'::regclass
+    AND objid = (SELECT oid FROM pg_catalog.pg_extension WHERE extname = 'kq_cx')
+    AND refclassid = 'pg_catalog.pg_namespace'::regclass;
+UPDATE pg_catalog.pg_extension SET extnamespace = 'kq_cx'::regnamespace WHERE extname = 'kq_cx';
+
+DO $$
+BEGIN
+    IF NOT EXISTS (SELECT FROM pg_roles WHERE rolname = 'kq_cx_admin') THEN
+        CREATE ROLE kq_cx_admin NOLOGIN;
+    END IF;
+END
+$$;
+
+CREATE SEQUENCE kq_cx.kq_cx_calendar_definition_id_seq;
+
+CREATE TABLE kq_cx.kq_cx_calendar_definition (
+    calendar_id int8 NOT NULL
+        DEFAULT -nextval('kq_cx.kq_cx_calendar_definition_id_seq'),
+    namespace text NOT NULL DEFAULT '',
+    xuid text NOT NULL,
+    parent_id int8,
+    dates date[] NOT NULL,
+    CONSTRAINT kq_cx_calendar_definition_pk PRIMARY KEY (calendar_id),
+    CONSTRAINT kq_cx_calendar_definition_xuid UNIQUE (namespace, xuid)
+);
+
+SELECT pg_catalog.pg_extension_config_dump('kq_cx.kq_cx_calendar_definition', '');
+SELECT pg_catalog.pg_extension_config_dump('kq_cx.kq_cx_calendar_definition_id_seq', '');
+
+CREATE TABLE kq_cx.kq_cx_loader_profile (
+    profile text NOT NULL,
+    schema text,
+    q_schema_validation text,
+    q1_get_calendar_min_max_id text,
+    q2_get_calendars_entry_count text,
+    q3_get_calendar_entries text,
+    q3_get_calendar_entries_by_id text,
+    q4_get_calendar_exceptions text,
+    CONSTRAINT kq_cx_loader_profile_pk PRIMARY KEY (profile)
+);
+
+SELECT pg_catalog.pg_extension_config_dump('kq_cx.kq_cx_loader_profile', '');
+
+CREATE FUNCTION kq_cx.kq_cx_admin_functions() RETURNS text[]
+    LANGUAGE c IMMUTABLE STRICT PARALLEL SAFE
+    AS 'MODULE_PATHNAME', 'kq_cx_admin_functions_wrapper';
+
+CREATE FUNCTION kq_cx.kq_cx_audit_log() RETURNS TABLE (
+    "logged_at" timestamptz,
+    "operation" text,
+    "user_name" text,
+    "pid" integer,
+    "duration_ms" double precision,
+    "entries" bigint
+)
+    LANGUAGE c STRICT PARALLEL SAFE
+    AS 'MODULE_PATHNAME', 'kq_cx_audit_log_wrapper';
+
+CREATE FUNCTION kq_cx.kq_cx_bench(
+    "calendar_xuid" text,
+    "iterations" integer DEFAULT 100000
+) RETURNS TABLE (
+    "pattern" text,
+    "iterations" bigint,
+    "p50_ns" bigint,
+    "p90_ns" bigint,
+    "p99_ns" bigint,
+    "max_ns" bigint,
+    "mean_ns" double precision
+)
+    LANGUAGE c STRICT PARALLEL SAFE
+    AS 'MODULE_PATHNAME', 'kq_cx_bench_wrapper';
+
+CREATE FUNCTION kq_cx.kq_cx_distribute() RETURNS TABLE (
+    "node_name" text,
+    "node_port" integer,
+    "command" text,
+    "success" boolean,
+    "result" text
+)
+    LANGUAGE c STRICT
+    AS 'MODULE_PATHNAME', 'kq_cx_distribute_wrapper';
+
+CREATE FUNCTION kq_cx.kq_cx_compare_calendars("calendar_a" text, "calendar_b" text) RETURNS TABLE (
+    "only_in_a" bigint,
+    "only_in_b" bigint,
+    "common" bigint
+)
+    LANGUAGE c STABLE STRICT PARALLEL SAFE
+    AS 'MODULE_PATHNAME', 'kq_cx_compare_calendars_wrapper';
+
+CREATE FUNCTION kq_cx.kq_cx_calendar_differences(
+    "calendar_a" text,
+    "calendar_b" text
+) RETURNS TABLE (
+    "entry" date,
+    "side" text
+)
+    LANGUAGE c STABLE STRICT PARALLEL SAFE
+    AS 'MODULE_PATHNAME', 'kq_cx_calendar_differences_wrapper';
+
+CREATE FUNCTION kq_cx.kq_cx_watch_source_tables() RETURNS bigint
+    LANGUAGE c STRICT
+    AS 'MODULE_PATHNAME', 'kq_cx_watch_source_tables_wrapper';
+
+CREATE FUNCTION kq_cx.kq_cx_define_calendar(
+    "calendar_xuid" text,
+    "dates" date[],
+    "namespace" text DEFAULT '',
+    "parent_id" bigint DEFAULT NULL
+) RETURNS bigint
+    LANGUAGE c
+    AS 'MODULE_PATHNAME', 'kq_cx_define_calendar_wrapper';
+
+CREATE FUNCTION kq_cx.kq_cx_define_holiday_calendar(
+    "calendar_xuid" text,
+    "jurisdiction" text,
+    "from_date" date,
+    "to_date" date,
+    "namespace" text DEFAULT '',
+    "parent_id" bigint DEFAULT NULL
+) RETURNS bigint
+    LANGUAGE c
+    AS 'MODULE_PATHNAME', 'kq_cx_define_holiday_calendar_wrapper';
+
+CREATE FUNCTION kq_cx.kq_cx_define_calendar_cron(
+    "calendar_xuid" text,
+    "cron_expression" text,
+    "from_date" date,
+    "to_date" date,
+    "namespace" text DEFAULT '',
+    "parent_id" bigint DEFAULT NULL
+) RETURNS bigint
+    LANGUAGE c
+    AS 'MODULE_PATHNAME', 'kq_cx_define_calendar_cron_wrapper';
+
+CREATE FUNCTION kq_cx.kq_cx_add_days(
+    "input_date" date,
+    "interval" bigint,
+    "calendar_id" bigint,
+    "generation" bigint
+) RETURNS date
+    LANGUAGE c STABLE STRICT PARALLEL SAFE
+    AS 'MODULE_PATHNAME', 'kq_cx_add_days_generation_wrapper';
+
+CREATE FUNCTION kq_cx.kq_cx_add_days_xuid(
+    "input_date" date,
+    "interval" bigint,
+    "calendar_xuid" text,
+    "generation" bigint
+) RETURNS date
+    LANGUAGE c STABLE STRICT PARALLEL SAFE
+    AS 'MODULE_PATHNAME', 'kq_cx_add_days_xuid_generation_wrapper';
+
+CREATE FUNCTION kq_cx.kq_cx_generations() RETURNS TABLE (
+    "generation" bigint,
+    "current" boolean,
+    "kept_at" timestamptz,
+    "calendars" bigint,
+    "entries" bigint
+)
+    LANGUAGE c STRICT PARALLEL SAFE
+    AS 'MODULE_PATHNAME', 'kq_cx_generations_wrapper';
+
+CREATE FUNCTION kq_cx.kq_cx_dump_cache() RETURNS bytea
+    LANGUAGE c STRICT PARALLEL SAFE
+    AS 'MODULE_PATHNAME', 'kq_cx_dump_cache_wrapper';
+
+CREATE FUNCTION kq_cx.kq_cx_dump_cache_file("path" text) RETURNS bigint
+    LANGUAGE c STRICT
+    AS 'MODULE_PATHNAME', 'kq_cx_dump_cache_file_wrapper';
+
+CREATE FUNCTION kq_cx.kq_cx_load_cache("image" bytea) RETURNS bigint
+    LANGUAGE c STRICT
+    AS 'MODULE_PATHNAME', 'kq_cx_load_cache_wrapper';
+
+CREATE FUNCTION kq_cx.kq_cx_cache_info() RETURNS TABLE (
+    "calendar_id" bigint,
+    "calendar_xuid" text,
+    "entries" bigint,
+    "page_size" integer,
+    "page_map_entries" bigint,
+    "checksum" text,
+    "calendar_name" text,
+    "calendar_type" text,
+    "timezone" text,
+    "lookups" bigint,
+    "last_used" timestamptz,
+    "first_entry" date,
+    "last_entry" date,
+    "bytes_used" bigint,
+    "loaded_at" timestamptz,
+    "load_duration_ms" double precision,
+    "skip_tables_built" boolean
+)
+    LANGUAGE c STRICT PARALLEL SAFE
+    AS 'MODULE_PATHNAME', 'kq_cx_cache_info_wrapper';
+
+CREATE FUNCTION kq_cx.kq_cx_list_calendars() RETURNS TABLE (
+    "calendar_id" bigint,
+    "calendar_xuid" text,
+    "entry_count" bigint
+)
+    LANGUAGE c STABLE STRICT PARALLEL SAFE
+    AS 'MODULE_PATHNAME', 'kq_cx_list_calendars_wrapper';
+
+CREATE FUNCTION kq_cx.kq_cx_resolve_xuid(
+    "calendar_xuid" text,
+    "missing_ok" boolean DEFAULT true
+) RETURNS bigint
+    LANGUAGE c STABLE STRICT PARALLEL SAFE
+    AS 'MODULE_PATHNAME', 'kq_cx_resolve_xuid_wrapper';
+
+CREATE FUNCTION kq_cx.kq_cx_resolve_id(
+    "calendar_id" bigint,
+    "missing_ok" boolean DEFAULT true
+) RETURNS text
+    LANGUAGE c STABLE STRICT PARALLEL SAFE
+    AS 'MODULE_PATHNAME', 'kq_cx_resolve_id_wrapper';
+
+CREATE FUNCTION kq_cx.kq_cx_info() RETURNS TABLE (
+    "property" text,
+    "value" text
+)
+    LANGUAGE c STRICT PARALLEL SAFE
+    AS 'MODULE_PATHNAME', 'kq_cx_info_wrapper';
+
+CREATE FUNCTION kq_cx.kq_cx_settings() RETURNS TABLE (
+    "name" text,
+    "setting" text,
+    "source" text
+)
+    LANGUAGE c STRICT PARALLEL SAFE
+    AS 'MODULE_PATHNAME', 'kq_cx_settings_wrapper';
+
+CREATE FUNCTION kq_cx.kq_cx_info_json() RETURNS jsonb
+    LANGUAGE c STRICT PARALLEL SAFE
+    AS 'MODULE_PATHNAME', 'kq_cx_info_json_wrapper';
+
+CREATE FUNCTION kq_cx.kq_cx_health() RETURNS TABLE (
+    "cache_available" boolean,
+    "cache_stale" boolean,
+    "population_failures" integer,
+    "last_error" text,
+    "last_error_phase" text,
+    "last_error_at" timestamptz,
+    "cache_state" text,
+    "generation" bigint,
+    "calendars" bigint,
+    "entries" bigint,
+    "last_populate_at" timestamptz,
+    "memory_used" bigint,
+    "memory_limit" bigint,
+    "empty_calendars" text[]
+)
+    LANGUAGE c STRICT PARALLEL SAFE
+    AS 'MODULE_PATHNAME', 'kq_cx_health_wrapper';
+
+CREATE FUNCTION kq_cx.kq_cx_ready() RETURNS boolean
+    LANGUAGE c STRICT PARALLEL SAFE
+    AS 'MODULE_PATHNAME', 'kq_cx_ready_wrapper';
+
+CREATE FUNCTION kq_cx.kq_cx_display_cache() RETURNS TABLE (
+    "calendar" text,
+    "entry" date
+)
+    LANGUAGE c STRICT PARALLEL SAFE
+    AS 'MODULE_PATHNAME', 'kq_cx_display_cache_wrapper';
+
+CREATE FUNCTION kq_cx.kq_cx_display_page_map() RETURNS TABLE (
+    "calendar" text,
+    "index" bigint
+)
+    LANGUAGE c STRICT PARALLEL SAFE
+    AS 'MODULE_PATHNAME', 'kq_cx_display_page_map_wrapper';
+
+CREATE FUNCTION kq_cx.kq_cx_page_stats("calendar_xuid" text) RETURNS TABLE (
+    "page_index" integer,
+    "start_offset" integer,
+    "entry_count" integer,
+    "date_range" daterange
+)
+    LANGUAGE c STRICT PARALLEL SAFE
+    AS 'MODULE_PATHNAME', 'kq_cx_page_stats_wrapper';
+
+CREATE FUNCTION kq_cx.kq_cx_rebuild_pages(
+    "calendar_xuid" text DEFAULT NULL,
+    "page_size" integer DEFAULT NULL
+) RETURNS bigint
+    LANGUAGE c
+    AS 'MODULE_PATHNAME', 'kq_cx_rebuild_pages_wrapper';
+
+CREATE FUNCTION kq_cx.kq_cx_invalidate_cache() RETURNS text
+    LANGUAGE c STRICT PARALLEL SAFE
+    AS 'MODULE_PATHNAME', 'kq_cx_invalidate_cache_wrapper';
+
+CREATE FUNCTION kq_cx.kq_cx_add_days(
+    "input_date" date,
+    "interval" bigint,
+    "calendar_id" bigint
+) RETURNS date
+    LANGUAGE c STABLE STRICT PARALLEL SAFE
+    AS 'MODULE_PATHNAME', 'kq_cx_add_days_wrapper';
+
+CREATE FUNCTION kq_cx.kq_cx_add_days_xuid(
+    "input_date" date,
+    "interval" bigint,
+    "calendar_xuid" text
+) RETURNS date
+    LANGUAGE c STABLE STRICT PARALLEL SAFE
+    AS 'MODULE_PATHNAME', 'kq_cx_add_days_xuid_wrapper';
+
+CREATE FUNCTION kq_cx.kq_cx_add_days_optional(
+    "input_date" date,
+    "interval" bigint,
+    "calendar_xuid" text
+) RETURNS date
+    LANGUAGE c STABLE PARALLEL SAFE
+    AS 'MODULE_PATHNAME', 'kq_cx_add_days_optional_wrapper';
+
+CREATE FUNCTION kq_cx.kq_cx_add_days_ordered(
+    "input_date" date,
+    "interval" bigint,
+    "calendar_xuid" text
+) RETURNS date
+    LANGUAGE c STABLE STRICT PARALLEL SAFE
+    AS 'MODULE_PATHNAME', 'kq_cx_add_days_ordered_wrapper';
+
+CREATE FUNCTION kq_cx.kq_cx_add_days_xuid(
+    "input_date" date,
+    "interval" bigint,
+    "namespace" text,
+    "calendar_xuid" text
+) RETURNS date
+    LANGUAGE c STABLE STRICT PARALLEL SAFE
+    AS 'MODULE_PATHNAME', 'kq_cx_add_days_namespace_xuid_wrapper';
+
+CREATE FUNCTION kq_cx.kq_cx_offset(
+    "input_date" date,
+    "steps" integer,
+    "direction" text,
+    "calendar_xuid" text
+) RETURNS date
+    LANGUAGE c STABLE STRICT PARALLEL SAFE
+    AS 'MODULE_PATHNAME', 'kq_cx_offset_wrapper';
+
+CREATE FUNCTION kq_cx.kq_cx_add_days_debug(
+    "input_date" date,
+    "interval" bigint,
+    "calendar_xuid" text
+) RETURNS TABLE (
+    "result_date" date,
+    "hit_index" integer,
+    "result_index" integer,
+    "page_map_index" integer,
+    "oob_flag" text
+)
+    LANGUAGE c STABLE STRICT PARALLEL SAFE
+    AS 'MODULE_PATHNAME', 'kq_cx_add_days_debug_wrapper';
+
+CREATE FUNCTION kq_cx.kq_cx_explain_lookup("input_date" date, "calendar_xuid" text) RETURNS TABLE (
+    "step" integer,
+    "action" text,
+    "detail" text
+)
+    LANGUAGE c STABLE STRICT PARALLEL SAFE
+    AS 'MODULE_PATHNAME', 'kq_cx_explain_lookup_wrapper';
+
+CREATE FUNCTION kq_cx.kq_cx_add_days_many(
+    "input_date" date,
+    "interval" bigint,
+    "calendar_xuids" text[]
+) RETURNS TABLE (
+    "calendar_xuid" text,
+    "result_date" date
+)
+    LANGUAGE c STABLE STRICT PARALLEL SAFE
+    AS 'MODULE_PATHNAME', 'kq_cx_add_days_many_wrapper';
+
+CREATE FUNCTION kq_cx.kq_cx_add_days_grid(
+    "dates" date[],
+    "intervals" bigint[],
+    "calendar_xuid" text
+) RETURNS TABLE (
+    "input_date" date,
+    "interval" bigint,
+    "result_date" date
+)
+    LANGUAGE c STABLE STRICT PARALLEL SAFE
+    AS 'MODULE_PATHNAME', 'kq_cx_add_days_grid_wrapper';
+
+CREATE FUNCTION kq_cx.kq_cx_populate_cache("force" boolean DEFAULT false) RETURNS text
+    LANGUAGE c STRICT PARALLEL SAFE
+    AS 'MODULE_PATHNAME', 'kq_cx_populate_cache_wrapper';
+
+CREATE FUNCTION kq_cx.kq_cx_refresh_cache() RETURNS TABLE (
+    "calendar_xuid" text,
+    "entries_before" bigint,
+    "entries_after" bigint,
+    "added" bigint,
+    "removed" bigint,
+    "changed" boolean
+)
+    LANGUAGE c STRICT
+    AS 'MODULE_PATHNAME', 'kq_cx_refresh_cache_wrapper';
+
+CREATE FUNCTION kq_cx.kq_cx_populate_cache(
+    "force" boolean,
+    VARIADIC "calendar_xuids" text[]
+) RETURNS bigint
+    LANGUAGE c STRICT PARALLEL SAFE
+    AS 'MODULE_PATHNAME', 'kq_cx_populate_calendars_wrapper';
+
+CREATE FUNCTION kq_cx.kq_cx_stats() RETURNS TABLE (
+    "lock_name" text,
+    "lock_mode" text,
+    "acquisitions" bigint,
+    "wait_total_ms" double precision,
+    "wait_p50_us" double precision,
+    "wait_p99_us" double precision,
+    "hold_total_ms" double precision,
+    "hold_p50_us" double precision,
+    "hold_p99_us" double precision
+)
+    LANGUAGE c STRICT PARALLEL SAFE
+    AS 'MODULE_PATHNAME', 'kq_cx_stats_wrapper';
+
+CREATE FUNCTION kq_cx.kq_cx_reset_stats() RETURNS text
+    LANGUAGE c STRICT PARALLEL SAFE
+    AS 'MODULE_PATHNAME', 'kq_cx_reset_stats_wrapper';
+
+CREATE FUNCTION kq_cx.kq_cx_is_entry("input_date" date, "calendar_xuid" text) RETURNS boolean
+    LANGUAGE c STABLE STRICT PARALLEL SAFE
+    AS 'MODULE_PATHNAME', 'kq_cx_is_entry_wrapper';
+
+CREATE FUNCTION kq_cx.kq_cx_calendar_details("calendar_xuid" text) RETURNS TABLE (
+    "calendar_id" bigint,
+    "calendar_name" text,
+    "calendar_type" text,
+    "timezone" text,
+    "parent_id" bigint,
+    "entries" bigint,
+    "first_entry" date,
+    "last_entry" date
+)
+    LANGUAGE c STABLE STRICT PARALLEL SAFE
+    AS 'MODULE_PATHNAME', 'kq_cx_calendar_details_wrapper';
+
+CREATE FUNCTION kq_cx.kq_cx_add_entries("calendar_xuid" text, "dates" date[]) RETURNS bigint
+    LANGUAGE c STRICT
+    AS 'MODULE_PATHNAME', 'kq_cx_add_entries_wrapper';
+
+CREATE FUNCTION kq_cx.kq_cx_remove_entries("calendar_xuid" text, "dates" date[]) RETURNS bigint
+    LANGUAGE c STRICT
+    AS 'MODULE_PATHNAME', 'kq_cx_remove_entries_wrapper';
+
+CREATE FUNCTION kq_cx.kq_cx_generation() RETURNS bigint
+    LANGUAGE c STABLE STRICT PARALLEL SAFE
+    AS 'MODULE_PATHNAME', 'kq_cx_generation_wrapper';
+
+CREATE FUNCTION kq_cx.kq_cx_add_days_pinned(
+    "input_date" date,
+    "interval" bigint,
+    "namespace" text,
+    "calendar_xuid" text,
+    "generation" bigint
+) RETURNS date
+    LANGUAGE c IMMUTABLE STRICT PARALLEL SAFE
+    AS 'MODULE_PATHNAME', 'kq_cx_add_days_pinned_wrapper';
+
+CREATE FUNCTION kq_cx.kq_cx_rebuild_pinned_indexes() RETURNS SETOF text
+    LANGUAGE c STRICT
+    AS 'MODULE_PATHNAME', 'kq_cx_rebuild_pinned_indexes_wrapper';
+
+CREATE FUNCTION kq_cx.kq_cx_define_profile(
+    "profile" text,
+    "schema" text DEFAULT NULL,
+    "q_schema_validation" text DEFAULT NULL,
+    "q1_get_calendar_min_max_id" text DEFAULT NULL,
+    "q2_get_calendars_entry_count" text DEFAULT NULL,
+    "q3_get_calendar_entries" text DEFAULT NULL,
+    "q3_get_calendar_entries_by_id" text DEFAULT NULL,
+    "q4_get_calendar_exceptions" text DEFAULT NULL
+) RETURNS text
+    LANGUAGE c
+    AS 'MODULE_PATHNAME', 'kq_cx_define_profile_wrapper';
+
+CREATE FUNCTION kq_cx.kq_cx_drop_profile("profile" text) RETURNS boolean
+    LANGUAGE c STRICT
+    AS 'MODULE_PATHNAME', 'kq_cx_drop_profile_wrapper';
+
+CREATE FUNCTION kq_cx.kq_cx_entries_in("range" daterange, "calendar_xuid" text) RETURNS SETOF date
+    LANGUAGE c STABLE STRICT PARALLEL SAFE
+    AS 'MODULE_PATHNAME', 'kq_cx_entries_in_wrapper';
+
+CREATE FUNCTION kq_cx.kq_cx_non_entries_between(
+    "start_date" date,
+    "end_date" date,
+    "calendar_xuid" text
+) RETURNS SETOF date
+    LANGUAGE c STABLE STRICT PARALLEL SAFE
+    AS 'MODULE_PATHNAME', 'kq_cx_non_entries_between_wrapper';
+
+CREATE FUNCTION kq_cx.kq_cx_coverage("calendar_xuid" text, "range" daterange) RETURNS TABLE (
+    "days" bigint,
+    "entries" bigint,
+    "coverage" double precision
+)
+    LANGUAGE c STABLE STRICT PARALLEL SAFE
+    AS 'MODULE_PATHNAME', 'kq_cx_coverage_wrapper';
+
+CREATE FUNCTION kq_cx.kq_cx_period_range("input_date" date, "calendar_xuid" text) RETURNS daterange
+    LANGUAGE c STABLE STRICT PARALLEL SAFE
+    AS 'MODULE_PATHNAME', 'kq_cx_period_range_wrapper';
+
+CREATE FUNCTION kq_cx.kq_cx_entries_spanned(
+    "from_date" date,
+    "to_date" date,
+    "calendar_xuid" text
+) RETURNS bigint
+    LANGUAGE c STABLE STRICT PARALLEL SAFE
+    AS 'MODULE_PATHNAME', 'kq_cx_entries_spanned_wrapper';
+
+CREATE FUNCTION kq_cx.kq_cx_days_until_next_entry(
+    "input_date" date,
+    "calendar_xuid" text
+) RETURNS integer
+    LANGUAGE c STABLE STRICT PARALLEL SAFE
+    AS 'MODULE_PATHNAME', 'kq_cx_days_until_next_entry_wrapper';
+
+CREATE FUNCTION kq_cx.kq_cx_period_index("input_date" date, "calendar_xuid" text) RETURNS integer
+    LANGUAGE c STABLE STRICT PARALLEL SAFE
+    AS 'MODULE_PATHNAME', 'kq_cx_period_index_wrapper';
+
+CREATE FUNCTION kq_cx.kq_cx_add_periods(
+    "input_date" date,
+    "periods" integer,
+    "calendar_xuid" text,
+    "period_xuid" text
+) RETURNS date
+    LANGUAGE c STABLE STRICT PARALLEL SAFE
+    AS 'MODULE_PATHNAME', 'kq_cx_add_periods_wrapper';
+
+CREATE FUNCTION kq_cx.kq_cx_gaps("calendar_xuid" text, "min_gap" integer) RETURNS TABLE (
+    "gap_start" date,
+    "gap_end" date,
+    "days" integer
+)
+    LANGUAGE c STABLE STRICT PARALLEL SAFE
+    AS 'MODULE_PATHNAME', 'kq_cx_gaps_wrapper';
+
+CREATE FUNCTION kq_cx.kq_cx_entry_histogram("calendar_xuid" text, "bucket" text) RETURNS TABLE (
+    "bucket_start" date,
+    "entries" bigint
+)
+    LANGUAGE c STABLE STRICT PARALLEL SAFE
+    AS 'MODULE_PATHNAME', 'kq_cx_entry_histogram_wrapper';
+
+CREATE FUNCTION kq_cx.kq_cx_partition_bounds(
+    "calendar_xuid" text,
+    "from_date" date,
+    "to_date" date,
+    "name_prefix" text DEFAULT NULL
+) RETURNS TABLE (
+    "partition_name" text,
+    "range_start" date,
+    "range_end" date
+)
+    LANGUAGE c STABLE PARALLEL SAFE
+    AS 'MODULE_PATHNAME', 'kq_cx_partition_bounds_wrapper';
+
+CREATE FUNCTION kq_cx.kq_cx_simulate(
+    "input_date" date,
+    "interval" bigint,
+    "calendar_xuid" text
+) RETURNS date
+    LANGUAGE c STABLE STRICT PARALLEL SAFE
+    AS 'MODULE_PATHNAME', 'kq_cx_simulate_wrapper';
+
+CREATE FUNCTION kq_cx.kq_cx_pin_generation() RETURNS bigint
+    LANGUAGE c STRICT PARALLEL UNSAFE
+    AS 'MODULE_PATHNAME', 'kq_cx_pin_generation_wrapper';
+
+CREATE FUNCTION kq_cx.kq_cx_unpin_generation() RETURNS boolean
+    LANGUAGE c STRICT PARALLEL UNSAFE
+    AS 'MODULE_PATHNAME', 'kq_cx_unpin_generation_wrapper';
+
+CREATE FUNCTION kq_cx.kq_cx_stress(
+    "workers" integer DEFAULT 4,
+    "seconds" integer DEFAULT 10
+) RETURNS TABLE (
+    "workers" integer,
+    "repopulations" bigint,
+    "lookups" bigint,
+    "torn_reads" bigint,
+    "failed_workers" integer,
+    "hung_workers" integer,
+    "cache_being_filled" boolean
+)
+    LANGUAGE c STRICT
+    AS 'MODULE_PATHNAME', 'kq_cx_stress_wrapper';
+
+CREATE FUNCTION kq_cx.kq_cx_generate_test_calendars(
+    "count" integer,
+    "entries_per_calendar" integer,
+    "pattern" text DEFAULT 'daily',
+    "persist" boolean DEFAULT false
+) RETURNS TABLE (
+    "calendar_id" bigint,
+    "calendar_xuid" text,
+    "entries" bigint
+)
+    LANGUAGE c STRICT
+    AS 'MODULE_PATHNAME', 'kq_cx_generate_test_calendars_wrapper';
+
+CREATE FUNCTION kq_cx.kq_cx_add_days_uncached(
+    "input_date" date,
+    "interval" bigint,
+    "calendar_xuid" text
+) RETURNS date
+    LANGUAGE c STABLE STRICT PARALLEL SAFE
+    AS 'MODULE_PATHNAME', 'kq_cx_add_days_uncached_wrapper';
+
+CREATE FUNCTION kq_cx.kq_cx_top_calendars("n" integer DEFAULT 10) RETURNS TABLE (
+    "calendar_id" bigint,
+    "calendar_xuid" text,
+    "lookups" bigint,
+    "avg_lookup_us" double precision,
+    "last_used" timestamptz
+)
+    LANGUAGE c STRICT PARALLEL SAFE
+    AS 'MODULE_PATHNAME', 'kq_cx_top_calendars_wrapper';
+
+CREATE FUNCTION kq_cx.kq_cx_verify_cache() RETURNS TABLE (
+    "calendar_id" bigint,
+    "calendar_xuid" text,
+    "in_sync" boolean,
+    "cached_entries" bigint,
+    "db_entries" bigint,
+    "checksum_match" boolean
+)
+    LANGUAGE c STRICT
+    AS 'MODULE_PATHNAME', 'kq_cx_verify_cache_wrapper';
+
+CREATE FUNCTION kq_cx.kq_cx_verify_lookups("samples" integer DEFAULT 100) RETURNS TABLE (
+    "calendar_id" bigint,
+    "calendar_xuid" text,
+    "input_date" date,
+    "interval" integer,
+    "cached_date" date,
+    "simulated_date" date
+)
+    LANGUAGE c STRICT
+    AS 'MODULE_PATHNAME', 'kq_cx_verify_lookups_wrapper';
+
+CREATE FUNCTION kq_cx.kq_cx_validate_source() RETURNS TABLE (
+    "calendar_id" bigint,
+    "calendar_xuid" text,
+    "valid" boolean,
+    "source_rows" bigint,
+    "duplicates" bigint,
+    "null_dates" bigint,
+    "out_of_order" bigint,
+    "outside_window" bigint
+)
+    LANGUAGE c STRICT
+    AS 'MODULE_PATHNAME', 'kq_cx_validate_source_wrapper';
+
+CREATE TYPE kq_cx.kq_cx_span_state AS (
+    calendar_xuid text,
+    min_date date,
+    max_date date
+);
+
+CREATE FUNCTION kq_cx.kq_cx_span_accum(
+    state kq_cx.kq_cx_span_state,
+    input_date date,
+    calendar_xuid text
+) RETURNS kq_cx.kq_cx_span_state
+    LANGUAGE sql IMMUTABLE PARALLEL SAFE
+AS $$
+    SELECT CASE
+        WHEN input_date IS NULL THEN state
+        ELSE ROW(
+            calendar_xuid,
+            LEAST((state).min_date, input_date),
+            GREATEST((state).max_date, input_date)
+        )::kq_cx.kq_cx_span_state
+    END
+$$;
+
+CREATE FUNCTION kq_cx.kq_cx_span_combine(
+    state kq_cx.kq_cx_span_state,
+    other kq_cx.kq_cx_span_state
+) RETURNS kq_cx.kq_cx_span_state
+    LANGUAGE sql IMMUTABLE PARALLEL SAFE
+AS $$
+    SELECT ROW(
+        coalesce((state).calendar_xuid, (other).calendar_xuid),
+        LEAST((state).min_date, (other).min_date),
+        GREATEST((state).max_date, (other).max_date)
+    )::kq_cx.kq_cx_span_state
+$$;
+
+CREATE FUNCTION kq_cx.kq_cx_span_final(state kq_cx.kq_cx_span_state) RETURNS int8
+    LANGUAGE sql STABLE PARALLEL SAFE
+AS $$
+    SELECT kq_cx.kq_cx_entries_spanned(
+        (state).min_date,
+        (state).max_date,
+        (state).calendar_xuid
+    )
+$$;
+
+CREATE AGGREGATE kq_cx.kq_cx_span(date, text) (
+    SFUNC = kq_cx.kq_cx_span_accum,
+    STYPE = kq_cx.kq_cx_span_state,
+    COMBINEFUNC = kq_cx.kq_cx_span_combine,
+    FINALFUNC = kq_cx.kq_cx_span_final,
+    PARALLEL = SAFE
+);
+
+CREATE FUNCTION kq_cx.kq_cx_pinned_generation() RETURNS int8
+    LANGUAGE sql IMMUTABLE PARALLEL SAFE
+AS $$ SELECT 0::int8 $$;
+ALTER FUNCTION kq_cx.kq_cx_pinned_generation() OWNER TO kq_cx_admin;
+
+CREATE FUNCTION kq_cx.kq_cx_ddl_invalidate() RETURNS event_trigger
+    LANGUAGE c AS 'MODULE_PATHNAME', 'kq_cx_ddl_invalidate';
+CREATE FUNCTION kq_cx.kq_cx_truncate_invalidate() RETURNS trigger
+    LANGUAGE c AS 'MODULE_PATHNAME', 'kq_cx_truncate_invalidate';
+
+CREATE EVENT TRIGGER kq_cx_ddl_invalidate ON ddl_command_end
+    WHEN TAG IN ('ALTER TABLE', 'CREATE TABLE', 'CREATE TABLE AS', 'SELECT INTO')
+    EXECUTE FUNCTION kq_cx.kq_cx_ddl_invalidate();
+CREATE EVENT TRIGGER kq_cx_drop_invalidate ON sql_drop
+    WHEN TAG IN ('DROP TABLE', 'DROP SCHEMA')
+    EXECUTE FUNCTION kq_cx.kq_cx_ddl_invalidate();
+
+SELECT kq_cx.kq_cx_watch_source_tables();
+
+DO $$
+BEGIN
+    IF NOT EXISTS (SELECT FROM pg_roles WHERE rolname = 'kq_cx_reader') THEN
+        CREATE ROLE kq_cx_reader NOLOGIN;
+    END IF;
+END
+$$;
+GRANT kq_cx_reader TO kq_cx_admin;
+GRANT USAGE ON SCHEMA kq_cx TO kq_cx_reader;
+GRANT SELECT ON kq_cx.kq_cx_calendar_definition TO kq_cx_reader;
+GRANT INSERT, UPDATE, DELETE ON kq_cx.kq_cx_calendar_definition TO kq_cx_admin;
+GRANT USAGE ON SEQUENCE kq_cx.kq_cx_calendar_definition_id_seq TO kq_cx_admin;
+GRANT SELECT ON kq_cx.kq_cx_loader_profile TO kq_cx_reader;
+GRANT INSERT, UPDATE, DELETE ON kq_cx.kq_cx_loader_profile TO kq_cx_admin;
+
+DO $$
+DECLARE
+    function_signature regprocedure;
+    mutating bool;
+BEGIN
+    FOR function_signature, mutating IN
+        SELECT p.oid::regprocedure, p.proname = ANY (kq_cx.kq_cx_admin_functions())
+        FROM pg_proc p
+        WHERE p.pronamespace = 'kq_cx'::regnamespace
+    LOOP
+        EXECUTE format('REVOKE EXECUTE ON FUNCTION %s FROM PUBLIC', function_signature);
+        EXECUTE format(
+            'GRANT EXECUTE ON FUNCTION %s TO %I',
+            function_signature,
+            CASE WHEN mutating THEN 'kq_cx_admin' ELSE 'kq_cx_reader' END
+        );
+    END LOOP;
+END
+$$;
+
+DO $$ begin RAISE NOTICE 'ketteQ In-Memory Calendar Extension Upgrade: kq_cx: 1.0.1 -> 1.1.0 completed.'; end; $$;
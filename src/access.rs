@@ -0,0 +1,139 @@
+use std::ffi::CStr;
+
+use pgrx::prelude::*;
+
+use crate::{ENFORCE_ADMIN_ROLE, READ_ONLY};
+
+/// Role allowed to run the cache-mutating functions, created by the extension script.
+const ADMIN_ROLE: &CStr = c"kq_cx_admin";
+
+/// Functions that change the cache, only executable by `kq_cx_admin`. Every function calling
+/// `ensure_cache_admin` is listed, the extension script grants the others to `kq_cx_reader`.
+pub(crate) const ADMIN_FUNCTIONS: &[&str] = &[
+    "kq_cx_add_entries",
+    "kq_cx_define_calendar",
+    "kq_cx_define_calendar_cron",
+    "kq_cx_define_holiday_calendar",
+    "kq_cx_define_profile",
+    "kq_cx_distribute",
+    "kq_cx_drop_profile",
+    "kq_cx_dump_cache_file",
+    "kq_cx_generate_test_calendars",
+    "kq_cx_invalidate_cache",
+    "kq_cx_load_cache",
+    "kq_cx_populate_cache",
+    "kq_cx_rebuild_pages",
+    "kq_cx_rebuild_pinned_indexes",
+    "kq_cx_refresh_cache",
+    "kq_cx_remove_entries",
+    "kq_cx_reset_stats",
+    "kq_cx_stress",
+    "kq_cx_watch_source_tables",
+];
+
+extension_sql!(
+    r#"
+DO $$
+BEGIN
+    IF NOT EXISTS (SELECT FROM pg_roles WHERE rolname = 'kq_cx_admin') THEN
+        CREATE ROLE kq_cx_admin NOLOGIN;
+    END IF;
+END
+$$;
+"#,
+    name = "create_admin_role",
+);
+
+/// Nothing is executable by PUBLIC: the functions of `ADMIN_FUNCTIONS` are granted to
+/// `kq_cx_admin`, the others to `kq_cx_reader`, which `kq_cx_admin` is a member of. Run last so
+/// every function already exists.
+extension_sql!(
+    r#"
+DO $$
+BEGIN
+    IF NOT EXISTS (SELECT FROM pg_roles WHERE rolname = 'kq_cx_reader') THEN
+        CREATE ROLE kq_cx_reader NOLOGIN;
+    END IF;
+END
+$$;
+GRANT kq_cx_reader TO kq_cx_admin;
+GRANT USAGE ON SCHEMA @extschema@ TO kq_cx_reader;
+GRANT SELECT ON @extschema@.kq_cx_calendar_definition TO kq_cx_reader;
+GRANT INSERT, UPDATE, DELETE ON @extschema@.kq_cx_calendar_definition TO kq_cx_admin;
+GRANT USAGE ON SEQUENCE @extschema@.kq_cx_calendar_definition_id_seq TO kq_cx_admin;
+GRANT SELECT ON @extschema@.kq_cx_loader_profile TO kq_cx_reader;
+GRANT INSERT, UPDATE, DELETE ON @extschema@.kq_cx_loader_profile TO kq_cx_admin;
+
+DO $$
+DECLARE
+    function_signature regprocedure;
+    mutating bool;
+BEGIN
+    FOR function_signature, mutating IN
+        SELECT p.oid::regprocedure, p.proname = ANY (@extschema@.kq_cx_admin_functions())
+        FROM pg_proc p
+        WHERE p.pronamespace = '@extschema@'::regnamespace
+    LOOP
+        EXECUTE format('REVOKE EXECUTE ON FUNCTION %s FROM PUBLIC', function_signature);
+        EXECUTE format(
+            'GRANT EXECUTE ON FUNCTION %s TO %I',
+            function_signature,
+            CASE WHEN mutating THEN 'kq_cx_admin' ELSE 'kq_cx_reader' END
+        );
+    END LOOP;
+END
+$$;
+"#,
+    name = "grant_function_privileges",
+    finalize,
+);
+
+/// `ADMIN_FUNCTIONS`, read by the extension script when it grants the execute privileges.
+#[pg_extern(immutable, parallel_safe)]
+fn kq_cx_admin_functions() -> Vec<&'static str> {
+    ADMIN_FUNCTIONS.to_vec()
+}
+
+/// Errors out unless the current user is a superuser or has the privileges of `kq_cx_admin`.
+/// The check can be disabled with `kq.calendar.enforce_admin_role = off`.
+pub(crate) fn ensure_cache_admin(function_name: &str) {
+    debug_assert!(
+        ADMIN_FUNCTIONS.contains(&function_name),
+        "{function_name} is missing from ADMIN_FUNCTIONS"
+    );
+    if !ENFORCE_ADMIN_ROLE.get() {
+        return;
+    }
+
+    let allowed = unsafe {
+        if pg_sys::superuser() {
+            true
+        } else {
+            let admin_role = pg_sys::get_role_oid(ADMIN_ROLE.as_ptr(), true);
+            admin_role != pg_sys::InvalidOid
+                && pg_sys::has_privs_of_role(pg_sys::GetUserId(), admin_role)
+        }
+    };
+
+    if !allowed {
+        ereport!(
+            ERROR,
+            PgSqlErrorCode::ERRCODE_INSUFFICIENT_PRIVILEGE,
+            format!("permission denied for function {function_name}"),
+            "Only superusers and members of kq_cx_admin can modify the calendar cache."
+        );
+    }
+}
+
+/// Errors out when `kq.calendar.read_only` is on, used by every function that changes the
+/// contents of an already populated cache.
+pub(crate) fn ensure_writable(function_name: &str) {
+    if READ_ONLY.get() {
+        ereport!(
+            ERROR,
+            PgSqlErrorCode::ERRCODE_READ_ONLY_SQL_TRANSACTION,
+            format!("cannot execute {function_name} while kq.calendar.read_only is on"),
+            "The calendar cache is frozen, a superuser must turn kq.calendar.read_only off first."
+        );
+    }
+}
@@ -0,0 +1,261 @@
+use std::ffi::CStr;
+use std::time::Instant;
+
+use pgrx::lwlock::PgLwLock;
+use pgrx::pg_sys::panic::CaughtError;
+use pgrx::prelude::*;
+use pgrx::shmem::*;
+
+use crate::{get_guc_string_opt, AUDIT_TABLE, GUC_AUDIT_TABLE};
+
+const AUDIT_LOG_CAPACITY: usize = 256;
+const AUDIT_USER_NAME_MAX_LEN: usize = 64;
+
+type AuditUserName = heapless::String<AUDIT_USER_NAME_MAX_LEN>;
+
+#[derive(Default, Clone, Copy, Debug, PartialEq)]
+pub(crate) enum AuditOperation {
+    #[default]
+    Populate,
+    Invalidate,
+    Generate,
+    Define,
+    Patch,
+}
+
+impl AuditOperation {
+    fn as_str(&self) -> &'static str {
+        match self {
+            AuditOperation::Populate => "populate",
+            AuditOperation::Invalidate => "invalidate",
+            AuditOperation::Generate => "generate",
+            AuditOperation::Define => "define",
+            AuditOperation::Patch => "patch",
+        }
+    }
+}
+
+#[derive(Default, Clone, Debug)]
+pub struct AuditEntry {
+    sequence: u64,
+    logged_at: pg_sys::TimestampTz,
+    operation: AuditOperation,
+    user_name: AuditUserName,
+    pid: i32,
+    duration_us: i64,
+    entries: i64,
+}
+
+/// Fixed-size ring buffer, once full the oldest entry is overwritten.
+#[derive(Default, Clone, Debug)]
+pub struct AuditLog {
+    entries: heapless::Vec<AuditEntry, AUDIT_LOG_CAPACITY>,
+    next_sequence: u64,
+}
+
+unsafe impl PGRXSharedMemory for AuditLog {}
+
+pub(crate) static AUDIT_LOG: PgLwLock<AuditLog> = PgLwLock::new();
+
+fn current_user_name() -> AuditUserName {
+    let user_name = unsafe {
+        let name_ptr = pg_sys::GetUserNameFromId(pg_sys::GetUserId(), true);
+        if name_ptr.is_null() {
+            String::new()
+        } else {
+            CStr::from_ptr(name_ptr).to_string_lossy().to_string()
+        }
+    };
+    // user names are at most NAMEDATALEN - 1 bytes, truncate on a char boundary just in case
+    let mut truncated = AuditUserName::new();
+    for c in user_name.chars() {
+        if truncated.push(c).is_err() {
+            break;
+        }
+    }
+    truncated
+}
+
+/// Records a cache operation performed by the current session, `started` is taken before the
+/// operation ran and `entries` is the number of calendar entries affected.
+pub(crate) fn record(operation: AuditOperation, started: Instant, entries: usize) {
+    let entry = {
+        let mut audit_log = AUDIT_LOG.exclusive();
+        let entry = AuditEntry {
+            sequence: audit_log.next_sequence,
+            logged_at: unsafe { pg_sys::GetCurrentTimestamp() },
+            operation,
+            user_name: current_user_name(),
+            pid: unsafe { pg_sys::MyProcPid },
+            duration_us: started.elapsed().as_micros() as i64,
+            entries: entries as i64,
+        };
+        let slot = (audit_log.next_sequence % AUDIT_LOG_CAPACITY as u64) as usize;
+        if audit_log.entries.len() < AUDIT_LOG_CAPACITY {
+            audit_log.entries.push(entry.clone()).unwrap();
+        } else {
+            audit_log.entries[slot] = entry.clone();
+        }
+        audit_log.next_sequence += 1;
+        entry
+    };
+
+    mirror_to_table(&entry);
+}
+
+/// Copies the entry into `kq.calendar.audit_table` when configured. Skipped in parallel workers
+/// and read-only transactions where the insert is not allowed. The insert runs as the owner of
+/// the table, so sessions that populate the cache without the privilege to write it (a lazy
+/// population started by a `kq_cx_reader` lookup) are still audited, and in a subtransaction: a
+/// failure is reported as a warning instead of aborting the audited operation.
+fn mirror_to_table(entry: &AuditEntry) {
+    let Some(audit_table) = get_guc_string_opt(GUC_AUDIT_TABLE, &AUDIT_TABLE) else {
+        return;
+    };
+    if unsafe { pg_sys::IsInParallelMode() || pg_sys::XactReadOnly } {
+        debug2!("audit entry not mirrored to {audit_table}: read-only context");
+        return;
+    }
+
+    let (memory_context, resource_owner) =
+        unsafe { (pg_sys::CurrentMemoryContext, pg_sys::CurrentResourceOwner) };
+    unsafe { pg_sys::BeginInternalSubTransaction(std::ptr::null()) };
+    PgTryBuilder::new(|| {
+        insert_as_owner(&audit_table, entry);
+        unsafe {
+            pg_sys::ReleaseCurrentSubTransaction();
+            pg_sys::MemoryContextSwitchTo(memory_context);
+            pg_sys::CurrentResourceOwner = resource_owner;
+        }
+    })
+    .catch_others(|error| {
+        // rolling back the subtransaction also restores the user switched by insert_as_owner
+        unsafe {
+            pg_sys::MemoryContextSwitchTo(memory_context);
+            pg_sys::RollbackAndReleaseCurrentSubTransaction();
+            pg_sys::MemoryContextSwitchTo(memory_context);
+            pg_sys::CurrentResourceOwner = resource_owner;
+        }
+        let report = match &error {
+            CaughtError::PostgresError(report)
+            | CaughtError::ErrorReport(report)
+            | CaughtError::RustPanic {
+                ereport: report, ..
+            } => report,
+        };
+        if report.sql_error_code() == PgSqlErrorCode::ERRCODE_QUERY_CANCELED {
+            error.rethrow()
+        }
+        warning!(
+            "cannot write audit entry to {audit_table}. {}",
+            report.message()
+        );
+    })
+    .execute()
+}
+
+/// Inserts the entry into the audit table as the owner of the table.
+fn insert_as_owner(audit_table: &str, entry: &AuditEntry) {
+    let owner = Spi::get_one_with_args::<pg_sys::Oid>(
+        "SELECT relowner FROM pg_class WHERE oid = $1::regclass",
+        vec![(PgBuiltInOids::TEXTOID.oid(), audit_table.into_datum())],
+    )
+    .unwrap_or_else(|spi_error| error!("{spi_error}"))
+    .unwrap_or_else(|| error!("relation \"{audit_table}\" does not exist"));
+
+    let query = format!(
+        "INSERT INTO {audit_table} (logged_at, operation, user_name, pid, duration_ms, entries) \
+         VALUES ($1, $2, $3, $4, $5, $6)"
+    );
+    let (mut user_id, mut security_context) = (pg_sys::InvalidOid, 0);
+    unsafe {
+        pg_sys::GetUserIdAndSecContext(&mut user_id, &mut security_context);
+        pg_sys::SetUserIdAndSecContext(
+            owner,
+            security_context | pg_sys::SECURITY_RESTRICTED_OPERATION as i32,
+        );
+    }
+    Spi::run_with_args(
+        &query,
+        Some(vec![
+            (
+                PgBuiltInOids::TIMESTAMPTZOID.oid(),
+                Some(pg_sys::Datum::from(entry.logged_at)),
+            ),
+            (
+                PgBuiltInOids::TEXTOID.oid(),
+                entry.operation.as_str().into_datum(),
+            ),
+            (
+                PgBuiltInOids::TEXTOID.oid(),
+                entry.user_name.as_str().into_datum(),
+            ),
+            (PgBuiltInOids::INT4OID.oid(), entry.pid.into_datum()),
+            (
+                PgBuiltInOids::FLOAT8OID.oid(),
+                (entry.duration_us as f64 / 1000.0).into_datum(),
+            ),
+            (PgBuiltInOids::INT8OID.oid(), entry.entries.into_datum()),
+        ]),
+    )
+    .unwrap_or_else(|spi_error| error!("{spi_error}"));
+    unsafe { pg_sys::SetUserIdAndSecContext(user_id, security_context) };
+}
+
+#[pg_extern(parallel_safe)]
+fn kq_cx_audit_log() -> TableIterator<
+    'static,
+    (
+        name!(logged_at, Option<TimestampWithTimeZone>),
+        name!(operation, String),
+        name!(user_name, String),
+        name!(pid, i32),
+        name!(duration_ms, f64),
+        name!(entries, i64),
+    ),
+> {
+    let mut entries = AUDIT_LOG.share().entries.clone();
+    entries.sort_unstable_by_key(|entry| entry.sequence);
+    TableIterator::new(entries.into_iter().map(|entry| {
+        (
+            unsafe {
+                TimestampWithTimeZone::from_datum(pg_sys::Datum::from(entry.logged_at), false)
+            },
+            entry.operation.as_str().to_string(),
+            entry.user_name.to_string(),
+            entry.pid,
+            entry.duration_us as f64 / 1000.0,
+            entry.entries,
+        )
+    }))
+}
+
+#[cfg(any(test, feature = "pg_test"))]
+#[pg_schema]
+mod tests {
+    use pgrx::prelude::*;
+
+    #[pg_test]
+    fn test_mirror_to_table() {
+        Spi::run(
+            "CREATE TABLE audit_test (logged_at timestamptz, operation text, user_name text, \
+             pid int, duration_ms float8, entries int8)",
+        )
+        .unwrap();
+        Spi::run("SET LOCAL kq.calendar.audit_table = 'audit_test'").unwrap();
+        crate::clear_cache();
+        crate::ensure_cache_populated();
+        assert_eq!(
+            Spi::get_one::<i64>("SELECT count(*) FROM audit_test WHERE operation = 'populate'"),
+            Ok(Some(1))
+        );
+    }
+
+    #[pg_test]
+    fn test_mirror_failure_is_a_warning() {
+        Spi::run("SET LOCAL kq.calendar.audit_table = 'no_such_audit_table'").unwrap();
+        crate::clear_cache();
+        crate::ensure_cache_populated();
+        assert!(crate::kq_cx_ready());
+    }
+}
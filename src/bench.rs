@@ -0,0 +1,98 @@
+use std::hint::black_box;
+use std::time::Instant;
+
+use kq_cx_core::math;
+use kq_cx_core::synthetic::XorShift64;
+use pgrx::prelude::*;
+
+use crate::{find_calendar_id, share_calendar, xuid_not_found_error, CALENDAR_ID_MAP};
+
+const BENCH_PATTERNS: [&str; 3] = ["random", "sequential", "oob"];
+
+/// Builds the list of input dates (pg epoch days) for the given pattern.
+fn bench_dates(pattern: &str, first_date: i32, last_date: i32, iterations: usize) -> Vec<i32> {
+    let range = (last_date - first_date + 1) as u64;
+    let mut rng = XorShift64::new(0x9E37_79B9_7F4A_7C15);
+    (0..iterations)
+        .map(|i| match pattern {
+            "random" => first_date + (rng.next_u64() % range) as i32,
+            "sequential" => first_date + (i as u64 % range) as i32,
+            _ => {
+                // out of bounds, alternate before the first and after the last entry
+                let offset = 1 + (rng.next_u64() % 365) as i32;
+                if i % 2 == 0 {
+                    first_date - offset
+                } else {
+                    last_date + offset
+                }
+            }
+        })
+        .collect()
+}
+
+fn percentile(sorted_ns: &[u64], percent: usize) -> i64 {
+    sorted_ns[(sorted_ns.len() - 1) * percent / 100] as i64
+}
+
+/// Runs synthetic lookups against a cached calendar and reports the latency distribution of each
+/// pattern. Every lookup takes the shared lock like `kq_cx_add_days` does, so the numbers include
+/// the locking overhead seen by real queries.
+#[pg_extern(parallel_safe)]
+fn kq_cx_bench(
+    calendar_xuid: &str,
+    iterations: default!(i32, 100000),
+) -> TableIterator<
+    'static,
+    (
+        name!(pattern, String),
+        name!(iterations, i64),
+        name!(p50_ns, i64),
+        name!(p90_ns, i64),
+        name!(p99_ns, i64),
+        name!(max_ns, i64),
+        name!(mean_ns, f64),
+    ),
+> {
+    crate::ensure_cache_populated();
+    if iterations <= 0 {
+        error!("iterations must be greater than 0");
+    }
+
+    let calendar_id = find_calendar_id(None, calendar_xuid)
+        .unwrap_or_else(|| xuid_not_found_error(calendar_xuid));
+    let (first_date, last_date) = match share_calendar(&CALENDAR_ID_MAP.share(), calendar_id) {
+        Some(calendar) if !calendar.dates().is_empty() => (
+            *calendar.dates().first().unwrap(),
+            *calendar.dates().last().unwrap(),
+        ),
+        _ => error!("calendar_xuid = {calendar_xuid} has no entries to benchmark"),
+    };
+
+    let rows: Vec<_> = BENCH_PATTERNS
+        .iter()
+        .map(|pattern| {
+            let dates = bench_dates(pattern, first_date, last_date, iterations as usize);
+            let mut durations_ns: Vec<u64> = Vec::with_capacity(dates.len());
+            for date in dates {
+                let start = Instant::now();
+                if let Some(calendar) = share_calendar(&CALENDAR_ID_MAP.share(), calendar_id) {
+                    black_box(math::add_calendar_days(&calendar, black_box(date), 1));
+                }
+                durations_ns.push(start.elapsed().as_nanos() as u64);
+            }
+            durations_ns.sort_unstable();
+
+            let total_ns: u64 = durations_ns.iter().sum();
+            (
+                pattern.to_string(),
+                durations_ns.len() as i64,
+                percentile(&durations_ns, 50),
+                percentile(&durations_ns, 90),
+                percentile(&durations_ns, 99),
+                *durations_ns.last().unwrap() as i64,
+                total_ns as f64 / durations_ns.len() as f64,
+            )
+        })
+        .collect();
+    TableIterator::new(rows)
+}
@@ -0,0 +1,141 @@
+use std::ffi::CStr;
+use std::mem::size_of;
+
+use pgrx::prelude::*;
+
+use crate::{
+    audit, generations, locks, membership, metadata, stress, usage, xuids, CalendarControl,
+    CalendarIdMap, CalendarStripe, CalendarXuidIdMap, CALENDAR_LOCK_STRIPES, GUC_MAX_SHARED_MEMORY,
+    MAX_SHARED_MEMORY,
+};
+
+/// Optional segments turned off, in this order, when the reservation exceeds the budget.
+const OPTIONAL_SEGMENTS: [(&str, &CStr, &CStr); 2] = [
+    ("kept generations", c"kq.calendar.kept_generations", c"0"),
+    (
+        "membership bitmaps",
+        c"kq.calendar.membership_bitmaps",
+        c"off",
+    ),
+];
+
+/// Shared memory requested at server start by each segment, in bytes. Fixed by the capacities
+/// of the build and by the settings enabling the optional segments.
+pub(crate) fn reservations() -> Vec<(&'static str, usize)> {
+    let mut reservations = vec![
+        (
+            "calendars",
+            CALENDAR_LOCK_STRIPES * size_of::<CalendarStripe>(),
+        ),
+        (
+            "calendar maps",
+            size_of::<CalendarIdMap>()
+                + size_of::<CalendarXuidIdMap>()
+                + size_of::<xuids::LongXuidMap>()
+                + size_of::<CalendarControl>(),
+        ),
+        (
+            "metadata",
+            size_of::<metadata::CalendarMetadataMap>() + size_of::<metadata::CalendarLoadMap>(),
+        ),
+        (
+            "statistics",
+            size_of::<audit::AuditLog>()
+                + size_of::<usage::CalendarUsageMap>()
+                + size_of::<locks::LockStats>()
+                + size_of::<stress::StressRun>(),
+        ),
+    ];
+    if membership::enabled() {
+        reservations.push((
+            "membership bitmaps",
+            size_of::<membership::CalendarBitmapMap>(),
+        ));
+    }
+    if generations::enabled() {
+        reservations.push((
+            "kept generations",
+            size_of::<generations::GenerationSlots>(),
+        ));
+    }
+    reservations
+}
+
+fn total(reservations: &[(&str, usize)]) -> usize {
+    reservations.iter().map(|(_, bytes)| bytes).sum()
+}
+
+/// Checks the reservation against kq.calendar.max_shared_memory and logs it, before the segments
+/// are requested. Over the budget the optional segments are turned off with a warning, the
+/// server does not start when the others alone exceed it.
+pub(crate) fn check_budget() {
+    let mut reservations = reservations();
+    let budget_kb = MAX_SHARED_MEMORY.get();
+    if budget_kb > 0 {
+        let budget = budget_kb as usize * 1024;
+        for (segment, setting, off) in OPTIONAL_SEGMENTS {
+            if total(&reservations) <= budget {
+                break;
+            }
+            let Some(position) = reservations.iter().position(|(name, _)| *name == segment) else {
+                continue;
+            };
+            reservations.remove(position);
+            unsafe {
+                pg_sys::SetConfigOption(
+                    setting.as_ptr(),
+                    off.as_ptr(),
+                    pg_sys::GucContext::PGC_POSTMASTER,
+                    pg_sys::GucSource::PGC_S_OVERRIDE,
+                );
+            }
+            warning!(
+                "kq_cx shared memory exceeds {GUC_MAX_SHARED_MEMORY} = {budget_kb}kB, {} turned off",
+                setting.to_string_lossy()
+            );
+        }
+        if total(&reservations) > budget {
+            ereport!(
+                ERROR,
+                PgSqlErrorCode::ERRCODE_PROGRAM_LIMIT_EXCEEDED,
+                format!(
+                    "kq_cx needs {}kB of shared memory, {GUC_MAX_SHARED_MEMORY} is {budget_kb}kB",
+                    total(&reservations).div_ceil(1024)
+                ),
+                "Raise kq.calendar.max_shared_memory, the remaining segments cannot be turned off."
+            );
+        }
+    }
+
+    let segments: Vec<String> = reservations
+        .iter()
+        .map(|(name, bytes)| format!("{name} {}kB", bytes.div_ceil(1024)))
+        .collect();
+    log!(
+        "kq_cx reserves {}kB of shared memory: {}",
+        total(&reservations).div_ceil(1024),
+        segments.join(", ")
+    );
+}
+
+#[cfg(any(test, feature = "pg_test"))]
+#[pg_schema]
+mod tests {
+    use pgrx::prelude::*;
+
+    #[pg_test]
+    fn test_reservations() {
+        let reservations = super::reservations();
+        assert_eq!(
+            reservations[0],
+            (
+                "calendars",
+                crate::CALENDAR_LOCK_STRIPES * std::mem::size_of::<crate::CalendarStripe>()
+            )
+        );
+        assert!(!reservations
+            .iter()
+            .any(|(name, _)| *name == "kept generations"));
+        assert!(super::total(&reservations) > reservations[0].1);
+    }
+}
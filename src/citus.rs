@@ -0,0 +1,76 @@
+use pgrx::prelude::*;
+
+use crate::access;
+
+/// Commands run on every Citus worker by `kq_cx_distribute`, in order.
+const WORKER_COMMANDS: [&str; 2] = [
+    "CREATE EXTENSION IF NOT EXISTS kq_cx",
+    "SELECT kq_cx.kq_cx_populate_cache()",
+];
+
+/// Installs the extension and populates the cache on all the Citus workers, so lookups pushed
+/// down to the workers find a filled cache. kq_cx_add_days and kq_cx_add_days_xuid are stable
+/// and parallel safe, which lets Citus push them down with the columns they read. Returns one row
+/// per worker and command.
+#[pg_extern]
+fn kq_cx_distribute() -> TableIterator<
+    'static,
+    (
+        name!(node_name, String),
+        name!(node_port, i32),
+        name!(command, &'static str),
+        name!(success, bool),
+        name!(result, String),
+    ),
+> {
+    access::ensure_cache_admin("kq_cx_distribute");
+
+    let citus_installed =
+        Spi::get_one::<bool>("SELECT EXISTS (SELECT FROM pg_extension WHERE extname = 'citus')")
+            .unwrap_or_else(|spi_error| error!("cannot check for citus. {spi_error}"))
+            .unwrap_or(false);
+    if !citus_installed {
+        ereport!(
+            ERROR,
+            PgSqlErrorCode::ERRCODE_FEATURE_NOT_SUPPORTED,
+            "kq_cx_distribute requires the citus extension",
+            "Run it on the Citus coordinator."
+        );
+    }
+
+    let mut rows = vec![];
+    for command in WORKER_COMMANDS {
+        Spi::connect(|client| {
+            let tuple_table = client
+                .select(
+                    "SELECT nodename, nodeport, success, result FROM run_command_on_workers($1)",
+                    None,
+                    Some(vec![(PgBuiltInOids::TEXTOID.oid(), command.into_datum())]),
+                )
+                .unwrap_or_else(|spi_error| {
+                    error!("cannot run '{command}' on workers. {spi_error}")
+                });
+            for row in tuple_table {
+                rows.push((
+                    row[1].value::<String>().ok().flatten().unwrap_or_default(),
+                    row[2].value::<i32>().ok().flatten().unwrap_or_default(),
+                    command,
+                    row[3].value::<bool>().ok().flatten().unwrap_or(false),
+                    row[4].value::<String>().ok().flatten().unwrap_or_default(),
+                ));
+            }
+        });
+    }
+    TableIterator::new(rows)
+}
+
+#[cfg(any(test, feature = "pg_test"))]
+#[pg_schema]
+mod tests {
+    use pgrx::prelude::*;
+
+    #[pg_test(error = "kq_cx_distribute requires the citus extension")]
+    fn test_distribute_requires_citus() {
+        super::kq_cx_distribute().for_each(drop);
+    }
+}
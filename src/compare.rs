@@ -0,0 +1,79 @@
+use kq_cx_core::diff::{diff_dates, DatesDiff};
+use pgrx::prelude::*;
+
+use crate::{
+    ensure_cache_populated, find_calendar_id, share_calendar, xuid_not_found_error, PgDate,
+    CALENDAR_ID_MAP,
+};
+
+fn cached_dates(calendar_xuid: &str) -> Vec<i32> {
+    let calendar_id = find_calendar_id(None, calendar_xuid)
+        .unwrap_or_else(|| xuid_not_found_error(calendar_xuid));
+    share_calendar(&CALENDAR_ID_MAP.share(), calendar_id)
+        .map(|calendar| calendar.dates().to_vec())
+        .unwrap_or_default()
+}
+
+fn diff_calendars(calendar_a: &str, calendar_b: &str) -> DatesDiff {
+    ensure_cache_populated();
+    diff_dates(&cached_dates(calendar_a), &cached_dates(calendar_b))
+}
+
+/// Counts the entries found only in `calendar_a`, only in `calendar_b` and in both.
+#[pg_extern(parallel_safe, stable)]
+fn kq_cx_compare_calendars(
+    calendar_a: &str,
+    calendar_b: &str,
+) -> TableIterator<
+    'static,
+    (
+        name!(only_in_a, i64),
+        name!(only_in_b, i64),
+        name!(common, i64),
+    ),
+> {
+    let diff = diff_calendars(calendar_a, calendar_b);
+    TableIterator::once((
+        diff.only_in_a.len() as i64,
+        diff.only_in_b.len() as i64,
+        diff.common as i64,
+    ))
+}
+
+/// Entries found in only one of the calendars, `side` is 'a' or 'b'.
+#[pg_extern(parallel_safe, stable)]
+fn kq_cx_calendar_differences(
+    calendar_a: &str,
+    calendar_b: &str,
+) -> TableIterator<'static, (name!(entry, PgDate), name!(side, &'static str))> {
+    let diff = diff_calendars(calendar_a, calendar_b);
+    let mut rows: Vec<(i32, &'static str)> = diff
+        .only_in_a
+        .into_iter()
+        .map(|date| (date, "a"))
+        .chain(diff.only_in_b.into_iter().map(|date| (date, "b")))
+        .collect();
+    rows.sort_unstable();
+    TableIterator::new(
+        rows.into_iter()
+            .map(|(date, side)| (unsafe { PgDate::from_pg_epoch_days(date) }, side)),
+    )
+}
+
+#[cfg(any(test, feature = "pg_test"))]
+#[pg_schema]
+mod tests {
+    #[pg_test]
+    fn test_compare_calendars() {
+        // month: 2024-01..06, quarter: 2024-01, 04, 07, 10 and 2025-01, 04, 07, 10
+        assert_eq!(
+            super::kq_cx_compare_calendars("month", "quarter").next(),
+            Some((4, 6, 2))
+        );
+        let first = super::kq_cx_calendar_differences("month", "quarter").next();
+        assert_eq!(
+            first,
+            Some((pgrx::datum::Date::new(2024, 2, 1).unwrap(), "a"))
+        );
+    }
+}
@@ -0,0 +1,245 @@
+use std::ffi::CStr;
+
+use pgrx::prelude::*;
+
+use crate::events::{self, Event};
+use crate::{access, define, with_schema, CALENDAR_CONTROL, INVALIDATE_ON_DDL, READ_ONLY};
+
+/// Source tables whose DDL invalidates the cache, see `is_source_table`.
+const SOURCE_TABLES: [&str; 2] = ["{schema}.calendar", "{schema}.calendar_date"];
+
+const TRUNCATE_TRIGGER: &str = "kq_cx_invalidate_on_truncate";
+
+/// The event triggers fire on the commands that can replace the rows of a source table without a
+/// DML statement: altering or recreating it (the swap-table pattern renames a freshly loaded
+/// table) and dropping it. TRUNCATE does not fire event triggers, the source tables get a
+/// statement trigger instead, see `kq_cx_watch_source_tables`.
+extension_sql!(
+    r#"
+CREATE FUNCTION @extschema@.kq_cx_ddl_invalidate() RETURNS event_trigger
+    LANGUAGE c AS 'MODULE_PATHNAME', 'kq_cx_ddl_invalidate';
+CREATE FUNCTION @extschema@.kq_cx_truncate_invalidate() RETURNS trigger
+    LANGUAGE c AS 'MODULE_PATHNAME', 'kq_cx_truncate_invalidate';
+
+CREATE EVENT TRIGGER kq_cx_ddl_invalidate ON ddl_command_end
+    WHEN TAG IN ('ALTER TABLE', 'CREATE TABLE', 'CREATE TABLE AS', 'SELECT INTO')
+    EXECUTE FUNCTION @extschema@.kq_cx_ddl_invalidate();
+CREATE EVENT TRIGGER kq_cx_drop_invalidate ON sql_drop
+    WHEN TAG IN ('DROP TABLE', 'DROP SCHEMA')
+    EXECUTE FUNCTION @extschema@.kq_cx_ddl_invalidate();
+
+SELECT @extschema@.kq_cx_watch_source_tables();
+"#,
+    name = "create_ddl_invalidation_triggers",
+    requires = [kq_cx_watch_source_tables],
+);
+
+/// True when `identity` (as reported by the event trigger functions) is one of the source tables
+/// in kq.calendar.schema. With an empty schema the tables are matched by name only.
+fn is_source_table(identity: &str) -> bool {
+    SOURCE_TABLES.iter().any(|table| {
+        let table = with_schema(table);
+        match table.contains('.') {
+            true => identity == table,
+            false => identity.rsplit_once('.').map(|(_, name)| name) == Some(table.as_str()),
+        }
+    })
+}
+
+/// Invalidates the cache after a change to a source table made outside of the loader queries.
+/// Nothing to do while the cache is empty, or frozen by kq.calendar.read_only.
+fn invalidate_for(reason: &str, table: &str) {
+    if !INVALIDATE_ON_DDL.get() || READ_ONLY.get() || !CALENDAR_CONTROL.share().cache_filled {
+        return;
+    }
+    let entry_count = crate::clear_cache();
+    events::emit(
+        Event::CacheInvalidated,
+        &format!("calendar cache invalidated by {reason} of {table}: entries = {entry_count}"),
+        &[
+            ("entries", entry_count.into()),
+            ("background_reload", false.into()),
+            ("reason", reason.into()),
+            ("table", table.into()),
+        ],
+    );
+}
+
+/// Identities of the tables created, altered or dropped by the command that fired the event
+/// trigger, the ones of the source tables only.
+fn changed_source_tables(event: &str) -> Vec<String> {
+    let query = match event {
+        "sql_drop" => {
+            "SELECT object_identity FROM pg_event_trigger_dropped_objects() \
+             WHERE object_type = 'table'"
+        }
+        _ => {
+            "SELECT object_identity FROM pg_event_trigger_ddl_commands() \
+             WHERE object_type = 'table'"
+        }
+    };
+    Spi::connect(|client| {
+        client
+            .select(query, None, None)
+            .unwrap_or_else(|err| error!("cannot read the changed tables: {err}"))
+            .filter_map(|row| row[1].value::<String>().ok().flatten())
+            .filter(|identity| is_source_table(identity))
+            .collect()
+    })
+}
+
+/// Version 1 calling convention record of `kq_cx_ddl_invalidate`, C functions must export one.
+#[no_mangle]
+pub extern "C" fn pg_finfo_kq_cx_ddl_invalidate() -> &'static pg_sys::Pg_finfo_record {
+    const V1_API: pg_sys::Pg_finfo_record = pg_sys::Pg_finfo_record { api_version: 1 };
+    &V1_API
+}
+
+/// Event trigger function, invalidates the cache when a source table is altered, (re)created or
+/// dropped. A recreated source table gets the TRUNCATE trigger again.
+#[pg_guard]
+#[no_mangle]
+pub unsafe extern "C" fn kq_cx_ddl_invalidate(fcinfo: pg_sys::FunctionCallInfo) -> pg_sys::Datum {
+    let context = (*fcinfo).context;
+    if context.is_null() || !pgrx::is_a(context, pg_sys::NodeTag::T_EventTriggerData) {
+        error!("kq_cx_ddl_invalidate() must be called by an event trigger");
+    }
+    let data = context as *mut pg_sys::EventTriggerData;
+    let event = CStr::from_ptr((*data).event).to_string_lossy();
+    let tables = changed_source_tables(&event);
+    if let Some(table) = tables.first() {
+        let reason = match event.as_ref() {
+            "sql_drop" => "DROP",
+            _ => "DDL",
+        };
+        invalidate_for(reason, table);
+        if event != "sql_drop" {
+            watch_source_tables();
+        }
+    }
+    pg_sys::Datum::from(0)
+}
+
+#[no_mangle]
+pub extern "C" fn pg_finfo_kq_cx_truncate_invalidate() -> &'static pg_sys::Pg_finfo_record {
+    const V1_API: pg_sys::Pg_finfo_record = pg_sys::Pg_finfo_record { api_version: 1 };
+    &V1_API
+}
+
+/// AFTER TRUNCATE statement trigger function of the source tables.
+#[pg_guard]
+#[no_mangle]
+pub unsafe extern "C" fn kq_cx_truncate_invalidate(
+    fcinfo: pg_sys::FunctionCallInfo,
+) -> pg_sys::Datum {
+    let context = (*fcinfo).context;
+    if context.is_null() || !pgrx::is_a(context, pg_sys::NodeTag::T_TriggerData) {
+        error!("kq_cx_truncate_invalidate() must be called by a trigger");
+    }
+    let data = context as *mut pg_sys::TriggerData;
+    let relation = (*data).tg_relation;
+    let table = CStr::from_ptr(pg_sys::get_rel_name((*relation).rd_id)).to_string_lossy();
+    invalidate_for("TRUNCATE", &table);
+    pg_sys::Datum::from(0)
+}
+
+/// Creates or replaces the TRUNCATE trigger of `table`.
+#[cfg(not(feature = "pg13"))]
+fn truncate_trigger_sql(table: &str, extension_schema: &str) -> String {
+    format!(
+        "CREATE OR REPLACE TRIGGER {TRUNCATE_TRIGGER} AFTER TRUNCATE ON {table} \
+         FOR EACH STATEMENT EXECUTE FUNCTION {extension_schema}.kq_cx_truncate_invalidate()"
+    )
+}
+
+/// PostgreSQL 13 has no CREATE OR REPLACE TRIGGER, the trigger is dropped and created again in
+/// the same transaction.
+#[cfg(feature = "pg13")]
+fn truncate_trigger_sql(table: &str, extension_schema: &str) -> String {
+    format!(
+        "DROP TRIGGER IF EXISTS {TRUNCATE_TRIGGER} ON {table}; \
+         CREATE TRIGGER {TRUNCATE_TRIGGER} AFTER TRUNCATE ON {table} \
+         FOR EACH STATEMENT EXECUTE FUNCTION {extension_schema}.kq_cx_truncate_invalidate()"
+    )
+}
+
+/// Creates the TRUNCATE trigger on the source tables that exist, returns their number.
+fn watch_source_tables() -> i64 {
+    let Some(extension_schema) = define::extension_schema() else {
+        return 0;
+    };
+    let mut watched = 0;
+    for table in SOURCE_TABLES.map(with_schema) {
+        let exists = Spi::get_one_with_args::<bool>(
+            "SELECT to_regclass($1) IS NOT NULL",
+            vec![(PgBuiltInOids::TEXTOID.oid(), table.as_str().into_datum())],
+        )
+        .ok()
+        .flatten()
+        .unwrap_or(false);
+        if !exists {
+            continue;
+        }
+        Spi::run(&truncate_trigger_sql(&table, &extension_schema))
+            .unwrap_or_else(|err| error!("cannot create the TRUNCATE trigger of {table}: {err}"));
+        watched += 1;
+    }
+    watched
+}
+
+/// Adds the trigger invalidating the cache on TRUNCATE to the source tables in
+/// kq.calendar.schema, e.g. after changing it. Tables created or renamed later get it from the
+/// event trigger. Returns the number of tables watched.
+#[pg_extern]
+fn kq_cx_watch_source_tables() -> i64 {
+    access::ensure_cache_admin("kq_cx_watch_source_tables");
+    watch_source_tables()
+}
+
+#[cfg(any(test, feature = "pg_test"))]
+#[pg_schema]
+mod tests {
+    use pgrx::prelude::*;
+
+    fn cache_filled() -> bool {
+        crate::CALENDAR_CONTROL.share().cache_filled
+    }
+
+    #[pg_test]
+    fn test_source_tables() {
+        assert!(super::is_source_table("plan.calendar_date"));
+        assert!(!super::is_source_table("plan.calendar_date_new"));
+        assert!(!super::is_source_table("other.calendar"));
+        Spi::run("SET LOCAL kq.calendar.schema = ''").unwrap();
+        assert!(super::is_source_table("other.calendar"));
+    }
+
+    #[pg_test]
+    fn test_ddl_invalidates_the_cache() {
+        crate::ensure_cache_populated();
+        Spi::run("ALTER TABLE plan.calendar_date ADD COLUMN note text").unwrap();
+        assert!(!cache_filled());
+
+        // swap-table pattern
+        crate::ensure_cache_populated();
+        Spi::run(
+            "CREATE TABLE plan.calendar_date_new (LIKE plan.calendar_date); \
+             INSERT INTO plan.calendar_date_new SELECT * FROM plan.calendar_date",
+        )
+        .unwrap();
+        assert!(cache_filled());
+        Spi::run(
+            "ALTER TABLE plan.calendar_date RENAME TO calendar_date_old; \
+             ALTER TABLE plan.calendar_date_new RENAME TO calendar_date; \
+             DROP TABLE plan.calendar_date_old",
+        )
+        .unwrap();
+        assert!(!cache_filled());
+
+        // the new table got the TRUNCATE trigger
+        crate::ensure_cache_populated();
+        Spi::run("TRUNCATE plan.calendar_date").unwrap();
+        assert!(!cache_filled());
+        crate::clear_cache();
+    }
+}
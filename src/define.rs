@@ -0,0 +1,326 @@
+use std::cell::Cell;
+use std::ffi::c_void;
+use std::time::Instant;
+
+use kq_cx_core::cron::CronSchedule;
+use kq_cx_core::holidays::{self, Jurisdiction};
+use kq_cx_core::{Calendar, MAX_ENTRIES_PER_CALENDAR};
+use pgrx::prelude::*;
+
+use crate::{access, audit, calendar_key, install_calendar, metadata, PgDate, CALENDAR_CONTROL};
+
+/// Calendars created with `kq_cx_define_calendar`. Marked as a configuration table so pg_dump
+/// includes its rows, ids are negative so they never collide with the plan.calendar ids.
+extension_sql!(
+    r#"
+CREATE SEQUENCE @extschema@.kq_cx_calendar_definition_id_seq;
+
+CREATE TABLE @extschema@.kq_cx_calendar_definition (
+    calendar_id int8 NOT NULL
+        DEFAULT -nextval('@extschema@.kq_cx_calendar_definition_id_seq'),
+    namespace text NOT NULL DEFAULT '',
+    xuid text NOT NULL,
+    parent_id int8,
+    dates date[] NOT NULL,
+    CONSTRAINT kq_cx_calendar_definition_pk PRIMARY KEY (calendar_id),
+    CONSTRAINT kq_cx_calendar_definition_xuid UNIQUE (namespace, xuid)
+);
+
+SELECT pg_catalog.pg_extension_config_dump('@extschema@.kq_cx_calendar_definition', '');
+SELECT pg_catalog.pg_extension_config_dump('@extschema@.kq_cx_calendar_definition_id_seq', '');
+"#,
+    name = "create_calendar_definition_table",
+);
+
+thread_local! {
+    /// Deepest transaction nesting level that installed a defined calendar, 0 when none did.
+    static DEFINED_AT_LEVEL: Cell<i32> = const { Cell::new(0) };
+    static CALLBACKS_REGISTERED: Cell<bool> = const { Cell::new(false) };
+}
+
+/// A defined calendar is in shared memory as soon as it is installed, its row only once the
+/// transaction commits: the cache is invalidated when the (sub)transaction that installed it is
+/// rolled back, the next population reads the definitions that were committed.
+fn invalidate_on_abort() {
+    if !CALLBACKS_REGISTERED.replace(true) {
+        unsafe {
+            pg_sys::RegisterXactCallback(Some(xact_callback), std::ptr::null_mut());
+            pg_sys::RegisterSubXactCallback(Some(subxact_callback), std::ptr::null_mut());
+        }
+    }
+    let level = unsafe { pg_sys::GetCurrentTransactionNestLevel() };
+    DEFINED_AT_LEVEL.set(DEFINED_AT_LEVEL.get().max(level));
+}
+
+fn invalidate_rolled_back() {
+    debug1!("calendar definition rolled back, invalidating the cache");
+    crate::clear_cache();
+}
+
+#[pg_guard]
+unsafe extern "C" fn xact_callback(event: pg_sys::XactEvent::Type, _arg: *mut c_void) {
+    match event {
+        pg_sys::XactEvent::XACT_EVENT_ABORT | pg_sys::XactEvent::XACT_EVENT_PARALLEL_ABORT => {
+            if DEFINED_AT_LEVEL.replace(0) > 0 {
+                invalidate_rolled_back();
+            }
+        }
+        pg_sys::XactEvent::XACT_EVENT_COMMIT
+        | pg_sys::XactEvent::XACT_EVENT_PARALLEL_COMMIT
+        | pg_sys::XactEvent::XACT_EVENT_PREPARE => DEFINED_AT_LEVEL.set(0),
+        _ => {}
+    }
+}
+
+/// Runs while the subtransaction ending is still the current one. Its definitions belong to the
+/// parent once it commits, they are undone when it rolls back.
+#[pg_guard]
+unsafe extern "C" fn subxact_callback(
+    event: pg_sys::SubXactEvent::Type,
+    _subxact_id: pg_sys::SubTransactionId,
+    _parent_subxact_id: pg_sys::SubTransactionId,
+    _arg: *mut c_void,
+) {
+    let level = pg_sys::GetCurrentTransactionNestLevel();
+    if DEFINED_AT_LEVEL.get() < level {
+        return;
+    }
+    match event {
+        pg_sys::SubXactEvent::SUBXACT_EVENT_ABORT_SUB => {
+            DEFINED_AT_LEVEL.set(level - 1);
+            invalidate_rolled_back();
+        }
+        pg_sys::SubXactEvent::SUBXACT_EVENT_COMMIT_SUB => DEFINED_AT_LEVEL.set(level - 1),
+        _ => {}
+    }
+}
+
+/// Quoted schema of the extension, `None` when the extension is not created in the current
+/// database (the library can be preloaded cluster-wide).
+pub(crate) fn extension_schema() -> Option<String> {
+    Spi::get_one::<String>(
+        "SELECT quote_ident(n.nspname) \
+         FROM pg_extension e JOIN pg_namespace n ON n.oid = e.extnamespace \
+         WHERE e.extname = 'kq_cx'",
+    )
+    .ok()
+    .flatten()
+}
+
+/// Qualified name of the definition table, see `extension_schema`.
+pub(crate) fn definition_table() -> Option<String> {
+    extension_schema().map(|schema| format!("{schema}.kq_cx_calendar_definition"))
+}
+
+/// Creates or replaces an ad-hoc calendar. The definition is stored in
+/// kq_cx_calendar_definition, so it survives restarts and pg_dump, and is installed in the cache
+/// right away when the cache is already populated (the cache is invalidated if the transaction
+/// is rolled back). Returns the calendar id.
+#[pg_extern]
+fn kq_cx_define_calendar(
+    calendar_xuid: &str,
+    dates: Vec<PgDate>,
+    namespace: default!(&str, "''"),
+    parent_id: default!(Option<i64>, "NULL"),
+) -> i64 {
+    access::ensure_cache_admin("kq_cx_define_calendar");
+    access::ensure_writable("kq_cx_define_calendar");
+    let started = Instant::now();
+
+    let mut entries: Vec<i32> = dates.iter().map(|date| date.to_pg_epoch_days()).collect();
+    entries.sort_unstable();
+    entries.dedup();
+    if entries.len() > MAX_ENTRIES_PER_CALENDAR {
+        error!("calendar xuid = {calendar_xuid} has more than {MAX_ENTRIES_PER_CALENDAR} entries");
+    }
+    let Some(key) = calendar_key(namespace, calendar_xuid) else {
+        error!("calendar xuid = {calendar_xuid} or its namespace is too long")
+    };
+    let mut calendar = Calendar::from_dates(&entries)
+        .unwrap_or_else(|err| error!("cannot create calendar xuid = {calendar_xuid}: {err}"));
+    calendar.set_parent_id(parent_id);
+
+    let table = definition_table().unwrap_or_else(|| error!("extension kq_cx is not installed"));
+    let dates: Vec<PgDate> = entries
+        .iter()
+        .map(|date| unsafe { PgDate::from_pg_epoch_days(*date) })
+        .collect();
+    let calendar_id = Spi::get_one_with_args::<i64>(
+        &format!(
+            "INSERT INTO {table} (namespace, xuid, parent_id, dates) VALUES ($1, $2, $3, $4) \
+             ON CONFLICT (namespace, xuid) DO UPDATE \
+             SET parent_id = EXCLUDED.parent_id, dates = EXCLUDED.dates \
+             RETURNING calendar_id"
+        ),
+        vec![
+            (PgBuiltInOids::TEXTOID.oid(), namespace.into_datum()),
+            (PgBuiltInOids::TEXTOID.oid(), calendar_xuid.into_datum()),
+            (PgBuiltInOids::INT8OID.oid(), parent_id.into_datum()),
+            (PgBuiltInOids::DATEARRAYOID.oid(), dates.into_datum()),
+        ],
+    )
+    .unwrap_or_else(|spi_error| error!("cannot store calendar xuid = {calendar_xuid}. {spi_error}"))
+    .unwrap_or_else(|| error!("cannot get the id of calendar xuid = {calendar_xuid}"));
+
+    // an empty cache picks the definition up on its next population
+    if CALENDAR_CONTROL.share().cache_filled {
+        invalidate_on_abort();
+        install_calendar(calendar_id, key, calendar, calendar_xuid);
+        metadata::record_load(calendar_id, started);
+    }
+
+    audit::record(audit::AuditOperation::Define, started, entries.len());
+    calendar_id
+}
+
+/// Creates or replaces a calendar whose entries are the public holidays of `jurisdiction`
+/// (US_FEDERAL, GB_ENGLAND or DE_NATIONAL) between the two dates, both included. Holidays falling
+/// on a weekend are entered on the day they are observed.
+#[pg_extern]
+fn kq_cx_define_holiday_calendar(
+    calendar_xuid: &str,
+    jurisdiction: &str,
+    from_date: PgDate,
+    to_date: PgDate,
+    namespace: default!(&str, "''"),
+    parent_id: default!(Option<i64>, "NULL"),
+) -> i64 {
+    access::ensure_cache_admin("kq_cx_define_holiday_calendar");
+    access::ensure_writable("kq_cx_define_holiday_calendar");
+    let parsed_jurisdiction = Jurisdiction::parse(jurisdiction).unwrap_or_else(|| {
+        error!(
+            "unknown jurisdiction '{jurisdiction}', expected US_FEDERAL, GB_ENGLAND or DE_NATIONAL"
+        )
+    });
+    let dates = holidays::holidays(
+        parsed_jurisdiction,
+        from_date.to_pg_epoch_days(),
+        to_date.to_pg_epoch_days(),
+    )
+    .into_iter()
+    .map(|(day, _)| unsafe { PgDate::from_pg_epoch_days(day) })
+    .collect();
+    kq_cx_define_calendar(calendar_xuid, dates, namespace, parent_id)
+}
+
+/// Creates or replaces a calendar whose entries are the days a cron schedule runs on between the
+/// two dates, both included. Only the day-of-month, month and day-of-week fields select dates.
+#[pg_extern]
+fn kq_cx_define_calendar_cron(
+    calendar_xuid: &str,
+    cron_expression: &str,
+    from_date: PgDate,
+    to_date: PgDate,
+    namespace: default!(&str, "''"),
+    parent_id: default!(Option<i64>, "NULL"),
+) -> i64 {
+    access::ensure_cache_admin("kq_cx_define_calendar_cron");
+    access::ensure_writable("kq_cx_define_calendar_cron");
+    let schedule = CronSchedule::parse(cron_expression).unwrap_or_else(|err| {
+        ereport!(
+            ERROR,
+            PgSqlErrorCode::ERRCODE_INVALID_PARAMETER_VALUE,
+            format!("invalid cron expression \"{cron_expression}\": {err}"),
+            "Use the five fields minute, hour, day-of-month, month and day-of-week."
+        )
+    });
+    let dates = schedule
+        .dates(from_date.to_pg_epoch_days(), to_date.to_pg_epoch_days())
+        .into_iter()
+        .map(|day| unsafe { PgDate::from_pg_epoch_days(day) })
+        .collect();
+    kq_cx_define_calendar(calendar_xuid, dates, namespace, parent_id)
+}
+
+#[cfg(any(test, feature = "pg_test"))]
+#[pg_schema]
+mod tests {
+    use pgrx::prelude::*;
+
+    #[pg_test]
+    fn test_defined_calendar_is_stored_and_loaded() {
+        let date = |month| pgrx::datum::Date::new(2024, month, 1).unwrap();
+        let calendar_id = super::kq_cx_define_calendar("half", vec![date(7), date(1)], "", None);
+        assert!(calendar_id < 0);
+
+        // the loader repopulates it from the definition table
+        crate::clear_cache();
+        crate::ensure_cache_populated();
+        assert_eq!(
+            crate::kq_cx_add_days_xuid(date(2), 1, "half"),
+            Some(date(7))
+        );
+        crate::clear_cache();
+    }
+
+    #[pg_test]
+    fn test_rolled_back_definition_invalidates_the_cache() {
+        let date = pgrx::datum::Date::new(2024, 1, 1).unwrap();
+        crate::ensure_cache_populated();
+        let (memory_context, resource_owner) =
+            unsafe { (pg_sys::CurrentMemoryContext, pg_sys::CurrentResourceOwner) };
+        unsafe { pg_sys::BeginInternalSubTransaction(std::ptr::null()) };
+        super::kq_cx_define_calendar("rolled_back", vec![date], "", None);
+        assert_eq!(
+            crate::kq_cx_add_days_xuid(date, 0, "rolled_back"),
+            Some(date)
+        );
+        unsafe {
+            pg_sys::RollbackAndReleaseCurrentSubTransaction();
+            pg_sys::MemoryContextSwitchTo(memory_context);
+            pg_sys::CurrentResourceOwner = resource_owner;
+        }
+        assert!(!crate::kq_cx_ready());
+        assert_eq!(crate::find_calendar_id(None, "rolled_back"), None);
+    }
+
+    #[pg_test]
+    fn test_holiday_calendar() {
+        let date = |month, day| pgrx::datum::Date::new(2024, month, day).unwrap();
+        crate::ensure_cache_populated();
+        super::kq_cx_define_holiday_calendar(
+            "us_holidays",
+            "us_federal",
+            date(1, 1),
+            date(12, 31),
+            "",
+            None,
+        );
+        assert_eq!(
+            crate::kq_cx_add_days_xuid(date(7, 1), 1, "us_holidays"),
+            Some(date(7, 4))
+        );
+        assert_eq!(
+            crate::kq_cx_add_days_xuid(date(11, 1), 1, "us_holidays"),
+            Some(date(11, 11))
+        );
+        crate::clear_cache();
+    }
+
+    #[pg_test]
+    fn test_cron_calendar() {
+        let date = |month, day| pgrx::datum::Date::new(2024, month, day).unwrap();
+        crate::ensure_cache_populated();
+        super::kq_cx_define_calendar_cron(
+            "billing",
+            "0 6 1,15 * *",
+            date(1, 1),
+            date(12, 31),
+            "",
+            None,
+        );
+        assert_eq!(
+            crate::kq_cx_add_days_xuid(date(3, 20), 2, "billing"),
+            Some(date(4, 15))
+        );
+        crate::clear_cache();
+    }
+
+    #[pg_test(
+        error = "invalid cron expression \"0 0 1 *\": cron expression must have 5 fields, found 4"
+    )]
+    fn test_cron_calendar_rejects_bad_expressions() {
+        let date = pgrx::datum::Date::new(2024, 1, 1).unwrap();
+        super::kq_cx_define_calendar_cron("billing", "0 0 1 *", date, date, "", None);
+    }
+}
@@ -0,0 +1,113 @@
+use pgrx::prelude::*;
+use serde_json::{Map, Value};
+
+use crate::{get_guc_string_opt, GUC_LOG_FORMAT, LOG_FORMAT};
+
+/// Format of the messages logged by `emit`, kq.calendar.log_format.
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum LogFormat {
+    Text,
+    Json,
+}
+
+/// Unknown values log as text, a typo in the setting must not break the population.
+fn log_format() -> LogFormat {
+    let value = get_guc_string_opt(GUC_LOG_FORMAT, &LOG_FORMAT).unwrap_or_default();
+    match value.trim().to_ascii_lowercase().as_str() {
+        "json" => LogFormat::Json,
+        _ => LogFormat::Text,
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub(crate) enum EventLevel {
+    Debug1,
+    Log,
+}
+
+/// Cache events written to the server log.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub(crate) enum Event {
+    PopulationStarted,
+    PopulationFinished,
+    PopulationFailed,
+    CacheInvalidated,
+}
+
+impl Event {
+    fn as_str(&self) -> &'static str {
+        match self {
+            Event::PopulationStarted => "population_started",
+            Event::PopulationFinished => "population_finished",
+            Event::PopulationFailed => "population_failed",
+            Event::CacheInvalidated => "cache_invalidated",
+        }
+    }
+
+    fn level(&self) -> EventLevel {
+        match self {
+            Event::PopulationStarted => EventLevel::Debug1,
+            _ => EventLevel::Log,
+        }
+    }
+}
+
+/// Single-line JSON object with the fixed `source`, `event`, `pid` and `message` keys followed by
+/// the fields of the event.
+fn json_line(event: Event, message: &str, fields: &[(&str, Value)]) -> String {
+    let mut object = Map::new();
+    object.insert("source".into(), "kq_cx".into());
+    object.insert("event".into(), event.as_str().into());
+    object.insert("pid".into(), unsafe { pg_sys::MyProcPid }.into());
+    object.insert("message".into(), message.into());
+    for (key, value) in fields {
+        object.insert(key.to_string(), value.clone());
+    }
+    Value::Object(object).to_string()
+}
+
+/// Logs the event, as `message` or, with kq.calendar.log_format = json, as a JSON object with
+/// `fields` as well.
+pub(crate) fn emit(event: Event, message: &str, fields: &[(&str, Value)]) {
+    let line = match log_format() {
+        LogFormat::Text => message.to_string(),
+        LogFormat::Json => json_line(event, message, fields),
+    };
+    match event.level() {
+        EventLevel::Debug1 => debug1!("{line}"),
+        EventLevel::Log => log!("{line}"),
+    }
+}
+
+#[cfg(any(test, feature = "pg_test"))]
+#[pg_schema]
+mod tests {
+    use pgrx::prelude::*;
+    use serde_json::Value;
+
+    use super::{Event, LogFormat};
+
+    #[pg_test]
+    fn test_json_events() {
+        assert_eq!(super::log_format(), LogFormat::Text);
+        Spi::run("SET LOCAL kq.calendar.log_format = 'json'").unwrap();
+        assert_eq!(super::log_format(), LogFormat::Json);
+
+        let line = super::json_line(
+            Event::PopulationFinished,
+            "cache ready",
+            &[("calendars", 3.into())],
+        );
+        assert!(!line.contains('\n'));
+        let object: Value = serde_json::from_str(&line).unwrap();
+        assert_eq!(object["source"], "kq_cx");
+        assert_eq!(object["event"], "population_finished");
+        assert_eq!(object["message"], "cache ready");
+        assert_eq!(object["calendars"], 3);
+
+        // the population logs through the same path
+        crate::clear_cache();
+        crate::ensure_cache_populated();
+        crate::clear_cache();
+    }
+}
@@ -0,0 +1,84 @@
+use kq_cx_core::fixture::{self, BUILTIN};
+use pgrx::prelude::*;
+
+use crate::loader::LoadedCalendar;
+use crate::{get_guc_string_opt, image, FIXTURE_FILE, GUC_FIXTURE_FILE, MAX_CALENDARS};
+
+/// Fixture populating the cache instead of the loader queries, kq.calendar.fixture_file: a file
+/// path or `builtin`.
+pub(crate) fn fixture_file() -> Option<String> {
+    get_guc_string_opt(GUC_FIXTURE_FILE, &FIXTURE_FILE)
+}
+
+/// Reads the calendars of a fixture, the bundled dataset when `name` is `builtin`. Relative paths
+/// are relative to the data directory.
+pub(crate) fn read_fixture(name: &str) -> Vec<LoadedCalendar> {
+    let calendars = if name.trim().eq_ignore_ascii_case(BUILTIN) {
+        fixture::builtin()
+    } else {
+        let text = std::fs::read_to_string(name)
+            .unwrap_or_else(|err| error!("cannot read fixture \"{name}\": {err}"));
+        fixture::parse(&text).unwrap_or_else(|err| {
+            ereport!(
+                ERROR,
+                PgSqlErrorCode::ERRCODE_DATA_EXCEPTION,
+                format!("cannot read fixture \"{name}\": {err}"),
+                "Each line is calendar_id,xuid,YYYY-MM-DD."
+            )
+        })
+    };
+    if calendars.len() > MAX_CALENDARS {
+        error!(
+            "fixture \"{name}\" has {} calendars, limit is {MAX_CALENDARS}",
+            calendars.len()
+        );
+    }
+    image::loaded_calendars(calendars)
+}
+
+#[cfg(any(test, feature = "pg_test"))]
+#[pg_schema]
+mod tests {
+    use pgrx::prelude::*;
+
+    #[pg_test]
+    fn test_builtin_fixture() {
+        let calendars = super::read_fixture("Builtin");
+        assert_eq!(calendars.len(), 5);
+        assert_eq!(calendars[2].xuid, "month");
+        assert_eq!(calendars[2].calendar.dates().len(), 240);
+
+        Spi::run("SET LOCAL kq.calendar.fixture_file = 'builtin'").unwrap();
+        crate::clear_cache();
+        let date = |year, month, day| pgrx::datum::Date::new(year, month, day).unwrap();
+        assert_eq!(
+            crate::kq_cx_add_days_xuid(date(2030, 5, 15), 1, "quarter"),
+            Some(date(2030, 7, 1))
+        );
+        assert_eq!(crate::find_calendar_id(None, "month"), Some(3));
+        crate::clear_cache();
+    }
+
+    #[pg_test]
+    fn test_fixture_file() {
+        let path = std::env::temp_dir().join("kq_cx_test_fixture.csv");
+        std::fs::write(
+            &path,
+            "1,month,2024-02-01\n1,month,2024-01-01\n2,year,2024-01-01\n",
+        )
+        .unwrap();
+        let calendars = super::read_fixture(path.to_str().unwrap());
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(calendars.len(), 2);
+        assert_eq!(calendars[0].calendar_id, 1);
+        assert_eq!(calendars[0].calendar.dates().len(), 2);
+        assert_eq!(calendars[1].xuid, "year");
+    }
+
+    #[pg_test(
+        error = "cannot read fixture \"/nonexistent/fixture.csv\": No such file or directory (os error 2)"
+    )]
+    fn test_missing_fixture() {
+        super::read_fixture("/nonexistent/fixture.csv");
+    }
+}
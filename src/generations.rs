@@ -0,0 +1,299 @@
+use std::cell::Cell;
+
+use kq_cx_core::{math, Calendar};
+use pgrx::datum::TimestampWithTimeZone;
+use pgrx::lwlock::PgLwLock;
+use pgrx::prelude::*;
+use pgrx::shmem::*;
+
+use crate::{
+    add_days, add_days_xuid, current_namespace, ensure_cache_populated, oob, resolve_parents,
+    share_calendars, xuids, CalendarXuidIdMap, PgDate, CALENDAR_CONTROL, CALENDAR_ID_MAP,
+    CALENDAR_XUID_ID_MAP, KEPT_GENERATIONS, MAX_CALENDARS,
+};
+
+/// Upper bound of kq.calendar.kept_generations.
+pub(crate) const MAX_KEPT_GENERATIONS: i32 = 4;
+/// Entries of all the calendars of a kept generation, larger generations are not kept.
+const MAX_KEPT_ENTRIES: usize = 64 * 1024;
+/// The current generation and up to `MAX_KEPT_GENERATIONS` previous ones.
+const GENERATION_SLOTS: usize = MAX_KEPT_GENERATIONS as usize + 1;
+
+#[derive(Clone, Copy, Debug, Default)]
+struct KeptCalendar {
+    calendar_id: i64,
+    parent_id: Option<i64>,
+    /// Position of the entries in `GenerationSlot::dates`.
+    start: usize,
+    len: usize,
+}
+
+/// Calendars of one generation. Only the entries are kept, the lookups rebuild the page maps.
+#[derive(Debug, Default)]
+pub struct GenerationSlot {
+    /// 0 when the slot is empty.
+    generation: u64,
+    kept_at: pg_sys::TimestampTz,
+    calendars: heapless::Vec<KeptCalendar, MAX_CALENDARS>,
+    calendar_ids: CalendarXuidIdMap,
+    dates: heapless::Vec<i32, MAX_KEPT_ENTRIES>,
+}
+
+impl GenerationSlot {
+    fn calendar(&self, calendar_id: i64) -> Option<Box<Calendar>> {
+        let kept = self
+            .calendars
+            .iter()
+            .find(|kept| kept.calendar_id == calendar_id)?;
+        let mut calendar =
+            Box::new(Calendar::from_dates(&self.dates[kept.start..kept.start + kept.len]).ok()?);
+        calendar.set_parent_id(kept.parent_id);
+        Some(calendar)
+    }
+}
+
+#[derive(Debug, Default)]
+pub struct GenerationSlots {
+    slots: [GenerationSlot; GENERATION_SLOTS],
+}
+
+unsafe impl PGRXSharedMemory for GenerationSlots {}
+
+/// Only initialized when kq.calendar.kept_generations is above 0, check `enabled()` before
+/// touching it. Never held while taking another lock, it comes after the stripes.
+pub(crate) static CALENDAR_GENERATIONS: PgLwLock<GenerationSlots> = PgLwLock::new();
+
+thread_local! {
+    /// Last generation this backend found kept, see `keep_current`.
+    static KEPT_GENERATION: Cell<u64> = const { Cell::new(0) };
+}
+
+pub(crate) fn enabled() -> bool {
+    KEPT_GENERATIONS.get() > 0
+}
+
+/// Copies the current generation of the cache unless it is already kept, replacing the oldest
+/// one. Called before the lookups, so every generation that answered a lookup is kept.
+pub(crate) fn keep_current() {
+    if !enabled() {
+        return;
+    }
+    if KEPT_GENERATION.get() == CALENDAR_CONTROL.share().generation {
+        return;
+    }
+
+    // same lock order as the population, CALENDAR_ID_MAP first
+    let calendar_id_map = CALENDAR_ID_MAP.share();
+    let calendar_ids = CALENDAR_XUID_ID_MAP.share().clone();
+    let generation = CALENDAR_CONTROL.share().generation;
+    KEPT_GENERATION.set(generation);
+    if calendar_ids.is_empty() {
+        return;
+    }
+    let mut calendars = heapless::Vec::<KeptCalendar, MAX_CALENDARS>::new();
+    let mut dates = vec![];
+    for calendar in share_calendars(&calendar_id_map) {
+        calendars
+            .push(KeptCalendar {
+                calendar_id: calendar.calendar_id,
+                parent_id: calendar.parent_id(),
+                start: dates.len(),
+                len: calendar.dates().len(),
+            })
+            .unwrap();
+        dates.extend_from_slice(calendar.dates());
+    }
+    drop(calendar_id_map);
+    if dates.len() > MAX_KEPT_ENTRIES {
+        warning!(
+            "cache generation {generation} is not kept, its calendars have more than {MAX_KEPT_ENTRIES} entries"
+        );
+        return;
+    }
+    // installing a calendar only locks CALENDAR_ID_MAP in share mode
+    if CALENDAR_CONTROL.share().generation != generation {
+        KEPT_GENERATION.set(0);
+        return;
+    }
+
+    let in_use = KEPT_GENERATIONS.get() as usize + 1;
+    let mut generations = CALENDAR_GENERATIONS.exclusive();
+    let slots = &mut generations.slots[..in_use];
+    if slots.iter().any(|slot| slot.generation == generation) {
+        return;
+    }
+    let slot = slots.iter_mut().min_by_key(|slot| slot.generation).unwrap();
+    slot.generation = generation;
+    slot.kept_at = unsafe { pg_sys::GetCurrentTimestamp() };
+    slot.calendars = calendars;
+    slot.calendar_ids = calendar_ids;
+    slot.dates.clear();
+    slot.dates.extend_from_slice(&dates).unwrap();
+}
+
+fn generation_error(generation: i64) -> ! {
+    ereport!(
+        ERROR,
+        PgSqlErrorCode::ERRCODE_OBJECT_NOT_IN_PREREQUISITE_STATE,
+        format!("cache generation {generation} is not kept"),
+        "See kq_cx_generations() for the kept generations, kq.calendar.kept_generations sets how many are kept."
+    );
+    unreachable!()
+}
+
+/// Runs the lookup on the kept copy of `generation`, `None` when the calendar was not cached in
+/// that generation.
+fn kept_lookup(
+    generation: i64,
+    calendar_id: impl FnOnce(&CalendarXuidIdMap) -> Option<i64>,
+    date: i32,
+    interval: i64,
+) -> Option<PgDate> {
+    if !enabled() {
+        generation_error(generation);
+    }
+    let generations = CALENDAR_GENERATIONS.share();
+    let Some(slot) = generations
+        .slots
+        .iter()
+        .find(|slot| slot.generation != 0 && slot.generation as i64 == generation)
+    else {
+        generation_error(generation)
+    };
+    let calendar_id = calendar_id(&slot.calendar_ids)?;
+    let calendar = resolve_parents(calendar_id, date, |calendar_id| slot.calendar(calendar_id))?;
+    let lookup = math::lookup_calendar_days(&calendar, date, interval);
+    drop(generations);
+    let result_date = oob::lookup_date(calendar_id, &calendar, date, interval, &lookup)?;
+    Some(unsafe { PgDate::from_pg_epoch_days(result_date) })
+}
+
+/// Same as kq_cx_add_days, computed on the cache as it was in `generation`: the current one or
+/// one of the kq.calendar.kept_generations previous ones.
+#[pg_extern(parallel_safe, stable, name = "kq_cx_add_days")]
+fn kq_cx_add_days_generation(
+    input_date: PgDate,
+    interval: i64,
+    calendar_id: i64,
+    generation: i64,
+) -> Option<PgDate> {
+    ensure_cache_populated();
+    if CALENDAR_CONTROL.share().generation as i64 == generation {
+        return add_days(input_date, interval, calendar_id);
+    }
+    kept_lookup(
+        generation,
+        |_| Some(calendar_id),
+        input_date.to_pg_epoch_days(),
+        interval,
+    )
+}
+
+/// Same as kq_cx_add_days_xuid, computed on the cache as it was in `generation`, see
+/// `kq_cx_add_days_generation`.
+#[pg_extern(parallel_safe, stable, name = "kq_cx_add_days_xuid")]
+fn kq_cx_add_days_xuid_generation(
+    input_date: PgDate,
+    interval: i64,
+    calendar_xuid: &str,
+    generation: i64,
+) -> Option<PgDate> {
+    ensure_cache_populated();
+    if CALENDAR_CONTROL.share().generation as i64 == generation {
+        return add_days_xuid(input_date, interval, calendar_xuid);
+    }
+    let namespace = current_namespace();
+    let result_date = kept_lookup(
+        generation,
+        |calendar_ids| xuids::find(calendar_ids, &namespace, calendar_xuid),
+        input_date.to_pg_epoch_days(),
+        interval,
+    );
+    if result_date.is_none() {
+        warning!("calendar_xuid = {calendar_xuid} not found in cache generation {generation}");
+    }
+    result_date
+}
+
+/// Generations that can be passed to the lookup functions, the current one first. `kept_at` is
+/// when the generation answered its first lookup.
+#[pg_extern(parallel_safe)]
+fn kq_cx_generations() -> TableIterator<
+    'static,
+    (
+        name!(generation, i64),
+        name!(current, bool),
+        name!(kept_at, Option<TimestampWithTimeZone>),
+        name!(calendars, i64),
+        name!(entries, i64),
+    ),
+> {
+    if !enabled() {
+        return TableIterator::new(vec![]);
+    }
+    let current_generation = CALENDAR_CONTROL.share().generation;
+    let generations = CALENDAR_GENERATIONS.share();
+    let mut rows: Vec<_> = generations
+        .slots
+        .iter()
+        .filter(|slot| slot.generation != 0)
+        .map(|slot| {
+            (
+                slot.generation as i64,
+                slot.generation == current_generation,
+                unsafe {
+                    TimestampWithTimeZone::from_datum(pg_sys::Datum::from(slot.kept_at), false)
+                },
+                slot.calendars.len() as i64,
+                slot.dates.len() as i64,
+            )
+        })
+        .collect();
+    rows.sort_by_key(|(generation, ..)| -generation);
+    TableIterator::new(rows)
+}
+
+#[cfg(any(test, feature = "pg_test"))]
+#[pg_schema]
+mod tests {
+    use pgrx::prelude::*;
+
+    #[pg_test]
+    fn test_kept_generations() {
+        let date = |month| pgrx::datum::Date::new(2024, month, 1).unwrap();
+        crate::ensure_cache_populated();
+        super::keep_current();
+        let generation = crate::CALENDAR_CONTROL.share().generation as i64;
+        Spi::run("SELECT kq_cx_remove_entries('month', ARRAY['2024-02-01'::date])").unwrap();
+        super::keep_current();
+
+        assert_eq!(
+            crate::kq_cx_add_days_xuid(date(1), 1, "month"),
+            Some(date(3))
+        );
+        assert_eq!(
+            super::kq_cx_add_days_xuid_generation(date(1), 1, "month", generation),
+            Some(date(2))
+        );
+        assert_eq!(
+            super::kq_cx_add_days_generation(date(1), 1, 1, generation),
+            Some(date(2))
+        );
+        let kept: Vec<_> = super::kq_cx_generations()
+            .map(|(generation, current, ..)| (generation, current))
+            .collect();
+        assert!(kept.contains(&(generation, false)));
+        assert!(kept.contains(&(generation + 1, true)));
+        crate::clear_cache();
+    }
+
+    #[pg_test(error = "cache generation 1 is not kept")]
+    fn test_generation_not_kept() {
+        super::kq_cx_add_days_xuid_generation(
+            pgrx::datum::Date::new(2024, 1, 1).unwrap(),
+            1,
+            "month",
+            1,
+        );
+    }
+}
@@ -0,0 +1,207 @@
+use std::collections::HashMap;
+use std::time::Instant;
+
+use kq_cx_core::format::{self, CacheImage, ImageCalendar};
+use kq_cx_core::Calendar;
+use pgrx::prelude::*;
+
+use crate::loader::LoadedCalendar;
+use crate::metadata::CalendarMetadata;
+use crate::{
+    access, clear_calendars, ensure_cache_populated, get_guc_string_opt, install_loaded_calendars,
+    loaded_calendar_keys, share_calendars, xuids, CALENDAR_ID_MAP, CALENDAR_XUID_ID_MAP,
+    GUC_PREWARM_FILE, MAX_CALENDARS, PREWARM_FILE,
+};
+
+/// Copies the cache into a `CacheImage`, in calendar id order.
+fn current_image() -> CacheImage {
+    let keys: HashMap<i64, (String, String)> = CALENDAR_XUID_ID_MAP
+        .share()
+        .iter()
+        .map(|((namespace, xuid), calendar_id)| {
+            let xuid = xuids::cached_xuid("", xuid, *calendar_id);
+            (*calendar_id, (namespace.to_string(), xuid))
+        })
+        .collect();
+    let mut calendars: Vec<ImageCalendar> = share_calendars(&CALENDAR_ID_MAP.share())
+        .map(|calendar| {
+            let (namespace, xuid) = keys.get(&calendar.calendar_id).cloned().unwrap_or_default();
+            ImageCalendar {
+                calendar_id: calendar.calendar_id,
+                namespace,
+                xuid,
+                parent_id: calendar.parent_id(),
+                dates: calendar.dates().to_vec(),
+            }
+        })
+        .collect();
+    calendars.sort_by_key(|calendar| calendar.calendar_id);
+    CacheImage {
+        extension_version: env!("CARGO_PKG_VERSION").to_string(),
+        calendars,
+    }
+}
+
+/// Decodes an image into calendars ready to be installed, erroring out when the image is not
+/// compatible with this build.
+fn decode_image(bytes: &[u8]) -> Vec<LoadedCalendar> {
+    let image = format::decode(bytes).unwrap_or_else(|err| {
+        ereport!(
+            ERROR,
+            PgSqlErrorCode::ERRCODE_DATA_EXCEPTION,
+            format!("cannot load cache image: {err}"),
+            "Create the image again with kq_cx_dump_cache() on a server running this version."
+        )
+    });
+    if image.calendars.len() > MAX_CALENDARS {
+        error!(
+            "cache image has {} calendars, limit is {MAX_CALENDARS}",
+            image.calendars.len()
+        );
+    }
+    debug1!(
+        "cache image written by version {}, {} calendars",
+        image.extension_version,
+        image.calendars.len()
+    );
+    loaded_calendars(image.calendars)
+}
+
+/// Calendars of an image (or of a fixture) ready to be installed.
+pub(crate) fn loaded_calendars(calendars: Vec<ImageCalendar>) -> Vec<LoadedCalendar> {
+    calendars
+        .into_iter()
+        .map(|image_calendar| {
+            if let Err(err) = xuids::stored_key(
+                &image_calendar.namespace,
+                &image_calendar.xuid,
+                xuids::xuid_overflow(),
+            ) {
+                error!(
+                    "cannot read calendar_id = {}: {err}",
+                    image_calendar.calendar_id
+                );
+            }
+            let mut calendar = Box::new(
+                Calendar::from_dates(&image_calendar.dates).unwrap_or_else(|err| {
+                    error!(
+                        "cannot create calendar_id = {}: {err}",
+                        image_calendar.calendar_id
+                    )
+                }),
+            );
+            calendar.set_parent_id(image_calendar.parent_id);
+            LoadedCalendar {
+                calendar_id: image_calendar.calendar_id,
+                namespace: image_calendar.namespace,
+                xuid: image_calendar.xuid,
+                calendar,
+                metadata: CalendarMetadata::default(),
+                page_size: None,
+            }
+        })
+        .collect()
+}
+
+/// File to populate the cache from instead of running the loader queries, only used while the
+/// server is in recovery (a standby).
+pub(crate) fn prewarm_file() -> Option<String> {
+    let path = get_guc_string_opt(GUC_PREWARM_FILE, &PREWARM_FILE)?;
+    unsafe { pg_sys::RecoveryInProgress() }.then_some(path)
+}
+
+pub(crate) fn read_image_file(path: &str) -> Vec<LoadedCalendar> {
+    let bytes = std::fs::read(path)
+        .unwrap_or_else(|err| error!("cannot read cache image \"{path}\": {err}"));
+    decode_image(&bytes)
+}
+
+/// Returns the cache as a versioned binary image, see `kq_cx_load_cache`.
+#[pg_extern(parallel_safe)]
+fn kq_cx_dump_cache() -> Vec<u8> {
+    ensure_cache_populated();
+    format::encode(&current_image())
+}
+
+/// Writes the image returned by `kq_cx_dump_cache` to a file of the server, returns its size.
+#[pg_extern]
+fn kq_cx_dump_cache_file(path: &str) -> i64 {
+    if !unsafe { pg_sys::superuser() } {
+        ereport!(
+            ERROR,
+            PgSqlErrorCode::ERRCODE_INSUFFICIENT_PRIVILEGE,
+            "permission denied for function kq_cx_dump_cache_file",
+            "Only superusers can write files on the server."
+        );
+    }
+    let bytes = kq_cx_dump_cache();
+    std::fs::write(path, &bytes)
+        .unwrap_or_else(|err| error!("cannot write cache image \"{path}\": {err}"));
+    bytes.len() as i64
+}
+
+/// Replaces the cache with the calendars of an image, without running the loader queries.
+/// Returns the number of calendars loaded.
+#[pg_extern]
+fn kq_cx_load_cache(image: &[u8]) -> i64 {
+    access::ensure_cache_admin("kq_cx_load_cache");
+    access::ensure_writable("kq_cx_load_cache");
+    let started = Instant::now();
+    let loaded_calendars = decode_image(image);
+    let calendar_count = loaded_calendars.len() as i64;
+    let calendar_keys = loaded_calendar_keys(&loaded_calendars);
+
+    let mut calendar_id_map = CALENDAR_ID_MAP.exclusive();
+    let mut calendar_name_id_map = CALENDAR_XUID_ID_MAP.exclusive();
+    clear_calendars(&mut calendar_id_map);
+    calendar_name_id_map.clear();
+    install_loaded_calendars(
+        calendar_id_map,
+        calendar_name_id_map,
+        loaded_calendars,
+        calendar_keys,
+        started,
+    );
+    calendar_count
+}
+
+#[cfg(any(test, feature = "pg_test"))]
+#[pg_schema]
+mod tests {
+    use pgrx::prelude::*;
+
+    #[pg_test]
+    fn test_dump_and_load_round_trip() {
+        crate::clear_cache();
+        let image = super::kq_cx_dump_cache();
+        let checksum = crate::get_cache_checksum();
+
+        crate::clear_cache();
+        assert_eq!(super::kq_cx_load_cache(&image), 3);
+        assert_eq!(crate::get_cache_checksum(), checksum);
+    }
+
+    #[pg_test]
+    fn test_failed_load_keeps_the_cache() {
+        crate::ensure_cache_populated();
+        let checksum = crate::get_cache_checksum();
+        let mut image = super::current_image();
+        image.calendars[1].namespace = image.calendars[0].namespace.clone();
+        image.calendars[1].xuid = image.calendars[0].xuid.clone();
+        let image = kq_cx_core::format::encode(&image);
+
+        let failed = PgTryBuilder::new(|| {
+            super::kq_cx_load_cache(&image);
+            false
+        })
+        .catch_others(|_| true)
+        .execute();
+        assert!(failed);
+        assert_eq!(crate::get_cache_checksum(), checksum);
+    }
+
+    #[pg_test(error = "cannot load cache image: not a kq_cx cache image")]
+    fn test_load_rejects_other_data() {
+        super::kq_cx_load_cache(b"not an image");
+    }
+}
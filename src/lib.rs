@@ -1,35 +1,80 @@
-mod math;
-
-use pgrx::lwlock::PgLwLock;
+mod access;
+mod audit;
+mod bench;
+mod budget;
+mod citus;
+mod compare;
+mod ddl;
+mod define;
+mod events;
+mod fixture;
+mod generations;
+mod image;
+mod loader;
+mod locks;
+mod membership;
+mod metadata;
+mod oob;
+mod patch;
+mod pinned;
+mod populate;
+mod profiles;
+mod ranges;
+mod reload;
+mod simulate;
+mod snapshot;
+mod stress;
+mod synthetic;
+mod uncached;
+mod usage;
+mod verify;
+mod xuids;
+
+use events::Event;
+use kq_cx_core::diff::diff_dates;
+use kq_cx_core::{math, names, skip, Calendar, MAX_ENTRIES_PER_CALENDAR};
+use locks::{LockName, Tracked, TrackedLwLock};
+use pgrx::datum::{JsonB, Range, RangeBound};
+use pgrx::pg_sys::panic::{CaughtError, ErrorReport};
 use pgrx::prelude::*;
 use pgrx::shmem::*;
 use pgrx::spi::SpiResult;
-use pgrx::{pg_shmem_init, GucContext, GucFlags, GucRegistry, GucSetting, PgLwLockShareGuard};
+use pgrx::{
+    pg_shmem_init, GucContext, GucFlags, GucRegistry, GucSetting, PgLwLockExclusiveGuard,
+    PgLwLockShareGuard,
+};
+use std::cell::{Cell, RefCell};
+use std::collections::{BTreeMap, HashMap};
 use std::ffi::CStr;
+use std::marker::PhantomData;
 use std::str::FromStr;
-use std::time::Duration;
+use std::time::{Duration, Instant};
 
 pgrx::pg_module_magic!();
 
 const MAX_CALENDARS: usize = 64;
-const MAX_ENTRIES_PER_CALENDAR: usize = 8 * 1024;
-const MAX_PAGES_PER_CALENDAR: usize = 512;
+const CALENDAR_LOCK_STRIPES: usize = 8;
+const CALENDARS_PER_STRIPE: usize = MAX_CALENDARS / CALENDAR_LOCK_STRIPES;
 const CALENDAR_XUID_MAX_LEN: usize = 32;
+const CALENDAR_NAMESPACE_MAX_LEN: usize = 32;
+const POPULATION_ERROR_MAX_LEN: usize = 256;
+/// Backoff after the first failed population, doubled by every consecutive failure.
+const POPULATE_BACKOFF_MIN_MS: i64 = 1000;
+const POPULATE_BACKOFF_MAX_MS: i64 = 300_000;
+
+/// Replaced in the queries by the schema of the calendar tables, see `with_schema`.
+const SCHEMA_PLACEHOLDER: &str = "{schema}.";
 
 const DEF_Q1_VALIDATION_QUERY: &CStr = cr#"
     SELECT
-        COUNT(table_name) = 2
-    FROM
-        information_schema.tables
-    WHERE
-        table_schema = 'plan' AND
-        (table_name = 'calendar' OR table_name = 'calendar_date')
+        to_regclass('{schema}.calendar') IS NOT NULL AND
+        to_regclass('{schema}.calendar_date') IS NOT NULL
     ;"#;
 
-const DEF_Q2_GET_CALENDAR_IDS: &CStr = cr#"SELECT MIN(c.id), MAX(c.id) FROM plan.calendar c"#;
+const DEF_Q2_GET_CALENDAR_IDS: &CStr = cr#"SELECT MIN(c.id), MAX(c.id) FROM {schema}.calendar c"#;
 
 const DEF_Q3_GET_CAL_ENTRY_COUNT: &CStr =
-    cr#"SELECT id, xuid FROM plan.calendar c ORDER BY id ASC;"#;
+    cr#"SELECT id, xuid FROM {schema}.calendar c ORDER BY id ASC;"#;
 
 const DEF_Q4_GET_ENTRIES: &CStr = cr#"
     WITH
@@ -37,12 +82,12 @@ const DEF_Q4_GET_ENTRIES: &CStr = cr#"
             SELECT
                 (date_trunc('year', date) - INTERVAL '10 Years')::date AS min_date,
                 (date_trunc('year', date) + INTERVAL '12 Years')::date AS max_date
-            FROM plan.data_date
+            FROM {schema}.data_date
         )
     SELECT
         calendar_id, "date"
     FROM
-        plan.calendar_date cd
+        {schema}.calendar_date cd
         CROSS JOIN dd
     WHERE
         cd.date >= dd.min_date AND cd.date < dd.max_date
@@ -50,65 +95,257 @@ const DEF_Q4_GET_ENTRIES: &CStr = cr#"
         1, 2
     ;"#;
 
+/// Same rows as `DEF_Q4_GET_ENTRIES` for the calendar ids of the bigint[] $1 only, so reloading
+/// one calendar does not read and sort the entries of all of them.
+const DEF_Q4_GET_ENTRIES_BY_ID: &CStr = cr#"
+    WITH
+        dd AS (
+            SELECT
+                (date_trunc('year', date) - INTERVAL '10 Years')::date AS min_date,
+                (date_trunc('year', date) + INTERVAL '12 Years')::date AS max_date
+            FROM {schema}.data_date
+        )
+    SELECT
+        calendar_id, "date"
+    FROM
+        {schema}.calendar_date cd
+        CROSS JOIN dd
+    WHERE
+        cd.calendar_id = ANY($1) AND cd.date >= dd.min_date AND cd.date < dd.max_date
+    ORDER BY
+        1, 2
+    ;"#;
+
 // Types
 
 type GucStrSetting = GucSetting<Option<&'static CStr>>;
-type EntriesVec = heapless::Vec<i32, MAX_ENTRIES_PER_CALENDAR>;
-type PageMapVec = heapless::Vec<usize, MAX_PAGES_PER_CALENDAR>;
-type CalendarIdMap = heapless::FnvIndexMap<i64, Calendar, MAX_CALENDARS>;
-type CalendarXuidIdMap = heapless::FnvIndexMap<CalendarXuid, i64, MAX_CALENDARS>;
+/// Stripe of every cached calendar, see `CALENDAR_STRIPES`.
+type CalendarIdMap = heapless::FnvIndexMap<i64, usize, MAX_CALENDARS>;
+type CalendarStripe = heapless::FnvIndexMap<i64, Calendar, CALENDARS_PER_STRIPE>;
+type CalendarXuidIdMap = heapless::FnvIndexMap<CalendarKey, i64, MAX_CALENDARS>;
 type CalendarXuid = heapless::String<CALENDAR_XUID_MAX_LEN>;
+type CalendarNamespace = heapless::String<CALENDAR_NAMESPACE_MAX_LEN>;
+/// Calendars are unique per (namespace, xuid), calendars without a namespace use "".
+type CalendarKey = (CalendarNamespace, CalendarXuid);
+type PopulationError = heapless::String<POPULATION_ERROR_MAX_LEN>;
 type PgDate = pgrx::datum::Date;
 type CalendarInfo = (
-    i64,    // CalendarID
-    String, // Calendar Name
-    i64,    // Calendar Entries
-    i32,    // Calendar Page Size
-    i64,
-); // Calendar PageMap Entries
+    i64,                           // CalendarID
+    String,                        // Calendar Name
+    i64,                           // Calendar Entries
+    i32,                           // Calendar Page Size
+    i64,                           // Calendar PageMap Entries
+    String,                        // Calendar Checksum
+    Option<String>,                // Calendar Display Name
+    Option<String>,                // Calendar Type
+    Option<String>,                // Calendar Timezone
+    i64,                           // Calendar Lookups
+    Option<TimestampWithTimeZone>, // Calendar Last Used
+    Option<PgDate>,                // Calendar First Entry
+    Option<PgDate>,                // Calendar Last Entry
+    i64,                           // Calendar Bytes Used
+    Option<TimestampWithTimeZone>, // Calendar Loaded At
+    Option<f64>,                   // Calendar Load Duration (ms)
+    bool,                          // Calendar Skip Tables Built
+);
 
 // GUC Queries
 
+const GUC_Q1_VALIDATION_QUERY: &str = "kq.calendar.q_schema_validation";
+const GUC_Q2_GET_CALENDAR_IDS: &str = "kq.calendar.q1_get_calendar_min_max_id";
+const GUC_Q3_GET_CAL_ENTRY_COUNT: &str = "kq.calendar.q2_get_calendars_entry_count";
+const GUC_Q4_GET_ENTRIES: &str = "kq.calendar.q3_get_calendar_entries";
+const GUC_Q5_GET_EXCEPTIONS: &str = "kq.calendar.q4_get_calendar_exceptions";
+const GUC_Q4_GET_ENTRIES_BY_ID: &str = "kq.calendar.q3_get_calendar_entries_by_id";
+
 static Q1_VALIDATION_QUERY: GucStrSetting = GucStrSetting::new(Some(DEF_Q1_VALIDATION_QUERY));
 static Q2_GET_CALENDAR_IDS: GucStrSetting = GucStrSetting::new(Some(DEF_Q2_GET_CALENDAR_IDS));
 static Q3_GET_CAL_ENTRY_COUNT: GucStrSetting = GucStrSetting::new(Some(DEF_Q3_GET_CAL_ENTRY_COUNT));
 static Q4_GET_ENTRIES: GucStrSetting = GucStrSetting::new(Some(DEF_Q4_GET_ENTRIES));
+static Q5_GET_EXCEPTIONS: GucStrSetting = GucStrSetting::new(None);
+static Q4_GET_ENTRIES_BY_ID: GucStrSetting = GucStrSetting::new(None);
+
+// GUC Settings
+
+const GUC_AUDIT_TABLE: &str = "kq.calendar.audit_table";
+const GUC_TENANT: &str = "kq.calendar.tenant";
+const GUC_PREWARM_FILE: &str = "kq.calendar.prewarm_file";
+const GUC_OOB_PAST_DATE: &str = "kq.calendar.oob_past_date";
+const GUC_OOB_FUTURE_DATE: &str = "kq.calendar.oob_future_date";
+const GUC_VALIDATION: &str = "kq.calendar.validation";
+const GUC_SCHEMA: &str = "kq.calendar.schema";
+const GUC_PROFILE: &str = "kq.calendar.profile";
+const GUC_LOG_FORMAT: &str = "kq.calendar.log_format";
+const GUC_SKIP_INTERVALS: &str = "kq.calendar.skip_intervals";
+const GUC_POPULATE_ON: &str = "kq.calendar.populate_on";
+const GUC_XUID_OVERFLOW: &str = "kq.calendar.xuid_overflow";
+const GUC_XUID_NORMALIZATION: &str = "kq.calendar.xuid_normalization";
+const GUC_MAX_SHARED_MEMORY: &str = "kq.calendar.max_shared_memory";
+const GUC_EMPTY_CALENDAR: &str = "kq.calendar.empty_calendar";
+const GUC_FIXTURE_FILE: &str = "kq.calendar.fixture_file";
+
+static ENFORCE_ADMIN_ROLE: GucSetting<bool> = GucSetting::<bool>::new(true);
+static READ_ONLY: GucSetting<bool> = GucSetting::<bool>::new(false);
+static AUDIT_TABLE: GucStrSetting = GucStrSetting::new(None);
+static TENANT: GucStrSetting = GucStrSetting::new(None);
+static BACKGROUND_RELOAD: GucSetting<bool> = GucSetting::<bool>::new(false);
+static PREWARM_FILE: GucStrSetting = GucStrSetting::new(None);
+static MEMBERSHIP_BITMAPS: GucSetting<bool> = GucSetting::<bool>::new(false);
+static ALLOW_STALE_READS: GucSetting<bool> = GucSetting::<bool>::new(false);
+/// Milliseconds, 0 waits until the population is done.
+static POPULATE_WAIT_TIMEOUT: GucSetting<i32> = GucSetting::<i32>::new(0);
+static UNCACHED_FALLBACK: GucSetting<bool> = GucSetting::<bool>::new(false);
+static OOB_PAST_DATE: GucStrSetting = GucStrSetting::new(Some(c"1970-01-01"));
+static OOB_FUTURE_DATE: GucStrSetting = GucStrSetting::new(Some(c"2199-01-01"));
+static STRICT_BOUNDS: GucSetting<bool> = GucSetting::<bool>::new(false);
+static TRACK_LOCK_TIMES: GucSetting<bool> = GucSetting::<bool>::new(false);
+static TRACK_LOOKUP_TIMES: GucSetting<bool> = GucSetting::<bool>::new(false);
+static INVALIDATE_ON_DDL: GucSetting<bool> = GucSetting::<bool>::new(true);
+static LOG_MIN_DURATION: GucSetting<i32> = GucSetting::<i32>::new(-1);
+static KEPT_GENERATIONS: GucSetting<i32> = GucSetting::<i32>::new(0);
+/// kB, 0 is no limit.
+static MAX_SHARED_MEMORY: GucSetting<i32> = GucSetting::<i32>::new(0);
+static VALIDATION: GucStrSetting = GucStrSetting::new(Some(c"error"));
+static SCHEMA: GucStrSetting = GucStrSetting::new(Some(c"plan"));
+static PROFILE: GucStrSetting = GucStrSetting::new(None);
+static LOG_FORMAT: GucStrSetting = GucStrSetting::new(Some(c"text"));
+static SKIP_INTERVALS: GucStrSetting = GucStrSetting::new(None);
+static POPULATE_ON: GucStrSetting = GucStrSetting::new(Some(c"first_use"));
+static XUID_OVERFLOW: GucStrSetting = GucStrSetting::new(Some(c"error"));
+static XUID_NORMALIZATION: GucStrSetting = GucStrSetting::new(Some(c"none"));
+static EMPTY_CALENDAR: GucStrSetting = GucStrSetting::new(Some(c"passthrough"));
+static FIXTURE_FILE: GucStrSetting = GucStrSetting::new(None);
 
 // Structs
 
-#[derive(Default, Clone, Debug)]
-pub struct Calendar {
-    dates: EntriesVec,
-    page_size: i32,
-    first_page_offset: i32,
-    page_map: PageMapVec,
+/// Step of the cache population, recorded with the error when it fails.
+#[derive(Default, Clone, Copy, Debug, PartialEq)]
+pub(crate) enum PopulationPhase {
+    #[default]
+    Validate,
+    CheckCapacity,
+    Load,
+    ReadImage,
+    ReadFixture,
+    Install,
+}
+
+impl PopulationPhase {
+    fn as_str(&self) -> &'static str {
+        match self {
+            PopulationPhase::Validate => "validate",
+            PopulationPhase::CheckCapacity => "check capacity",
+            PopulationPhase::Load => "load",
+            PopulationPhase::ReadImage => "read image",
+            PopulationPhase::ReadFixture => "read fixture",
+            PopulationPhase::Install => "install",
+        }
+    }
+}
+
+/// Identifies the shared memory of this extension, "KQCX".
+const SHMEM_LAYOUT_MAGIC: u32 = 0x4b51_4358;
+/// Bump when a change to the shared structures keeps their size, PostgreSQL already refuses to
+/// attach to a shared struct whose size changed.
+const SHMEM_LAYOUT_VERSION: u32 = 1;
+
+/// Stamp of the binary that initialized the shared memory, first in `CalendarControl` so it is
+/// read at the same offset by every version.
+#[repr(C)]
+#[derive(Clone, Debug)]
+pub struct ShmemLayout {
+    magic: u32,
+    version: u32,
 }
 
-unsafe impl PGRXSharedMemory for Calendar {}
+impl Default for ShmemLayout {
+    fn default() -> Self {
+        ShmemLayout {
+            magic: SHMEM_LAYOUT_MAGIC,
+            version: SHMEM_LAYOUT_VERSION,
+        }
+    }
+}
 
+#[repr(C)]
 #[derive(Default, Clone, Debug)]
 pub struct CalendarControl {
+    layout: ShmemLayout,
     calendar_count: usize,
     entry_count: usize,
 
     cache_filled: bool,
     cache_being_filled: bool,
+    /// A population worker was started by a connecting backend, see `populate::install_hook`.
+    population_requested: bool,
+    /// Invalidated, a background worker is loading the replacement.
+    stale: bool,
+    /// The last population failed the capacity check, cleared by the next successful one.
+    capacity_exceeded: bool,
+    /// Consecutive failed populations, see `check_population_backoff`. Reset by a successful
+    /// population or by `clear_cache`.
+    population_failures: u32,
+    population_retry_at: pg_sys::TimestampTz,
+    /// Message of the last failed population, truncated to `POPULATION_ERROR_MAX_LEN` bytes.
+    population_error: PopulationError,
+    population_error_at: pg_sys::TimestampTz,
+    population_error_phase: PopulationPhase,
+    /// End of the last successful population, 0 before the first one.
+    populated_at: pg_sys::TimestampTz,
+    /// Changes every time the contents of the cache change, survives `clear_cache`. See
+    /// `next_generation`.
+    generation: u64,
 }
 
 unsafe impl PGRXSharedMemory for CalendarControl {}
 
 // Shared Objects
 
-static CALENDAR_ID_MAP: PgLwLock<CalendarIdMap> = PgLwLock::new();
-static CALENDAR_XUID_ID_MAP: PgLwLock<CalendarXuidIdMap> = PgLwLock::new();
-static CALENDAR_CONTROL: PgLwLock<CalendarControl> = PgLwLock::new();
+/// The calendars are spread over `CALENDAR_STRIPES`, each one behind its own lock, so replacing
+/// a calendar only blocks the lookups of the calendars in the same stripe. CALENDAR_ID_MAP is held
+/// in share mode while a stripe is locked, and in exclusive mode to add or remove calendars.
+static CALENDAR_ID_MAP: TrackedLwLock<CalendarIdMap> = TrackedLwLock::new(LockName::CalendarIdMap);
+static CALENDAR_STRIPES: [TrackedLwLock<CalendarStripe>; CALENDAR_LOCK_STRIPES] =
+    [const { TrackedLwLock::new(LockName::CalendarStripe) }; CALENDAR_LOCK_STRIPES];
+static CALENDAR_XUID_ID_MAP: TrackedLwLock<CalendarXuidIdMap> =
+    TrackedLwLock::new(LockName::CalendarXuidIdMap);
+static CALENDAR_CONTROL: TrackedLwLock<CalendarControl> =
+    TrackedLwLock::new(LockName::CalendarControl);
 
 #[pg_guard]
 pub extern "C" fn _PG_init() {
-    pg_shmem_init!(CALENDAR_ID_MAP);
-    pg_shmem_init!(CALENDAR_XUID_ID_MAP);
-    pg_shmem_init!(CALENDAR_CONTROL);
     init_gucs();
+    if unsafe { pg_sys::process_shared_preload_libraries_in_progress } {
+        budget::check_budget();
+    }
+    pg_shmem_init!(CALENDAR_ID_MAP.lock);
+    // pg_shmem_init! installs a hook per lock, it cannot be called in a loop
+    pg_shmem_init!(CALENDAR_STRIPES[0].lock);
+    pg_shmem_init!(CALENDAR_STRIPES[1].lock);
+    pg_shmem_init!(CALENDAR_STRIPES[2].lock);
+    pg_shmem_init!(CALENDAR_STRIPES[3].lock);
+    pg_shmem_init!(CALENDAR_STRIPES[4].lock);
+    pg_shmem_init!(CALENDAR_STRIPES[5].lock);
+    pg_shmem_init!(CALENDAR_STRIPES[6].lock);
+    pg_shmem_init!(CALENDAR_STRIPES[7].lock);
+    pg_shmem_init!(CALENDAR_XUID_ID_MAP.lock);
+    pg_shmem_init!(CALENDAR_CONTROL.lock);
+    pg_shmem_init!(audit::AUDIT_LOG);
+    pg_shmem_init!(metadata::CALENDAR_METADATA);
+    pg_shmem_init!(metadata::CALENDAR_LOADS);
+    pg_shmem_init!(usage::CALENDAR_USAGE);
+    pg_shmem_init!(locks::LOCK_STATS);
+    pg_shmem_init!(xuids::CALENDAR_LONG_XUIDS);
+    pg_shmem_init!(stress::STRESS_RUN);
+    if membership::enabled() {
+        pg_shmem_init!(membership::CALENDAR_BITMAPS);
+    }
+    if generations::enabled() {
+        pg_shmem_init!(generations::CALENDAR_GENERATIONS);
+    }
+    if unsafe { pg_sys::process_shared_preload_libraries_in_progress } {
+        populate::install_hook();
+    }
 
     info!("ketteQ Calendar Extension (kq_cx) Loaded");
 }
@@ -120,7 +357,7 @@ pub extern "C" fn _PG_fini() {
 
 fn init_gucs() {
     GucRegistry::define_string_guc(
-        "kq.calendar.q_schema_validation",
+        GUC_Q1_VALIDATION_QUERY,
         "Query to validate the existence of the required schemas.",
         "",
         &Q1_VALIDATION_QUERY,
@@ -128,7 +365,7 @@ fn init_gucs() {
         GucFlags::empty(),
     );
     GucRegistry::define_string_guc(
-        "kq.calendar.q1_get_calendar_min_max_id",
+        GUC_Q2_GET_CALENDAR_IDS,
         "Query to select the MIN and MAX calendars.",
         "",
         &Q2_GET_CALENDAR_IDS,
@@ -136,7 +373,7 @@ fn init_gucs() {
         GucFlags::empty(),
     );
     GucRegistry::define_string_guc(
-        "kq.calendar.q2_get_calendars_entry_count",
+        GUC_Q3_GET_CAL_ENTRY_COUNT,
         "Query to select the entry count for each calendar.",
         "",
         &Q3_GET_CAL_ENTRY_COUNT,
@@ -144,21 +381,376 @@ fn init_gucs() {
         GucFlags::empty(),
     );
     GucRegistry::define_string_guc(
-        "kq.calendar.q3_get_calendar_entries",
+        GUC_Q4_GET_ENTRIES,
         "Query to actually get the currencies and store it in the shared memory cache.",
         "",
         &Q4_GET_ENTRIES,
         GucContext::Suset,
         GucFlags::empty(),
     );
+    GucRegistry::define_string_guc(
+        GUC_Q4_GET_ENTRIES_BY_ID,
+        "Same as kq.calendar.q3_get_calendar_entries for the calendar ids of the bigint[] $1 only.",
+        "Used to load some calendars only. When not set, the built-in query is used with the default entries query and the entries query is filtered otherwise.",
+        &Q4_GET_ENTRIES_BY_ID,
+        GucContext::Suset,
+        GucFlags::empty(),
+    );
+    GucRegistry::define_string_guc(
+        GUC_Q5_GET_EXCEPTIONS,
+        "Optional query returning (calendar_id, date, 'add' or 'remove') rows merged into the entries.",
+        "Applied on top of the entries of Q3 every time the cache is populated.",
+        &Q5_GET_EXCEPTIONS,
+        GucContext::Suset,
+        GucFlags::empty(),
+    );
+    GucRegistry::define_bool_guc(
+        "kq.calendar.enforce_admin_role",
+        "Restrict the cache-mutating functions to superusers and members of kq_cx_admin.",
+        "",
+        &ENFORCE_ADMIN_ROLE,
+        GucContext::Suset,
+        GucFlags::empty(),
+    );
+    GucRegistry::define_bool_guc(
+        "kq.calendar.read_only",
+        "Reject every function that modifies the calendar cache.",
+        "Intended for freeze windows, lookups and the initial population keep working.",
+        &READ_ONLY,
+        GucContext::Suset,
+        GucFlags::empty(),
+    );
+    GucRegistry::define_string_guc(
+        GUC_AUDIT_TABLE,
+        "Table that receives a copy of every audit log entry.",
+        "The table needs the columns logged_at, operation, user_name, pid, duration_ms and entries. Entries are inserted as the owner of the table, failed inserts are reported as warnings.",
+        &AUDIT_TABLE,
+        GucContext::Suset,
+        GucFlags::empty(),
+    );
+    GucRegistry::define_bool_guc(
+        "kq.calendar.background_reload",
+        "Reload the cache in a background worker after kq_cx_invalidate_cache().",
+        "Lookups keep using the previous cache until the reloaded one is swapped in.",
+        &BACKGROUND_RELOAD,
+        GucContext::Suset,
+        GucFlags::empty(),
+    );
+    GucRegistry::define_string_guc(
+        GUC_PREWARM_FILE,
+        "Cache image used to populate the cache while the server is in recovery.",
+        "Created with kq_cx_dump_cache_file() on the primary, standbys then never run the loader queries.",
+        &PREWARM_FILE,
+        GucContext::Sighup,
+        GucFlags::empty(),
+    );
+    GucRegistry::define_string_guc(
+        GUC_FIXTURE_FILE,
+        "Fixture populating the cache instead of the loader queries: a file of calendar_id,xuid,date lines or builtin.",
+        "For test suites, builtin is a bundled dataset of day, week, month, quarter and year calendars from 2020 to 2039.",
+        &FIXTURE_FILE,
+        GucContext::Suset,
+        GucFlags::empty(),
+    );
+    GucRegistry::define_bool_guc(
+        "kq.calendar.membership_bitmaps",
+        "Keep a one bit per day bitmap of every calendar for kq_cx_is_entry().",
+        "Reserves the shared memory of the bitmaps at server start.",
+        &MEMBERSHIP_BITMAPS,
+        GucContext::Postmaster,
+        GucFlags::empty(),
+    );
+    GucRegistry::define_bool_guc(
+        "kq.calendar.allow_stale_reads",
+        "Answer lookups from a copy of the previous cache while another session populates it.",
+        "Only calendars already used by the session are copied, other lookups wait as usual.",
+        &ALLOW_STALE_READS,
+        GucContext::Userset,
+        GucFlags::empty(),
+    );
+    GucRegistry::define_int_guc(
+        "kq.calendar.populate_wait_timeout",
+        "Maximum time to wait for the cache population of another session.",
+        "The lookup fails with SQLSTATE 55P03 when the population takes longer. Zero waits indefinitely.",
+        &POPULATE_WAIT_TIMEOUT,
+        0,
+        i32::MAX,
+        GucContext::Userset,
+        GucFlags::UNIT_MS,
+    );
+    GucRegistry::define_bool_guc(
+        "kq.calendar.uncached_fallback",
+        "Compute lookups from plan.calendar_date while the cache is being populated or over capacity.",
+        "Exceptions, defined calendars and parent calendars are not applied to these lookups.",
+        &UNCACHED_FALLBACK,
+        GucContext::Userset,
+        GucFlags::empty(),
+    );
+    GucRegistry::define_string_guc(
+        GUC_TENANT,
+        "Namespace used by the xuid functions when none is given.",
+        "Matches the optional namespace column of the calendar list query.",
+        &TENANT,
+        GucContext::Userset,
+        GucFlags::empty(),
+    );
+    GucRegistry::define_string_guc(
+        GUC_OOB_PAST_DATE,
+        "Date returned by lookups before the first entry of a calendar.",
+        "YYYY-MM-DD, infinity or -infinity. Empty restores the default, 1970-01-01.",
+        &OOB_PAST_DATE,
+        GucContext::Suset,
+        GucFlags::empty(),
+    );
+    GucRegistry::define_string_guc(
+        GUC_OOB_FUTURE_DATE,
+        "Date returned by lookups after the last entry of a calendar.",
+        "YYYY-MM-DD, infinity or -infinity. Empty restores the default, 2199-01-01.",
+        &OOB_FUTURE_DATE,
+        GucContext::Suset,
+        GucFlags::empty(),
+    );
+    GucRegistry::define_bool_guc(
+        "kq.calendar.strict_bounds",
+        "Raise an error instead of returning the out-of-bounds dates.",
+        "Lookups whose input date or result is outside of the cached entries of the calendar fail.",
+        &STRICT_BOUNDS,
+        GucContext::Userset,
+        GucFlags::empty(),
+    );
+    GucRegistry::define_bool_guc(
+        "kq.calendar.track_lock_times",
+        "Collect the wait and hold times of the cache locks.",
+        "Reported by kq_cx_stats(). Reads the clock twice per lock acquisition.",
+        &TRACK_LOCK_TIMES,
+        GucContext::Suset,
+        GucFlags::empty(),
+    );
+    GucRegistry::define_bool_guc(
+        "kq.calendar.invalidate_on_ddl",
+        "Invalidate the cache when a source table is altered, recreated, dropped or truncated.",
+        "The source tables are calendar and calendar_date in kq.calendar.schema.",
+        &INVALIDATE_ON_DDL,
+        GucContext::Suset,
+        GucFlags::empty(),
+    );
+    GucRegistry::define_bool_guc(
+        "kq.calendar.track_lookup_times",
+        "Collect the average lookup time of every calendar.",
+        "Reported by kq_cx_top_calendars(). Reads the clock twice per lookup.",
+        &TRACK_LOOKUP_TIMES,
+        GucContext::Suset,
+        GucFlags::empty(),
+    );
+    GucRegistry::define_int_guc(
+        "kq.calendar.log_min_duration",
+        "Logs the lookups taking at least this many microseconds.",
+        "The arguments and the duration are logged at WARNING. Zero logs every lookup, -1 disables the logging.",
+        &LOG_MIN_DURATION,
+        -1,
+        i32::MAX,
+        GucContext::Suset,
+        GucFlags::empty(),
+    );
+    GucRegistry::define_string_guc(
+        GUC_SCHEMA,
+        "Schema of the calendar tables, replaces {schema} in the queries.",
+        "When empty the tables are resolved through the search_path.",
+        &SCHEMA,
+        GucContext::Suset,
+        GucFlags::empty(),
+    );
+    GucRegistry::define_string_guc(
+        GUC_LOG_FORMAT,
+        "Format of the population and invalidation messages of the server log: text or json.",
+        "With json every message is a single-line JSON object with fixed keys.",
+        &LOG_FORMAT,
+        GucContext::Suset,
+        GucFlags::empty(),
+    );
+    GucRegistry::define_string_guc(
+        GUC_SKIP_INTERVALS,
+        "Comma separated intervals whose lookups are precomputed at population, e.g. 1,-1,5,-5.",
+        "Each interval takes a slot per day of the calendar, calendars spanning too many days are searched as usual.",
+        &SKIP_INTERVALS,
+        GucContext::Suset,
+        GucFlags::empty(),
+    );
+    GucRegistry::define_string_guc(
+        GUC_POPULATE_ON,
+        "When the empty cache is populated: first_use, connect or manual.",
+        "connect populates it in a background worker when a backend connects, manual only with kq_cx_populate_cache().",
+        &POPULATE_ON,
+        GucContext::Suset,
+        GucFlags::empty(),
+    );
+    GucRegistry::define_string_guc(
+        GUC_XUID_OVERFLOW,
+        "What the population does with xuids longer than the cache keys: error, truncate or hash.",
+        "truncate and hash store the calendar under a key derived from the xuid, lookups still use the full xuid.",
+        &XUID_OVERFLOW,
+        GucContext::Suset,
+        GucFlags::empty(),
+    );
+    GucRegistry::define_string_guc(
+        GUC_XUID_NORMALIZATION,
+        "How xuids are normalized before they are cached or looked up: none, nfc or nfc_casefold.",
+        "Set for the whole server so the population and the lookups agree, invalidate the cache after changing it.",
+        &XUID_NORMALIZATION,
+        GucContext::Sighup,
+        GucFlags::empty(),
+    );
+    GucRegistry::define_string_guc(
+        GUC_EMPTY_CALENDAR,
+        "What lookups on a calendar without entries return: passthrough, null or error.",
+        "passthrough returns input_date + interval, kq_cx_health() lists the empty calendars.",
+        &EMPTY_CALENDAR,
+        GucContext::Userset,
+        GucFlags::empty(),
+    );
+    GucRegistry::define_string_guc(
+        GUC_PROFILE,
+        "Loader profile of kq_cx_loader_profile overriding the schema and query settings.",
+        "The settings a profile leaves NULL keep their value, when empty no profile is used.",
+        &PROFILE,
+        GucContext::Suset,
+        GucFlags::empty(),
+    );
+    GucRegistry::define_string_guc(
+        GUC_VALIDATION,
+        "What to do when the schema validation query fails: error, warn or skip.",
+        "With warn the cache is populated empty with a warning, skip does not run the validation query.",
+        &VALIDATION,
+        GucContext::Suset,
+        GucFlags::empty(),
+    );
+    GucRegistry::define_int_guc(
+        "kq.calendar.kept_generations",
+        "Number of previous cache generations kept for the lookups with a generation argument.",
+        "Reserves the shared memory of the kept generations at server start.",
+        &KEPT_GENERATIONS,
+        0,
+        generations::MAX_KEPT_GENERATIONS,
+        GucContext::Postmaster,
+        GucFlags::empty(),
+    );
+    GucRegistry::define_int_guc(
+        GUC_MAX_SHARED_MEMORY,
+        "Shared memory the extension may reserve at server start, zero for no limit.",
+        "Over the limit the kept generations and the membership bitmaps are turned off, then the server does not start.",
+        &MAX_SHARED_MEMORY,
+        0,
+        i32::MAX,
+        GucContext::Postmaster,
+        GucFlags::UNIT_KB,
+    );
+}
+
+thread_local! {
+    /// Processed query strings keyed by GUC name, along with the raw value they were built from.
+    /// `GucRegistry` does not expose assign hooks, so a cached entry is invalidated when the raw
+    /// value no longer matches the current setting.
+    static GUC_STRING_CACHE: RefCell<HashMap<&'static str, (Vec<u8>, String)>> =
+        RefCell::new(HashMap::new());
+
+    /// Last lookup per calendar id, for the cache generation they were made on.
+    static LOOKUP_MEMO: RefCell<(u64, HashMap<i64, Option<math::LookupMemo>>)> =
+        RefCell::new((0, HashMap::new()));
+
+    /// Last entry found per calendar id by kq_cx_add_days_ordered, for the cache generation it
+    /// was found on.
+    static ORDERED_CURSORS: RefCell<(u64, HashMap<i64, Option<math::OrderedCursor>>)> =
+        RefCell::new((0, HashMap::new()));
+
+    /// Step the population of this backend is running, see `record_population_failure`.
+    static POPULATION_PHASE: Cell<PopulationPhase> = Cell::new(PopulationPhase::default());
+
+    /// Set once `check_shmem_layout` found the shared memory stamped by this binary.
+    static SHMEM_LAYOUT_CHECKED: Cell<bool> = const { Cell::new(false) };
+}
+
+/// True when the shared memory was stamped by this binary, see `check_shmem_layout`.
+fn shmem_layout_matches() -> bool {
+    if SHMEM_LAYOUT_CHECKED.get() {
+        return true;
+    }
+    let layout = CALENDAR_CONTROL.share().layout.clone();
+    let matches = layout.magic == SHMEM_LAYOUT_MAGIC && layout.version == SHMEM_LAYOUT_VERSION;
+    SHMEM_LAYOUT_CHECKED.set(matches);
+    matches
+}
+
+/// Refuses to use shared memory initialized by another build of the extension, e.g. when a new
+/// library was installed without restarting the server and is loaded next to the old postmaster.
+fn check_shmem_layout() {
+    if shmem_layout_matches() {
+        return;
+    }
+    let layout = CALENDAR_CONTROL.share().layout.clone();
+    let detail = match layout.magic {
+        SHMEM_LAYOUT_MAGIC => format!(
+            "The shared memory has layout version {}, this library expects version {SHMEM_LAYOUT_VERSION}.",
+            layout.version
+        ),
+        _ => format!(
+            "The shared memory has no layout stamp, this library expects version {SHMEM_LAYOUT_VERSION}."
+        ),
+    };
+    ErrorReport::new(
+        PgSqlErrorCode::ERRCODE_OBJECT_NOT_IN_PREREQUISITE_STATE,
+        "the calendar cache shared memory was initialized by another version of kq_cx",
+        pgrx::function_name!(),
+    )
+    .set_detail(detail)
+    .set_hint("Restart the server to load the installed version.")
+    .report(PgLogLevel::ERROR);
+}
+
+fn get_guc_string(name: &'static str, guc: &GucStrSetting) -> String {
+    get_guc_string_opt(name, guc)
+        .unwrap_or_else(|| error!("{name} is not set, use RESET {name} to restore the default"))
+}
+
+/// Replaces `{schema}.` in the query with the quoted kq.calendar.schema, or removes it when the
+/// setting is empty so the tables are resolved through the search_path.
+fn with_schema(query: &str) -> String {
+    if !query.contains(SCHEMA_PLACEHOLDER) {
+        return query.to_string();
+    }
+    let schema = profiles::setting(GUC_SCHEMA).or_else(|| get_guc_string_opt(GUC_SCHEMA, &SCHEMA));
+    let prefix = match schema.filter(|schema| !schema.is_empty()) {
+        Some(schema) => format!("{}.", pgrx::spi::quote_identifier(schema.trim())),
+        None => String::new(),
+    };
+    query.replace(SCHEMA_PLACEHOLDER, &prefix)
+}
+
+/// Query of a query setting, see `with_schema`.
+/// The kq.calendar.profile profile takes precedence over the setting.
+fn get_query(name: &'static str, guc: &GucStrSetting) -> String {
+    with_schema(&profiles::setting(name).unwrap_or_else(|| get_guc_string(name, guc)))
+}
+
+fn get_query_opt(name: &'static str, guc: &GucStrSetting) -> Option<String> {
+    profiles::setting(name)
+        .or_else(|| get_guc_string_opt(name, guc))
+        .filter(|query| !query.is_empty())
+        .map(|query| with_schema(&query))
 }
 
-fn get_guc_string(guc: &GucStrSetting) -> String {
-    let value = String::from_utf8_lossy(guc.get().expect("cannot get GUC value.").to_bytes())
-        .to_string()
-        .replace('\n', " ");
-    debug2!("Query: {value}");
-    value
+/// Same as `get_guc_string` but for optional settings, `None` when the GUC is NULL or empty.
+fn get_guc_string_opt(name: &'static str, guc: &GucStrSetting) -> Option<String> {
+    let raw = guc.get().filter(|raw| !raw.is_empty())?;
+    let value = GUC_STRING_CACHE.with_borrow_mut(|cache| match cache.get(name) {
+        Some((cached_raw, value)) if cached_raw.as_slice() == raw.to_bytes() => value.clone(),
+        _ => {
+            let value = String::from_utf8_lossy(raw.to_bytes()).replace('\n', " ");
+            debug2!("Query: {value}");
+            cache.insert(name, (raw.to_bytes().to_vec(), value.clone()));
+            value
+        }
+    });
+    Some(value)
 }
 
 /// The function `ensure_cache_populated` populates the cache with calendar data from the database, ensuring
@@ -170,217 +762,808 @@ fn is_cache_filled() -> bool {
     }
 
     if CALENDAR_CONTROL.share().cache_being_filled {
+        let timeout = match POPULATE_WAIT_TIMEOUT.get() {
+            0 => None,
+            timeout => Some(Duration::from_millis(timeout as u64)),
+        };
+        let started = Instant::now();
         while CALENDAR_CONTROL.share().cache_being_filled {
+            check_for_interrupts!();
+            if timeout.is_some_and(|timeout| started.elapsed() >= timeout) {
+                ereport!(
+                    ERROR,
+                    PgSqlErrorCode::ERRCODE_LOCK_NOT_AVAILABLE,
+                    "timed out waiting for the calendar cache population",
+                    "Another session is populating the cache, see kq.calendar.populate_wait_timeout."
+                );
+            }
             std::thread::sleep(Duration::from_millis(1));
         }
-        return true;
+        // false when the other session failed or was cancelled
+        return CALENDAR_CONTROL.share().cache_filled;
     }
 
     false
 }
 
 fn ensure_cache_populated() {
+    populate_cache_if_empty(false)
+}
+
+/// Populates the empty cache, `explicit` for kq_cx_populate_cache() and the population worker,
+/// which populate it whatever kq.calendar.populate_on is.
+fn populate_cache_if_empty(explicit: bool) {
+    check_shmem_layout();
     if is_cache_filled() {
+        generations::keep_current();
         return;
     }
+    if !explicit && populate::populate_on() == populate::PopulateOn::Manual {
+        populate::not_populated_error();
+    }
+    check_population_backoff();
 
-    validate_compatible_db();
+    let source = match (fixture::fixture_file(), image::prewarm_file()) {
+        (Some(fixture_file), _) => PopulationSource::Fixture(fixture_file),
+        (None, Some(prewarm_file)) => PopulationSource::Image(prewarm_file),
+        (None, None) => PopulationSource::Queries,
+    };
+    {
+        let mut control = CALENDAR_CONTROL.exclusive();
+        //someone else might have filled it already
+        if control.cache_filled || control.cache_being_filled {
+            drop(control);
+            return populate_cache_if_empty(explicit);
+        }
+        control.cache_being_filled = true;
+        control.population_requested = false;
+    }
+    let _filling = FillingGuard;
+    PgTryBuilder::new(|| populate_cache(source))
+        .catch_others(|error| {
+            record_population_failure(&error);
+            error.rethrow()
+        })
+        .execute();
+}
 
-    // Lock CALENDAR_ID_MAP
-    let mut calendar_id_map = CALENDAR_ID_MAP.exclusive();
+/// Where the calendars of a population are read from.
+enum PopulationSource {
+    /// The loader queries.
+    Queries,
+    /// A cache image, kq.calendar.prewarm_file on standbys.
+    Image(String),
+    /// kq.calendar.fixture_file.
+    Fixture(String),
+}
 
-    //someone else might have filled it already
-    if is_cache_filled() {
-        return;
-    }
+fn populate_cache(source: PopulationSource) {
+    let started = Instant::now();
+
+    // The loader runs without any lock: LWLocks hold off interrupts, a population under them
+    // could not be cancelled. The other backends wait on cache_being_filled instead.
+    events::emit(
+        Event::PopulationStarted,
+        "calendar cache population started",
+        &[(
+            "from",
+            match source {
+                PopulationSource::Queries => "queries",
+                PopulationSource::Image(_) => "image",
+                PopulationSource::Fixture(_) => "fixture",
+            }
+            .into(),
+        )],
+    );
+    let loaded_calendars = match source {
+        PopulationSource::Image(path) => {
+            POPULATION_PHASE.set(PopulationPhase::ReadImage);
+            image::read_image_file(&path)
+        }
+        PopulationSource::Fixture(fixture_file) => {
+            POPULATION_PHASE.set(PopulationPhase::ReadFixture);
+            fixture::read_fixture(&fixture_file)
+        }
+        PopulationSource::Queries => {
+            POPULATION_PHASE.set(PopulationPhase::Validate);
+            if validate_compatible_db() {
+                POPULATION_PHASE.set(PopulationPhase::CheckCapacity);
+                loader::check_capacity();
+                POPULATION_PHASE.set(PopulationPhase::Load);
+                loader::load_calendars()
+            } else {
+                vec![]
+            }
+        }
+    };
 
-    CALENDAR_CONTROL.exclusive().cache_being_filled = true;
+    let calendar_keys = loaded_calendar_keys(&loaded_calendars);
 
-    let mut calendar_name_id_map = CALENDAR_XUID_ID_MAP.exclusive();
-    // Load calendars (id, name and entry count)
-    let mut calendar_count: usize = 0;
-    Spi::connect(|client| {
-        match client.select(&get_guc_string(&Q3_GET_CAL_ENTRY_COUNT), None, None) {
-            Ok(tuple_table) => {
-                for row in tuple_table {
-                    let calendar_id = row[1]
-                        .value::<i64>()
-                        .unwrap_or_else(|err| error!("server interface error - {err}"))
-                        .unwrap_or_else(|| error!("cannot get calendar_id"));
+    POPULATION_PHASE.set(PopulationPhase::Install);
+    install_loaded_calendars(
+        CALENDAR_ID_MAP.exclusive(),
+        CALENDAR_XUID_ID_MAP.exclusive(),
+        loaded_calendars,
+        calendar_keys,
+        started,
+    );
+}
 
-                    let xuid = row[2]
-                        .value::<String>()
-                        .unwrap_or_else(|err| error!("server interface error - {err}"))
-                        .unwrap_or_else(|| error!("cannot get calendar xuid"));
+/// Fails without running the loader while the circuit breaker is open, i.e. until the backoff of
+/// the last failed population has elapsed. Without it every lookup would run the failing queries
+/// again.
+fn check_population_backoff() {
+    let control = CALENDAR_CONTROL.share();
+    let now = unsafe { pg_sys::GetCurrentTimestamp() };
+    if control.population_failures == 0 || now >= control.population_retry_at {
+        return;
+    }
+    let failures = control.population_failures;
+    let retry_in_ms = (control.population_retry_at - now) / 1000;
+    let last_error = control.population_error.to_string();
+    drop(control);
+    ereport!(
+        ERROR,
+        PgSqlErrorCode::ERRCODE_OBJECT_NOT_IN_PREREQUISITE_STATE,
+        format!(
+            "calendar cache population failed {failures} times in a row, next attempt in {retry_in_ms} ms"
+        ),
+        format!("Last error: {last_error}")
+    );
+}
 
-                    let xuid_str: &str = &xuid;
-                    let name_string = CalendarXuid::from_str(xuid_str).unwrap();
+/// Counts a failed population and doubles the backoff, cancellations are not failures.
+fn record_population_failure(error: &CaughtError) {
+    let report = match error {
+        CaughtError::PostgresError(report)
+        | CaughtError::ErrorReport(report)
+        | CaughtError::RustPanic {
+            ereport: report, ..
+        } => report,
+    };
+    if report.sql_error_code() == PgSqlErrorCode::ERRCODE_QUERY_CANCELED {
+        return;
+    }
+    let population_error: PopulationError = truncated(report.message());
+
+    let mut control = CALENDAR_CONTROL.exclusive();
+    control.population_failures += 1;
+    let backoff_ms = POPULATE_BACKOFF_MIN_MS
+        .saturating_mul(1 << (control.population_failures - 1).min(16))
+        .min(POPULATE_BACKOFF_MAX_MS);
+    let now = unsafe { pg_sys::GetCurrentTimestamp() };
+    control.population_retry_at = now + backoff_ms * 1000;
+    control.population_error = population_error;
+    control.population_error_at = now;
+    control.population_error_phase = POPULATION_PHASE.get();
+    let failures = control.population_failures;
+    drop(control);
+
+    let phase = POPULATION_PHASE.get().as_str();
+    events::emit(
+        Event::PopulationFailed,
+        &format!(
+            "calendar cache population failed in phase {phase}: {}",
+            report.message()
+        ),
+        &[
+            ("phase", phase.into()),
+            ("error", report.message().into()),
+            ("failures", failures.into()),
+            ("retry_in_ms", backoff_ms.into()),
+        ],
+    );
+}
 
-                    // Create a new calendar
-                    calendar_id_map
-                        .insert(calendar_id, Calendar::default())
-                        .unwrap();
-                    calendar_name_id_map
-                        .insert(name_string, calendar_id)
-                        .unwrap();
+/// Longest prefix of `value` that fits in `N` bytes, never cut inside a character.
+fn truncated<const N: usize>(value: &str) -> heapless::String<N> {
+    let mut truncated = heapless::String::new();
+    for c in value.chars() {
+        if truncated.push(c).is_err() {
+            break;
+        }
+    }
+    truncated
+}
 
-                    calendar_count += 1;
-                }
-            }
-            Err(spi_error) => {
-                error!("cannot get calendars information. {}", spi_error)
-            }
-        };
-    });
+/// Clears `cache_being_filled` when the population is cancelled or fails, the waiting backends
+/// would otherwise spin forever. Dropped while unwinding from the ERROR.
+struct FillingGuard;
 
-    // Fill Cache
-    let mut total_entries: usize = 0;
-    Spi::connect(|client| {
-        let select = client.select(&get_guc_string(&Q4_GET_ENTRIES), None, None);
-        match select {
-            Ok(tuple_table) => {
-                for row in tuple_table {
-                    let calendar_id = row[1]
-                        .value::<i64>()
-                        .unwrap_or_else(|err| error!("server interface error - {err}"))
-                        .unwrap_or_else(|| error!("cannot get calendar_id"));
-                    let calendar_entry = row[2]
-                        .value::<PgDate>()
-                        .unwrap_or_else(|err| error!("server interface error - {err}"))
-                        .unwrap_or_else(|| error!("cannot get calendar_entry"));
-
-                    debug2!(
-                        ">> got entry: {calendar_id} => {calendar_entry} ({})",
-                        calendar_entry.to_pg_epoch_days()
-                    );
-
-                    if let Some(calendar) = calendar_id_map.get_mut(&calendar_id) {
-                        if calendar
-                            .dates
-                            .push(calendar_entry.to_pg_epoch_days())
-                            .is_err()
-                        {
-                            error!("cannot add more entries to calendar_id = {calendar_id}");
-                        }
-                        total_entries += 1;
-                    } else {
-                        error!(
-                            "cannot add entries: calendar_id = {} not initialized",
-                            calendar_id
-                        )
-                    }
-                }
-            }
-            Err(spi_error) => {
-                error!("Cannot load calendar entries. {}", spi_error)
-            }
+impl Drop for FillingGuard {
+    fn drop(&mut self) {
+        if std::thread::panicking() {
+            CALENDAR_CONTROL.exclusive().cache_being_filled = false;
         }
-    });
+    }
+}
 
-    debug2!("{total_entries} entries loaded");
+/// Differences of one calendar between the cache and the loaded calendars replacing it, see
+/// kq_cx_refresh_cache(). The entry counts are `None` on the side missing the calendar.
+struct CalendarChange {
+    xuid: String,
+    entries_before: Option<i64>,
+    entries_after: Option<i64>,
+    added: i64,
+    removed: i64,
+}
 
-    // Page Size init
-    calendar_id_map
-        .iter_mut()
-        .by_ref()
-        .for_each(|(calendar_id, calendar)| {
-            if calendar.dates.is_empty() {
-                return;
+/// Diffs the cached calendars with the loaded ones by calendar id, the caller holds the maps
+/// exclusively. Calendars dropped from the source are reported under their cached xuid.
+fn calendar_changes(
+    calendar_id_map: &CalendarIdMap,
+    calendar_name_id_map: &CalendarXuidIdMap,
+    loaded_calendars: &[loader::LoadedCalendar],
+) -> Vec<CalendarChange> {
+    let mut cached_xuids: BTreeMap<i64, String> = calendar_name_id_map
+        .iter()
+        .map(|((namespace, xuid), calendar_id)| {
+            let xuid = xuids::cached_xuid(namespace, xuid, *calendar_id);
+            (*calendar_id, qualified_xuid(namespace, &xuid))
+        })
+        .collect();
+    let mut changes = vec![];
+    for loaded in loaded_calendars {
+        cached_xuids.remove(&loaded.calendar_id);
+        let after = loaded.calendar.dates();
+        let (entries_before, added, removed) =
+            match share_calendar(calendar_id_map, loaded.calendar_id) {
+                Some(calendar) => {
+                    let diff = diff_dates(calendar.dates(), after);
+                    (
+                        Some(calendar.dates().len() as i64),
+                        diff.only_in_b.len() as i64,
+                        diff.only_in_a.len() as i64,
+                    )
+                }
+                None => (None, after.len() as i64, 0),
+            };
+        changes.push(CalendarChange {
+            xuid: qualified_xuid(&loaded.namespace, &loaded.xuid),
+            entries_before,
+            entries_after: Some(after.len() as i64),
+            added,
+            removed,
+        });
+    }
+    for (calendar_id, xuid) in cached_xuids {
+        let entries = share_calendar(calendar_id_map, calendar_id)
+            .map_or(0, |calendar| calendar.dates().len() as i64);
+        changes.push(CalendarChange {
+            xuid,
+            entries_before: Some(entries),
+            entries_after: None,
+            added: 0,
+            removed: entries,
+        });
+    }
+    changes.sort_by(|a, b| a.xuid.cmp(&b.xuid));
+    changes
+}
+
+/// Replaces the cache with freshly loaded calendars without emptying it first, lookups keep
+/// using the current calendars while the new ones are read from the source tables. With `report`
+/// the calendars are diffed during the swap and their changes returned.
+fn reload_cache(report: bool) -> Vec<CalendarChange> {
+    let started = Instant::now();
+    let fixture_file = fixture::fixture_file();
+    events::emit(
+        Event::PopulationStarted,
+        "calendar cache reload started",
+        &[(
+            "from",
+            if fixture_file.is_some() {
+                "fixture"
+            } else {
+                "queries"
             }
+            .into(),
+        )],
+    );
+    let loaded_calendars = if let Some(fixture_file) = fixture_file {
+        fixture::read_fixture(&fixture_file)
+    } else if validate_compatible_db() {
+        loader::check_capacity();
+        loader::load_calendars()
+    } else {
+        vec![]
+    };
+    let calendar_keys = loaded_calendar_keys(&loaded_calendars);
 
-            let first_date = calendar.dates.first().expect("cannot get first_date");
-            let last_date = calendar.dates.last().expect("cannot get last_date");
-            let entry_count = calendar.dates.len() as i64;
+    let mut calendar_id_map = CALENDAR_ID_MAP.exclusive();
+    let mut calendar_name_id_map = CALENDAR_XUID_ID_MAP.exclusive();
+    let changes = match report {
+        true => calendar_changes(&calendar_id_map, &calendar_name_id_map, &loaded_calendars),
+        false => vec![],
+    };
+    clear_calendars(&mut calendar_id_map);
+    calendar_name_id_map.clear();
+    install_loaded_calendars(
+        calendar_id_map,
+        calendar_name_id_map,
+        loaded_calendars,
+        calendar_keys,
+        started,
+    );
+    changes
+}
 
-            let page_size_tmp = math::calculate_page_size(*first_date, *last_date, entry_count);
-            if page_size_tmp == 0 {
-                error!("page size cannot be 0, cannot be calculated")
-            }
-            let first_page_offset = first_date / page_size_tmp;
-
-            calendar.first_page_offset = first_page_offset;
-            calendar.page_size = page_size_tmp;
-
-            // Create page map
-            calendar.page_map.push(0).unwrap();
-            let mut prev_page_index = 0;
-            for calendar_date_index in 0..calendar.dates.len() {
-                let date: &i32 = calendar
-                    .dates
-                    .get(calendar_date_index)
-                    .expect("cannot get date from cache");
-                let page_index = (date / page_size_tmp) - first_page_offset;
-                while prev_page_index < page_index {
-                    prev_page_index += 1;
-                    calendar
-                        .page_map
-                        .insert(prev_page_index as usize, calendar_date_index)
-                        .unwrap();
-                }
+/// Keys of the loaded calendars in CALENDAR_XUID_ID_MAP, in the same order. Errors out when a
+/// key cannot be derived, two calendars have the same key or the calendars do not fit in the
+/// cache, before the cache is cleared: a failed population leaves the cached calendars in place.
+fn loaded_calendar_keys(loaded_calendars: &[loader::LoadedCalendar]) -> Vec<CalendarKey> {
+    if loaded_calendars.len() > MAX_CALENDARS {
+        error!(
+            "cannot add {} calendars: the cache is limited to {MAX_CALENDARS} calendars",
+            loaded_calendars.len()
+        );
+    }
+    let xuid_overflow = xuids::xuid_overflow();
+    let mut calendar_ids = HashMap::new();
+    loaded_calendars
+        .iter()
+        .map(|loaded| {
+            let calendar_key = xuids::stored_key(&loaded.namespace, &loaded.xuid, xuid_overflow)
+                .unwrap_or_else(|err| {
+                    error!("cannot add calendar_id = {}: {err}", loaded.calendar_id)
+                });
+            if let Some(other_id) = calendar_ids.insert(calendar_key.clone(), loaded.calendar_id) {
+                error!(
+                    "cannot add calendar_id = {}: its xuid has the same key as calendar_id = {other_id}",
+                    loaded.calendar_id
+                );
             }
+            calendar_key
+        })
+        .collect()
+}
 
-            debug2!("page_map created: calendar_id = {calendar_id}, page_size = {page_size_tmp}");
-        });
+/// Moves the calendars into the (locked, empty) shared maps under the keys computed by
+/// `loaded_calendar_keys` and marks the cache as filled. The maps are unlocked before the
+/// population is audited and reported: the audit table is written in a subtransaction, whose
+/// abort would release every lock of the backend.
+fn install_loaded_calendars(
+    mut calendar_id_map: Tracked<PgLwLockExclusiveGuard<'static, CalendarIdMap>>,
+    mut calendar_name_id_map: Tracked<PgLwLockExclusiveGuard<'static, CalendarXuidIdMap>>,
+    loaded_calendars: Vec<loader::LoadedCalendar>,
+    calendar_keys: Vec<CalendarKey>,
+    started: Instant,
+) {
+    let calendar_count = loaded_calendars.len();
+    let mut total_entries: usize = 0;
+    let skip_intervals = skip_intervals();
+    for (mut loaded, calendar_key) in loaded_calendars.into_iter().zip(calendar_keys) {
+        total_entries += loaded.calendar.dates().len();
+        if !loaded.calendar.build_skip_tables(&skip_intervals) {
+            warning!(
+                "skip tables not built: calendar_id = {} has too many entries for {} intervals",
+                loaded.calendar_id,
+                skip_intervals.len()
+            );
+        }
+        // room checked by loaded_calendar_keys
+        assert!(insert_calendar(
+            &mut calendar_id_map,
+            loaded.calendar_id,
+            *loaded.calendar
+        ));
+        metadata::update(loaded.calendar_id, &loaded.metadata);
+        metadata::record_load(loaded.calendar_id, started);
+        calendar_name_id_map
+            .insert(calendar_key, loaded.calendar_id)
+            .unwrap();
+        xuids::record(loaded.calendar_id, &loaded.xuid);
+    }
 
-    *CALENDAR_CONTROL.exclusive() = CalendarControl {
+    let mut control = CALENDAR_CONTROL.exclusive();
+    *control = CalendarControl {
         entry_count: total_entries,
         calendar_count,
         cache_filled: true,
         cache_being_filled: false,
+        stale: false,
+        capacity_exceeded: false,
+        populated_at: unsafe { pg_sys::GetCurrentTimestamp() },
+        generation: next_generation(control.generation),
+        layout: control.layout.clone(),
+        ..Default::default()
     };
-
-    debug2!("cache ready. calendars = {calendar_count}, entries = {total_entries}")
+    let generation = control.generation;
+    drop(control);
+    drop(calendar_name_id_map);
+    drop(calendar_id_map);
+
+    audit::record(audit::AuditOperation::Populate, started, total_entries);
+
+    events::emit(
+        Event::PopulationFinished,
+        &format!("calendar cache ready: calendars = {calendar_count}, entries = {total_entries}"),
+        &[
+            ("calendars", calendar_count.into()),
+            ("entries", total_entries.into()),
+            ("generation", generation.into()),
+            (
+                "duration_ms",
+                (started.elapsed().as_micros() as f64 / 1000.0).into(),
+            ),
+        ],
+    );
 }
 
-/// Checks if the schema is compatible with the extension.
-fn validate_compatible_db() {
-    let spi_result: SpiResult<Option<bool>> = Spi::get_one(&get_guc_string(&Q1_VALIDATION_QUERY));
-    match spi_result {
-        Ok(found_tables_opt) => match found_tables_opt {
-            None => {
-                error!("The current database is not compatible with the ketteQ Calendar Extension.")
-            }
-            Some(valid) => {
-                if !valid {
-                    error!("The current database is not compatible with the ketteQ Calendar Extension.")
-                }
-            }
-        },
+/// Adds or replaces one calendar of the populated cache. Replacing a cached calendar only locks
+/// its stripe, lookups of the calendars in the other stripes go on.
+pub(crate) fn install_calendar(
+    calendar_id: i64,
+    key: CalendarKey,
+    calendar: Calendar,
+    calendar_xuid: &str,
+) {
+    let entry_count = calendar.dates().len();
+    let calendar_id_map = CALENDAR_ID_MAP.share();
+    if let Some(stripe) = calendar_id_map.get(&calendar_id) {
+        let mut calendar_stripe = CALENDAR_STRIPES[*stripe].exclusive();
+        let replaced = calendar_stripe
+            .insert(calendar_id, calendar)
+            .unwrap()
+            .unwrap();
+        membership::update(calendar_id, &calendar_stripe[&calendar_id]);
+
+        // changed before the stripe is unlocked, see kq_cx_add_days
+        let mut control = CALENDAR_CONTROL.exclusive();
+        control.entry_count = control.entry_count - replaced.dates().len() + entry_count;
+        control.generation = next_generation(control.generation);
+        return;
+    }
+    drop(calendar_id_map);
+
+    let mut calendar_id_map = CALENDAR_ID_MAP.exclusive();
+    let mut calendar_xuid_id_map = CALENDAR_XUID_ID_MAP.exclusive();
+    // another session may have added it while the map was unlocked
+    let replaced_entries =
+        share_calendar(&calendar_id_map, calendar_id).map(|replaced| replaced.dates().len());
+    if let Some(other_id) = calendar_xuid_id_map
+        .get(&key)
+        .filter(|other_id| **other_id != calendar_id)
+    {
+        error!("cannot add calendar xuid = {calendar_xuid}: it has the same key as calendar_id = {other_id}");
+    }
+    if !insert_calendar(&mut calendar_id_map, calendar_id, calendar) {
+        error!("cannot add calendar xuid = {calendar_xuid}: the cache is limited to {MAX_CALENDARS} calendars");
+    }
+    calendar_xuid_id_map.insert(key, calendar_id).unwrap();
+    xuids::record(calendar_id, calendar_xuid);
+
+    let mut control = CALENDAR_CONTROL.exclusive();
+    if replaced_entries.is_none() {
+        control.calendar_count += 1;
+    }
+    control.entry_count = control.entry_count - replaced_entries.unwrap_or(0) + entry_count;
+    control.generation = next_generation(control.generation);
+}
+
+/// Generation that follows `generation`. The first one is seeded from the clock, so generations
+/// are not reused after a restart and values pinned by kq_cx_add_days_pinned become invalid.
+fn next_generation(generation: u64) -> u64 {
+    match generation {
+        0 => unsafe { pg_sys::GetCurrentTimestamp() as u64 },
+        _ => generation + 1,
+    }
+}
+
+/// What to do when the validation query finds the database incompatible, kq.calendar.validation.
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum ValidationMode {
+    Error,
+    Warn,
+    Skip,
+}
+
+fn validation_mode() -> ValidationMode {
+    let value = get_guc_string_opt(GUC_VALIDATION, &VALIDATION).unwrap_or_default();
+    match value.trim().to_ascii_lowercase().as_str() {
+        "" | "error" => ValidationMode::Error,
+        "warn" => ValidationMode::Warn,
+        "skip" => ValidationMode::Skip,
+        _ => ereport!(
+            ERROR,
+            PgSqlErrorCode::ERRCODE_INVALID_PARAMETER_VALUE,
+            format!("invalid value for {GUC_VALIDATION}: \"{value}\""),
+            "Use error, warn or skip."
+        ),
+    }
+}
+
+/// Hot intervals of kq.calendar.skip_intervals, see `kq_cx_core::skip`.
+fn skip_intervals() -> Vec<i32> {
+    let value = get_guc_string_opt(GUC_SKIP_INTERVALS, &SKIP_INTERVALS).unwrap_or_default();
+    skip::parse_intervals(&value).unwrap_or_else(|err| {
+        ereport!(
+            ERROR,
+            PgSqlErrorCode::ERRCODE_INVALID_PARAMETER_VALUE,
+            format!("invalid value for {GUC_SKIP_INTERVALS}: {err}"),
+            "Use a comma separated list of integers, e.g. 1,-1,5,-5."
+        )
+    })
+}
+
+/// Checks if the schema is compatible with the extension, false when it is not and
+/// kq.calendar.validation is `warn`: the cache is then populated empty.
+fn validate_compatible_db() -> bool {
+    let mode = validation_mode();
+    if mode == ValidationMode::Skip {
+        return true;
+    }
+    let spi_result: SpiResult<Option<bool>> =
+        Spi::get_one(&get_query(GUC_Q1_VALIDATION_QUERY, &Q1_VALIDATION_QUERY));
+    match spi_result {
+        Ok(Some(true)) => true,
+        Ok(_) if mode == ValidationMode::Warn => {
+            warning!("The current database is not compatible with the ketteQ Calendar Extension, the calendar cache is empty.");
+            false
+        }
+        Ok(_) => {
+            error!("The current database is not compatible with the ketteQ Calendar Extension.")
+        }
         Err(spi_error) => {
             error!("Cannot validate current database. {}", spi_error)
         }
     }
 }
 
+/// Returns the xuid of the calendar, prefixed with `namespace/` when it has a namespace.
 fn get_calendar_xuid_from_id(
-    shared_calendar_xuid_id_map: PgLwLockShareGuard<CalendarXuidIdMap>,
+    shared_calendar_xuid_id_map: Tracked<PgLwLockShareGuard<CalendarXuidIdMap>>,
     calendar_id: &i64,
 ) -> String {
     shared_calendar_xuid_id_map
         .iter()
         .find(|&(_, map_calendar_id)| map_calendar_id == calendar_id)
-        .map(|(m_calendar_xuid, _)| m_calendar_xuid.to_string())
+        .map(|((namespace, xuid), _)| xuids::cached_xuid(namespace, xuid, *calendar_id))
         .unwrap()
 }
 
-fn get_calendars_info() -> Vec<CalendarInfo> {
-    CALENDAR_ID_MAP
-        .share()
+fn qualified_xuid(namespace: &str, xuid: &str) -> String {
+    match namespace.is_empty() {
+        true => xuid.to_string(),
+        false => format!("{namespace}/{xuid}"),
+    }
+}
+
+/// A cached calendar, its stripe stays locked in share mode until the reference is dropped. It
+/// borrows the CALENDAR_ID_MAP guard it was found with, which cannot be released first.
+struct CalendarRef<'a> {
+    stripe: Tracked<PgLwLockShareGuard<'static, CalendarStripe>>,
+    calendar_id: i64,
+    calendar_id_map: PhantomData<&'a CalendarIdMap>,
+}
+
+impl std::ops::Deref for CalendarRef<'_> {
+    type Target = Calendar;
+
+    fn deref(&self) -> &Calendar {
+        &self.stripe[&self.calendar_id]
+    }
+}
+
+/// Locks the stripe of the calendar in share mode, the caller holds CALENDAR_ID_MAP.
+fn share_calendar(calendar_id_map: &CalendarIdMap, calendar_id: i64) -> Option<CalendarRef<'_>> {
+    let stripe = CALENDAR_STRIPES[*calendar_id_map.get(&calendar_id)?].share();
+    Some(CalendarRef {
+        stripe,
+        calendar_id,
+        calendar_id_map: PhantomData,
+    })
+}
+
+/// Every cached calendar in load order, see `share_calendar`.
+fn share_calendars(calendar_id_map: &CalendarIdMap) -> impl Iterator<Item = CalendarRef<'_>> {
+    calendar_id_map
+        .keys()
+        .filter_map(|calendar_id| share_calendar(calendar_id_map, *calendar_id))
+}
+
+/// Adds or replaces a calendar in its stripe, the caller holds CALENDAR_ID_MAP exclusively.
+/// Returns false when the cache already has MAX_CALENDARS calendars.
+fn insert_calendar(
+    calendar_id_map: &mut CalendarIdMap,
+    calendar_id: i64,
+    calendar: Calendar,
+) -> bool {
+    let stripe = match calendar_id_map.get(&calendar_id) {
+        Some(stripe) => *stripe,
+        None => {
+            // the emptiest stripe, it has room as long as the map has room
+            let stripe = (0..CALENDAR_LOCK_STRIPES)
+                .min_by_key(|stripe| {
+                    calendar_id_map
+                        .values()
+                        .filter(|used| *used == stripe)
+                        .count()
+                })
+                .unwrap();
+            if calendar_id_map.insert(calendar_id, stripe).is_err() {
+                return false;
+            }
+            stripe
+        }
+    };
+    let mut calendar_stripe = CALENDAR_STRIPES[stripe].exclusive();
+    calendar_stripe.insert(calendar_id, calendar).unwrap();
+    membership::update(calendar_id, &calendar_stripe[&calendar_id]);
+    true
+}
+
+/// Removes every calendar, the caller holds CALENDAR_ID_MAP exclusively.
+fn clear_calendars(calendar_id_map: &mut CalendarIdMap) {
+    for stripe in &CALENDAR_STRIPES {
+        stripe.exclusive().clear();
+    }
+    calendar_id_map.clear();
+    membership::clear();
+    metadata::clear();
+    xuids::clear();
+}
+
+/// Returns the calendar used for lookups of `date`: the calendar itself, or the closest parent
+/// that covers the date when the calendar is empty or the date is outside of its entries.
+fn resolve_calendar(
+    calendar_id_map: &CalendarIdMap,
+    calendar_id: i64,
+    date: i32,
+) -> Option<CalendarRef<'_>> {
+    resolve_parents(calendar_id, date, |calendar_id| {
+        share_calendar(calendar_id_map, calendar_id)
+    })
+}
+
+/// Same as `resolve_calendar`, getting the calendars with `get_calendar`.
+fn resolve_parents<C: std::ops::Deref<Target = Calendar>>(
+    calendar_id: i64,
+    date: i32,
+    get_calendar: impl Fn(i64) -> Option<C>,
+) -> Option<C> {
+    let mut calendar = get_calendar(calendar_id)?;
+    // the depth limit stops parent cycles
+    for _ in 0..MAX_CALENDARS {
+        if calendar.covers(date) {
+            break;
+        }
+        match calendar.parent_id().and_then(&get_calendar) {
+            Some(parent) => calendar = parent,
+            None => break,
+        }
+    }
+    Some(calendar)
+}
+
+/// `None` when the namespace or the xuid do not fit in shared memory.
+fn calendar_key(namespace: &str, xuid: &str) -> Option<CalendarKey> {
+    Some((
+        CalendarNamespace::from_str(namespace).ok()?,
+        CalendarXuid::from_str(&xuids::normalized(xuid)).ok()?,
+    ))
+}
+
+fn current_namespace() -> String {
+    get_guc_string_opt(GUC_TENANT, &TENANT).unwrap_or_default()
+}
+
+/// Looks up a calendar by xuid, `namespace` defaults to the kq.calendar.tenant setting.
+fn find_calendar_id(namespace: Option<&str>, xuid: &str) -> Option<i64> {
+    let namespace = match namespace {
+        Some(namespace) => namespace.to_string(),
+        None => current_namespace(),
+    };
+    xuids::find(&CALENDAR_XUID_ID_MAP.share(), &namespace, xuid)
+}
+
+/// Detail of the message for xuids missing from the cache: the number of cached calendars and
+/// the closest xuid of the namespace to each one, most misses are typos.
+fn xuid_not_found_detail(namespace: &str, calendar_xuids: &[&str]) -> String {
+    let calendar_xuid_id_map = CALENDAR_XUID_ID_MAP.share();
+    let cached = match calendar_xuid_id_map.len() {
+        1 => "1 calendar is cached".to_string(),
+        count => format!("{count} calendars are cached"),
+    };
+    let namespace_xuids = || {
+        calendar_xuid_id_map
+            .keys()
+            .filter(|(key_namespace, _)| key_namespace.as_str() == namespace)
+            .map(|(_, xuid)| xuid.as_str())
+    };
+    let suggestions: Vec<String> = calendar_xuids
         .iter()
-        .map(|(calendar_id, calendar)| {
+        .filter_map(|calendar_xuid| {
+            let suggestion = names::closest(calendar_xuid, namespace_xuids())?;
+            Some(match calendar_xuids.len() {
+                1 => format!("'{suggestion}'"),
+                _ => format!("'{suggestion}' for '{calendar_xuid}'"),
+            })
+        })
+        .collect();
+    match suggestions.is_empty() {
+        true => format!("{cached}."),
+        false => format!("{cached}, did you mean {}?", suggestions.join(", ")),
+    }
+}
+
+fn xuid_not_found(namespace: Option<&str>, calendar_xuids: &[&str]) -> ErrorReport {
+    let label = match namespace {
+        Some(namespace) => format!("{namespace}/{}", calendar_xuids.join(", ")),
+        None => calendar_xuids.join(", "),
+    };
+    let namespace = namespace.map_or_else(current_namespace, str::to_string);
+    ErrorReport::new(
+        PgSqlErrorCode::ERRCODE_UNDEFINED_OBJECT,
+        format!("calendar_xuid = {label} not found in cache"),
+        pgrx::function_name!(),
+    )
+    .set_detail(xuid_not_found_detail(&namespace, calendar_xuids))
+}
+
+/// Warns that the calendar is not cached, `namespace` defaults to the kq.calendar.tenant setting.
+pub(crate) fn warn_xuid_not_found(namespace: Option<&str>, calendar_xuid: &str) {
+    xuid_not_found(namespace, &[calendar_xuid]).report(PgLogLevel::WARNING);
+}
+
+pub(crate) fn xuid_not_found_error(calendar_xuid: &str) -> ! {
+    xuid_not_found(None, &[calendar_xuid]).report(PgLogLevel::ERROR);
+    unreachable!()
+}
+
+fn get_calendars_info() -> Vec<CalendarInfo> {
+    check_shmem_layout();
+    let calendar_id_map = CALENDAR_ID_MAP.share();
+    calendar_id_map
+        .keys()
+        .filter_map(|calendar_id| {
+            // CALENDAR_XUID_ID_MAP is locked before the stripe, as in kq_cx_define_calendar
             let calendar_xuid =
                 get_calendar_xuid_from_id(CALENDAR_XUID_ID_MAP.share(), calendar_id);
-            (
-                *calendar_id,
+            let calendar = share_calendar(&calendar_id_map, *calendar_id)?;
+            let metadata = metadata::get(*calendar_id);
+            let (lookups, last_used) = usage::get(*calendar_id);
+            let load = metadata::get_load(*calendar_id);
+            let to_date = |day: &i32| unsafe { PgDate::from_pg_epoch_days(*day) };
+            Some((
+                calendar.calendar_id,
                 calendar_xuid,
-                calendar.dates.len() as i64,
-                calendar.page_size,
-                calendar.page_map.len() as i64,
-            )
+                calendar.dates().len() as i64,
+                calendar.page_size(),
+                calendar.page_map().len() as i64,
+                format_checksum(calendar.checksum()),
+                metadata.name(),
+                metadata.calendar_type(),
+                metadata.timezone(),
+                lookups,
+                last_used.and_then(|last_used| unsafe {
+                    TimestampWithTimeZone::from_datum(pg_sys::Datum::from(last_used), false)
+                }),
+                calendar.dates().first().map(to_date),
+                calendar.dates().last().map(to_date),
+                calendar.used_bytes() as i64,
+                load.and_then(|load| unsafe {
+                    TimestampWithTimeZone::from_datum(pg_sys::Datum::from(load.loaded_at), false)
+                }),
+                load.map(|load| load.duration_us as f64 / 1000.0),
+                calendar.skip_tables().is_built(),
+            ))
         })
         .collect()
 }
 
+fn format_checksum(checksum: u64) -> String {
+    format!("{checksum:016x}")
+}
+
+/// Checksum of the whole cache, equal on two clusters with identical calendar data.
+fn get_cache_checksum() -> u64 {
+    let checksums: Vec<(i64, u64)> = share_calendars(&CALENDAR_ID_MAP.share())
+        .map(|calendar| (calendar.calendar_id, calendar.checksum()))
+        .collect();
+    kq_cx_core::cache_checksum(&checksums)
+}
+
 #[pg_extern(parallel_safe)]
 fn kq_cx_cache_info() -> TableIterator<
     'static,
@@ -390,41 +1573,126 @@ fn kq_cx_cache_info() -> TableIterator<
         name!(entries, i64),
         name!(page_size, i32),
         name!(page_map_entries, i64),
+        name!(checksum, String),
+        name!(calendar_name, Option<String>),
+        name!(calendar_type, Option<String>),
+        name!(timezone, Option<String>),
+        name!(lookups, i64),
+        name!(last_used, Option<TimestampWithTimeZone>),
+        name!(first_entry, Option<PgDate>),
+        name!(last_entry, Option<PgDate>),
+        name!(bytes_used, i64),
+        name!(loaded_at, Option<TimestampWithTimeZone>),
+        name!(load_duration_ms, Option<f64>),
+        name!(skip_tables_built, bool),
     ),
 > {
     TableIterator::new(get_calendars_info())
 }
 
+/// Cheap listing of the cached calendars for frequent callers such as application dropdowns: no
+/// page map statistics, and the xuids are read in a single pass over CALENDAR_XUID_ID_MAP.
+#[pg_extern(parallel_safe, stable)]
+fn kq_cx_list_calendars() -> TableIterator<
+    'static,
+    (
+        name!(calendar_id, i64),
+        name!(calendar_xuid, String),
+        name!(entry_count, i64),
+    ),
+> {
+    ensure_cache_populated();
+    let calendar_id_map = CALENDAR_ID_MAP.share();
+    let calendar_xuids: HashMap<i64, String> = CALENDAR_XUID_ID_MAP
+        .share()
+        .iter()
+        .map(|((namespace, xuid), calendar_id)| {
+            (
+                *calendar_id,
+                xuids::cached_xuid(namespace, xuid, *calendar_id),
+            )
+        })
+        .collect();
+    let rows: Vec<_> = share_calendars(&calendar_id_map)
+        .filter_map(|calendar| {
+            let calendar_xuid = calendar_xuids.get(&calendar.calendar_id)?.clone();
+            Some((
+                calendar.calendar_id,
+                calendar_xuid,
+                calendar.dates().len() as i64,
+            ))
+        })
+        .collect();
+    TableIterator::new(rows)
+}
+
+/// Id of the cached calendar with the xuid (in the kq.calendar.tenant namespace). A missing
+/// calendar returns NULL, or raises an error with `missing_ok => false`.
+#[pg_extern(parallel_safe, stable)]
+fn kq_cx_resolve_xuid(calendar_xuid: &str, missing_ok: default!(bool, true)) -> Option<i64> {
+    ensure_cache_populated();
+    let calendar_id = find_calendar_id(None, calendar_xuid);
+    if calendar_id.is_none() && !missing_ok {
+        xuid_not_found_error(calendar_xuid);
+    }
+    calendar_id
+}
+
+/// Xuid of the cached calendar with the id, qualified with its namespace when it has one. A
+/// missing calendar returns NULL, or raises an error with `missing_ok => false`.
+#[pg_extern(parallel_safe, stable)]
+fn kq_cx_resolve_id(calendar_id: i64, missing_ok: default!(bool, true)) -> Option<String> {
+    ensure_cache_populated();
+    let calendar_xuid = CALENDAR_XUID_ID_MAP
+        .share()
+        .iter()
+        .find(|(_, cached_id)| **cached_id == calendar_id)
+        .map(|((namespace, xuid), _)| xuids::cached_xuid(namespace, xuid, calendar_id));
+    if calendar_xuid.is_none() && !missing_ok {
+        ereport!(
+            ERROR,
+            PgSqlErrorCode::ERRCODE_UNDEFINED_OBJECT,
+            format!("calendar_id = {calendar_id} not found in cache")
+        );
+    }
+    calendar_xuid
+}
+
 #[pg_extern(parallel_safe)]
 fn kq_cx_info() -> TableIterator<'static, (name!(property, String), name!(value, String))> {
+    check_shmem_layout();
     let control = CALENDAR_CONTROL.share().clone();
     let mut data: Vec<(String, String)> = vec![];
     data.push((
-        "PostgreSQL SDK Version".to_string(),
-        pg_sys::PG_VERSION_NUM.to_string(),
-    ));
-    data.push((
-        "PostgreSQL SDK Build".to_string(),
-        pg_sys::PG_VERSION_STR.to_str().unwrap().to_owned(),
+        "Cache Available".to_string(),
+        control.cache_filled.to_string(),
     ));
+    data.push(("Cache Stale".to_string(), control.stale.to_string()));
     data.push((
-        "Extension Version".to_string(),
-        env!("CARGO_PKG_VERSION").to_string(),
+        "Population Failures".to_string(),
+        control.population_failures.to_string(),
     ));
-    if cfg!(debug_assertions) {
-        data.push(("Build Type".to_string(), "Debug".to_string()));
-    } else {
-        data.push(("Build Type".to_string(), "Release".to_string()));
+    if control.population_failures > 0 {
+        data.push((
+            "Last Population Error".to_string(),
+            control.population_error.to_string(),
+        ));
+        data.push((
+            "Last Population Error Phase".to_string(),
+            control.population_error_phase.as_str().to_string(),
+        ));
+        data.push((
+            "Last Population Error At".to_string(),
+            timestamp_to_string(control.population_error_at),
+        ));
     }
-    data.push(("Max Calendars".to_string(), format!("{}", MAX_CALENDARS)));
-    data.push((
-        "Max Entries per calendar".to_string(),
-        format!("{}", MAX_ENTRIES_PER_CALENDAR),
-    ));
     data.push((
-        "Cache Available".to_string(),
-        control.cache_filled.to_string(),
+        "Cache Generation".to_string(),
+        control.generation.to_string(),
     ));
+    if let Some(generation) = snapshot::pinned_generation() {
+        data.push(("Pinned Generation".to_string(), generation.to_string()));
+    }
     data.push((
         "Slice Cache Size (Calendar ID Count)".to_string(),
         control.calendar_count.to_string(),
@@ -434,16 +1702,12 @@ fn kq_cx_info() -> TableIterator<'static, (name!(property, String), name!(value,
         control.entry_count.to_string(),
     ));
     data.push((
-        "[Q1] Get Calendar IDs".to_string(),
-        get_guc_string(&Q2_GET_CALENDAR_IDS),
-    ));
-    data.push((
-        "[Q2] Get Calendar Entry Count per Calendar ID".to_string(),
-        get_guc_string(&Q3_GET_CAL_ENTRY_COUNT),
+        "Membership Bitmaps Size (Bytes)".to_string(),
+        membership::bitmaps_size().to_string(),
     ));
     data.push((
-        "[Q3] Get Calendar Entries".to_string(),
-        get_guc_string(&Q4_GET_ENTRIES),
+        "Cache Checksum".to_string(),
+        format_checksum(get_cache_checksum()),
     ));
     get_calendars_info().iter().for_each(|calendar_info| {
         data.push((
@@ -459,103 +1723,1138 @@ fn kq_cx_info() -> TableIterator<'static, (name!(property, String), name!(value,
             "    Page Map Entry Count".to_string(),
             format!("{}", calendar_info.4),
         ));
+        data.push(("    Checksum".to_string(), calendar_info.5.clone()));
+        data.push(("    Lookups".to_string(), format!("{}", calendar_info.9)));
     });
     TableIterator::new(data)
 }
 
+/// Configuration of the extension, one row per build constant (source `build`) and per
+/// kq.calendar.* setting (source as in pg_settings). `kq_cx_info()` only reports runtime state, so
+/// the output of this function can be diffed across environments.
 #[pg_extern(parallel_safe)]
-fn kq_cx_display_cache() -> TableIterator<'static, (name!(calendar, String), name!(entry, PgDate))>
-{
-    let mut data: Vec<(String, PgDate)> = vec![];
+fn kq_cx_settings() -> TableIterator<
+    'static,
+    (
+        name!(name, String),
+        name!(setting, Option<String>),
+        name!(source, String),
+    ),
+> {
+    let build_type = match cfg!(debug_assertions) {
+        true => "debug",
+        false => "release",
+    };
+    let mut rows: Vec<(String, Option<String>, String)> = [
+        ("postgresql_version_num", pg_sys::PG_VERSION_NUM.to_string()),
+        (
+            "postgresql_build",
+            pg_sys::PG_VERSION_STR.to_str().unwrap().to_owned(),
+        ),
+        ("extension_version", env!("CARGO_PKG_VERSION").to_string()),
+        ("shmem_layout_version", SHMEM_LAYOUT_VERSION.to_string()),
+        ("build_type", build_type.to_string()),
+        ("max_calendars", MAX_CALENDARS.to_string()),
+        (
+            "max_entries_per_calendar",
+            MAX_ENTRIES_PER_CALENDAR.to_string(),
+        ),
+    ]
+    .into_iter()
+    .map(|(name, setting)| (name.to_string(), Some(setting), "build".to_string()))
+    .collect();
+
+    Spi::connect(|client| {
+        let select = client.select(
+            "SELECT name, setting, source FROM pg_settings \
+             WHERE name LIKE 'kq.calendar.%' ORDER BY name",
+            None,
+            None,
+        );
+        match select {
+            Ok(tuple_table) => {
+                for row in tuple_table {
+                    let column = |index: usize| {
+                        row[index]
+                            .value::<String>()
+                            .unwrap_or_else(|err| error!("server interface error - {err}"))
+                    };
+                    rows.push((
+                        column(1).unwrap_or_default(),
+                        column(2),
+                        column(3).unwrap_or_default(),
+                    ));
+                }
+            }
+            Err(spi_error) => error!("cannot read the kq.calendar settings. {spi_error}"),
+        }
+    });
+    TableIterator::new(rows)
+}
+
+/// Same contents as `kq_cx_info()` as a JSON document, for monitoring agents: `build`, `settings`
+/// (every kq.calendar.* setting), `control` and one object per calendar in `calendars`.
+#[pg_extern(parallel_safe)]
+fn kq_cx_info_json() -> JsonB {
+    check_shmem_layout();
+    let control = CALENDAR_CONTROL.share().clone();
+    let settings = Spi::get_one::<JsonB>(
+        "SELECT coalesce(jsonb_object_agg(name, setting), '{}') FROM pg_settings \
+         WHERE name LIKE 'kq.calendar.%'",
+    )
+    .unwrap_or_else(|spi_error| error!("cannot read the kq.calendar settings. {spi_error}"))
+    .map_or(serde_json::Value::Null, |settings| settings.0);
+    let last_population_error = match control.population_failures {
+        0 => serde_json::Value::Null,
+        _ => serde_json::json!({
+            "message": control.population_error.as_str(),
+            "phase": control.population_error_phase.as_str(),
+            "at": timestamp_to_string(control.population_error_at),
+        }),
+    };
+    let calendars: Vec<serde_json::Value> = get_calendars_info()
+        .into_iter()
+        .map(|info| {
+            serde_json::json!({
+                "calendar_id": info.0,
+                "calendar_xuid": info.1,
+                "entries": info.2,
+                "page_size": info.3,
+                "page_map_entries": info.4,
+                "checksum": info.5,
+                "calendar_name": info.6,
+                "calendar_type": info.7,
+                "timezone": info.8,
+                "lookups": info.9,
+                "last_used": info.10.map(|last_used| last_used.to_string()),
+                "first_entry": info.11.map(|date| date.to_string()),
+                "last_entry": info.12.map(|date| date.to_string()),
+                "bytes_used": info.13,
+                "loaded_at": info.14.map(|loaded_at| loaded_at.to_string()),
+                "load_duration_ms": info.15,
+                "skip_tables_built": info.16,
+            })
+        })
+        .collect();
+
+    JsonB(serde_json::json!({
+        "build": {
+            "postgresql_version_num": pg_sys::PG_VERSION_NUM,
+            "extension_version": env!("CARGO_PKG_VERSION"),
+            "shmem_layout_version": SHMEM_LAYOUT_VERSION,
+            "debug": cfg!(debug_assertions),
+            "max_calendars": MAX_CALENDARS,
+            "max_entries_per_calendar": MAX_ENTRIES_PER_CALENDAR,
+        },
+        "settings": settings,
+        "control": {
+            "cache_available": control.cache_filled,
+            "cache_being_filled": control.cache_being_filled,
+            "cache_stale": control.stale,
+            "population_failures": control.population_failures,
+            "last_population_error": last_population_error,
+            "generation": control.generation,
+            "calendar_count": control.calendar_count,
+            "entry_count": control.entry_count,
+            "membership_bitmaps_bytes": membership::bitmaps_size(),
+            "cache_checksum": format_checksum(get_cache_checksum()),
+        },
+        "calendars": calendars,
+    }))
+}
+
+fn timestamp_to_string(timestamp: pg_sys::TimestampTz) -> String {
+    unsafe { TimestampWithTimeZone::from_datum(pg_sys::Datum::from(timestamp), false) }
+        .map(|timestamp| timestamp.to_string())
+        .unwrap_or_default()
+}
+
+/// One word summary of the cache for monitoring: a population is running, the cache is stale or
+/// the last population failed, before whether it is filled.
+fn cache_state(control: &CalendarControl) -> &'static str {
+    if control.cache_being_filled {
+        "filling"
+    } else if control.stale {
+        "stale"
+    } else if control.population_failures > 0 {
+        "failed"
+    } else if control.cache_filled {
+        "ready"
+    } else {
+        "empty"
+    }
+}
+
+/// Bytes of shared memory taken by the cached calendars (and the membership bitmaps), and reserved
+/// for them.
+fn cache_memory() -> (usize, usize) {
+    let calendar_id_map = CALENDAR_ID_MAP.share();
+    let used: usize = share_calendars(&calendar_id_map)
+        .map(|calendar| calendar.used_bytes())
+        .sum();
+    drop(calendar_id_map);
+    let mut limit = CALENDAR_LOCK_STRIPES * std::mem::size_of::<CalendarStripe>();
+    if membership::enabled() {
+        limit += std::mem::size_of::<membership::CalendarBitmapMap>();
+    }
+    (used + membership::bitmaps_size(), limit)
+}
+
+/// State of the cache and of its last failed population, for monitoring from any session. One
+/// row with everything `kq_cx_info()` reports about the whole cache.
+#[pg_extern(parallel_safe)]
+fn kq_cx_health() -> TableIterator<
+    'static,
+    (
+        name!(cache_available, bool),
+        name!(cache_stale, bool),
+        name!(population_failures, i32),
+        name!(last_error, Option<String>),
+        name!(last_error_phase, Option<String>),
+        name!(last_error_at, Option<TimestampWithTimeZone>),
+        name!(cache_state, String),
+        name!(generation, i64),
+        name!(calendars, i64),
+        name!(entries, i64),
+        name!(last_populate_at, Option<TimestampWithTimeZone>),
+        name!(memory_used, i64),
+        name!(memory_limit, i64),
+        name!(empty_calendars, Vec<String>),
+    ),
+> {
+    let control = CALENDAR_CONTROL.share().clone();
+    let failed = control.population_failures > 0;
+    let (memory_used, memory_limit) = cache_memory();
+    let empty_calendars = get_empty_calendar_xuids();
+    TableIterator::once((
+        control.cache_filled,
+        control.stale,
+        control.population_failures as i32,
+        failed.then(|| control.population_error.to_string()),
+        failed.then(|| control.population_error_phase.as_str().to_string()),
+        failed
+            .then(|| unsafe {
+                TimestampWithTimeZone::from_datum(
+                    pg_sys::Datum::from(control.population_error_at),
+                    false,
+                )
+            })
+            .flatten(),
+        cache_state(&control).to_string(),
+        control.generation as i64,
+        control.calendar_count as i64,
+        control.entry_count as i64,
+        (control.populated_at != 0)
+            .then(|| unsafe {
+                TimestampWithTimeZone::from_datum(pg_sys::Datum::from(control.populated_at), false)
+            })
+            .flatten(),
+        memory_used as i64,
+        memory_limit as i64,
+        empty_calendars,
+    ))
+}
+
+/// Xuids of the cached calendars without entries nor a parent, whose lookups follow
+/// kq.calendar.empty_calendar. Read under the same locks as the calendars, a calendar without a
+/// xuid is reported by its id.
+fn get_empty_calendar_xuids() -> Vec<String> {
+    let calendar_id_map = CALENDAR_ID_MAP.share();
+    let calendar_xuids: HashMap<i64, String> = CALENDAR_XUID_ID_MAP
+        .share()
+        .iter()
+        .map(|((namespace, xuid), calendar_id)| {
+            (
+                *calendar_id,
+                xuids::cached_xuid(namespace, xuid, *calendar_id),
+            )
+        })
+        .collect();
+    let mut calendar_ids: Vec<i64> = share_calendars(&calendar_id_map)
+        .filter(|calendar| calendar.dates().is_empty() && calendar.parent_id().is_none())
+        .map(|calendar| calendar.calendar_id)
+        .collect();
+    drop(calendar_id_map);
+    calendar_ids.sort_unstable();
+    calendar_ids
+        .into_iter()
+        .map(|calendar_id| {
+            calendar_xuids
+                .get(&calendar_id)
+                .cloned()
+                .unwrap_or_else(|| calendar_id.to_string())
+        })
+        .collect()
+}
+
+/// Readiness probe: true only when the cache is populated, no population is running and the last
+/// one did not fail. Never populates the cache nor raises an error, for Kubernetes readiness probes
+/// or pgbouncer's server_check_query.
+#[pg_extern(parallel_safe)]
+fn kq_cx_ready() -> bool {
+    if !shmem_layout_matches() {
+        return false;
+    }
+    let control = CALENDAR_CONTROL.share();
+    control.cache_filled && !control.cache_being_filled && control.population_failures == 0
+}
+
+/// Snapshots the `(calendar_id, label)` pairs of the cached calendars, so display functions can
+/// stream each calendar's rows without holding the map locks for the whole scan.
+fn get_calendar_labels() -> Vec<(i64, String)> {
     CALENDAR_ID_MAP
         .share()
         .iter()
-        .for_each(|(calendar_id, calendar)| {
-            let calendar_name =
+        .map(|(calendar_id, _)| {
+            let calendar_xuid =
                 get_calendar_xuid_from_id(CALENDAR_XUID_ID_MAP.share(), calendar_id);
-            calendar.dates.iter().for_each(|date| {
-                data.push((format!("{} ({})", calendar_id, calendar_name), unsafe {
-                    PgDate::from_pg_epoch_days(*date)
-                }));
-            });
+            (*calendar_id, format!("{} ({})", calendar_id, calendar_xuid))
+        })
+        .collect()
+}
+
+#[pg_extern(parallel_safe)]
+fn kq_cx_display_cache() -> TableIterator<'static, (name!(calendar, String), name!(entry, PgDate))>
+{
+    // Rows are produced one calendar at a time, the share lock is only held while copying the
+    // dates of the calendar being returned.
+    let rows = get_calendar_labels()
+        .into_iter()
+        .flat_map(|(calendar_id, label)| {
+            let dates = share_calendar(&CALENDAR_ID_MAP.share(), calendar_id)
+                .map(|calendar| calendar.dates().to_vec())
+                .unwrap_or_default();
+            dates
+                .into_iter()
+                .map(move |date| (label.clone(), unsafe { PgDate::from_pg_epoch_days(date) }))
         });
-    TableIterator::new(data)
+    TableIterator::new(rows)
 }
 
 #[pg_extern(parallel_safe)]
 fn kq_cx_display_page_map() -> TableIterator<'static, (name!(calendar, String), name!(index, i64))>
 {
-    let mut data: Vec<(String, i64)> = vec![];
-    CALENDAR_ID_MAP
-        .share()
-        .iter()
-        .for_each(|(calendar_id, calendar)| {
-            let calendar_name =
-                get_calendar_xuid_from_id(CALENDAR_XUID_ID_MAP.share(), calendar_id);
-            calendar.page_map.iter().for_each(|index| {
-                data.push((
-                    format!("{} ({})", calendar_id, calendar_name),
-                    *index as i64,
-                ));
-            });
+    let rows = get_calendar_labels()
+        .into_iter()
+        .flat_map(|(calendar_id, label)| {
+            let page_map = share_calendar(&CALENDAR_ID_MAP.share(), calendar_id)
+                .map(|calendar| calendar.page_map().to_vec())
+                .unwrap_or_default();
+            page_map
+                .into_iter()
+                .map(move |index| (label.clone(), index as i64))
         });
-    TableIterator::new(data)
+    TableIterator::new(rows)
 }
 
+/// Pages of the page map of a calendar, with the number of entries and the days each one covers.
 #[pg_extern(parallel_safe)]
-fn kq_cx_invalidate_cache() -> &'static str {
+fn kq_cx_page_stats(
+    calendar_xuid: &str,
+) -> TableIterator<
+    'static,
+    (
+        name!(page_index, i32),
+        name!(start_offset, i32),
+        name!(entry_count, i32),
+        name!(date_range, Range<PgDate>),
+    ),
+> {
+    ensure_cache_populated();
+    let Some(calendar_id) = find_calendar_id(None, calendar_xuid) else {
+        xuid_not_found_error(calendar_xuid)
+    };
+    let calendar_id_map = CALENDAR_ID_MAP.share();
+    let Some(calendar) = share_calendar(&calendar_id_map, calendar_id) else {
+        error!("calendar_id = {calendar_id} not found in cache")
+    };
+    let page_size = calendar.page_size();
+    let pages = math::page_stats(&calendar);
+    drop(calendar);
+    drop(calendar_id_map);
+
+    TableIterator::new(pages.into_iter().map(move |page| {
+        let date_range = Range::new(
+            RangeBound::Inclusive(unsafe { PgDate::from_pg_epoch_days(page.first_day) }),
+            RangeBound::Exclusive(unsafe {
+                PgDate::from_pg_epoch_days(page.first_day + page_size)
+            }),
+        );
+        (
+            page.page_index as i32,
+            page.start_offset as i32,
+            page.entry_count as i32,
+            date_range,
+        )
+    }))
+}
+
+/// Recomputes the page size and page map of one calendar (all of them when NULL) from the cached
+/// entries, with `page_size` instead of the calculated size when given. The entries are not
+/// reloaded. Returns the number of calendars rebuilt.
+#[pg_extern]
+fn kq_cx_rebuild_pages(
+    calendar_xuid: default!(Option<&str>, "NULL"),
+    page_size: default!(Option<i32>, "NULL"),
+) -> i64 {
+    access::ensure_cache_admin("kq_cx_rebuild_pages");
+    if page_size.is_some_and(|page_size| page_size <= 0) {
+        error!("page_size must be greater than zero");
+    }
+    ensure_cache_populated();
+    let calendar_ids: Vec<i64> = match calendar_xuid {
+        Some(calendar_xuid) => vec![find_calendar_id(None, calendar_xuid)
+            .unwrap_or_else(|| xuid_not_found_error(calendar_xuid))],
+        None => CALENDAR_ID_MAP.share().keys().copied().collect(),
+    };
+
+    let calendar_id_map = CALENDAR_ID_MAP.share();
+    let mut rebuilt = 0;
+    for calendar_id in calendar_ids {
+        let Some(stripe) = calendar_id_map.get(&calendar_id) else {
+            continue;
+        };
+        let mut calendar_stripe = CALENDAR_STRIPES[*stripe].exclusive();
+        let Some(calendar) = calendar_stripe.get_mut(&calendar_id) else {
+            continue;
+        };
+        // built aside, a failed rebuild leaves the cached page map untouched
+        let mut rebuilt_calendar = Box::new(calendar.clone());
+        if let Err(err) = rebuilt_calendar.build_page_map_sized(page_size) {
+            error!("cannot rebuild the page map of calendar_id = {calendar_id}: {err}");
+        }
+        *calendar = *rebuilt_calendar;
+
+        // the lookup memos refer to the page map, changed before the stripe is unlocked
+        let mut control = CALENDAR_CONTROL.exclusive();
+        control.generation = next_generation(control.generation);
+        rebuilt += 1;
+    }
+    rebuilt
+}
+
+/// Empties the cache, returns the number of entries that were cached.
+fn clear_cache() -> usize {
     debug2!("Waiting for lock...");
     let mut calendar_id_map = CALENDAR_ID_MAP.exclusive();
 
     CALENDAR_XUID_ID_MAP.exclusive().clear();
-    *CALENDAR_CONTROL.exclusive() = CalendarControl::default();
+    let mut control = CALENDAR_CONTROL.exclusive();
+    let entry_count = control.entry_count;
+    *control = CalendarControl {
+        populated_at: control.populated_at,
+        generation: next_generation(control.generation),
+        layout: control.layout.clone(),
+        ..Default::default()
+    };
+    drop(control);
 
-    calendar_id_map.clear();
-    "Cache invalidated."
+    clear_calendars(&mut calendar_id_map);
+    entry_count
 }
 
-#[pg_extern(parallel_safe, immutable)]
-fn kq_cx_add_days(input_date: PgDate, interval: i32, calendar_id: i64) -> Option<PgDate> {
-    ensure_cache_populated();
-    match CALENDAR_ID_MAP.share().get(&calendar_id) {
-        None => {
-            warning!("calendar_id = {calendar_id} not found in cache");
-            None
+#[pg_extern(parallel_safe)]
+fn kq_cx_invalidate_cache() -> &'static str {
+    access::ensure_cache_admin("kq_cx_invalidate_cache");
+    access::ensure_writable("kq_cx_invalidate_cache");
+    let started = Instant::now();
+    // marked before the worker starts, a worker finishing first clears the mark
+    let marked_stale = BACKGROUND_RELOAD.get().then(|| {
+        let mut control = CALENDAR_CONTROL.exclusive();
+        if control.cache_filled {
+            control.stale = true;
         }
-        Some(calendar) => {
-            let result_date =
-                math::add_calendar_days(calendar, input_date.to_pg_epoch_days(), interval);
-            let result_date = unsafe { PgDate::from_pg_epoch_days(result_date) };
-            Some(result_date)
+        control.cache_filled.then_some(control.entry_count)
+    });
+    if let Some(entry_count) = marked_stale.flatten() {
+        if reload::start_worker() {
+            audit::record(audit::AuditOperation::Invalidate, started, entry_count);
+            events::emit(
+                Event::CacheInvalidated,
+                "calendar cache marked as stale, reloading in the background",
+                &[
+                    ("entries", entry_count.into()),
+                    ("background_reload", true.into()),
+                ],
+            );
+            return "Cache marked as stale, reloading in the background.";
         }
+        CALENDAR_CONTROL.exclusive().stale = false;
+        warning!("cannot start the reload worker, clearing the cache instead");
     }
+    let entry_count = clear_cache();
+    audit::record(audit::AuditOperation::Invalidate, started, entry_count);
+    events::emit(
+        Event::CacheInvalidated,
+        &format!("calendar cache invalidated: entries = {entry_count}"),
+        &[
+            ("entries", entry_count.into()),
+            ("background_reload", false.into()),
+        ],
+    );
+    "Cache invalidated."
 }
 
-#[pg_extern(parallel_safe, immutable)]
-fn kq_cx_add_days_xuid(input_date: Date, interval: i32, calendar_xuid: &str) -> Option<PgDate> {
-    ensure_cache_populated();
-    let calendar_xuid: CalendarXuid = heapless::String::from_str(calendar_xuid).unwrap();
-    match CALENDAR_XUID_ID_MAP.share().get(&calendar_xuid) {
-        None => {
-            warning!("calendar_xuid = {calendar_xuid} not found in cache");
-            None
-        }
-        Some(calendar_id) => kq_cx_add_days(input_date, interval, *calendar_id),
+/// Runs `lookup` and logs it at WARNING when it takes kq.calendar.log_min_duration microseconds
+/// or more, `arguments` is only formatted for slow lookups.
+pub(crate) fn log_slow_lookup<R>(
+    function_name: &str,
+    arguments: impl FnOnce() -> String,
+    lookup: impl FnOnce() -> R,
+) -> R {
+    let min_duration = LOG_MIN_DURATION.get();
+    if min_duration < 0 {
+        return lookup();
     }
+    let started = Instant::now();
+    let result = lookup();
+    let duration_us = started.elapsed().as_micros();
+    if duration_us >= min_duration as u128 {
+        warning!(
+            "slow lookup: {function_name}({}) took {duration_us} us",
+            arguments()
+        );
+    }
+    result
 }
 
-#[pg_extern(parallel_safe)]
-fn kq_cx_populate_cache() -> &'static str {
-    ensure_cache_populated();
-    "Cache populated."
+/// Intervals beyond the entries return the out-of-bounds dates. The interval is a bigint, an
+/// int argument is cast to it.
+#[pg_extern(parallel_safe, stable)]
+fn kq_cx_add_days(input_date: PgDate, interval: i64, calendar_id: i64) -> Option<PgDate> {
+    log_slow_lookup(
+        "kq_cx_add_days",
+        || format!("{input_date}, {interval}, {calendar_id}"),
+        || add_days(input_date, interval, calendar_id),
+    )
 }
 
-#[cfg(any(test, feature = "pg_test"))]
-#[pg_schema]
-mod tests {
-    use pgrx::prelude::*;
+/// How the cached lookups locate the entry of the input date.
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum LookupMode {
+    /// Reuses the period or page of the previous lookup, see `math::LookupMemo`.
+    Memo,
+    /// Searches from the entry of the previous lookup, see `math::OrderedCursor`.
+    Ordered,
+}
 
-    extension_sql_file!("../sql/test_data.sql");
+fn add_days(input_date: PgDate, interval: i64, calendar_id: i64) -> Option<PgDate> {
+    add_days_with(input_date, interval, calendar_id, LookupMode::Memo)
+}
+
+fn add_days_with(
+    input_date: PgDate,
+    interval: i64,
+    calendar_id: i64,
+    mode: LookupMode,
+) -> Option<PgDate> {
+    if snapshot::pinned_generation().is_some() {
+        let result_date =
+            snapshot::pinned_add_days(calendar_id, input_date.to_pg_epoch_days(), interval)?;
+        return Some(unsafe { PgDate::from_pg_epoch_days(result_date) });
+    }
+    if let Some(result_date) =
+        snapshot::stale_add_days(calendar_id, input_date.to_pg_epoch_days(), interval)
+    {
+        return Some(unsafe { PgDate::from_pg_epoch_days(result_date) });
+    }
+    if uncached::fallback_active() {
+        let result_date = uncached::add_days(calendar_id, input_date, interval);
+        return Some(unsafe { PgDate::from_pg_epoch_days(result_date) });
+    }
+    ensure_cache_populated();
+    let started = usage::start();
+    let calendar_id_map = CALENDAR_ID_MAP.share();
+    let Some(calendar) =
+        resolve_calendar(&calendar_id_map, calendar_id, input_date.to_pg_epoch_days())
+    else {
+        warning!("calendar_id = {calendar_id} not found in cache");
+        return None;
+    };
+
+    // the calendar and the generation are replaced together while its stripe is locked
+    let generation = CALENDAR_CONTROL.share().generation;
+    let lookup = match mode {
+        LookupMode::Memo => LOOKUP_MEMO.with_borrow_mut(|(memo_generation, memos)| {
+            if *memo_generation != generation {
+                *memo_generation = generation;
+                memos.clear();
+            }
+            math::lookup_calendar_days_memo(
+                &calendar,
+                input_date.to_pg_epoch_days(),
+                interval,
+                memos.entry(calendar.calendar_id).or_default(),
+            )
+        }),
+        LookupMode::Ordered => ORDERED_CURSORS.with_borrow_mut(|(cursor_generation, cursors)| {
+            if *cursor_generation != generation {
+                *cursor_generation = generation;
+                cursors.clear();
+            }
+            math::lookup_calendar_days_ordered(
+                &calendar,
+                input_date.to_pg_epoch_days(),
+                interval,
+                cursors.entry(calendar.calendar_id).or_default(),
+            )
+        }),
+    };
+    let result_date = oob::lookup_date(
+        calendar.calendar_id,
+        &calendar,
+        input_date.to_pg_epoch_days(),
+        interval,
+        &lookup,
+    );
+    drop(calendar);
+    usage::record(&calendar_id_map, calendar_id, 1, started);
+    snapshot::remember(&calendar_id_map, calendar_id, generation);
+    Some(unsafe { PgDate::from_pg_epoch_days(result_date?) })
+}
+
+/// Same as `kq_cx_add_days` on the calendar of `calendar_xuid` in the current namespace.
+#[pg_extern(parallel_safe, stable)]
+fn kq_cx_add_days_xuid(input_date: Date, interval: i64, calendar_xuid: &str) -> Option<PgDate> {
+    log_slow_lookup(
+        "kq_cx_add_days_xuid",
+        || format!("{input_date}, {interval}, {calendar_xuid}"),
+        || add_days_xuid(input_date, interval, calendar_xuid),
+    )
+}
+
+/// Same as kq_cx_add_days_xuid when a calendar is given, a NULL or empty calendar_xuid is plain
+/// day arithmetic (`input_date + interval`). Not strict, NULL dates and intervals return NULL.
+#[pg_extern(parallel_safe, stable)]
+fn kq_cx_add_days_optional(
+    input_date: Option<Date>,
+    interval: Option<i64>,
+    calendar_xuid: Option<&str>,
+) -> Option<PgDate> {
+    let (input_date, interval) = (input_date?, interval?);
+    match calendar_xuid {
+        Some(calendar_xuid) if !calendar_xuid.is_empty() => {
+            kq_cx_add_days_xuid(input_date, interval, calendar_xuid)
+        }
+        _ => {
+            let lookup = math::plain_arithmetic(input_date.to_pg_epoch_days(), interval);
+            Some(unsafe { PgDate::from_pg_epoch_days(oob::result_date(&lookup)) })
+        }
+    }
+}
+
+fn add_days_xuid(input_date: Date, interval: i64, calendar_xuid: &str) -> Option<PgDate> {
+    add_days_xuid_with(input_date, interval, calendar_xuid, LookupMode::Memo)
+}
+
+fn add_days_xuid_with(
+    input_date: Date,
+    interval: i64,
+    calendar_xuid: &str,
+    mode: LookupMode,
+) -> Option<PgDate> {
+    if let Some(calendar_id) = snapshot::pinned_calendar_id(None, calendar_xuid) {
+        return add_days_with(input_date, interval, calendar_id?, mode);
+    }
+    if let Some(calendar_id) = snapshot::stale_calendar_id(None, calendar_xuid) {
+        return add_days_with(input_date, interval, calendar_id, mode);
+    }
+    if uncached::fallback_active() {
+        return uncached::add_days_xuid(input_date, interval, calendar_xuid);
+    }
+    ensure_cache_populated();
+    match find_calendar_id(None, calendar_xuid) {
+        None => {
+            warn_xuid_not_found(None, calendar_xuid);
+            None
+        }
+        Some(calendar_id) => {
+            snapshot::remember_calendar_id(None, calendar_xuid, calendar_id);
+            add_days_with(input_date, interval, calendar_id, mode)
+        }
+    }
+}
+
+/// Same as kq_cx_add_days_xuid, faster when the dates come in order (ascending or descending),
+/// e.g. over an ordered date column or in a window query: the search resumes from the entry found
+/// for the previous date of the backend instead of starting over.
+#[pg_extern(parallel_safe, stable)]
+fn kq_cx_add_days_ordered(input_date: Date, interval: i64, calendar_xuid: &str) -> Option<PgDate> {
+    log_slow_lookup(
+        "kq_cx_add_days_ordered",
+        || format!("{input_date}, {interval}, {calendar_xuid}"),
+        || add_days_xuid_with(input_date, interval, calendar_xuid, LookupMode::Ordered),
+    )
+}
+
+#[pg_extern(parallel_safe, stable, name = "kq_cx_add_days_xuid")]
+fn kq_cx_add_days_namespace_xuid(
+    input_date: Date,
+    interval: i64,
+    namespace: &str,
+    calendar_xuid: &str,
+) -> Option<PgDate> {
+    if let Some(calendar_id) = snapshot::pinned_calendar_id(Some(namespace), calendar_xuid) {
+        return kq_cx_add_days(input_date, interval, calendar_id?);
+    }
+    if let Some(calendar_id) = snapshot::stale_calendar_id(Some(namespace), calendar_xuid) {
+        return kq_cx_add_days(input_date, interval, calendar_id);
+    }
+    ensure_cache_populated();
+    match find_calendar_id(Some(namespace), calendar_xuid) {
+        None => {
+            warn_xuid_not_found(Some(namespace), calendar_xuid);
+            None
+        }
+        Some(calendar_id) => {
+            snapshot::remember_calendar_id(Some(namespace), calendar_xuid, calendar_id);
+            kq_cx_add_days(input_date, interval, calendar_id)
+        }
+    }
+}
+
+/// Calendar id of `calendar_xuid` in the current namespace for the lookups answered by
+/// `snapshot::with_calendar`: from the pinned generation or the backend snapshot when they answer
+/// the lookups, from the cache otherwise. `None` when the calendar is not found.
+fn snapshot_calendar_id(calendar_xuid: &str) -> Option<i64> {
+    if let Some(calendar_id) = snapshot::pinned_calendar_id(None, calendar_xuid) {
+        return calendar_id;
+    }
+    if let Some(calendar_id) = snapshot::stale_calendar_id(None, calendar_xuid) {
+        return Some(calendar_id);
+    }
+    ensure_cache_populated();
+    find_calendar_id(None, calendar_xuid)
+}
+
+/// Moves `steps` entries forward or backward from `input_date`, see
+/// `math::offset_calendar_days`. `kq_cx_add_days` keeps its legacy semantics.
+#[pg_extern(parallel_safe, stable)]
+fn kq_cx_offset(
+    input_date: PgDate,
+    steps: i32,
+    direction: &str,
+    calendar_xuid: &str,
+) -> Option<PgDate> {
+    let Some(parsed_direction) = math::Direction::parse(direction) else {
+        ereport!(
+            ERROR,
+            PgSqlErrorCode::ERRCODE_INVALID_PARAMETER_VALUE,
+            format!("invalid direction \"{direction}\""),
+            "Use 'forward' or 'backward'."
+        )
+    };
+    if steps < 0 {
+        ereport!(
+            ERROR,
+            PgSqlErrorCode::ERRCODE_INVALID_PARAMETER_VALUE,
+            format!("steps must not be negative, got {steps}"),
+            "Use the other direction to move the opposite way."
+        )
+    }
+    let Some(calendar_id) = snapshot_calendar_id(calendar_xuid) else {
+        warn_xuid_not_found(None, calendar_xuid);
+        return None;
+    };
+    let date = input_date.to_pg_epoch_days();
+    // strict mode reports the steps with their sign, as an interval
+    let interval = match parsed_direction {
+        math::Direction::Forward => steps as i64,
+        math::Direction::Backward => -(steps as i64),
+    };
+    let offset = |calendar: &Calendar| {
+        let lookup = math::offset_calendar_days(calendar, date, steps as u32, parsed_direction);
+        oob::lookup_date(calendar.calendar_id, calendar, date, interval, &lookup)
+    };
+    if let Some(result_date) = snapshot::with_calendar(calendar_id, date, offset) {
+        return Some(unsafe { PgDate::from_pg_epoch_days(result_date.flatten()?) });
+    }
+    ensure_cache_populated();
+    let started = usage::start();
+    let calendar_id_map = CALENDAR_ID_MAP.share();
+    let calendar = resolve_calendar(&calendar_id_map, calendar_id, date)?;
+    let result_date = offset(&calendar);
+    drop(calendar);
+    usage::record(&calendar_id_map, calendar_id, 1, started);
+    Some(unsafe { PgDate::from_pg_epoch_days(result_date?) })
+}
+
+#[pg_extern(parallel_safe, stable)]
+fn kq_cx_add_days_debug(
+    input_date: PgDate,
+    interval: i64,
+    calendar_xuid: &str,
+) -> TableIterator<
+    'static,
+    (
+        name!(result_date, PgDate),
+        name!(hit_index, i32),
+        name!(result_index, i32),
+        name!(page_map_index, i32),
+        name!(oob_flag, Option<&'static str>),
+    ),
+> {
+    let Some(calendar_id) = snapshot_calendar_id(calendar_xuid) else {
+        xuid_not_found_error(calendar_xuid)
+    };
+    let date = input_date.to_pg_epoch_days();
+    let lookup_days = |calendar: &Calendar| math::lookup_calendar_days(calendar, date, interval);
+    let lookup = match snapshot::with_calendar(calendar_id, date, lookup_days) {
+        Some(lookup) => {
+            lookup.unwrap_or_else(|| error!("calendar_id = {calendar_id} not found in cache"))
+        }
+        None => {
+            ensure_cache_populated();
+            let calendar_id_map = CALENDAR_ID_MAP.share();
+            match resolve_calendar(&calendar_id_map, calendar_id, date) {
+                None => error!("calendar_id = {calendar_id} not found in cache"),
+                Some(calendar) => lookup_days(&calendar),
+            }
+        }
+    };
+    let oob_flag = lookup.out_of_bounds.map(|oob| match oob {
+        math::OutOfBounds::Past => "past",
+        math::OutOfBounds::Future => "future",
+    });
+    TableIterator::once((
+        unsafe { PgDate::from_pg_epoch_days(oob::result_date(&lookup)) },
+        lookup.hit_index,
+        lookup.result_index,
+        lookup.page_map_index,
+        oob_flag,
+    ))
+}
+
+#[pg_extern(parallel_safe, stable)]
+fn kq_cx_explain_lookup(
+    input_date: PgDate,
+    calendar_xuid: &str,
+) -> TableIterator<
+    'static,
+    (
+        name!(step, i32),
+        name!(action, String),
+        name!(detail, String),
+    ),
+> {
+    ensure_cache_populated();
+    let Some(calendar_id) = find_calendar_id(None, calendar_xuid) else {
+        xuid_not_found_error(calendar_xuid)
+    };
+    let date = input_date.to_pg_epoch_days();
+    let calendar_id_map = CALENDAR_ID_MAP.share();
+    let Some(calendar) = resolve_calendar(&calendar_id_map, calendar_id, date) else {
+        error!("calendar_id = {calendar_id} not found in cache")
+    };
+    let format_date = |date: i32| unsafe { PgDate::from_pg_epoch_days(date) }.to_string();
+
+    let rows: Vec<_> = math::explain_closest_index_from_left(date, &calendar)
+        .into_iter()
+        .map(|step| match step {
+            math::LookupStep::PageIndex { page_map_index } => (
+                "page index".to_string(),
+                format!(
+                    "{date} / page_size {} - first_page_offset {} = {page_map_index}",
+                    calendar.page_size(),
+                    calendar.first_page_offset()
+                ),
+            ),
+            math::LookupStep::OutsideEntries {
+                first_date,
+                last_date,
+            } => (
+                "outside entries".to_string(),
+                format!(
+                    "{} not in [{}, {}]",
+                    format_date(date),
+                    format_date(first_date),
+                    format_date(last_date)
+                ),
+            ),
+            math::LookupStep::PageBounds {
+                start_index,
+                end_index,
+            } => (
+                "page bounds".to_string(),
+                format!(
+                    "entries [{start_index}, {end_index}] = [{}, {}]",
+                    format_date(calendar.dates()[start_index as usize]),
+                    format_date(calendar.dates()[end_index.max(start_index) as usize])
+                ),
+            ),
+            math::LookupStep::Compare {
+                left,
+                right,
+                mid,
+                mid_date,
+            } => (
+                "compare".to_string(),
+                format!(
+                    "left {left}, right {right}, mid {mid}: {} vs {}",
+                    format_date(mid_date),
+                    format_date(date)
+                ),
+            ),
+            math::LookupStep::Interpolation { guess, hit } => (
+                "interpolation".to_string(),
+                format!(
+                    "guess {guess} = {}{}",
+                    format_date(calendar.dates()[guess as usize]),
+                    if hit { "" } else { ", missed" }
+                ),
+            ),
+            math::LookupStep::Found { index } => ("found".to_string(), format!("index {index}")),
+        })
+        .collect();
+
+    TableIterator::new(
+        rows.into_iter()
+            .enumerate()
+            .map(|(step, (action, detail))| (step as i32 + 1, action, detail)),
+    )
+}
+
+/// Same as kq_cx_add_days_xuid for several calendars, the locks are taken once for all of them
+/// (a pinned generation is read instead, see `kq_cx_pin_generation`). Unknown calendars return
+/// NULL.
+#[pg_extern(parallel_safe, stable)]
+fn kq_cx_add_days_many(
+    input_date: PgDate,
+    interval: i64,
+    calendar_xuids: Vec<Option<String>>,
+) -> TableIterator<
+    'static,
+    (
+        name!(calendar_xuid, String),
+        name!(result_date, Option<PgDate>),
+    ),
+> {
+    if snapshot::active() {
+        // answered calendar by calendar from the pinned generation or the backend snapshot
+        let rows: Vec<_> = calendar_xuids
+            .into_iter()
+            .flatten()
+            .map(|xuid| {
+                let result_date = add_days_xuid(input_date, interval, &xuid);
+                (xuid, result_date)
+            })
+            .collect();
+        return TableIterator::new(rows);
+    }
+    ensure_cache_populated();
+    let namespace = current_namespace();
+    let date = input_date.to_pg_epoch_days();
+
+    // same lock order as the population, CALENDAR_ID_MAP first
+    let calendar_id_map = CALENDAR_ID_MAP.share();
+    let calendar_xuid_id_map = CALENDAR_XUID_ID_MAP.share();
+    let mut missing = vec![];
+    let rows: Vec<_> = calendar_xuids
+        .into_iter()
+        .flatten()
+        .map(|xuid| {
+            let started = usage::start();
+            let calendar_id = xuids::find(&calendar_xuid_id_map, &namespace, &xuid);
+            let calendar = calendar_id
+                .and_then(|calendar_id| resolve_calendar(&calendar_id_map, calendar_id, date));
+            let result_date = match (calendar_id, calendar) {
+                (Some(calendar_id), Some(calendar)) => {
+                    let lookup = math::lookup_calendar_days(&calendar, date, interval);
+                    let result_date =
+                        oob::lookup_date(calendar.calendar_id, &calendar, date, interval, &lookup);
+                    drop(calendar);
+                    usage::record(&calendar_id_map, calendar_id, 1, started);
+                    result_date
+                        .map(|result_date| unsafe { PgDate::from_pg_epoch_days(result_date) })
+                }
+                _ => {
+                    missing.push(xuid.clone());
+                    None
+                }
+            };
+            (xuid, result_date)
+        })
+        .collect();
+
+    drop(calendar_xuid_id_map);
+    drop(calendar_id_map);
+    if !missing.is_empty() {
+        let missing: Vec<&str> = missing.iter().map(String::as_str).collect();
+        xuid_not_found(None, &missing).report(PgLogLevel::WARNING);
+    }
+    TableIterator::new(rows)
+}
+
+/// Same as kq_cx_add_days_xuid for every pair of `dates` and `intervals`, ordered by date then
+/// interval, the locks are taken once for the whole grid. NULL elements are skipped, an unknown
+/// calendar returns no rows.
+#[pg_extern(parallel_safe, stable)]
+fn kq_cx_add_days_grid(
+    dates: Vec<Option<PgDate>>,
+    intervals: Vec<Option<i64>>,
+    calendar_xuid: &str,
+) -> TableIterator<
+    'static,
+    (
+        name!(input_date, PgDate),
+        name!(interval, i64),
+        name!(result_date, Option<PgDate>),
+    ),
+> {
+    let Some(calendar_id) = snapshot_calendar_id(calendar_xuid) else {
+        warn_xuid_not_found(None, calendar_xuid);
+        return TableIterator::new(vec![]);
+    };
+    let intervals: Vec<i64> = intervals.into_iter().flatten().collect();
+    if snapshot::active() {
+        // answered pair by pair from the pinned generation or the backend snapshot
+        let rows: Vec<_> = dates
+            .into_iter()
+            .flatten()
+            .flat_map(|input_date| {
+                intervals.iter().map(move |interval| {
+                    let result_date = add_days(input_date, *interval, calendar_id);
+                    (input_date, *interval, result_date)
+                })
+            })
+            .collect();
+        return TableIterator::new(rows);
+    }
+    ensure_cache_populated();
+
+    let started = usage::start();
+    let calendar_id_map = CALENDAR_ID_MAP.share();
+    let mut rows = Vec::with_capacity(dates.len() * intervals.len());
+    for input_date in dates.into_iter().flatten() {
+        let date = input_date.to_pg_epoch_days();
+        // the parent may differ from one date to the next
+        let Some(calendar) = resolve_calendar(&calendar_id_map, calendar_id, date) else {
+            continue;
+        };
+        for interval in &intervals {
+            let lookup = math::lookup_calendar_days(&calendar, date, *interval);
+            let result_date =
+                oob::lookup_date(calendar.calendar_id, &calendar, date, *interval, &lookup)
+                    .map(|result_date| unsafe { PgDate::from_pg_epoch_days(result_date) });
+            rows.push((input_date, *interval, result_date));
+        }
+    }
+    usage::record(&calendar_id_map, calendar_id, rows.len() as u64, started);
+    TableIterator::new(rows)
+}
+
+/// Populates the cache when it is empty. With `force` the calendars are loaded again and replace
+/// the cached ones in one step, lookups keep using the current calendars in the meantime.
+#[pg_extern(parallel_safe)]
+fn kq_cx_populate_cache(force: default!(bool, false)) -> &'static str {
+    access::ensure_cache_admin("kq_cx_populate_cache");
+    // an explicit populate does not wait for the backoff of the previous failures
+    CALENDAR_CONTROL.exclusive().population_retry_at = 0;
+    if force && CALENDAR_CONTROL.share().cache_filled {
+        access::ensure_writable("kq_cx_populate_cache");
+        reload_cache(false);
+        return "Cache reloaded.";
+    }
+    populate_cache_if_empty(true);
+    "Cache populated."
+}
+
+/// Loads the calendars again and replaces the cached ones in one step, like
+/// `kq_cx_populate_cache(true)`, reporting per calendar the entries before and after and how many
+/// were added and removed. An empty cache is populated first, its report then has no changes.
+#[pg_extern]
+fn kq_cx_refresh_cache() -> TableIterator<
+    'static,
+    (
+        name!(calendar_xuid, String),
+        name!(entries_before, Option<i64>),
+        name!(entries_after, Option<i64>),
+        name!(added, i64),
+        name!(removed, i64),
+        name!(changed, bool),
+    ),
+> {
+    access::ensure_cache_admin("kq_cx_refresh_cache");
+    access::ensure_writable("kq_cx_refresh_cache");
+    CALENDAR_CONTROL.exclusive().population_retry_at = 0;
+    populate_cache_if_empty(true);
+    let rows = reload_cache(true).into_iter().map(|change| {
+        let changed = change.added > 0
+            || change.removed > 0
+            || change.entries_before.is_none()
+            || change.entries_after.is_none();
+        (
+            change.xuid,
+            change.entries_before,
+            change.entries_after,
+            change.added,
+            change.removed,
+            changed,
+        )
+    });
+    TableIterator::new(rows.collect::<Vec<_>>())
+}
+
+/// Loads the listed calendars (xuids of the kq.calendar.tenant namespace) into the populated
+/// cache. Calendars already cached are only loaded again with `force`. Returns the number of
+/// calendars loaded.
+#[pg_extern(parallel_safe, name = "kq_cx_populate_cache")]
+fn kq_cx_populate_calendars(force: bool, calendar_xuids: VariadicArray<&str>) -> i64 {
+    access::ensure_cache_admin("kq_cx_populate_cache");
+    access::ensure_writable("kq_cx_populate_cache");
+    CALENDAR_CONTROL.exclusive().population_retry_at = 0;
+    populate_cache_if_empty(true);
+    let started = Instant::now();
+
+    let namespace = current_namespace();
+    let mut keys: Vec<(String, String)> = vec![];
+    for calendar_xuid in calendar_xuids.iter().flatten() {
+        if force || find_calendar_id(Some(&namespace), calendar_xuid).is_none() {
+            keys.push((namespace.clone(), calendar_xuid.to_string()));
+        }
+    }
+    if keys.is_empty() {
+        return 0;
+    }
+
+    let loaded_calendars = match fixture::fixture_file() {
+        Some(fixture_file) => fixture::read_fixture(&fixture_file)
+            .into_iter()
+            .filter(|loaded| keys.contains(&(loaded.namespace.clone(), loaded.xuid.clone())))
+            .collect(),
+        None => loader::load_calendars_by_key(&keys),
+    };
+    for (_, calendar_xuid) in &keys {
+        if !loaded_calendars
+            .iter()
+            .any(|loaded| loaded.xuid == *calendar_xuid)
+        {
+            error!("calendar xuid = {calendar_xuid} not found by the calendar list query");
+        }
+    }
+    let mut entry_count = 0;
+    for loaded in &loaded_calendars {
+        entry_count += loaded.calendar.dates().len();
+    }
+    let calendar_count = loaded_calendars.len() as i64;
+    let xuid_overflow = xuids::xuid_overflow();
+    for loaded in loaded_calendars {
+        let key = xuids::stored_key(&loaded.namespace, &loaded.xuid, xuid_overflow)
+            .unwrap_or_else(|err| error!("cannot add calendar_id = {}: {err}", loaded.calendar_id));
+        install_calendar(loaded.calendar_id, key, *loaded.calendar, &loaded.xuid);
+        metadata::update(loaded.calendar_id, &loaded.metadata);
+        metadata::record_load(loaded.calendar_id, started);
+    }
+    audit::record(audit::AuditOperation::Populate, started, entry_count);
+    calendar_count
+}
+
+#[cfg(any(test, feature = "pg_test"))]
+#[pg_schema]
+mod tests {
+    use pgrx::prelude::*;
+
+    extension_sql_file!("../sql/test_data.sql");
 
     fn create_date(year: i32, month: u8, day: u8) -> crate::PgDate {
         crate::PgDate::new(year, month, day).expect("Failed to create date")
@@ -564,10 +2863,166 @@ mod tests {
     #[pg_test]
     fn test_populate_cache() {
         crate::kq_cx_cache_info();
-        crate::kq_cx_populate_cache();
+        crate::kq_cx_populate_cache(false);
         crate::kq_cx_cache_info();
     }
 
+    #[pg_test]
+    fn test_refresh_cache_reports_changes() {
+        crate::clear_cache();
+        assert!(crate::kq_cx_refresh_cache().all(|row| !row.5));
+
+        Spi::run(
+            "INSERT INTO plan.calendar_date (calendar_id, \"date\") VALUES (1, '2024-07-01'), (1, '2024-08-01'); \
+             DELETE FROM plan.calendar_date WHERE calendar_id = 2 AND \"date\" = '2025-10-01'",
+        )
+        .unwrap();
+        let changes: Vec<_> = crate::kq_cx_refresh_cache()
+            .filter(|row| row.5)
+            .map(|row| (row.0, row.1, row.2, row.3, row.4))
+            .collect();
+        assert_eq!(
+            changes,
+            vec![
+                ("month".to_string(), Some(6), Some(8), 2, 0),
+                ("quarter".to_string(), Some(8), Some(7), 0, 1),
+            ]
+        );
+        crate::clear_cache();
+    }
+
+    #[pg_test]
+    fn test_populate_forced_and_listed_calendars() {
+        crate::kq_cx_populate_cache(false);
+        assert_eq!(crate::kq_cx_populate_cache(true), "Cache reloaded.");
+
+        let generation = crate::CALENDAR_CONTROL.share().generation;
+        assert_eq!(
+            Spi::get_one::<i64>("SELECT kq_cx_populate_cache(false, 'month')"),
+            Ok(Some(0))
+        );
+        assert_eq!(crate::CALENDAR_CONTROL.share().generation, generation);
+        assert_eq!(
+            Spi::get_one::<i64>("SELECT kq_cx_populate_cache(true, 'month', 'quarter')"),
+            Ok(Some(2))
+        );
+        assert_ne!(crate::CALENDAR_CONTROL.share().generation, generation);
+        assert_eq!(
+            crate::kq_cx_add_days_xuid(create_date(2024, 1, 1), 1, "quarter"),
+            Some(create_date(2024, 4, 1))
+        );
+    }
+
+    #[pg_test]
+    fn test_page_stats_cover_every_entry() {
+        let (pages, entries) = Spi::get_two::<i64, i64>(
+            "SELECT count(*), sum(entry_count) FROM kq_cx_page_stats('quarter')",
+        )
+        .unwrap();
+        assert_eq!(entries, Some(8));
+        assert!(pages.unwrap() >= 1);
+        let misplaced = Spi::get_one::<i64>(
+            "SELECT count(*) FROM kq_cx_page_stats('quarter') p \
+             WHERE p.entry_count <> (SELECT count(*) FROM kq_cx_entries_in(p.date_range, 'quarter'))",
+        );
+        assert_eq!(misplaced, Ok(Some(0)));
+    }
+
+    #[pg_test]
+    fn test_rebuild_pages_with_page_size() {
+        crate::ensure_cache_populated();
+        let expected = crate::kq_cx_add_days_xuid(create_date(2024, 5, 15), 2, "month");
+        assert_eq!(crate::kq_cx_rebuild_pages(Some("month"), Some(3)), 1);
+        assert_eq!(
+            Spi::get_one::<i32>("SELECT page_size FROM kq_cx_cache_info() WHERE calendar_id = 1"),
+            Ok(Some(3))
+        );
+        assert_eq!(
+            crate::kq_cx_add_days_xuid(create_date(2024, 5, 15), 2, "month"),
+            expected
+        );
+        assert!(crate::kq_cx_rebuild_pages(None, None) >= 3);
+    }
+
+    #[pg_test]
+    fn test_skip_intervals_answer_the_hot_lookups() {
+        let dates = [create_date(2024, 8, 15), create_date(2025, 5, 1)];
+        crate::clear_cache();
+        crate::ensure_cache_populated();
+        let expected: Vec<_> = dates
+            .iter()
+            .map(|date| crate::kq_cx_add_days(*date, -2, 2))
+            .collect();
+
+        Spi::run("SET LOCAL kq.calendar.skip_intervals = '1, -2'").unwrap();
+        crate::clear_cache();
+        crate::ensure_cache_populated();
+        {
+            let calendar_id_map = crate::CALENDAR_ID_MAP.share();
+            let calendar = crate::share_calendar(&calendar_id_map, 2).unwrap();
+            assert!(calendar.skip_tables().is_built());
+            assert_eq!(calendar.skip_tables().intervals(), [1, -2]);
+        }
+        assert!(crate::kq_cx_cache_info().all(|info| info.16));
+        let found: Vec<_> = dates
+            .iter()
+            .map(|date| crate::kq_cx_add_days(*date, -2, 2))
+            .collect();
+        assert_eq!(found, expected);
+        crate::clear_cache();
+    }
+
+    #[pg_test(error = "invalid value for kq.calendar.skip_intervals: \"x\" is not an interval")]
+    fn test_invalid_skip_intervals() {
+        Spi::run("SET LOCAL kq.calendar.skip_intervals = '1,x'").unwrap();
+        crate::skip_intervals();
+    }
+
+    #[pg_test]
+    fn test_health_after_population() {
+        crate::kq_cx_populate_cache(false);
+        let (available, stale, failures, last_error, ..) = crate::kq_cx_health().next().unwrap();
+        assert!(available);
+        assert!(!stale);
+        assert_eq!(failures, 0);
+        assert_eq!(last_error, None);
+    }
+
+    #[pg_test]
+    fn test_health_state_and_memory() {
+        crate::clear_cache();
+        let health = |column| {
+            Spi::get_one::<String>(&format!("SELECT ({column})::text FROM kq_cx_health()"))
+                .unwrap()
+                .unwrap()
+        };
+        assert_eq!(health("cache_state"), "empty");
+        assert_eq!(health("memory_used"), "0");
+
+        crate::ensure_cache_populated();
+        assert_eq!(health("cache_state"), "ready");
+        assert_eq!(health("calendars"), "3");
+        assert_eq!(health("entries"), "18");
+        assert_eq!(
+            health(
+                "last_populate_at IS NOT NULL AND memory_used > 0 AND memory_used < memory_limit"
+            ),
+            "true"
+        );
+        crate::clear_cache();
+    }
+
+    #[pg_test]
+    fn test_calendars_are_spread_over_stripes() {
+        crate::ensure_cache_populated();
+        let calendar_id_map = crate::CALENDAR_ID_MAP.share();
+        let stripes: std::collections::HashSet<_> = calendar_id_map.values().collect();
+        assert_eq!(
+            stripes.len(),
+            calendar_id_map.len().min(crate::CALENDAR_LOCK_STRIPES)
+        );
+    }
+
     #[pg_test]
     fn test_add_calendar_days() {
         assert_eq!(
@@ -576,6 +3031,535 @@ mod tests {
         )
     }
 
+    #[pg_test]
+    fn test_slow_lookups_are_still_answered() {
+        Spi::run("SET LOCAL kq.calendar.log_min_duration = 0").unwrap();
+        assert_eq!(
+            crate::kq_cx_add_days(create_date(2024, 1, 1), 1, 1),
+            Some(create_date(2024, 2, 1))
+        );
+        assert_eq!(
+            crate::kq_cx_add_days_xuid(create_date(2024, 1, 1), 1, "month"),
+            Some(create_date(2024, 2, 1))
+        );
+    }
+
+    #[pg_test]
+    fn test_add_days_without_calendar() {
+        for calendar_xuid in [None, Some("")] {
+            assert_eq!(
+                crate::kq_cx_add_days_optional(
+                    Some(create_date(2024, 1, 30)),
+                    Some(2),
+                    calendar_xuid
+                ),
+                Some(create_date(2024, 2, 1))
+            );
+        }
+        assert_eq!(
+            crate::kq_cx_add_days_optional(Some(create_date(2024, 1, 30)), Some(2), Some("month")),
+            crate::kq_cx_add_days_xuid(create_date(2024, 1, 30), 2, "month")
+        );
+        assert_eq!(crate::kq_cx_add_days_optional(None, Some(2), None), None);
+    }
+
+    #[pg_test]
+    fn test_cache_info_reports_extent_and_load() {
+        crate::ensure_cache_populated();
+        let info = crate::kq_cx_cache_info()
+            .find(|info| info.1 == "quarter")
+            .unwrap();
+        assert_eq!(info.11, Some(create_date(2024, 1, 1)));
+        assert_eq!(info.12, Some(create_date(2025, 10, 1)));
+        assert!(info.13 >= 8 * 4);
+        assert!(info.14.is_some());
+        assert!(info.15.is_some_and(|duration_ms| duration_ms >= 0.0));
+    }
+
+    #[pg_test]
+    fn test_info_json() {
+        crate::ensure_cache_populated();
+        let info = crate::kq_cx_info_json().0;
+        assert_eq!(info["control"]["cache_available"], true);
+        assert_eq!(info["settings"]["kq.calendar.strict_bounds"], "off");
+        let calendars = info["calendars"].as_array().unwrap();
+        assert_eq!(calendars.len(), crate::kq_cx_cache_info().count());
+        let quarter = calendars
+            .iter()
+            .find(|calendar| calendar["calendar_xuid"] == "quarter")
+            .unwrap();
+        assert_eq!(quarter["entries"], 8);
+        assert_eq!(quarter["first_entry"], "2024-01-01");
+    }
+
+    #[pg_test]
+    fn test_calendar_schema() {
+        Spi::run(
+            "CREATE SCHEMA plan_test; \
+             CREATE TABLE plan_test.calendar AS SELECT * FROM plan.calendar WHERE id = 2; \
+             CREATE TABLE plan_test.calendar_date AS \
+                 SELECT * FROM plan.calendar_date WHERE calendar_id = 2; \
+             CREATE TABLE plan_test.data_date AS SELECT * FROM plan.data_date",
+        )
+        .unwrap();
+        let cached_xuids = || {
+            crate::clear_cache();
+            crate::kq_cx_list_calendars()
+                .map(|(_, calendar_xuid, _)| calendar_xuid)
+                .collect::<Vec<_>>()
+        };
+
+        Spi::run("SET LOCAL kq.calendar.schema = 'plan_test'").unwrap();
+        assert_eq!(cached_xuids(), vec!["quarter"]);
+
+        Spi::run("SET LOCAL kq.calendar.schema = ''").unwrap();
+        Spi::run("SET LOCAL search_path = plan_test, public, kq_cx").unwrap();
+        assert_eq!(cached_xuids(), vec!["quarter"]);
+        crate::clear_cache();
+    }
+
+    #[pg_test]
+    fn test_validation_modes() {
+        Spi::run("SET LOCAL kq.calendar.q_schema_validation = 'SELECT false'").unwrap();
+        crate::clear_cache();
+        let failed = PgTryBuilder::new(|| {
+            crate::ensure_cache_populated();
+            false
+        })
+        .catch_others(|_| true)
+        // resets the population backoff
+        .finally(|| {
+            crate::clear_cache();
+        })
+        .execute();
+        assert!(failed);
+
+        Spi::run("SET LOCAL kq.calendar.validation = 'warn'").unwrap();
+        crate::ensure_cache_populated();
+        assert!(crate::kq_cx_ready());
+        assert_eq!(crate::kq_cx_list_calendars().count(), 0);
+        assert_eq!(
+            crate::kq_cx_add_days_xuid(create_date(2024, 1, 1), 1, "month"),
+            None
+        );
+
+        Spi::run("SET LOCAL kq.calendar.validation = 'skip'").unwrap();
+        crate::clear_cache();
+        assert_eq!(
+            crate::kq_cx_add_days_xuid(create_date(2024, 1, 1), 1, "month"),
+            Some(create_date(2024, 2, 1))
+        );
+        crate::clear_cache();
+    }
+
+    #[pg_test]
+    fn test_settings() {
+        Spi::run("SET LOCAL kq.calendar.strict_bounds = on").unwrap();
+        let settings: Vec<_> = crate::kq_cx_settings().collect();
+        assert!(settings.contains(&(
+            "max_calendars".to_string(),
+            Some(crate::MAX_CALENDARS.to_string()),
+            "build".to_string()
+        )));
+        assert!(settings.contains(&(
+            "kq.calendar.strict_bounds".to_string(),
+            Some("on".to_string()),
+            "session".to_string()
+        )));
+        assert!(!crate::kq_cx_info().any(|(property, _)| property == "Max Calendars"));
+    }
+
+    #[pg_test]
+    fn test_list_calendars() {
+        let calendars: Vec<_> = crate::kq_cx_list_calendars().collect();
+        assert_eq!(calendars.len(), crate::kq_cx_cache_info().count());
+        assert!(calendars.contains(&(1, "month".to_string(), 6)));
+        assert!(calendars.contains(&(2, "quarter".to_string(), 8)));
+    }
+
+    #[pg_test]
+    fn test_resolve_xuid_and_id() {
+        assert_eq!(crate::kq_cx_resolve_xuid("quarter", true), Some(2));
+        assert_eq!(crate::kq_cx_resolve_xuid("quater", true), None);
+        assert_eq!(
+            crate::kq_cx_resolve_id(2, true),
+            Some("quarter".to_string())
+        );
+        assert_eq!(crate::kq_cx_resolve_id(99, true), None);
+    }
+
+    #[pg_test(error = "calendar_id = 99 not found in cache")]
+    fn test_resolve_missing_id() {
+        crate::kq_cx_resolve_id(99, false);
+    }
+
+    #[pg_test]
+    fn test_xuid_suggestions() {
+        crate::ensure_cache_populated();
+        let cached = crate::CALENDAR_XUID_ID_MAP.share().len();
+        assert_eq!(
+            crate::xuid_not_found_detail("", &["quater"]),
+            format!("{cached} calendars are cached, did you mean 'quarter'?")
+        );
+        assert_eq!(
+            crate::xuid_not_found_detail("", &["Month", "fortnight"]),
+            format!("{cached} calendars are cached, did you mean 'month' for 'Month'?")
+        );
+        assert_eq!(
+            crate::xuid_not_found_detail("other_tenant", &["quater"]),
+            format!("{cached} calendars are cached.")
+        );
+    }
+
+    #[pg_test]
+    fn test_ready() {
+        crate::clear_cache();
+        assert!(!crate::kq_cx_ready());
+        crate::ensure_cache_populated();
+        assert!(crate::kq_cx_ready());
+
+        crate::CALENDAR_CONTROL.exclusive().population_failures = 1;
+        assert!(!crate::kq_cx_ready());
+        crate::CALENDAR_CONTROL.exclusive().population_failures = 0;
+    }
+
+    #[pg_test]
+    fn test_shmem_layout_mismatch() {
+        let stamp = |version| {
+            crate::CALENDAR_CONTROL.exclusive().layout.version = version;
+            crate::SHMEM_LAYOUT_CHECKED.set(false);
+        };
+        stamp(crate::SHMEM_LAYOUT_VERSION + 1);
+        let refused = PgTryBuilder::new(|| {
+            crate::ensure_cache_populated();
+            false
+        })
+        .catch_when(
+            PgSqlErrorCode::ERRCODE_OBJECT_NOT_IN_PREREQUISITE_STATE,
+            |_| true,
+        )
+        .finally(|| stamp(crate::SHMEM_LAYOUT_VERSION))
+        .execute();
+        assert!(refused);
+        crate::ensure_cache_populated();
+    }
+
+    #[pg_test]
+    fn test_clear_cache_keeps_layout_stamp() {
+        crate::CALENDAR_CONTROL.exclusive().layout.version = crate::SHMEM_LAYOUT_VERSION + 1;
+        crate::clear_cache();
+        let version = crate::CALENDAR_CONTROL.share().layout.version;
+        crate::CALENDAR_CONTROL.exclusive().layout.version = crate::SHMEM_LAYOUT_VERSION;
+        assert_eq!(version, crate::SHMEM_LAYOUT_VERSION + 1);
+    }
+
+    #[pg_test]
+    fn test_bigint_intervals() {
+        assert_eq!(
+            crate::kq_cx_add_days(create_date(2024, 1, 1), 2, 1),
+            Some(create_date(2024, 3, 1))
+        );
+        assert_eq!(
+            crate::kq_cx_add_days(create_date(2024, 1, 1), i64::MAX, 1),
+            Some(create_date(2199, 1, 1))
+        );
+        assert_eq!(
+            crate::kq_cx_add_days(create_date(2024, 1, 1), i64::MIN, 1),
+            Some(create_date(1970, 1, 1))
+        );
+        assert_eq!(
+            crate::kq_cx_add_days_xuid(create_date(2024, 1, 1), 1 << 40, "month"),
+            Some(create_date(2199, 1, 1))
+        );
+    }
+
+    #[pg_test]
+    fn test_untyped_intervals_resolve() {
+        assert_eq!(
+            Spi::get_one::<crate::PgDate>(
+                "SELECT kq_cx_add_days_xuid('2024-01-01', NULL, 'month')"
+            )
+            .unwrap(),
+            None
+        );
+        assert_eq!(
+            Spi::get_one::<crate::PgDate>("SELECT kq_cx_add_days('2024-01-01', '2', 1)").unwrap(),
+            Some(create_date(2024, 3, 1))
+        );
+        assert_eq!(
+            Spi::get_one::<crate::PgDate>("SELECT kq_cx_add_days('2024-01-01', 2::int, 1)")
+                .unwrap(),
+            Some(create_date(2024, 3, 1))
+        );
+    }
+
+    #[pg_test]
+    fn test_add_days_debug_matches_add_days() {
+        let (result_date, hit_index, result_index, _, oob_flag) =
+            crate::kq_cx_add_days_debug(create_date(2024, 1, 1), 1, "month")
+                .next()
+                .unwrap();
+        assert_eq!(
+            Some(result_date),
+            crate::kq_cx_add_days(create_date(2024, 1, 1), 1, 1)
+        );
+        assert_eq!(result_index, hit_index + 1);
+        assert_eq!(oob_flag, None);
+    }
+
+    #[pg_test]
+    fn test_memoized_add_days_matches_debug() {
+        // consecutive days hit the memo of the previous lookup, then a new generation drops it
+        for _ in 0..2 {
+            let first_date = create_date(2024, 1, 1).to_pg_epoch_days();
+            for day in 0..90 {
+                let date = unsafe { crate::PgDate::from_pg_epoch_days(first_date + day) };
+                let (expected, ..) = crate::kq_cx_add_days_debug(date, 1, "month")
+                    .next()
+                    .unwrap();
+                assert_eq!(crate::kq_cx_add_days(date, 1, 1), Some(expected));
+            }
+            crate::clear_cache();
+        }
+    }
+
+    #[pg_test]
+    fn test_explain_lookup_ends_with_found() {
+        let steps: Vec<_> =
+            crate::kq_cx_explain_lookup(create_date(2024, 1, 15), "month").collect();
+        // monthly entries are searched by interpolation
+        assert_eq!(steps[0].1, "interpolation");
+        assert_eq!(steps.last().unwrap().1, "found");
+    }
+
+    #[pg_test]
+    fn test_offset_directions() {
+        let date = |month, day| create_date(2024, month, day);
+        assert_eq!(
+            crate::kq_cx_offset(date(2, 15), 1, "forward", "month"),
+            Some(date(3, 1))
+        );
+        assert_eq!(
+            crate::kq_cx_offset(date(2, 15), 1, "backward", "month"),
+            Some(date(2, 1))
+        );
+        assert_eq!(
+            crate::kq_cx_offset(date(3, 1), 2, "Backward", "month"),
+            Some(date(1, 1))
+        );
+        // legacy semantics: the closest entry before the date is step 0
+        assert_eq!(
+            crate::kq_cx_add_days_xuid(date(2, 15), -1, "month"),
+            Some(date(1, 1))
+        );
+    }
+
+    #[pg_test]
+    fn test_add_days_xuid_with_namespace() {
+        Spi::run(
+            "SET LOCAL kq.calendar.q2_get_calendars_entry_count = \
+             'SELECT id, xuid, ''acme'' AS namespace FROM plan.calendar ORDER BY id'",
+        )
+        .unwrap();
+        crate::clear_cache();
+        crate::ensure_cache_populated();
+
+        let expected = Some(create_date(2024, 2, 1));
+        assert_eq!(
+            crate::kq_cx_add_days_namespace_xuid(create_date(2024, 1, 1), 1, "acme", "month"),
+            expected
+        );
+        assert_eq!(
+            crate::kq_cx_add_days_xuid(create_date(2024, 1, 1), 1, "month"),
+            None
+        );
+        Spi::run("SET LOCAL kq.calendar.tenant = 'acme'").unwrap();
+        assert_eq!(
+            crate::kq_cx_add_days_xuid(create_date(2024, 1, 1), 1, "month"),
+            expected
+        );
+
+        // leave a cache without namespaces for the other tests
+        crate::clear_cache();
+    }
+
+    #[pg_test]
+    fn test_add_days_falls_back_to_parent() {
+        Spi::run(
+            "SET LOCAL kq.calendar.q2_get_calendars_entry_count = \
+             'SELECT id, xuid, CASE WHEN id = 1 THEN 2 END AS parent_id FROM plan.calendar ORDER BY id'",
+        )
+        .unwrap();
+        crate::clear_cache();
+        crate::ensure_cache_populated();
+
+        // month only has entries until 2024-06-01, later dates use quarter
+        assert_eq!(
+            crate::kq_cx_add_days(create_date(2024, 3, 15), 1, 1),
+            Some(create_date(2024, 4, 1))
+        );
+        assert_eq!(
+            crate::kq_cx_add_days(create_date(2024, 8, 15), 1, 1),
+            Some(create_date(2024, 10, 1))
+        );
+
+        crate::clear_cache();
+    }
+
+    #[pg_test]
+    fn test_add_days_ordered_matches_add_days() {
+        let mismatches = Spi::get_one::<i64>(
+            "SELECT count(*) FROM ( \
+                 SELECT d, kq_cx_add_days_ordered(d, -1, 'quarter') AS ordered \
+                 FROM (SELECT d::date FROM generate_series('2023-12-01'::date, '2026-01-31', \
+                       '3 days') d ORDER BY d DESC) dates \
+             ) s WHERE ordered IS DISTINCT FROM kq_cx_add_days_xuid(d, -1, 'quarter')",
+        );
+        assert_eq!(mismatches, Ok(Some(0)));
+        // out of order after the scan
+        assert_eq!(
+            crate::kq_cx_add_days_ordered(create_date(2025, 5, 15), 1, "quarter"),
+            Some(create_date(2025, 7, 1))
+        );
+    }
+
+    #[pg_test]
+    fn test_add_days_many() {
+        let rows: Vec<_> = crate::kq_cx_add_days_many(
+            create_date(2024, 1, 15),
+            1,
+            vec![Some("month".to_string()), Some("quarter".to_string()), None],
+        )
+        .collect();
+        assert_eq!(
+            rows,
+            vec![
+                ("month".to_string(), Some(create_date(2024, 2, 1))),
+                ("quarter".to_string(), Some(create_date(2024, 4, 1))),
+            ]
+        );
+    }
+
+    #[pg_test]
+    fn test_add_days_grid() {
+        let date = |month, day| create_date(2024, month, day);
+        let rows: Vec<_> = crate::kq_cx_add_days_grid(
+            vec![Some(date(1, 15)), None, Some(date(3, 1))],
+            vec![Some(-1), Some(1), None],
+            "month",
+        )
+        .collect();
+        assert_eq!(
+            rows,
+            vec![
+                // out of bounds, the sentinel: only empty calendars return NULL
+                (date(1, 15), -1, Some(create_date(1970, 1, 1))),
+                (date(1, 15), 1, Some(date(2, 1))),
+                (date(3, 1), -1, Some(date(2, 1))),
+                (date(3, 1), 1, Some(date(4, 1))),
+            ]
+        );
+    }
+
+    #[pg_test(error = "permission denied for function kq_cx_invalidate_cache")]
+    fn test_invalidate_requires_admin_role() {
+        Spi::run("CREATE ROLE kq_cx_test_analyst").unwrap();
+        Spi::run("SET ROLE kq_cx_test_analyst").unwrap();
+        crate::kq_cx_invalidate_cache();
+    }
+
+    #[pg_test]
+    fn test_function_privileges() {
+        let can_execute = |role: &str, function: &str| {
+            Spi::get_one::<bool>(&format!(
+                "SELECT has_function_privilege('{role}', 'kq_cx.{function}', 'EXECUTE')"
+            ))
+            .unwrap()
+            .unwrap()
+        };
+        Spi::run("CREATE ROLE kq_cx_test_nobody").unwrap();
+        assert!(!can_execute(
+            "kq_cx_test_nobody",
+            "kq_cx_invalidate_cache()"
+        ));
+        assert!(!can_execute("kq_cx_test_nobody", "kq_cx_info()"));
+        assert!(!can_execute("kq_cx_reader", "kq_cx_invalidate_cache()"));
+        assert!(can_execute(
+            "kq_cx_reader",
+            "kq_cx_add_days_xuid(date, bigint, text)"
+        ));
+        assert!(can_execute("kq_cx_admin", "kq_cx_invalidate_cache()"));
+        assert!(can_execute("kq_cx_admin", "kq_cx_info()"));
+
+        let (admin_functions, reader_executable) = Spi::get_two::<i64, bool>(
+            "SELECT count(DISTINCT p.proname), \
+             bool_or(has_function_privilege('kq_cx_reader', p.oid, 'EXECUTE')) \
+             FROM pg_proc p \
+             WHERE p.pronamespace = 'kq_cx'::regnamespace \
+             AND p.proname = ANY (kq_cx.kq_cx_admin_functions())",
+        )
+        .unwrap();
+        assert_eq!(
+            admin_functions,
+            Some(crate::access::ADMIN_FUNCTIONS.len() as i64)
+        );
+        assert_eq!(reader_executable, Some(false));
+    }
+
+    /// Same arithmetic computed with a window function over plan.calendar_date, NULL when the
+    /// result falls outside the calendar.
+    const SQL_REFERENCE_ADD_DAYS: &str = r#"
+        WITH
+            entries AS (
+                SELECT "date", (row_number() OVER (ORDER BY "date") - 1)::int AS idx
+                FROM plan.calendar_date
+                WHERE calendar_id = $1
+            ),
+            prev AS (
+                SELECT COALESCE(MAX(idx), -1) AS idx FROM entries WHERE "date" <= $2
+            )
+        SELECT (
+            SELECT e."date"
+            FROM prev JOIN entries e ON e.idx = prev.idx + $3
+            WHERE prev.idx >= 0
+        )"#;
+
+    #[pg_test]
+    fn test_add_days_matches_sql_reference() {
+        let first_day = create_date(2023, 11, 1).to_pg_epoch_days();
+        for calendar_id in 1..=3i64 {
+            for day in (0..1400).step_by(11) {
+                let input_date = unsafe { crate::PgDate::from_pg_epoch_days(first_day + day) };
+                for interval in -3..=3i32 {
+                    let expected = Spi::get_one_with_args::<crate::PgDate>(
+                        SQL_REFERENCE_ADD_DAYS,
+                        vec![
+                            (PgBuiltInOids::INT8OID.oid(), calendar_id.into_datum()),
+                            (PgBuiltInOids::DATEOID.oid(), input_date.into_datum()),
+                            (PgBuiltInOids::INT4OID.oid(), interval.into_datum()),
+                        ],
+                    )
+                    .unwrap();
+                    let cached = crate::kq_cx_add_days(input_date, interval.into(), calendar_id)
+                        .unwrap()
+                        .to_pg_epoch_days();
+                    match expected {
+                        Some(expected) => assert_eq!(
+                            cached,
+                            expected.to_pg_epoch_days(),
+                            "calendar_id = {calendar_id}, date = {input_date}, interval = {interval}"
+                        ),
+                        None => assert!(
+                            cached == kq_cx_core::math::DATE_PAST
+                                || cached == kq_cx_core::math::DATE_FUTURE,
+                            "calendar_id = {calendar_id}, date = {input_date}, interval = {interval}"
+                        ),
+                    }
+                }
+            }
+        }
+    }
+
     // #[pg_test]
     // fn test_conv_pgdate_to_i32() {
     //     assert_eq!(
@@ -603,6 +3587,8 @@ pub mod pg_test {
             "log_min_messages = debug2",
             "log_min_error_statement = debug2",
             "client_min_messages = debug2",
+            "search_path = '\"$user\", public, kq_cx'",
+            "kq.calendar.kept_generations = 2",
         ]
     }
 }
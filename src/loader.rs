@@ -0,0 +1,619 @@
+use std::collections::{BTreeMap, HashMap, HashSet};
+
+use kq_cx_core::{Calendar, MAX_ENTRIES_PER_CALENDAR};
+use pgrx::prelude::*;
+
+use crate::metadata::CalendarMetadata;
+use crate::xuids::XuidOverflow;
+use crate::{
+    get_query, get_query_opt, oob, profiles, qualified_xuid, with_schema, xuids, PgDate,
+    CALENDAR_CONTROL, CALENDAR_NAMESPACE_MAX_LEN, DEF_Q4_GET_ENTRIES, DEF_Q4_GET_ENTRIES_BY_ID,
+    GUC_Q3_GET_CAL_ENTRY_COUNT, GUC_Q4_GET_ENTRIES, GUC_Q4_GET_ENTRIES_BY_ID,
+    GUC_Q5_GET_EXCEPTIONS, MAX_CALENDARS, Q3_GET_CAL_ENTRY_COUNT, Q4_GET_ENTRIES,
+    Q4_GET_ENTRIES_BY_ID, Q5_GET_EXCEPTIONS,
+};
+
+/// Calendar read from the source tables, not yet installed in shared memory.
+pub(crate) struct LoadedCalendar {
+    pub(crate) calendar_id: i64,
+    /// Optional `namespace` column of Q3, empty when the query does not return it.
+    pub(crate) namespace: String,
+    pub(crate) xuid: String,
+    pub(crate) calendar: Box<Calendar>,
+    pub(crate) metadata: CalendarMetadata,
+    /// Optional `page_size` column of Q3, the page size is calculated from the entries when NULL.
+    pub(crate) page_size: Option<i32>,
+}
+
+/// Runs the loader queries (Q3 and Q4) and returns the calendars in Q3 order, with their page
+/// maps already built. Nothing in shared memory is touched.
+pub(crate) fn load_calendars() -> Vec<LoadedCalendar> {
+    load_matching_calendars(None)
+}
+
+/// Same as `load_calendars` for the calendars with one of the `(namespace, xuid)` keys only, Q4
+/// is filtered on their ids.
+pub(crate) fn load_calendars_by_key(keys: &[(String, String)]) -> Vec<LoadedCalendar> {
+    load_matching_calendars(Some(keys))
+}
+
+fn load_matching_calendars(keys: Option<&[(String, String)]>) -> Vec<LoadedCalendar> {
+    let is_selected = |loaded: &LoadedCalendar| {
+        keys.is_none_or(|keys| {
+            keys.iter()
+                .any(|(namespace, xuid)| *namespace == loaded.namespace && *xuid == loaded.xuid)
+        })
+    };
+    let mut calendars: Vec<LoadedCalendar> = load_calendar_list()
+        .into_iter()
+        .filter(is_selected)
+        .collect();
+    let calendar_ids: Option<Vec<i64>> =
+        keys.map(|_| calendars.iter().map(|loaded| loaded.calendar_id).collect());
+    let index_by_id: HashMap<i64, usize> = calendars
+        .iter()
+        .enumerate()
+        .map(|(index, loaded)| (loaded.calendar_id, index))
+        .collect();
+
+    let mut total_entries: usize = 0;
+    load_entries(calendar_ids, |calendar_id, date| {
+        let Some(index) = index_by_id.get(&calendar_id) else {
+            error!("cannot add entries: calendar_id = {calendar_id} not initialized")
+        };
+        if let Err(err) = calendars[*index].calendar.push_date(date) {
+            error!("cannot add more entries to calendar_id = {calendar_id}: {err}");
+        }
+        total_entries += 1;
+    });
+    debug2!("{total_entries} entries loaded");
+
+    // the lookups binary search the entries, Q4 may be overridden with another order
+    for loaded in calendars.iter_mut() {
+        if loaded.calendar.sort_dates() {
+            warning!(
+                "calendar_id = {} ({}): the entries query returned its dates out of ascending order \
+                 or repeated, they were sorted",
+                loaded.calendar_id,
+                qualified_xuid(&loaded.namespace, &loaded.xuid)
+            );
+        }
+    }
+
+    for (calendar_id, (added, removed)) in load_exceptions() {
+        check_for_interrupts!();
+        let Some(index) = index_by_id.get(&calendar_id) else {
+            debug1!("exceptions ignored: calendar_id = {calendar_id} not loaded");
+            continue;
+        };
+        if let Err(err) = calendars[*index]
+            .calendar
+            .apply_exceptions(&added, &removed)
+        {
+            error!("cannot apply exceptions to calendar_id = {calendar_id}: {err}");
+        }
+    }
+    calendars.extend(load_defined_calendars().into_iter().filter(is_selected));
+
+    for loaded in calendars.iter_mut() {
+        check_for_interrupts!();
+        if let Err(err) = loaded.calendar.build_page_map_sized(loaded.page_size) {
+            error!(
+                "cannot create page_map: calendar_id = {}: {err}",
+                loaded.calendar_id
+            );
+        }
+        debug2!(
+            "page_map created: calendar_id = {}, page_size = {}",
+            loaded.calendar_id,
+            loaded.calendar.page_size()
+        );
+    }
+    calendars
+}
+
+/// Counts the rows the loader queries would return and compares them with the shared memory
+/// capacities, reporting every calendar that does not fit before anything is loaded.
+pub(crate) fn check_capacity() {
+    let calendars = load_calendar_list();
+    let entry_counts = load_entry_counts();
+    let calendar_count = calendars.len() + load_defined_calendars().len();
+
+    let mut problems = vec![];
+    if calendar_count > MAX_CALENDARS {
+        problems.push(format!(
+            "{calendar_count} calendars, limit is {MAX_CALENDARS}"
+        ));
+    }
+    let xuid_overflow = xuids::xuid_overflow();
+    let mut keys: HashMap<(&str, String), &LoadedCalendar> = HashMap::new();
+    for loaded in &calendars {
+        match xuids::stored_xuid(&loaded.xuid, xuid_overflow) {
+            Err(err) => problems.push(format!("calendar_id = {}: {err}", loaded.calendar_id)),
+            Ok(key) => {
+                if xuid_overflow == XuidOverflow::Truncate && key.len() < loaded.xuid.len() {
+                    warning!(
+                        "calendar_id = {}: xuid \"{}\" truncated to \"{key}\"",
+                        loaded.calendar_id,
+                        loaded.xuid
+                    );
+                }
+                let key = (loaded.namespace.as_str(), key.to_string());
+                if let Some(other) = keys.insert(key.clone(), loaded) {
+                    problems.push(format!(
+                        "calendar_id = {} ({}) and calendar_id = {} ({}) have the same key \"{}\"",
+                        other.calendar_id, other.xuid, loaded.calendar_id, loaded.xuid, key.1
+                    ));
+                }
+            }
+        }
+        if loaded.namespace.len() > CALENDAR_NAMESPACE_MAX_LEN {
+            problems.push(format!(
+                "calendar_id = {}: namespace \"{}\" is {} bytes long, limit is {CALENDAR_NAMESPACE_MAX_LEN}",
+                loaded.calendar_id,
+                loaded.namespace,
+                loaded.namespace.len()
+            ));
+        }
+        let entries = entry_counts.get(&loaded.calendar_id).copied().unwrap_or(0);
+        if entries > MAX_ENTRIES_PER_CALENDAR as i64 {
+            problems.push(format!(
+                "calendar_id = {} ({}): {entries} entries, limit is {MAX_ENTRIES_PER_CALENDAR}",
+                loaded.calendar_id, loaded.xuid
+            ));
+        }
+    }
+
+    if !problems.is_empty() {
+        CALENDAR_CONTROL.exclusive().capacity_exceeded = true;
+        ereport!(
+            ERROR,
+            PgSqlErrorCode::ERRCODE_PROGRAM_LIMIT_EXCEEDED,
+            "calendar cache capacity exceeded",
+            problems.join("\n")
+        );
+    }
+}
+
+/// Number of Q4 rows per calendar_id, Q4 is wrapped in an aggregate so the entries are only
+/// counted and never sent back.
+fn load_entry_counts() -> HashMap<i64, i64> {
+    let q4 = get_query(GUC_Q4_GET_ENTRIES, &Q4_GET_ENTRIES);
+    let query = format!(
+        "SELECT calendar_id, COUNT(*) FROM ({}) AS entries(calendar_id, entry_date) GROUP BY 1",
+        q4.trim().trim_end_matches(';')
+    );
+    let mut entry_counts = HashMap::new();
+    Spi::connect(|client| match client.select(&query, None, None) {
+        Ok(tuple_table) => {
+            for row in tuple_table {
+                let calendar_id = row[1]
+                    .value::<i64>()
+                    .unwrap_or_else(|err| error!("server interface error - {err}"))
+                    .unwrap_or_else(|| error!("cannot get calendar_id"));
+                let entries = row[2]
+                    .value::<i64>()
+                    .unwrap_or_else(|err| error!("server interface error - {err}"))
+                    .unwrap_or_default();
+                entry_counts.insert(calendar_id, entries);
+            }
+        }
+        Err(spi_error) => {
+            error!("cannot count calendar entries. {}", spi_error)
+        }
+    });
+    entry_counts
+}
+
+/// Runs the optional exceptions query, returns the (added, removed) dates of every calendar.
+fn load_exceptions() -> HashMap<i64, (Vec<i32>, Vec<i32>)> {
+    let mut exceptions: HashMap<i64, (Vec<i32>, Vec<i32>)> = HashMap::new();
+    let Some(query) = get_query_opt(GUC_Q5_GET_EXCEPTIONS, &Q5_GET_EXCEPTIONS) else {
+        return exceptions;
+    };
+    Spi::connect(|client| match client.select(&query, None, None) {
+        Ok(tuple_table) => {
+            for row in tuple_table {
+                check_for_interrupts!();
+                let calendar_id = row[1]
+                    .value::<i64>()
+                    .unwrap_or_else(|err| error!("server interface error - {err}"))
+                    .unwrap_or_else(|| error!("cannot get calendar_id"));
+                let date = row[2]
+                    .value::<PgDate>()
+                    .unwrap_or_else(|err| error!("server interface error - {err}"))
+                    .unwrap_or_else(|| error!("cannot get exception date"));
+                let operation = row[3]
+                    .value::<String>()
+                    .unwrap_or_else(|err| error!("server interface error - {err}"))
+                    .unwrap_or_default();
+
+                let (added, removed) = exceptions.entry(calendar_id).or_default();
+                match operation.as_str() {
+                    "add" => added.push(date.to_pg_epoch_days()),
+                    "remove" => removed.push(date.to_pg_epoch_days()),
+                    _ => error!(
+                        "invalid exception '{operation}' for calendar_id = {calendar_id}, expected add or remove"
+                    ),
+                }
+            }
+        }
+        Err(spi_error) => {
+            error!("cannot load calendar exceptions. {}", spi_error)
+        }
+    });
+    exceptions
+}
+
+/// Calendars stored by `kq_cx_define_calendar`, the dates are already sorted and unique.
+fn load_defined_calendars() -> Vec<LoadedCalendar> {
+    let Some(table) = crate::define::definition_table() else {
+        return vec![];
+    };
+    let mut calendars = vec![];
+    Spi::connect(|client| {
+        let select = client.select(
+            &format!(
+                "SELECT calendar_id, namespace, xuid, parent_id, dates FROM {table} \
+                 ORDER BY calendar_id"
+            ),
+            None,
+            None,
+        );
+        match select {
+            Ok(tuple_table) => {
+                for row in tuple_table {
+                    let calendar_id = row[1]
+                        .value::<i64>()
+                        .unwrap_or_else(|err| error!("server interface error - {err}"))
+                        .unwrap_or_else(|| error!("cannot get calendar_id"));
+                    let namespace = row[2]
+                        .value::<String>()
+                        .unwrap_or_else(|err| error!("server interface error - {err}"))
+                        .unwrap_or_default();
+                    let xuid = row[3]
+                        .value::<String>()
+                        .unwrap_or_else(|err| error!("server interface error - {err}"))
+                        .unwrap_or_else(|| error!("cannot get calendar xuid"));
+                    let parent_id = row[4]
+                        .value::<i64>()
+                        .unwrap_or_else(|err| error!("server interface error - {err}"));
+                    let dates = row[5]
+                        .value::<Vec<PgDate>>()
+                        .unwrap_or_else(|err| error!("server interface error - {err}"))
+                        .unwrap_or_default();
+
+                    check_for_interrupts!();
+                    let mut calendar = Box::<Calendar>::default();
+                    calendar.set_parent_id(parent_id);
+                    for date in dates {
+                        if let Err(err) = calendar.push_date(date.to_pg_epoch_days()) {
+                            error!("cannot add more entries to calendar_id = {calendar_id}: {err}");
+                        }
+                    }
+                    calendars.push(LoadedCalendar {
+                        calendar_id,
+                        namespace,
+                        xuid,
+                        calendar,
+                        metadata: CalendarMetadata::default(),
+                        page_size: None,
+                    });
+                }
+            }
+            Err(spi_error) => {
+                error!("cannot load defined calendars. {}", spi_error)
+            }
+        }
+    });
+    calendars
+}
+
+/// Load calendars (id and xuid)
+fn load_calendar_list() -> Vec<LoadedCalendar> {
+    let mut calendars = vec![];
+    Spi::connect(|client| {
+        match client.select(
+            &get_query(GUC_Q3_GET_CAL_ENTRY_COUNT, &Q3_GET_CAL_ENTRY_COUNT),
+            None,
+            None,
+        ) {
+            Ok(tuple_table) => {
+                for row in tuple_table {
+                    let calendar_id = row[1]
+                        .value::<i64>()
+                        .unwrap_or_else(|err| error!("server interface error - {err}"))
+                        .unwrap_or_else(|| error!("cannot get calendar_id"));
+
+                    let xuid = row[2]
+                        .value::<String>()
+                        .unwrap_or_else(|err| error!("server interface error - {err}"))
+                        .unwrap_or_else(|| error!("cannot get calendar xuid"));
+
+                    let namespace = row
+                        .get_by_name::<String, _>("namespace")
+                        .ok()
+                        .flatten()
+                        .unwrap_or_default();
+
+                    let mut calendar = Box::<Calendar>::default();
+                    calendar.set_parent_id(row.get_by_name::<i64, _>("parent_id").ok().flatten());
+
+                    let page_size = row.get_by_name::<i32, _>("page_size").ok().flatten();
+                    if page_size.is_some_and(|page_size| page_size <= 0) {
+                        error!(
+                            "page_size of calendar_id = {calendar_id} must be greater than zero"
+                        );
+                    }
+
+                    let optional_text =
+                        |column: &str| row.get_by_name::<String, _>(column).ok().flatten();
+                    let metadata = CalendarMetadata::new(
+                        optional_text("name"),
+                        optional_text("calendar_type"),
+                        optional_text("timezone"),
+                    );
+
+                    calendars.push(LoadedCalendar {
+                        calendar_id,
+                        namespace,
+                        xuid,
+                        calendar,
+                        metadata,
+                        page_size,
+                    });
+                }
+            }
+            Err(spi_error) => {
+                error!("cannot get calendars information. {}", spi_error)
+            }
+        };
+    });
+    calendars
+}
+
+/// Query returning the Q4 rows of the calendar ids of the bigint[] $1. A custom Q4 is filtered
+/// when kq.calendar.q3_get_calendar_entries_by_id is not set, the built-in parameterized query
+/// would not return the same rows.
+fn entries_by_id_query() -> String {
+    if let Some(query) = get_query_opt(GUC_Q4_GET_ENTRIES_BY_ID, &Q4_GET_ENTRIES_BY_ID) {
+        return query;
+    }
+    if profiles::setting(GUC_Q4_GET_ENTRIES).is_none()
+        && Q4_GET_ENTRIES
+            .get()
+            .is_some_and(|raw| raw.to_bytes() == DEF_Q4_GET_ENTRIES.to_bytes())
+    {
+        return with_schema(DEF_Q4_GET_ENTRIES_BY_ID.to_str().unwrap());
+    }
+    format!(
+        "SELECT * FROM ({}) AS entries(calendar_id, entry_date) WHERE calendar_id = ANY($1)",
+        get_query(GUC_Q4_GET_ENTRIES, &Q4_GET_ENTRIES)
+            .trim()
+            .trim_end_matches(';')
+    )
+}
+
+/// Runs Q4 and calls `add_entry(calendar_id, date)` for every row, dates are pg epoch days.
+/// Only the rows of `calendar_ids` are returned when given.
+fn load_entries(calendar_ids: Option<Vec<i64>>, mut add_entry: impl FnMut(i64, i32)) {
+    let (query, args) = match calendar_ids {
+        None => (get_query(GUC_Q4_GET_ENTRIES, &Q4_GET_ENTRIES), None),
+        Some(calendar_ids) => (
+            entries_by_id_query(),
+            Some(vec![(
+                PgBuiltInOids::INT8ARRAYOID.oid(),
+                calendar_ids.into_datum(),
+            )]),
+        ),
+    };
+    Spi::connect(|client| {
+        let select = client.select(&query, None, args);
+        match select {
+            Ok(tuple_table) => {
+                for row in tuple_table {
+                    // Q4 can return millions of rows, let a cancel or statement_timeout in
+                    check_for_interrupts!();
+                    let calendar_id = row[1]
+                        .value::<i64>()
+                        .unwrap_or_else(|err| error!("server interface error - {err}"))
+                        .unwrap_or_else(|| error!("cannot get calendar_id"));
+                    let calendar_entry = row[2]
+                        .value::<PgDate>()
+                        .unwrap_or_else(|err| error!("server interface error - {err}"))
+                        .unwrap_or_else(|| error!("cannot get calendar_entry"));
+
+                    debug2!(
+                        ">> got entry: {calendar_id} => {calendar_entry} ({})",
+                        calendar_entry.to_pg_epoch_days()
+                    );
+
+                    add_entry(calendar_id, calendar_entry.to_pg_epoch_days());
+                }
+            }
+            Err(spi_error) => {
+                error!("Cannot load calendar entries. {}", spi_error)
+            }
+        }
+    });
+}
+
+/// Problems found in the Q4 rows of one calendar by `check_source`.
+#[derive(Debug, Default)]
+pub(crate) struct SourceCheck {
+    pub(crate) calendar_id: i64,
+    /// `None` for calendar ids returned by Q4 only.
+    pub(crate) xuid: Option<String>,
+    pub(crate) rows: i64,
+    pub(crate) duplicates: i64,
+    pub(crate) null_dates: i64,
+    /// Rows with a date before the one of the previous row of the calendar.
+    pub(crate) out_of_order: i64,
+    /// Rows on or outside of the kq.calendar.oob_past_date and oob_future_date dates, lookups
+    /// would not tell them from out-of-bounds results.
+    pub(crate) outside_window: i64,
+}
+
+/// Runs Q3 and Q4 as the loader does, counting the rows it would reject or mishandle instead of
+/// failing. Nothing in shared memory is touched. Returns one check per calendar, in id order.
+pub(crate) fn check_source() -> Vec<SourceCheck> {
+    let mut checks: BTreeMap<i64, SourceCheck> = load_calendar_list()
+        .into_iter()
+        .map(|loaded| {
+            (
+                loaded.calendar_id,
+                SourceCheck {
+                    calendar_id: loaded.calendar_id,
+                    xuid: Some(qualified_xuid(&loaded.namespace, &loaded.xuid)),
+                    ..Default::default()
+                },
+            )
+        })
+        .collect();
+    let mut seen: HashMap<i64, (HashSet<i32>, i32)> = HashMap::new();
+    let (past_date, future_date) = (oob::past_date(), oob::future_date());
+
+    let query = get_query(GUC_Q4_GET_ENTRIES, &Q4_GET_ENTRIES);
+    Spi::connect(|client| match client.select(&query, None, None) {
+        Ok(tuple_table) => {
+            for row in tuple_table {
+                check_for_interrupts!();
+                let calendar_id = row[1]
+                    .value::<i64>()
+                    .unwrap_or_else(|err| error!("server interface error - {err}"))
+                    .unwrap_or_else(|| error!("cannot get calendar_id"));
+                let date = row[2]
+                    .value::<PgDate>()
+                    .unwrap_or_else(|err| error!("server interface error - {err}"));
+
+                let check = checks.entry(calendar_id).or_insert_with(|| SourceCheck {
+                    calendar_id,
+                    ..Default::default()
+                });
+                check.rows += 1;
+                let Some(date) = date else {
+                    check.null_dates += 1;
+                    continue;
+                };
+                let date = date.to_pg_epoch_days();
+                if date <= past_date || date >= future_date {
+                    check.outside_window += 1;
+                }
+                let (dates, previous) = seen
+                    .entry(calendar_id)
+                    .or_insert_with(|| (HashSet::new(), i32::MIN));
+                if !dates.insert(date) {
+                    check.duplicates += 1;
+                }
+                if date < *previous {
+                    check.out_of_order += 1;
+                }
+                *previous = date;
+            }
+        }
+        Err(spi_error) => {
+            error!("Cannot load calendar entries. {}", spi_error)
+        }
+    });
+    checks.into_values().collect()
+}
+
+#[cfg(any(test, feature = "pg_test"))]
+#[pg_schema]
+mod tests {
+    use pgrx::prelude::*;
+
+    #[pg_test(error = "calendar cache capacity exceeded")]
+    fn test_capacity_check_rejects_long_xuid() {
+        Spi::run(
+            "INSERT INTO plan.calendar (id, \"name\", xuid) \
+             VALUES (99, 'too long', repeat('x', 40))",
+        )
+        .unwrap();
+        super::check_capacity();
+    }
+
+    #[pg_test(error = "calendar cache capacity exceeded")]
+    fn test_capacity_check_rejects_truncated_collisions() {
+        Spi::run("SET LOCAL kq.calendar.xuid_overflow = 'truncate'").unwrap();
+        Spi::run(
+            "INSERT INTO plan.calendar (id, \"name\", xuid) \
+             VALUES (98, 'long a', repeat('x', 40) || 'a'), (99, 'long b', repeat('x', 40) || 'b')",
+        )
+        .unwrap();
+        super::check_capacity();
+    }
+
+    #[pg_test]
+    fn test_descending_entries_are_sorted() {
+        Spi::run(
+            "SET LOCAL kq.calendar.q3_get_calendar_entries = \
+             'SELECT calendar_id, \"date\" FROM plan.calendar_date ORDER BY 2 DESC'",
+        )
+        .unwrap();
+        let month = super::load_calendars()
+            .into_iter()
+            .find(|loaded| loaded.calendar_id == 1)
+            .unwrap();
+        let dates = month.calendar.dates();
+        assert_eq!(dates.len(), 6);
+        assert!(dates.windows(2).all(|pair| pair[0] < pair[1]));
+    }
+
+    #[pg_test]
+    fn test_exceptions_are_merged() {
+        Spi::run(
+            "SET LOCAL kq.calendar.q4_get_calendar_exceptions = \
+             'SELECT 1::int8, ''2024-01-15''::date, ''add'' \
+              UNION ALL SELECT 1::int8, ''2024-02-01''::date, ''remove'''",
+        )
+        .unwrap();
+        let month = super::load_calendars()
+            .into_iter()
+            .find(|loaded| loaded.calendar_id == 1)
+            .unwrap();
+        let date = |month, day| pgrx::datum::Date::new(2024, month, day).unwrap();
+        assert_eq!(
+            month.calendar.dates()[..3],
+            [date(1, 1), date(1, 15), date(3, 1)].map(|date| date.to_pg_epoch_days())
+        );
+    }
+
+    #[pg_test]
+    fn test_page_size_column_overrides_the_calculated_size() {
+        Spi::run(
+            "SET LOCAL kq.calendar.q2_get_calendars_entry_count = \
+             'SELECT id, xuid, CASE xuid WHEN ''quarter'' THEN 8 END AS page_size \
+              FROM plan.calendar ORDER BY id'",
+        )
+        .unwrap();
+        let calendars = super::load_calendars();
+        let page_size = |calendar_id| {
+            calendars
+                .iter()
+                .find(|loaded| loaded.calendar_id == calendar_id)
+                .unwrap()
+                .calendar
+                .page_size()
+        };
+        assert_eq!(page_size(2), 8);
+        assert_ne!(page_size(1), 8);
+    }
+
+    #[pg_test]
+    fn test_listed_calendars_use_the_entries_by_id_query() {
+        let key = (String::new(), "month".to_string());
+        let month = super::load_calendars_by_key(std::slice::from_ref(&key));
+        assert_eq!(month.len(), 1);
+        assert_eq!(month[0].calendar.dates().len(), 6);
+
+        Spi::run(
+            "SET LOCAL kq.calendar.q3_get_calendar_entries_by_id = \
+             'SELECT calendar_id, \"date\" FROM plan.calendar_date \
+              WHERE calendar_id = ANY($1) AND \"date\" < ''2024-04-01'' ORDER BY 1, 2'",
+        )
+        .unwrap();
+        let month = super::load_calendars_by_key(&[key]);
+        assert_eq!(month[0].calendar.dates().len(), 3);
+    }
+}
@@ -0,0 +1,278 @@
+use std::ops::{Deref, DerefMut};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::{Duration, Instant};
+
+use kq_cx_core::latency::{self, LATENCY_BUCKETS};
+use pgrx::lwlock::{PgLwLock, PgLwLockExclusiveGuard, PgLwLockShareGuard};
+use pgrx::prelude::*;
+use pgrx::shmem::*;
+
+use crate::TRACK_LOCK_TIMES;
+
+/// Cache locks whose wait and hold times are tracked.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub(crate) enum LockName {
+    CalendarIdMap,
+    CalendarStripe,
+    CalendarXuidIdMap,
+    CalendarControl,
+}
+
+const LOCK_NAMES: [LockName; 4] = [
+    LockName::CalendarIdMap,
+    LockName::CalendarStripe,
+    LockName::CalendarXuidIdMap,
+    LockName::CalendarControl,
+];
+
+impl LockName {
+    fn as_str(&self) -> &'static str {
+        match self {
+            LockName::CalendarIdMap => "calendar_id_map",
+            LockName::CalendarStripe => "calendar_stripe",
+            LockName::CalendarXuidIdMap => "calendar_xuid_id_map",
+            LockName::CalendarControl => "calendar_control",
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum LockMode {
+    Share,
+    Exclusive,
+}
+
+impl LockMode {
+    fn as_str(&self) -> &'static str {
+        match self {
+            LockMode::Share => "share",
+            LockMode::Exclusive => "exclusive",
+        }
+    }
+}
+
+/// Durations counted in `kq_cx_core::latency` buckets.
+#[derive(Debug, Default)]
+pub struct LockTimes {
+    total_ns: AtomicU64,
+    buckets: [AtomicU64; LATENCY_BUCKETS],
+}
+
+impl LockTimes {
+    fn record(&self, duration: Duration) {
+        let ns = duration.as_nanos().min(u64::MAX as u128) as u64;
+        self.total_ns.fetch_add(ns, Ordering::Relaxed);
+        self.buckets[latency::bucket(ns)].fetch_add(1, Ordering::Relaxed);
+    }
+
+    fn buckets(&self) -> [u64; LATENCY_BUCKETS] {
+        std::array::from_fn(|index| self.buckets[index].load(Ordering::Relaxed))
+    }
+
+    fn reset(&self) {
+        self.total_ns.store(0, Ordering::Relaxed);
+        self.buckets
+            .iter()
+            .for_each(|bucket| bucket.store(0, Ordering::Relaxed));
+    }
+}
+
+#[derive(Debug, Default)]
+pub struct LockModeStats {
+    wait: LockTimes,
+    hold: LockTimes,
+}
+
+/// Indexed by `LockName` then `LockMode`. The counters are atomics so recording only takes
+/// `LOCK_STATS` in share mode.
+#[derive(Debug, Default)]
+pub struct LockStats {
+    locks: [[LockModeStats; 2]; LOCK_NAMES.len()],
+}
+
+unsafe impl PGRXSharedMemory for LockStats {}
+
+/// Taken after the tracked lock, never held while taking another lock.
+pub(crate) static LOCK_STATS: PgLwLock<LockStats> = PgLwLock::new();
+
+fn mode_stats(stats: &LockStats, name: LockName, mode: LockMode) -> &LockModeStats {
+    &stats.locks[name as usize][mode as usize]
+}
+
+/// A `PgLwLock` recording, when kq.calendar.track_lock_times is on, how long its guards waited
+/// for the lock and held it.
+pub(crate) struct TrackedLwLock<T> {
+    pub(crate) lock: PgLwLock<T>,
+    name: LockName,
+}
+
+impl<T> TrackedLwLock<T> {
+    pub(crate) const fn new(name: LockName) -> Self {
+        TrackedLwLock {
+            lock: PgLwLock::new(),
+            name,
+        }
+    }
+
+    pub(crate) fn share(&self) -> Tracked<PgLwLockShareGuard<'_, T>> {
+        Tracked::acquire(self.name, LockMode::Share, || self.lock.share())
+    }
+
+    pub(crate) fn exclusive(&self) -> Tracked<PgLwLockExclusiveGuard<'_, T>> {
+        Tracked::acquire(self.name, LockMode::Exclusive, || self.lock.exclusive())
+    }
+}
+
+/// Guard of a `TrackedLwLock`, the hold time is recorded when it is dropped.
+pub(crate) struct Tracked<G> {
+    guard: G,
+    name: LockName,
+    mode: LockMode,
+    acquired_at: Option<Instant>,
+}
+
+impl<G> Tracked<G> {
+    fn acquire(name: LockName, mode: LockMode, acquire: impl FnOnce() -> G) -> Self {
+        if !TRACK_LOCK_TIMES.get() {
+            return Tracked {
+                guard: acquire(),
+                name,
+                mode,
+                acquired_at: None,
+            };
+        }
+        let started = Instant::now();
+        let guard = acquire();
+        let acquired_at = Instant::now();
+        mode_stats(&LOCK_STATS.share(), name, mode)
+            .wait
+            .record(acquired_at - started);
+        Tracked {
+            guard,
+            name,
+            mode,
+            acquired_at: Some(acquired_at),
+        }
+    }
+}
+
+impl<G: Deref> Deref for Tracked<G> {
+    type Target = G::Target;
+
+    fn deref(&self) -> &G::Target {
+        &self.guard
+    }
+}
+
+impl<G: DerefMut> DerefMut for Tracked<G> {
+    fn deref_mut(&mut self) -> &mut G::Target {
+        &mut self.guard
+    }
+}
+
+impl<G> Drop for Tracked<G> {
+    fn drop(&mut self) {
+        if let Some(acquired_at) = self.acquired_at {
+            mode_stats(&LOCK_STATS.share(), self.name, self.mode)
+                .hold
+                .record(acquired_at.elapsed());
+        }
+    }
+}
+
+fn percentile_us(buckets: &[u64; LATENCY_BUCKETS], percent: u64) -> Option<f64> {
+    latency::percentile(buckets, percent).map(|ns| ns as f64 / 1000.0)
+}
+
+/// Wait and hold times of the cache locks since the server started or `kq_cx_reset_stats()`,
+/// collected while kq.calendar.track_lock_times is on. The percentiles are the upper bounds of
+/// power-of-two buckets, precise to a factor of two.
+#[pg_extern(parallel_safe)]
+fn kq_cx_stats() -> TableIterator<
+    'static,
+    (
+        name!(lock_name, &'static str),
+        name!(lock_mode, &'static str),
+        name!(acquisitions, i64),
+        name!(wait_total_ms, f64),
+        name!(wait_p50_us, Option<f64>),
+        name!(wait_p99_us, Option<f64>),
+        name!(hold_total_ms, f64),
+        name!(hold_p50_us, Option<f64>),
+        name!(hold_p99_us, Option<f64>),
+    ),
+> {
+    let stats = LOCK_STATS.share();
+    let mut rows = vec![];
+    for name in LOCK_NAMES {
+        for mode in [LockMode::Share, LockMode::Exclusive] {
+            let times = mode_stats(&stats, name, mode);
+            let wait = times.wait.buckets();
+            let hold = times.hold.buckets();
+            let total_ms = |times: &LockTimes| times.total_ns.load(Ordering::Relaxed) as f64 / 1e6;
+            rows.push((
+                name.as_str(),
+                mode.as_str(),
+                wait.iter().sum::<u64>() as i64,
+                total_ms(&times.wait),
+                percentile_us(&wait, 50),
+                percentile_us(&wait, 99),
+                total_ms(&times.hold),
+                percentile_us(&hold, 50),
+                percentile_us(&hold, 99),
+            ));
+        }
+    }
+    TableIterator::new(rows)
+}
+
+/// Sets the lock times reported by `kq_cx_stats()` and the lookup counts and times reported by
+/// `kq_cx_top_calendars()` back to zero.
+#[pg_extern(parallel_safe)]
+fn kq_cx_reset_stats() -> &'static str {
+    crate::access::ensure_cache_admin("kq_cx_reset_stats");
+    let stats = LOCK_STATS.share();
+    for name in LOCK_NAMES {
+        for mode in [LockMode::Share, LockMode::Exclusive] {
+            let times = mode_stats(&stats, name, mode);
+            times.wait.reset();
+            times.hold.reset();
+        }
+    }
+    drop(stats);
+    crate::usage::reset();
+    "Lock statistics reset."
+}
+
+#[cfg(any(test, feature = "pg_test"))]
+#[pg_schema]
+mod tests {
+    use pgrx::prelude::*;
+
+    #[pg_test]
+    fn test_lock_times_are_tracked() {
+        let acquisitions = || {
+            Spi::get_one::<i64>(
+                "SELECT acquisitions FROM kq_cx_stats() \
+                 WHERE lock_name = 'calendar_id_map' AND lock_mode = 'share'",
+            )
+            .unwrap()
+            .unwrap()
+        };
+        crate::ensure_cache_populated();
+        let untracked = acquisitions();
+        crate::kq_cx_add_days_xuid(pgrx::datum::Date::new(2024, 1, 1).unwrap(), 1, "month");
+        assert_eq!(acquisitions(), untracked);
+
+        Spi::run("SET LOCAL kq.calendar.track_lock_times = on").unwrap();
+        crate::kq_cx_add_days_xuid(pgrx::datum::Date::new(2024, 1, 1).unwrap(), 1, "month");
+        assert!(acquisitions() > untracked);
+        assert!(Spi::get_one::<bool>(
+            "SELECT hold_p50_us IS NOT NULL FROM kq_cx_stats() \
+                 WHERE lock_name = 'calendar_stripe' AND lock_mode = 'share'"
+        )
+        .unwrap()
+        .unwrap());
+        crate::clear_cache();
+    }
+}
@@ -0,0 +1,83 @@
+use kq_cx_core::bitmap::MembershipBitmap;
+use kq_cx_core::Calendar;
+use pgrx::lwlock::PgLwLock;
+use pgrx::prelude::*;
+
+use crate::{
+    ensure_cache_populated, find_calendar_id, share_calendar, warn_xuid_not_found, PgDate,
+    CALENDAR_ID_MAP, MAX_CALENDARS, MEMBERSHIP_BITMAPS,
+};
+
+pub(crate) type CalendarBitmapMap = heapless::FnvIndexMap<i64, MembershipBitmap, MAX_CALENDARS>;
+
+/// Only initialized when kq.calendar.membership_bitmaps is on, so the memory is not reserved
+/// otherwise. Check `enabled()` before touching it.
+pub(crate) static CALENDAR_BITMAPS: PgLwLock<CalendarBitmapMap> = PgLwLock::new();
+
+pub(crate) fn enabled() -> bool {
+    MEMBERSHIP_BITMAPS.get()
+}
+
+/// Rebuilds the bitmap of a calendar, the caller holds the lock of its stripe.
+pub(crate) fn update(calendar_id: i64, calendar: &Calendar) {
+    if !enabled() {
+        return;
+    }
+    let mut bitmaps = CALENDAR_BITMAPS.exclusive();
+    // calendars spanning too many days use the binary search
+    match MembershipBitmap::from_dates(calendar.dates()) {
+        Some(bitmap) => {
+            bitmaps.insert(calendar_id, bitmap).unwrap();
+        }
+        None => {
+            bitmaps.remove(&calendar_id);
+        }
+    }
+}
+
+pub(crate) fn clear() {
+    if enabled() {
+        CALENDAR_BITMAPS.exclusive().clear();
+    }
+}
+
+/// Bytes used by the bitmaps, 0 when they are disabled.
+pub(crate) fn bitmaps_size() -> usize {
+    if !enabled() {
+        return 0;
+    }
+    CALENDAR_BITMAPS
+        .share()
+        .values()
+        .map(|bitmap| bitmap.size())
+        .sum()
+}
+
+/// True when the date is an entry of the calendar (parents are not considered).
+#[pg_extern(parallel_safe, stable)]
+fn kq_cx_is_entry(input_date: PgDate, calendar_xuid: &str) -> Option<bool> {
+    ensure_cache_populated();
+    let Some(calendar_id) = find_calendar_id(None, calendar_xuid) else {
+        warn_xuid_not_found(None, calendar_xuid);
+        return None;
+    };
+    let date = input_date.to_pg_epoch_days();
+
+    if enabled() {
+        if let Some(bitmap) = CALENDAR_BITMAPS.share().get(&calendar_id) {
+            return Some(bitmap.contains(date));
+        }
+    }
+    share_calendar(&CALENDAR_ID_MAP.share(), calendar_id).map(|calendar| calendar.contains(date))
+}
+
+#[cfg(any(test, feature = "pg_test"))]
+#[pg_schema]
+mod tests {
+    #[pg_test]
+    fn test_is_entry() {
+        let date = |month, day| pgrx::datum::Date::new(2024, month, day).unwrap();
+        assert_eq!(super::kq_cx_is_entry(date(4, 1), "quarter"), Some(true));
+        assert_eq!(super::kq_cx_is_entry(date(5, 1), "quarter"), Some(false));
+    }
+}
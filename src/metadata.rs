@@ -0,0 +1,180 @@
+use std::time::Instant;
+
+use pgrx::lwlock::PgLwLock;
+use pgrx::prelude::*;
+
+use crate::{
+    ensure_cache_populated, find_calendar_id, share_calendar, truncated, xuid_not_found_error,
+    PgDate, CALENDAR_ID_MAP, MAX_CALENDARS,
+};
+
+const CALENDAR_NAME_MAX_LEN: usize = 64;
+const CALENDAR_TYPE_MAX_LEN: usize = 32;
+const CALENDAR_TIMEZONE_MAX_LEN: usize = 64;
+
+/// Optional `name`, `calendar_type` and `timezone` columns of Q3, longer values are truncated.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub(crate) struct CalendarMetadata {
+    name: Option<heapless::String<CALENDAR_NAME_MAX_LEN>>,
+    calendar_type: Option<heapless::String<CALENDAR_TYPE_MAX_LEN>>,
+    timezone: Option<heapless::String<CALENDAR_TIMEZONE_MAX_LEN>>,
+}
+
+impl CalendarMetadata {
+    pub(crate) fn new(
+        name: Option<String>,
+        calendar_type: Option<String>,
+        timezone: Option<String>,
+    ) -> CalendarMetadata {
+        CalendarMetadata {
+            name: name.as_deref().map(truncated),
+            calendar_type: calendar_type.as_deref().map(truncated),
+            timezone: timezone.as_deref().map(truncated),
+        }
+    }
+
+    pub(crate) fn name(&self) -> Option<String> {
+        self.name.as_ref().map(|name| name.to_string())
+    }
+
+    pub(crate) fn calendar_type(&self) -> Option<String> {
+        self.calendar_type.as_ref().map(|value| value.to_string())
+    }
+
+    pub(crate) fn timezone(&self) -> Option<String> {
+        self.timezone.as_ref().map(|value| value.to_string())
+    }
+}
+
+pub(crate) type CalendarMetadataMap = heapless::FnvIndexMap<i64, CalendarMetadata, MAX_CALENDARS>;
+
+/// Calendars without any metadata column are not stored.
+pub(crate) static CALENDAR_METADATA: PgLwLock<CalendarMetadataMap> = PgLwLock::new();
+
+/// Last time a calendar was installed in the cache and how long the population (or the call
+/// that installed it) took until then.
+#[derive(Debug, Clone, Copy, Default)]
+pub(crate) struct CalendarLoad {
+    pub(crate) loaded_at: pg_sys::TimestampTz,
+    pub(crate) duration_us: i64,
+}
+
+pub(crate) type CalendarLoadMap = heapless::FnvIndexMap<i64, CalendarLoad, MAX_CALENDARS>;
+
+pub(crate) static CALENDAR_LOADS: PgLwLock<CalendarLoadMap> = PgLwLock::new();
+
+pub(crate) fn update(calendar_id: i64, metadata: &CalendarMetadata) {
+    let mut calendar_metadata = CALENDAR_METADATA.exclusive();
+    if *metadata == CalendarMetadata::default() {
+        calendar_metadata.remove(&calendar_id);
+    } else {
+        calendar_metadata
+            .insert(calendar_id, metadata.clone())
+            .unwrap();
+    }
+}
+
+pub(crate) fn clear() {
+    CALENDAR_METADATA.exclusive().clear();
+    CALENDAR_LOADS.exclusive().clear();
+}
+
+/// Records that the calendar was installed now, by an operation that began at `started`.
+pub(crate) fn record_load(calendar_id: i64, started: Instant) {
+    let load = CalendarLoad {
+        loaded_at: unsafe { pg_sys::GetCurrentTimestamp() },
+        duration_us: started.elapsed().as_micros() as i64,
+    };
+    CALENDAR_LOADS
+        .exclusive()
+        .insert(calendar_id, load)
+        .unwrap();
+}
+
+pub(crate) fn get_load(calendar_id: i64) -> Option<CalendarLoad> {
+    CALENDAR_LOADS.share().get(&calendar_id).copied()
+}
+
+pub(crate) fn get(calendar_id: i64) -> CalendarMetadata {
+    CALENDAR_METADATA
+        .share()
+        .get(&calendar_id)
+        .cloned()
+        .unwrap_or_default()
+}
+
+/// Metadata and extent of a cached calendar in one row, the metadata columns are NULL when Q3
+/// does not return them.
+#[pg_extern(parallel_safe, stable)]
+fn kq_cx_calendar_details(
+    calendar_xuid: &str,
+) -> TableIterator<
+    'static,
+    (
+        name!(calendar_id, i64),
+        name!(calendar_name, Option<String>),
+        name!(calendar_type, Option<String>),
+        name!(timezone, Option<String>),
+        name!(parent_id, Option<i64>),
+        name!(entries, i64),
+        name!(first_entry, Option<PgDate>),
+        name!(last_entry, Option<PgDate>),
+    ),
+> {
+    ensure_cache_populated();
+    let Some(calendar_id) = find_calendar_id(None, calendar_xuid) else {
+        xuid_not_found_error(calendar_xuid)
+    };
+    let calendar_id_map = CALENDAR_ID_MAP.share();
+    let Some(calendar) = share_calendar(&calendar_id_map, calendar_id) else {
+        error!("calendar_id = {calendar_id} not found in cache")
+    };
+    let to_date = |day: &i32| unsafe { PgDate::from_pg_epoch_days(*day) };
+    let parent_id = calendar.parent_id();
+    let entries = calendar.dates().len() as i64;
+    let first_entry = calendar.dates().first().map(to_date);
+    let last_entry = calendar.dates().last().map(to_date);
+    drop(calendar);
+    drop(calendar_id_map);
+
+    let metadata = get(calendar_id);
+    TableIterator::once((
+        calendar_id,
+        metadata.name(),
+        metadata.calendar_type(),
+        metadata.timezone(),
+        parent_id,
+        entries,
+        first_entry,
+        last_entry,
+    ))
+}
+
+#[cfg(any(test, feature = "pg_test"))]
+#[pg_schema]
+mod tests {
+    use pgrx::prelude::*;
+
+    #[pg_test]
+    fn test_calendar_details() {
+        Spi::run(
+            "SET LOCAL kq.calendar.q2_get_calendars_entry_count = \
+             'SELECT id, xuid, name, ''fiscal'' AS calendar_type FROM plan.calendar ORDER BY id'",
+        )
+        .unwrap();
+        crate::clear_cache();
+        crate::ensure_cache_populated();
+
+        let name = Spi::get_one::<String>("SELECT name FROM plan.calendar WHERE xuid = 'quarter'")
+            .unwrap();
+        let details = super::kq_cx_calendar_details("quarter").next().unwrap();
+        assert_eq!(details.1, name);
+        assert_eq!(details.2.as_deref(), Some("fiscal"));
+        assert_eq!(details.3, None);
+        assert_eq!(details.5, 8);
+        assert_eq!(details.6, pgrx::datum::Date::new(2024, 1, 1).ok());
+        assert_eq!(details.7, pgrx::datum::Date::new(2025, 10, 1).ok());
+
+        crate::clear_cache();
+    }
+}
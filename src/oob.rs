@@ -0,0 +1,234 @@
+use kq_cx_core::date;
+use kq_cx_core::math::{Lookup, OutOfBounds, DATE_FUTURE, DATE_PAST};
+use kq_cx_core::Calendar;
+use pgrx::pg_sys::panic::ErrorReport;
+use pgrx::prelude::*;
+
+use crate::{
+    get_guc_string_opt, GucStrSetting, PgDate, EMPTY_CALENDAR, GUC_EMPTY_CALENDAR,
+    GUC_OOB_FUTURE_DATE, GUC_OOB_PAST_DATE, OOB_FUTURE_DATE, OOB_PAST_DATE, STRICT_BOUNDS,
+};
+
+/// Parses a sentinel setting, infinity and -infinity are the PostgreSQL infinite dates.
+fn parse_sentinel(value: &str) -> Option<i32> {
+    match value.trim().to_ascii_lowercase().as_str() {
+        "infinity" => Some(i32::MAX),
+        "-infinity" => Some(i32::MIN),
+        value => date::parse_iso(value),
+    }
+}
+
+fn sentinel_date(name: &'static str, guc: &GucStrSetting, default: i32) -> i32 {
+    let Some(value) = get_guc_string_opt(name, guc) else {
+        return default;
+    };
+    parse_sentinel(&value).unwrap_or_else(|| {
+        ereport!(
+            ERROR,
+            PgSqlErrorCode::ERRCODE_INVALID_PARAMETER_VALUE,
+            format!("invalid value for {name}: \"{value}\""),
+            "Use a YYYY-MM-DD date, infinity or -infinity."
+        )
+    })
+}
+
+/// Date returned by lookups before the first entry, kq.calendar.oob_past_date.
+pub(crate) fn past_date() -> i32 {
+    sentinel_date(GUC_OOB_PAST_DATE, &OOB_PAST_DATE, DATE_PAST)
+}
+
+/// Date returned by lookups after the last entry, kq.calendar.oob_future_date.
+pub(crate) fn future_date() -> i32 {
+    sentinel_date(GUC_OOB_FUTURE_DATE, &OOB_FUTURE_DATE, DATE_FUTURE)
+}
+
+pub(crate) fn sentinel(out_of_bounds: OutOfBounds) -> i32 {
+    match out_of_bounds {
+        OutOfBounds::Past => past_date(),
+        OutOfBounds::Future => future_date(),
+    }
+}
+
+/// Result of a lookup with the configured sentinels, the settings are only read for
+/// out-of-bounds lookups.
+pub(crate) fn result_date(lookup: &Lookup) -> i32 {
+    lookup.out_of_bounds.map_or(lookup.result_date, sentinel)
+}
+
+/// True when kq.calendar.strict_bounds is on, out-of-bounds lookups then raise an error instead
+/// of returning the sentinels.
+pub(crate) fn strict() -> bool {
+    STRICT_BOUNDS.get()
+}
+
+/// What lookups on a calendar without entries (nor a parent) return, kq.calendar.empty_calendar.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub(crate) enum EmptyCalendar {
+    /// `input_date + interval`, as if the calendar had an entry every day.
+    Passthrough,
+    Null,
+    Error,
+}
+
+pub(crate) fn empty_calendar() -> EmptyCalendar {
+    let value = get_guc_string_opt(GUC_EMPTY_CALENDAR, &EMPTY_CALENDAR).unwrap_or_default();
+    match value.trim().to_ascii_lowercase().as_str() {
+        "" | "passthrough" => EmptyCalendar::Passthrough,
+        "null" => EmptyCalendar::Null,
+        "error" => EmptyCalendar::Error,
+        _ => ereport!(
+            ERROR,
+            PgSqlErrorCode::ERRCODE_INVALID_PARAMETER_VALUE,
+            format!("invalid value for {GUC_EMPTY_CALENDAR}: \"{value}\""),
+            "Use passthrough, null or error."
+        ),
+    }
+}
+
+/// Result date of a lookup on the calendar, after the checks of strict mode and of the empty
+/// calendar policy. `None` for the empty calendars with kq.calendar.empty_calendar = null.
+pub(crate) fn lookup_date(
+    calendar_id: i64,
+    calendar: &Calendar,
+    input_date: i32,
+    interval: i64,
+    lookup: &Lookup,
+) -> Option<i32> {
+    if calendar.dates().is_empty() {
+        match empty_calendar() {
+            EmptyCalendar::Passthrough => {}
+            EmptyCalendar::Null => return None,
+            EmptyCalendar::Error => ereport!(
+                ERROR,
+                PgSqlErrorCode::ERRCODE_DATA_EXCEPTION,
+                format!("calendar_id = {calendar_id} has no entries"),
+                "Check the entries query, or set kq.calendar.empty_calendar to passthrough or null."
+            ),
+        }
+    }
+    check_lookup(calendar_id, calendar, input_date, interval, lookup);
+    Some(result_date(lookup))
+}
+
+/// Errors out when, in strict mode, the input date or the result of a lookup on the calendar
+/// falls outside of its entries. Lookups on empty calendars are plain arithmetic, never checked.
+fn check_lookup(
+    calendar_id: i64,
+    calendar: &Calendar,
+    input_date: i32,
+    interval: i64,
+    lookup: &Lookup,
+) {
+    if !strict() {
+        return;
+    }
+    let (Some(first), Some(last)) = (calendar.dates().first(), calendar.dates().last()) else {
+        return;
+    };
+    if lookup.out_of_bounds.is_some() || input_date < *first || input_date > *last {
+        bounds_error(calendar_id, input_date, interval, (*first, *last));
+    }
+}
+
+pub(crate) fn bounds_error(
+    calendar_id: i64,
+    input_date: i32,
+    interval: i64,
+    (first, last): (i32, i32),
+) -> ! {
+    let to_string = |day: i32| unsafe { PgDate::from_pg_epoch_days(day) }.to_string();
+    ErrorReport::new(
+        PgSqlErrorCode::ERRCODE_DATETIME_VALUE_OUT_OF_RANGE,
+        format!(
+            "lookup of {} with interval {interval} is out of the bounds of calendar_id = {calendar_id}",
+            to_string(input_date)
+        ),
+        pgrx::function_name!(),
+    )
+    .set_detail(format!(
+        "The cached entries of the calendar go from {} to {}.",
+        to_string(first),
+        to_string(last)
+    ))
+    .set_hint(
+        "Widen the date window of the calendar entries query (kq.calendar.q3_get_calendar_entries), \
+         or turn kq.calendar.strict_bounds off to get the out-of-bounds dates.",
+    )
+    .report(PgLogLevel::ERROR);
+    unreachable!()
+}
+
+#[cfg(any(test, feature = "pg_test"))]
+#[pg_schema]
+mod tests {
+    use pgrx::prelude::*;
+
+    #[pg_test]
+    fn test_configured_sentinels() {
+        let date = |year, month, day| pgrx::datum::Date::new(year, month, day).unwrap();
+        assert_eq!(
+            crate::kq_cx_add_days_xuid(date(2024, 5, 1), 5, "month"),
+            Some(date(2199, 1, 1))
+        );
+
+        Spi::run("SET LOCAL kq.calendar.oob_future_date = '9999-12-31'").unwrap();
+        Spi::run("SET LOCAL kq.calendar.oob_past_date = '-infinity'").unwrap();
+        assert_eq!(
+            crate::kq_cx_add_days_xuid(date(2024, 5, 1), 5, "month"),
+            Some(date(9999, 12, 31))
+        );
+        assert_eq!(
+            crate::kq_cx_add_days_xuid(date(2023, 5, 1), 1, "month"),
+            Some(unsafe { pgrx::datum::Date::from_pg_epoch_days(i32::MIN) })
+        );
+    }
+
+    #[pg_test]
+    fn test_empty_calendar_policy() {
+        let date = |year, month, day| pgrx::datum::Date::new(year, month, day).unwrap();
+        Spi::run("SELECT kq_cx_define_calendar('empty', ARRAY[]::date[])").unwrap();
+        assert_eq!(
+            crate::kq_cx_add_days_xuid(date(2024, 5, 1), 3, "empty"),
+            Some(date(2024, 5, 4))
+        );
+        Spi::run("SET LOCAL kq.calendar.empty_calendar = 'null'").unwrap();
+        assert_eq!(
+            crate::kq_cx_add_days_xuid(date(2024, 5, 1), 3, "empty"),
+            None
+        );
+        assert_eq!(
+            Spi::get_one::<String>("SELECT empty_calendars::text FROM kq_cx_health()"),
+            Ok(Some("{empty}".to_string()))
+        );
+        crate::clear_cache();
+    }
+
+    #[pg_test(error = "calendar_id = 4 has no entries")]
+    fn test_empty_calendar_error() {
+        Spi::run(r#"INSERT INTO plan.calendar (id, "name", xuid) VALUES (4, 'Empty', 'empty')"#)
+            .unwrap();
+        crate::clear_cache();
+        Spi::run("SET LOCAL kq.calendar.empty_calendar = 'error'").unwrap();
+        crate::kq_cx_add_days_xuid(pgrx::datum::Date::new(2024, 5, 1).unwrap(), 3, "empty");
+    }
+
+    #[pg_test(
+        error = "lookup of 2024-05-01 with interval 2 is out of the bounds of calendar_id = 1"
+    )]
+    fn test_strict_bounds() {
+        Spi::run("SET LOCAL kq.calendar.strict_bounds = on").unwrap();
+        let date = |month, day| pgrx::datum::Date::new(2024, month, day).unwrap();
+        assert_eq!(
+            crate::kq_cx_add_days_xuid(date(5, 1), 1, "month"),
+            Some(date(6, 1))
+        );
+        crate::kq_cx_add_days_xuid(date(5, 1), 2, "month");
+    }
+
+    #[pg_test(error = "invalid value for kq.calendar.oob_future_date: \"someday\"")]
+    fn test_invalid_sentinel() {
+        Spi::run("SET LOCAL kq.calendar.oob_future_date = 'someday'").unwrap();
+        let date = pgrx::datum::Date::new(2024, 5, 1).unwrap();
+        crate::kq_cx_add_days_xuid(date, 5, "month");
+    }
+}
@@ -0,0 +1,120 @@
+use std::time::Instant;
+
+use pgrx::prelude::*;
+
+use crate::{
+    access, audit, ensure_cache_populated, find_calendar_id, membership, next_generation,
+    xuid_not_found_error, PgDate, CALENDAR_CONTROL, CALENDAR_ID_MAP, CALENDAR_STRIPES,
+};
+
+/// Adds and removes entries of a cached calendar in place and rebuilds its page map, only its
+/// stripe is locked. Returns the number of entries added minus the number removed.
+fn patch_calendar(function_name: &str, calendar_xuid: &str, added: &[i32], removed: &[i32]) -> i64 {
+    access::ensure_cache_admin(function_name);
+    access::ensure_writable(function_name);
+    let started = Instant::now();
+    ensure_cache_populated();
+    let Some(calendar_id) = find_calendar_id(None, calendar_xuid) else {
+        xuid_not_found_error(calendar_xuid)
+    };
+
+    let calendar_id_map = CALENDAR_ID_MAP.share();
+    let Some(stripe) = calendar_id_map.get(&calendar_id) else {
+        error!("calendar_id = {calendar_id} not found in cache")
+    };
+    let mut calendar_stripe = CALENDAR_STRIPES[*stripe].exclusive();
+    let calendar = calendar_stripe.get_mut(&calendar_id).unwrap();
+    // patched aside, the cached calendar is left untouched when the entries do not fit
+    let mut patched = Box::new(calendar.clone());
+    // keeps the page size, set by the loader or kq_cx_rebuild_pages (0 without entries)
+    let page_size = Some(calendar.page_size()).filter(|page_size| *page_size > 0);
+    if let Err(err) = patched
+        .apply_exceptions(added, removed)
+        .and_then(|_| patched.build_page_map_sized(page_size))
+    {
+        error!("cannot change the entries of calendar xuid = {calendar_xuid}: {err}");
+    }
+    let change = patched.dates().len() as i64 - calendar.dates().len() as i64;
+    *calendar = *patched;
+    membership::update(calendar_id, calendar);
+
+    // changed before the stripe is unlocked, see kq_cx_add_days
+    let mut control = CALENDAR_CONTROL.exclusive();
+    control.entry_count = (control.entry_count as i64 + change) as usize;
+    control.generation = next_generation(control.generation);
+    drop(control);
+    drop(calendar_stripe);
+
+    audit::record(
+        audit::AuditOperation::Patch,
+        started,
+        added.len() + removed.len(),
+    );
+    change
+}
+
+fn epoch_days(dates: Vec<PgDate>) -> Vec<i32> {
+    dates.iter().map(|date| date.to_pg_epoch_days()).collect()
+}
+
+/// Adds entries to a cached calendar without reloading it, dates already in the calendar are
+/// ignored. The source tables are not changed, the next population discards the new entries.
+/// Returns the number of entries added.
+#[pg_extern]
+fn kq_cx_add_entries(calendar_xuid: &str, dates: Vec<PgDate>) -> i64 {
+    patch_calendar("kq_cx_add_entries", calendar_xuid, &epoch_days(dates), &[])
+}
+
+/// Removes entries from a cached calendar without reloading it, see `kq_cx_add_entries`.
+/// Returns the number of entries removed.
+#[pg_extern]
+fn kq_cx_remove_entries(calendar_xuid: &str, dates: Vec<PgDate>) -> i64 {
+    -patch_calendar(
+        "kq_cx_remove_entries",
+        calendar_xuid,
+        &[],
+        &epoch_days(dates),
+    )
+}
+
+#[cfg(any(test, feature = "pg_test"))]
+#[pg_schema]
+mod tests {
+    use pgrx::prelude::*;
+
+    #[pg_test]
+    fn test_add_and_remove_entries() {
+        let date = |month, day| pgrx::datum::Date::new(2024, month, day).unwrap();
+        crate::ensure_cache_populated();
+        assert_eq!(
+            super::kq_cx_add_entries("month", vec![date(2, 15), date(3, 1), date(2, 15)]),
+            1
+        );
+        assert_eq!(
+            crate::kq_cx_add_days_xuid(date(2, 1), 1, "month"),
+            Some(date(2, 15))
+        );
+        assert_eq!(
+            super::kq_cx_remove_entries("month", vec![date(2, 15), date(3, 1), date(12, 1)]),
+            2
+        );
+        assert_eq!(
+            crate::kq_cx_add_days_xuid(date(2, 1), 1, "month"),
+            Some(date(4, 1))
+        );
+        crate::clear_cache();
+    }
+
+    #[pg_test]
+    fn test_patch_keeps_the_page_size() {
+        let date = |month, day| pgrx::datum::Date::new(2024, month, day).unwrap();
+        crate::ensure_cache_populated();
+        assert_eq!(crate::kq_cx_rebuild_pages(Some("month"), Some(3)), 1);
+        super::kq_cx_add_entries("month", vec![date(2, 15)]);
+        assert_eq!(
+            Spi::get_one::<i32>("SELECT page_size FROM kq_cx_cache_info() WHERE calendar_id = 1"),
+            Ok(Some(3))
+        );
+        crate::clear_cache();
+    }
+}
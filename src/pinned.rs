@@ -0,0 +1,164 @@
+use pgrx::prelude::*;
+
+use crate::define::extension_schema;
+use crate::{
+    access, ensure_cache_populated, find_calendar_id, resolve_calendar, warn_xuid_not_found,
+    PgDate, CALENDAR_CONTROL, CALENDAR_ID_MAP,
+};
+
+/// Generation the pinned indexes are built for, the last argument of their
+/// kq_cx_add_days_pinned calls. Replaced by kq_cx_rebuild_pinned_indexes before it reindexes
+/// them: being immutable, the call is folded into the new constant when the index is rebuilt.
+/// Owned by kq_cx_admin so its members can replace it.
+extension_sql!(
+    r#"
+CREATE FUNCTION @extschema@.kq_cx_pinned_generation() RETURNS int8
+    LANGUAGE sql IMMUTABLE PARALLEL SAFE
+AS $$ SELECT 0::int8 $$;
+ALTER FUNCTION @extschema@.kq_cx_pinned_generation() OWNER TO kq_cx_admin;
+"#,
+    name = "create_pinned_generation_function",
+    requires = ["create_admin_role"],
+);
+
+/// Current cache generation, the value to pin in kq_cx_add_days_pinned expressions.
+#[pg_extern(parallel_safe, stable)]
+fn kq_cx_generation() -> i64 {
+    ensure_cache_populated();
+    CALENDAR_CONTROL.share().generation as i64
+}
+
+/// Same as kq_cx_add_days_xuid on the calendar of `namespace`, declared immutable so it can be
+/// used in expression indexes. It errors out when the cache generation is not `generation`,
+/// instead of returning results that no longer match the ones stored in the index. The result
+/// only depends on the arguments and the cache: the session settings (kq.calendar.tenant,
+/// strict bounds, empty calendar policy, out-of-bounds dates) and pinned generations are
+/// ignored, out-of-bounds lookups return the default 1970-01-01 and 2199-01-01.
+#[pg_extern(parallel_safe, immutable)]
+fn kq_cx_add_days_pinned(
+    input_date: PgDate,
+    interval: i64,
+    namespace: &str,
+    calendar_xuid: &str,
+    generation: i64,
+) -> Option<PgDate> {
+    ensure_cache_populated();
+    let Some(calendar_id) = find_calendar_id(Some(namespace), calendar_xuid) else {
+        warn_xuid_not_found(Some(namespace), calendar_xuid);
+        return None;
+    };
+    let calendar_id_map = CALENDAR_ID_MAP.share();
+    let calendar = resolve_calendar(&calendar_id_map, calendar_id, input_date.to_pg_epoch_days());
+    // the calendar and the generation are replaced together while its stripe is locked
+    let current_generation = CALENDAR_CONTROL.share().generation as i64;
+    if current_generation != generation {
+        drop(calendar);
+        drop(calendar_id_map);
+        ereport!(
+            ERROR,
+            PgSqlErrorCode::ERRCODE_OBJECT_NOT_IN_PREREQUISITE_STATE,
+            format!(
+                "calendar cache generation is {current_generation}, the expression is pinned to {generation}"
+            ),
+            "Run kq_cx_rebuild_pinned_indexes() after the cache is refreshed."
+        );
+    }
+    let Some(calendar) = calendar else {
+        warning!("calendar_id = {calendar_id} not found in cache");
+        return None;
+    };
+    let lookup =
+        kq_cx_core::math::lookup_calendar_days(&calendar, input_date.to_pg_epoch_days(), interval);
+    Some(unsafe { PgDate::from_pg_epoch_days(lookup.result_date) })
+}
+
+/// Pins kq_cx_pinned_generation() to the current generation and reindexes the indexes whose
+/// expressions call it, returns the indexes that were rebuilt. Runs in the caller's
+/// transaction: an index that cannot be rebuilt rolls back the whole rebuild.
+#[pg_extern]
+fn kq_cx_rebuild_pinned_indexes() -> SetOfIterator<'static, String> {
+    access::ensure_cache_admin("kq_cx_rebuild_pinned_indexes");
+    access::ensure_writable("kq_cx_rebuild_pinned_indexes");
+    let generation = kq_cx_generation();
+    let schema = extension_schema().unwrap_or_else(|| error!("extension kq_cx is not installed"));
+    Spi::run(&format!(
+        "CREATE OR REPLACE FUNCTION {schema}.kq_cx_pinned_generation() RETURNS int8 \
+         LANGUAGE sql IMMUTABLE PARALLEL SAFE AS $$ SELECT {generation}::int8 $$"
+    ))
+    .unwrap_or_else(|spi_error| error!("cannot pin generation {generation}. {spi_error}"));
+
+    let indexes: Vec<(pg_sys::Oid, String)> = Spi::connect(|client| {
+        client
+            .select(
+                &format!(
+                    "SELECT DISTINCT d.objid, d.objid::regclass::text FROM pg_depend d \
+                     JOIN pg_index i ON i.indexrelid = d.objid \
+                     WHERE d.classid = 'pg_class'::regclass \
+                     AND d.refclassid = 'pg_proc'::regclass \
+                     AND d.refobjid = '{schema}.kq_cx_pinned_generation()'::regprocedure \
+                     ORDER BY 2"
+                ),
+                None,
+                None,
+            )
+            .unwrap_or_else(|spi_error| error!("cannot list pinned indexes. {spi_error}"))
+            .map(|row| {
+                (
+                    row[1].value::<pg_sys::Oid>().ok().flatten().unwrap(),
+                    row[2].value::<String>().ok().flatten().unwrap_or_default(),
+                )
+            })
+            .collect()
+    });
+
+    let mut rebuilt = vec![];
+    for (index_oid, index_name) in indexes {
+        debug1!("reindexing {index_name} for generation {generation}");
+        // the expressions cached by this backend were folded with the previous generation
+        unsafe { pg_sys::CacheInvalidateRelcacheByRelid(index_oid) };
+        Spi::run(&format!("REINDEX INDEX {index_name}"))
+            .unwrap_or_else(|spi_error| error!("cannot rebuild index {index_name}. {spi_error}"));
+        rebuilt.push(index_name);
+    }
+    SetOfIterator::new(rebuilt)
+}
+
+#[cfg(any(test, feature = "pg_test"))]
+#[pg_schema]
+mod tests {
+    use pgrx::prelude::*;
+
+    #[pg_test]
+    fn test_rebuild_pinned_indexes() {
+        super::kq_cx_rebuild_pinned_indexes().for_each(drop);
+        Spi::run("CREATE TABLE pinned_test (d date)").unwrap();
+        Spi::run(
+            "CREATE INDEX pinned_test_idx ON pinned_test \
+             (kq_cx_add_days_pinned(d, 1, '', 'month', kq_cx_pinned_generation()))",
+        )
+        .unwrap();
+        Spi::run("INSERT INTO pinned_test VALUES ('2024-01-01')").unwrap();
+
+        crate::clear_cache();
+        crate::ensure_cache_populated();
+        let rebuilt: Vec<String> = super::kq_cx_rebuild_pinned_indexes().collect();
+        assert_eq!(rebuilt, vec!["pinned_test_idx".to_string()]);
+        Spi::run("INSERT INTO pinned_test VALUES ('2024-02-01')").unwrap();
+    }
+
+    #[pg_test]
+    fn test_pinned_ignores_session_settings() {
+        let generation = super::kq_cx_generation();
+        let date = |year, month| pgrx::datum::Date::new(year, month, 1).unwrap();
+        Spi::run("SET LOCAL kq.calendar.tenant = 'acme'").unwrap();
+        Spi::run("SET LOCAL kq.calendar.strict_bounds = on").unwrap();
+        assert_eq!(
+            super::kq_cx_add_days_pinned(date(2024, 1), 1, "", "month", generation),
+            Some(date(2024, 2))
+        );
+        assert_eq!(
+            super::kq_cx_add_days_pinned(date(2024, 1), 100_000, "", "month", generation),
+            Some(unsafe { crate::PgDate::from_pg_epoch_days(kq_cx_core::math::DATE_FUTURE) })
+        );
+    }
+}
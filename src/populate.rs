@@ -0,0 +1,148 @@
+use std::ffi::CStr;
+
+use pgrx::bgworkers::*;
+use pgrx::prelude::*;
+
+use crate::{get_guc_string_opt, reload, CALENDAR_CONTROL, GUC_POPULATE_ON, POPULATE_ON};
+
+/// When the empty cache is populated, kq.calendar.populate_on.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub(crate) enum PopulateOn {
+    /// By the first lookup of any backend.
+    FirstUse,
+    /// By a background worker started when a backend connects, the first lookup still populates
+    /// it when the worker has not.
+    Connect,
+    /// Only by kq_cx_populate_cache(), lookups on the empty cache fail.
+    Manual,
+}
+
+fn parse_populate_on(value: &str) -> Option<PopulateOn> {
+    match value.trim().to_ascii_lowercase().as_str() {
+        "" | "first_use" => Some(PopulateOn::FirstUse),
+        "connect" => Some(PopulateOn::Connect),
+        "manual" => Some(PopulateOn::Manual),
+        _ => None,
+    }
+}
+
+pub(crate) fn populate_on() -> PopulateOn {
+    let value = get_guc_string_opt(GUC_POPULATE_ON, &POPULATE_ON).unwrap_or_default();
+    parse_populate_on(&value).unwrap_or_else(|| {
+        ereport!(
+            ERROR,
+            PgSqlErrorCode::ERRCODE_INVALID_PARAMETER_VALUE,
+            format!("invalid value for {GUC_POPULATE_ON}: \"{value}\""),
+            "Use first_use, connect or manual."
+        )
+    })
+}
+
+/// Raised by the lookups on the empty cache with kq.calendar.populate_on = manual.
+pub(crate) fn not_populated_error() -> ! {
+    ereport!(
+        ERROR,
+        PgSqlErrorCode::ERRCODE_OBJECT_NOT_IN_PREREQUISITE_STATE,
+        "the calendar cache is not populated",
+        "kq.calendar.populate_on is manual, populate it with kq_cx_populate_cache()."
+    );
+    unreachable!()
+}
+
+static mut PREV_CLIENT_AUTHENTICATION_HOOK: pg_sys::ClientAuthentication_hook_type = None;
+
+/// Installs the hook starting the population worker when a backend connects, it does nothing
+/// unless kq.calendar.populate_on = connect. Called from `_PG_init` when the library is preloaded,
+/// loaded later it is too late for the connection.
+pub(crate) fn install_hook() {
+    unsafe {
+        PREV_CLIENT_AUTHENTICATION_HOOK = pg_sys::ClientAuthentication_hook;
+        pg_sys::ClientAuthentication_hook = Some(populate_on_connect);
+    }
+}
+
+/// Runs once the client is authenticated, before the database is opened: the connection is not
+/// delayed, the worker populates the cache in the database the client connected to. Invalid
+/// settings are left for the lookups to report, an error here would refuse the connection.
+#[pg_guard]
+unsafe extern "C" fn populate_on_connect(port: *mut pg_sys::Port, status: i32) {
+    if let Some(prev_hook) = PREV_CLIENT_AUTHENTICATION_HOOK {
+        prev_hook(port, status);
+    }
+    if status != pg_sys::STATUS_OK as i32 || port.is_null() || (*port).database_name.is_null() {
+        return;
+    }
+    let value = get_guc_string_opt(GUC_POPULATE_ON, &POPULATE_ON).unwrap_or_default();
+    if parse_populate_on(&value) != Some(PopulateOn::Connect) {
+        return;
+    }
+
+    // one worker at a time, failed populations wait for the backoff of the lookups
+    let mut control = CALENDAR_CONTROL.exclusive();
+    if control.cache_filled
+        || control.cache_being_filled
+        || control.population_requested
+        || control.population_failures > 0
+    {
+        return;
+    }
+    control.population_requested = true;
+    drop(control);
+
+    let database_name = CStr::from_ptr((*port).database_name).to_string_lossy();
+    if !reload::start_worker_in(
+        "kq_cx cache population",
+        "kq_cx_populate_worker_main",
+        &database_name,
+    ) {
+        CALENDAR_CONTROL.exclusive().population_requested = false;
+        warning!("cannot start the kq_cx cache population worker");
+    }
+}
+
+/// Entry point of the population worker started by `populate_on_connect`.
+#[pg_guard]
+#[no_mangle]
+pub extern "C" fn kq_cx_populate_worker_main(_arg: pg_sys::Datum) {
+    BackgroundWorker::attach_signal_handlers(SignalWakeFlags::SIGTERM);
+    BackgroundWorker::connect_worker_to_spi(Some(BackgroundWorker::get_extra()), None);
+
+    BackgroundWorker::transaction(|| {
+        crate::populate_cache_if_empty(true);
+    });
+    debug1!("kq_cx cache population worker finished");
+}
+
+#[cfg(any(test, feature = "pg_test"))]
+#[pg_schema]
+mod tests {
+    use pgrx::prelude::*;
+
+    use super::PopulateOn;
+
+    #[pg_test]
+    fn test_populate_on() {
+        assert_eq!(super::populate_on(), PopulateOn::FirstUse);
+        assert_eq!(
+            super::parse_populate_on(" Connect"),
+            Some(PopulateOn::Connect)
+        );
+        assert_eq!(super::parse_populate_on("eager"), None);
+
+        Spi::run("SET LOCAL kq.calendar.populate_on = 'manual'").unwrap();
+        crate::clear_cache();
+        crate::kq_cx_populate_cache(false);
+        assert_eq!(
+            crate::kq_cx_add_days_xuid(pgrx::datum::Date::new(2024, 1, 15).unwrap(), 1, "month"),
+            Some(pgrx::datum::Date::new(2024, 2, 1).unwrap())
+        );
+        crate::clear_cache();
+    }
+
+    #[pg_test(error = "the calendar cache is not populated")]
+    fn test_manual_population_keeps_lookups_cold() {
+        Spi::run("SET LOCAL kq.calendar.populate_on = 'manual'").unwrap();
+        crate::clear_cache();
+        crate::kq_cx_add_days_xuid(pgrx::datum::Date::new(2024, 1, 15).unwrap(), 1, "month");
+    }
+}
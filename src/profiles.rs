@@ -0,0 +1,164 @@
+use pgrx::prelude::*;
+
+use crate::{access, define, get_guc_string_opt, GUC_PROFILE, PROFILE};
+
+/// Named sets of loader settings, selected with kq.calendar.profile. A NULL column falls back to
+/// the kq.calendar setting of the same name.
+extension_sql!(
+    r#"
+CREATE TABLE @extschema@.kq_cx_loader_profile (
+    profile text NOT NULL,
+    schema text,
+    q_schema_validation text,
+    q1_get_calendar_min_max_id text,
+    q2_get_calendars_entry_count text,
+    q3_get_calendar_entries text,
+    q3_get_calendar_entries_by_id text,
+    q4_get_calendar_exceptions text,
+    CONSTRAINT kq_cx_loader_profile_pk PRIMARY KEY (profile)
+);
+
+SELECT pg_catalog.pg_extension_config_dump('@extschema@.kq_cx_loader_profile', '');
+"#,
+    name = "create_loader_profile_table",
+);
+
+/// Qualified name of the profile table, `None` when the extension is not created in the current
+/// database.
+fn profile_table() -> Option<String> {
+    define::extension_schema().map(|schema| format!("{schema}.kq_cx_loader_profile"))
+}
+
+/// Value of the setting `name` in the kq.calendar.profile profile, `None` when no profile is
+/// selected or the profile leaves it NULL.
+pub(crate) fn setting(name: &str) -> Option<String> {
+    let profile = get_guc_string_opt(GUC_PROFILE, &PROFILE)?;
+    let column = name.strip_prefix("kq.calendar.").unwrap();
+    let Some(table) = profile_table() else {
+        error!("{GUC_PROFILE} = {profile} but extension kq_cx is not installed")
+    };
+    let (exists, value) = Spi::get_two_with_args::<bool, String>(
+        &format!(
+            "SELECT EXISTS (SELECT FROM {table} WHERE profile = $1), \
+             (SELECT {} FROM {table} WHERE profile = $1)",
+            pgrx::spi::quote_identifier(column)
+        ),
+        vec![(PgBuiltInOids::TEXTOID.oid(), profile.as_str().into_datum())],
+    )
+    .unwrap_or_else(|err| error!("cannot read loader profile {profile}: {err}"));
+    if exists != Some(true) {
+        ereport!(
+            ERROR,
+            PgSqlErrorCode::ERRCODE_UNDEFINED_OBJECT,
+            format!("loader profile {profile} does not exist"),
+            "Define it with kq_cx_define_profile() or reset kq.calendar.profile."
+        );
+    }
+    value.map(|value| value.replace('\n', " "))
+}
+
+/// Creates or replaces a loader profile. The NULL arguments fall back to the kq.calendar
+/// settings, so a profile only lists what differs from them.
+#[pg_extern]
+#[allow(clippy::too_many_arguments)]
+fn kq_cx_define_profile(
+    profile: &str,
+    schema: default!(Option<&str>, "NULL"),
+    q_schema_validation: default!(Option<&str>, "NULL"),
+    q1_get_calendar_min_max_id: default!(Option<&str>, "NULL"),
+    q2_get_calendars_entry_count: default!(Option<&str>, "NULL"),
+    q3_get_calendar_entries: default!(Option<&str>, "NULL"),
+    q3_get_calendar_entries_by_id: default!(Option<&str>, "NULL"),
+    q4_get_calendar_exceptions: default!(Option<&str>, "NULL"),
+) -> &'static str {
+    access::ensure_cache_admin("kq_cx_define_profile");
+    access::ensure_writable("kq_cx_define_profile");
+    let table = profile_table().unwrap_or_else(|| error!("extension kq_cx is not installed"));
+    let text = |value: Option<&str>| (PgBuiltInOids::TEXTOID.oid(), value.into_datum());
+    Spi::run_with_args(
+        &format!(
+            "INSERT INTO {table} VALUES ($1, $2, $3, $4, $5, $6, $7, $8) \
+             ON CONFLICT (profile) DO UPDATE SET \
+             schema = EXCLUDED.schema, \
+             q_schema_validation = EXCLUDED.q_schema_validation, \
+             q1_get_calendar_min_max_id = EXCLUDED.q1_get_calendar_min_max_id, \
+             q2_get_calendars_entry_count = EXCLUDED.q2_get_calendars_entry_count, \
+             q3_get_calendar_entries = EXCLUDED.q3_get_calendar_entries, \
+             q3_get_calendar_entries_by_id = EXCLUDED.q3_get_calendar_entries_by_id, \
+             q4_get_calendar_exceptions = EXCLUDED.q4_get_calendar_exceptions"
+        ),
+        Some(vec![
+            text(Some(profile)),
+            text(schema),
+            text(q_schema_validation),
+            text(q1_get_calendar_min_max_id),
+            text(q2_get_calendars_entry_count),
+            text(q3_get_calendar_entries),
+            text(q3_get_calendar_entries_by_id),
+            text(q4_get_calendar_exceptions),
+        ]),
+    )
+    .unwrap_or_else(|err| error!("cannot define loader profile {profile}: {err}"));
+    "Loader profile defined."
+}
+
+/// Deletes a loader profile, false when it does not exist.
+#[pg_extern]
+fn kq_cx_drop_profile(profile: &str) -> bool {
+    access::ensure_cache_admin("kq_cx_drop_profile");
+    access::ensure_writable("kq_cx_drop_profile");
+    let table = profile_table().unwrap_or_else(|| error!("extension kq_cx is not installed"));
+    Spi::get_one_with_args::<bool>(
+        &format!(
+            "WITH deleted AS (DELETE FROM {table} WHERE profile = $1 RETURNING 1) \
+             SELECT EXISTS (SELECT FROM deleted)"
+        ),
+        vec![(PgBuiltInOids::TEXTOID.oid(), profile.into_datum())],
+    )
+    .ok()
+    .flatten()
+    .unwrap_or(false)
+}
+
+#[cfg(any(test, feature = "pg_test"))]
+#[pg_schema]
+mod tests {
+    use pgrx::prelude::*;
+
+    #[pg_test]
+    fn test_profile_settings() {
+        super::kq_cx_define_profile(
+            "billing",
+            Some("billing"),
+            None,
+            None,
+            None,
+            Some("SELECT 1::int8, '2024-01-01'::date"),
+            None,
+            None,
+        );
+        assert_eq!(super::setting(crate::GUC_SCHEMA), None);
+
+        Spi::run("SET LOCAL kq.calendar.profile = 'billing'").unwrap();
+        assert_eq!(
+            super::setting(crate::GUC_SCHEMA),
+            Some("billing".to_string())
+        );
+        assert_eq!(super::setting(crate::GUC_Q5_GET_EXCEPTIONS), None);
+        assert_eq!(
+            crate::get_query(crate::GUC_Q4_GET_ENTRIES, &crate::Q4_GET_ENTRIES),
+            "SELECT 1::int8, '2024-01-01'::date"
+        );
+        assert!(crate::with_schema("SELECT * FROM {schema}.calendar").contains("billing.calendar"));
+
+        Spi::run("RESET kq.calendar.profile").unwrap();
+        assert!(super::kq_cx_drop_profile("billing"));
+        assert!(!super::kq_cx_drop_profile("billing"));
+    }
+
+    #[pg_test(error = "loader profile missing does not exist")]
+    fn test_missing_profile() {
+        Spi::run("SET LOCAL kq.calendar.profile = 'missing'").unwrap();
+        crate::get_query(crate::GUC_Q4_GET_ENTRIES, &crate::Q4_GET_ENTRIES);
+    }
+}
@@ -0,0 +1,491 @@
+use std::ops::Bound;
+
+use kq_cx_core::{date, math};
+use pgrx::datum::{Range, RangeBound};
+use pgrx::prelude::*;
+
+use crate::{
+    ensure_cache_populated, find_calendar_id, share_calendar, xuid_not_found_error, PgDate,
+    CALENDAR_ID_MAP,
+};
+
+fn to_bound(bound: &RangeBound<PgDate>) -> Bound<i32> {
+    match bound {
+        RangeBound::Infinite => Bound::Unbounded,
+        RangeBound::Inclusive(date) => Bound::Included(date.to_pg_epoch_days()),
+        RangeBound::Exclusive(date) => Bound::Excluded(date.to_pg_epoch_days()),
+    }
+}
+
+fn calendar_id_or_error(calendar_xuid: &str) -> i64 {
+    ensure_cache_populated();
+    find_calendar_id(None, calendar_xuid).unwrap_or_else(|| xuid_not_found_error(calendar_xuid))
+}
+
+/// Calendar entries inside `range`, in ascending order.
+#[pg_extern(parallel_safe, stable)]
+fn kq_cx_entries_in(range: Range<PgDate>, calendar_xuid: &str) -> SetOfIterator<'static, PgDate> {
+    let calendar_id = calendar_id_or_error(calendar_xuid);
+    let (Some(lower), Some(upper)) = (range.lower(), range.upper()) else {
+        // empty range
+        return SetOfIterator::new(vec![]);
+    };
+    let entries = share_calendar(&CALENDAR_ID_MAP.share(), calendar_id)
+        .map(|calendar| math::entries_between(&calendar, to_bound(lower), to_bound(upper)).to_vec())
+        .unwrap_or_default();
+    SetOfIterator::new(
+        entries
+            .into_iter()
+            .map(|date| unsafe { PgDate::from_pg_epoch_days(date) }),
+    )
+}
+
+/// Days between `start_date` and `end_date` (inclusive) that are not entries of the calendar, e.g.
+/// the non-working days of a working day calendar. The days are produced as they are read, only
+/// the entries inside the range are copied.
+#[pg_extern(parallel_safe, stable)]
+fn kq_cx_non_entries_between(
+    start_date: PgDate,
+    end_date: PgDate,
+    calendar_xuid: &str,
+) -> SetOfIterator<'static, PgDate> {
+    let calendar_id = calendar_id_or_error(calendar_xuid);
+    let (start, end) = (start_date.to_pg_epoch_days(), end_date.to_pg_epoch_days());
+    let calendar_id_map = CALENDAR_ID_MAP.share();
+    let Some(calendar) = share_calendar(&calendar_id_map, calendar_id) else {
+        return SetOfIterator::new(vec![]);
+    };
+    let days = math::non_entries_between(&calendar, start, end);
+    drop(calendar);
+    drop(calendar_id_map);
+    SetOfIterator::new(days.map(|day| unsafe { PgDate::from_pg_epoch_days(day) }))
+}
+
+/// Number of days between the two bounds, `None` when one of them is unbounded.
+fn day_count(lower: Bound<i32>, upper: Bound<i32>) -> Option<i64> {
+    let first = match lower {
+        Bound::Included(day) => day as i64,
+        Bound::Excluded(day) => day as i64 + 1,
+        Bound::Unbounded => return None,
+    };
+    let last = match upper {
+        Bound::Included(day) => day as i64,
+        Bound::Excluded(day) => day as i64 - 1,
+        Bound::Unbounded => return None,
+    };
+    Some((last - first + 1).max(0))
+}
+
+/// Days of `range`, entries of the calendar inside it and the fraction of the days that are
+/// entries (NULL for an empty range). A daily calendar covers 1.0, a weekly one about 0.14.
+#[pg_extern(parallel_safe, stable)]
+fn kq_cx_coverage(
+    calendar_xuid: &str,
+    range: Range<PgDate>,
+) -> TableIterator<
+    'static,
+    (
+        name!(days, i64),
+        name!(entries, i64),
+        name!(coverage, Option<f64>),
+    ),
+> {
+    let calendar_id = calendar_id_or_error(calendar_xuid);
+    let (days, entries) = match (range.lower(), range.upper()) {
+        (Some(lower), Some(upper)) => {
+            let (lower, upper) = (to_bound(lower), to_bound(upper));
+            let Some(days) = day_count(lower, upper) else {
+                error!("the coverage of an unbounded range cannot be computed")
+            };
+            let entries = share_calendar(&CALENDAR_ID_MAP.share(), calendar_id)
+                .map(|calendar| math::entries_between(&calendar, lower, upper).len() as i64)
+                .unwrap_or_default();
+            (days, entries)
+        }
+        // empty range
+        _ => (0, 0),
+    };
+    let coverage = (days > 0).then(|| entries as f64 / days as f64);
+    TableIterator::once((days, entries, coverage))
+}
+
+/// Period of the calendar containing `input_date` as `[entry, next entry)`, unbounded above
+/// after the last entry and NULL before the first one.
+#[pg_extern(parallel_safe, stable)]
+fn kq_cx_period_range(input_date: PgDate, calendar_xuid: &str) -> Option<Range<PgDate>> {
+    let calendar_id = calendar_id_or_error(calendar_xuid);
+    let (start, end) = math::period_bounds(
+        &share_calendar(&CALENDAR_ID_MAP.share(), calendar_id)?,
+        input_date.to_pg_epoch_days(),
+    )?;
+    let start = RangeBound::Inclusive(unsafe { PgDate::from_pg_epoch_days(start) });
+    let end = match end {
+        Some(end) => RangeBound::Exclusive(unsafe { PgDate::from_pg_epoch_days(end) }),
+        None => RangeBound::Infinite,
+    };
+    Some(Range::new(start, end))
+}
+
+/// Entries after `from_date` up to `to_date` (inclusive), negative when `to_date` is before
+/// `from_date`: the interval kq_cx_add_days needs to go from the period of one date to the period
+/// of the other.
+#[pg_extern(parallel_safe, stable)]
+fn kq_cx_entries_spanned(from_date: PgDate, to_date: PgDate, calendar_xuid: &str) -> i64 {
+    let calendar_id = calendar_id_or_error(calendar_xuid);
+    share_calendar(&CALENDAR_ID_MAP.share(), calendar_id)
+        .map(|calendar| {
+            math::entries_spanned(
+                &calendar,
+                from_date.to_pg_epoch_days(),
+                to_date.to_pg_epoch_days(),
+            )
+        })
+        .unwrap_or_default()
+}
+
+/// `kq_cx_span(date, calendar_xuid)` aggregates `kq_cx_entries_spanned` from the earliest to the
+/// latest date of the group, NULL dates are ignored. The state only keeps the two dates, the
+/// calendar is looked up once by the final function.
+extension_sql!(
+    r#"
+CREATE TYPE @extschema@.kq_cx_span_state AS (
+    calendar_xuid text,
+    min_date date,
+    max_date date
+);
+
+CREATE FUNCTION @extschema@.kq_cx_span_accum(
+    state @extschema@.kq_cx_span_state,
+    input_date date,
+    calendar_xuid text
+) RETURNS @extschema@.kq_cx_span_state
+    LANGUAGE sql IMMUTABLE PARALLEL SAFE
+AS $$
+    SELECT CASE
+        WHEN input_date IS NULL THEN state
+        ELSE ROW(
+            calendar_xuid,
+            LEAST((state).min_date, input_date),
+            GREATEST((state).max_date, input_date)
+        )::@extschema@.kq_cx_span_state
+    END
+$$;
+
+CREATE FUNCTION @extschema@.kq_cx_span_combine(
+    state @extschema@.kq_cx_span_state,
+    other @extschema@.kq_cx_span_state
+) RETURNS @extschema@.kq_cx_span_state
+    LANGUAGE sql IMMUTABLE PARALLEL SAFE
+AS $$
+    SELECT ROW(
+        coalesce((state).calendar_xuid, (other).calendar_xuid),
+        LEAST((state).min_date, (other).min_date),
+        GREATEST((state).max_date, (other).max_date)
+    )::@extschema@.kq_cx_span_state
+$$;
+
+CREATE FUNCTION @extschema@.kq_cx_span_final(state @extschema@.kq_cx_span_state) RETURNS int8
+    LANGUAGE sql STABLE PARALLEL SAFE
+AS $$
+    SELECT @extschema@.kq_cx_entries_spanned(
+        (state).min_date,
+        (state).max_date,
+        (state).calendar_xuid
+    )
+$$;
+
+CREATE AGGREGATE @extschema@.kq_cx_span(date, text) (
+    SFUNC = @extschema@.kq_cx_span_accum,
+    STYPE = @extschema@.kq_cx_span_state,
+    COMBINEFUNC = @extschema@.kq_cx_span_combine,
+    FINALFUNC = @extschema@.kq_cx_span_final,
+    PARALLEL = SAFE
+);
+"#,
+    name = "create_span_aggregate",
+    requires = [kq_cx_entries_spanned],
+);
+
+/// Days from `input_date` to the next entry of the calendar, 0 when the date is an entry and NULL
+/// after the last entry, e.g. for "ships in N days".
+#[pg_extern(parallel_safe, stable)]
+fn kq_cx_days_until_next_entry(input_date: PgDate, calendar_xuid: &str) -> Option<i32> {
+    let calendar_id = calendar_id_or_error(calendar_xuid);
+    let days = math::days_until_next_entry(
+        &share_calendar(&CALENDAR_ID_MAP.share(), calendar_id)?,
+        input_date.to_pg_epoch_days(),
+    )?;
+    Some(days as i32)
+}
+
+/// Zero-based number of the period (entry interval) containing `input_date`, the same for every
+/// date from an entry until the next one. NULL before the first entry.
+#[pg_extern(parallel_safe, stable)]
+fn kq_cx_period_index(input_date: PgDate, calendar_xuid: &str) -> Option<i32> {
+    let calendar_id = calendar_id_or_error(calendar_xuid);
+    let index = math::period_index(
+        &share_calendar(&CALENDAR_ID_MAP.share(), calendar_id)?,
+        input_date.to_pg_epoch_days(),
+    )?;
+    Some(index as i32)
+}
+
+/// Moves `input_date` by whole periods of `period_xuid` (e.g. fiscal months), landing on the entry
+/// of `calendar_xuid` (e.g. weeks) at the same position in the new period, or on its last entry
+/// when the period is shorter. NULL when a calendar does not cover the dates.
+#[pg_extern(parallel_safe, stable)]
+fn kq_cx_add_periods(
+    input_date: PgDate,
+    periods: i32,
+    calendar_xuid: &str,
+    period_xuid: &str,
+) -> Option<PgDate> {
+    let calendar_id = calendar_id_or_error(calendar_xuid);
+    let period_id = calendar_id_or_error(period_xuid);
+    let date = input_date.to_pg_epoch_days();
+    // one stripe locked at a time, the period bounds are copied first
+    let (period_start, start, end) = math::shifted_period(
+        &share_calendar(&CALENDAR_ID_MAP.share(), period_id)?,
+        date,
+        periods.into(),
+    )?;
+    let result_date = math::entry_at_position(
+        &share_calendar(&CALENDAR_ID_MAP.share(), calendar_id)?,
+        period_start,
+        date,
+        (start, end),
+    )?;
+    Some(unsafe { PgDate::from_pg_epoch_days(result_date) })
+}
+
+/// Consecutive entries more than `min_gap` days apart, e.g. a missing period of a monthly
+/// calendar shows up with `min_gap => 31`.
+#[pg_extern(parallel_safe, stable)]
+fn kq_cx_gaps(
+    calendar_xuid: &str,
+    min_gap: i32,
+) -> TableIterator<
+    'static,
+    (
+        name!(gap_start, PgDate),
+        name!(gap_end, PgDate),
+        name!(days, i32),
+    ),
+> {
+    let calendar_id = calendar_id_or_error(calendar_xuid);
+    let gaps = share_calendar(&CALENDAR_ID_MAP.share(), calendar_id)
+        .map(|calendar| math::gaps(&calendar, min_gap))
+        .unwrap_or_default();
+    TableIterator::new(gaps.into_iter().map(|(start, end)| {
+        (
+            unsafe { PgDate::from_pg_epoch_days(start) },
+            unsafe { PgDate::from_pg_epoch_days(end) },
+            end - start,
+        )
+    }))
+}
+
+/// Number of entries per `'year'` or `'month'`, computed from the cache. Years or months
+/// without entries are not listed.
+#[pg_extern(parallel_safe, stable)]
+fn kq_cx_entry_histogram(
+    calendar_xuid: &str,
+    bucket: &str,
+) -> TableIterator<'static, (name!(bucket_start, PgDate), name!(entries, i64))> {
+    let Some(bucket) = math::HistogramBucket::parse(bucket) else {
+        ereport!(
+            ERROR,
+            PgSqlErrorCode::ERRCODE_INVALID_PARAMETER_VALUE,
+            format!("invalid histogram bucket \"{bucket}\""),
+            "Use 'year' or 'month'."
+        )
+    };
+    let calendar_id = calendar_id_or_error(calendar_xuid);
+    let histogram = share_calendar(&CALENDAR_ID_MAP.share(), calendar_id)
+        .map(|calendar| math::entry_histogram(&calendar, bucket))
+        .unwrap_or_default();
+    TableIterator::new(
+        histogram
+            .into_iter()
+            .map(|(start, entries)| (unsafe { PgDate::from_pg_epoch_days(start) }, entries as i64)),
+    )
+}
+
+/// One partition per calendar period overlapping `[from_date, to_date)`, named
+/// `<prefix>_<YYYYMMDD of range_start>` (the prefix defaults to the xuid). The bounds are the
+/// calendar entries, so the first and last partitions can extend beyond the requested dates.
+#[pg_extern(parallel_safe, stable)]
+fn kq_cx_partition_bounds(
+    calendar_xuid: &str,
+    from_date: PgDate,
+    to_date: PgDate,
+    name_prefix: default!(Option<&str>, "NULL"),
+) -> TableIterator<
+    'static,
+    (
+        name!(partition_name, String),
+        name!(range_start, PgDate),
+        name!(range_end, PgDate),
+    ),
+> {
+    let calendar_id = calendar_id_or_error(calendar_xuid);
+    let periods = share_calendar(&CALENDAR_ID_MAP.share(), calendar_id)
+        .map(|calendar| {
+            math::periods_between(
+                &calendar,
+                from_date.to_pg_epoch_days(),
+                to_date.to_pg_epoch_days(),
+            )
+        })
+        .unwrap_or_default();
+    let prefix = name_prefix.unwrap_or(calendar_xuid).to_string();
+    TableIterator::new(periods.into_iter().map(move |(start, end)| {
+        let (year, month, day) = date::to_ymd(start);
+        (
+            format!("{prefix}_{year:04}{month:02}{day:02}"),
+            unsafe { PgDate::from_pg_epoch_days(start) },
+            unsafe { PgDate::from_pg_epoch_days(end) },
+        )
+    }))
+}
+
+#[cfg(any(test, feature = "pg_test"))]
+#[pg_schema]
+mod tests {
+    use pgrx::prelude::*;
+
+    #[pg_test]
+    fn test_entries_in_and_period_range() {
+        let entries = Spi::get_one::<i64>(
+            "SELECT count(*) FROM kq_cx_entries_in('[2024-02-01,2024-05-01)', 'month')",
+        );
+        assert_eq!(entries, Ok(Some(3)));
+
+        let period =
+            Spi::get_one::<String>("SELECT kq_cx_period_range('2024-04-15', 'quarter')::text");
+        assert_eq!(period, Ok(Some("[2024-04-01,2024-07-01)".to_string())));
+    }
+
+    #[pg_test]
+    fn test_non_entries_between() {
+        let days = Spi::get_one::<String>(
+            "SELECT string_agg(d::text, ',' ORDER BY d) \
+             FROM kq_cx_non_entries_between('2024-01-30', '2024-02-02', 'month') d",
+        );
+        assert_eq!(
+            days,
+            Ok(Some("2024-01-30,2024-01-31,2024-02-02".to_string()))
+        );
+        let blocked = Spi::get_one::<i64>(
+            "SELECT count(*) FROM kq_cx_non_entries_between('2024-01-01', '2024-12-31', 'quarter')",
+        );
+        assert_eq!(blocked, Ok(Some(366 - 4)));
+    }
+
+    #[pg_test]
+    fn test_coverage() {
+        let coverage = Spi::get_two::<i64, f64>(
+            "SELECT entries, coverage FROM kq_cx_coverage('month', '[2024-01-01,2024-03-01)')",
+        );
+        assert_eq!(coverage, Ok((Some(2), Some(2.0 / 60.0))));
+
+        let empty =
+            Spi::get_two::<i64, f64>("SELECT days, coverage FROM kq_cx_coverage('month', 'empty')");
+        assert_eq!(empty, Ok((Some(0), None)));
+    }
+
+    #[pg_test]
+    fn test_span_aggregate() {
+        let spans = Spi::get_one::<String>(
+            "SELECT string_agg(format('%s %s', g, span), ', ' ORDER BY g) FROM ( \
+                 SELECT g, kq_cx_span(d, 'month') AS span \
+                 FROM (VALUES (1, '2024-01-15'::date), (1, '2024-04-02'), (1, NULL), \
+                              (2, '2024-03-01'), (3, NULL)) v(g, d) \
+                 GROUP BY g) s",
+        );
+        assert_eq!(spans, Ok(Some("1 3, 2 0, 3 ".to_string())));
+        assert_eq!(
+            Spi::get_one::<i64>(
+                "SELECT kq_cx_entries_spanned('2024-04-02', '2024-01-15', 'month')"
+            ),
+            Ok(Some(-3))
+        );
+    }
+
+    #[pg_test]
+    fn test_days_until_next_entry() {
+        let days = |date: &str| {
+            Spi::get_one::<i32>(&format!(
+                "SELECT kq_cx_days_until_next_entry('{date}', 'quarter')"
+            ))
+        };
+        assert_eq!(days("2024-04-01"), Ok(Some(0)));
+        assert_eq!(days("2024-03-30"), Ok(Some(2)));
+        assert_eq!(days("2023-12-25"), Ok(Some(7)));
+        assert_eq!(days("2025-10-02"), Ok(None));
+    }
+
+    #[pg_test]
+    fn test_period_index() {
+        let index = |date: &str| {
+            Spi::get_one::<i32>(&format!("SELECT kq_cx_period_index('{date}', 'quarter')"))
+        };
+        assert_eq!(index("2024-01-01"), Ok(Some(0)));
+        assert_eq!(index("2024-06-30"), Ok(Some(1)));
+        assert_eq!(index("2030-01-01"), Ok(Some(7)));
+        assert_eq!(index("2023-12-31"), Ok(None));
+    }
+
+    #[pg_test]
+    fn test_add_periods() {
+        let add = |date: &str, periods: i32| {
+            Spi::get_one::<String>(&format!(
+                "SELECT kq_cx_add_periods('{date}', {periods}, 'month', 'quarter')::text"
+            ))
+        };
+        assert_eq!(add("2024-02-15", 1), Ok(Some("2024-05-01".to_string())));
+        assert_eq!(add("2024-06-01", -1), Ok(Some("2024-03-01".to_string())));
+        assert_eq!(add("2024-02-15", 2), Ok(None));
+        assert_eq!(add("2023-12-31", 1), Ok(None));
+    }
+
+    #[pg_test]
+    fn test_gaps() {
+        // quarter: one entry every 3 months
+        let gaps = Spi::get_one::<i64>("SELECT count(*) FROM kq_cx_gaps('quarter', 31)");
+        assert_eq!(gaps, Ok(Some(7)));
+        let gaps = Spi::get_one::<i64>("SELECT count(*) FROM kq_cx_gaps('quarter', 92)");
+        assert_eq!(gaps, Ok(Some(0)));
+    }
+
+    #[pg_test]
+    fn test_entry_histogram() {
+        let histogram = Spi::get_one::<String>(
+            "SELECT string_agg(format('%s %s', bucket_start, entries), ', ' ORDER BY bucket_start) \
+             FROM kq_cx_entry_histogram('quarter', 'year')",
+        );
+        assert_eq!(
+            histogram,
+            Ok(Some("2024-01-01 4, 2025-01-01 4".to_string()))
+        );
+        let months =
+            Spi::get_one::<i64>("SELECT count(*) FROM kq_cx_entry_histogram('month', 'month')");
+        assert_eq!(months, Ok(Some(6)));
+    }
+
+    #[pg_test]
+    fn test_partition_bounds() {
+        let partitions = Spi::get_one::<String>(
+            "SELECT string_agg(format('%s %s %s', partition_name, range_start, range_end), ', ' \
+             ORDER BY range_start) \
+             FROM kq_cx_partition_bounds('quarter', '2024-02-15', '2024-07-01', 'sales')",
+        );
+        assert_eq!(
+            partitions,
+            Ok(Some(
+                "sales_20240101 2024-01-01 2024-04-01, sales_20240401 2024-04-01 2024-07-01"
+                    .to_string()
+            ))
+        );
+    }
+}
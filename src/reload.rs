@@ -0,0 +1,43 @@
+use std::ffi::CStr;
+
+use pgrx::bgworkers::*;
+use pgrx::prelude::*;
+
+/// Starts a dynamic background worker that reloads the cache in the current database, returns
+/// false when the worker could not be registered (e.g. max_worker_processes is exhausted).
+pub(crate) fn start_worker() -> bool {
+    let database_name = unsafe { CStr::from_ptr(pg_sys::get_database_name(pg_sys::MyDatabaseId)) }
+        .to_string_lossy()
+        .into_owned();
+    start_worker_in(
+        "kq_cx cache reload",
+        "kq_cx_reload_worker_main",
+        &database_name,
+    )
+}
+
+/// Starts a dynamic background worker running `function` of the library in `database_name`.
+pub(crate) fn start_worker_in(name: &str, function: &str, database_name: &str) -> bool {
+    BackgroundWorkerBuilder::new(name)
+        .set_function(function)
+        .set_library("kq_cx")
+        .set_extra(database_name)
+        .enable_spi_access()
+        .set_restart_time(None)
+        .load_dynamic()
+        .is_ok()
+}
+
+/// Entry point of the reload worker, the settings of the database apply but the session
+/// settings of the backend that invalidated the cache do not.
+#[pg_guard]
+#[no_mangle]
+pub extern "C" fn kq_cx_reload_worker_main(_arg: pg_sys::Datum) {
+    BackgroundWorker::attach_signal_handlers(SignalWakeFlags::SIGTERM);
+    BackgroundWorker::connect_worker_to_spi(Some(BackgroundWorker::get_extra()), None);
+
+    BackgroundWorker::transaction(|| {
+        crate::reload_cache(false);
+    });
+    debug1!("kq_cx cache reload finished");
+}
@@ -0,0 +1,105 @@
+use kq_cx_core::math::{self, OutOfBounds};
+use pgrx::prelude::*;
+
+use crate::{oob, uncached, with_schema, PgDate};
+
+/// Position of the entry of the date (-1 before the first one), number of entries and entry at
+/// `position + interval`, in one query bounded by the entries of the calendar. Written without
+/// the page map, the search or the loader queries, so it shares no code with the cached lookups.
+const Q_SIMULATE: &str = r#"
+    WITH entries AS (
+        SELECT DISTINCT "date" FROM {schema}.calendar_date
+        WHERE calendar_id = $1 AND "date" IS NOT NULL
+    ), hit AS (
+        SELECT COUNT(*) FILTER (WHERE "date" <= $2) - 1 AS position, COUNT(*) AS entries
+        FROM entries
+    )
+    SELECT hit.position, hit.entries, (
+        SELECT "date" FROM entries ORDER BY "date"
+        OFFSET GREATEST(hit.position + $3, 0) LIMIT 1
+    )
+    FROM hit"#;
+
+/// Result of a lookup computed from plan.calendar_date, the reference the cached lookups are
+/// checked against.
+pub(crate) fn simulate(
+    calendar_id: i64,
+    input_date: i32,
+    interval: i64,
+) -> Result<i32, OutOfBounds> {
+    let (position, entries, result_date) = Spi::get_three_with_args::<i64, i64, PgDate>(
+        &with_schema(Q_SIMULATE),
+        vec![
+            (PgBuiltInOids::INT8OID.oid(), calendar_id.into_datum()),
+            (
+                PgBuiltInOids::DATEOID.oid(),
+                unsafe { PgDate::from_pg_epoch_days(input_date) }.into_datum(),
+            ),
+            (PgBuiltInOids::INT8OID.oid(), interval.into_datum()),
+        ],
+    )
+    .unwrap_or_else(|spi_error| error!("cannot read calendar_id = {calendar_id}. {spi_error}"));
+    let (position, entries) = (position.unwrap_or(-1), entries.unwrap_or(0));
+
+    // a calendar without entries is plain day arithmetic
+    if entries == 0 {
+        let lookup = math::plain_arithmetic(input_date, interval);
+        return lookup.out_of_bounds.map_or(Ok(lookup.result_date), Err);
+    }
+    let target = position.saturating_add(interval);
+    if position < 0 || target < 0 {
+        Err(OutOfBounds::Past)
+    } else if target >= entries {
+        Err(OutOfBounds::Future)
+    } else {
+        Ok(result_date.unwrap().to_pg_epoch_days())
+    }
+}
+
+/// Same as kq_cx_add_days_xuid, computed with a single query on plan.calendar and
+/// plan.calendar_date instead of the cache. The reference of kq_cx_verify_lookups(): exceptions,
+/// defined calendars and parents are not taken into account, out-of-bounds lookups return the
+/// sentinels even with kq.calendar.strict_bounds.
+#[pg_extern(parallel_safe, stable)]
+fn kq_cx_simulate(input_date: PgDate, interval: i64, calendar_xuid: &str) -> Option<PgDate> {
+    let Some(calendar_id) = uncached::calendar_id(calendar_xuid) else {
+        warning!("calendar_xuid = {calendar_xuid} not found in plan.calendar");
+        return None;
+    };
+    let result_date = simulate(calendar_id, input_date.to_pg_epoch_days(), interval)
+        .unwrap_or_else(oob::sentinel);
+    Some(unsafe { PgDate::from_pg_epoch_days(result_date) })
+}
+
+#[cfg(any(test, feature = "pg_test"))]
+#[pg_schema]
+mod tests {
+    use pgrx::prelude::*;
+
+    #[pg_test]
+    fn test_simulate_matches_cache() {
+        let first_day = pgrx::datum::Date::new(2023, 11, 1)
+            .unwrap()
+            .to_pg_epoch_days();
+        for calendar_xuid in ["month", "quarter", "year"] {
+            for day in (0..1400).step_by(41) {
+                let input_date = unsafe { crate::PgDate::from_pg_epoch_days(first_day + day) };
+                for interval in -4..=4 {
+                    assert_eq!(
+                        super::kq_cx_simulate(input_date, interval, calendar_xuid),
+                        crate::kq_cx_add_days_xuid(input_date, interval, calendar_xuid),
+                        "calendar_xuid = {calendar_xuid}, date = {input_date}, interval = {interval}"
+                    );
+                }
+            }
+        }
+    }
+
+    #[pg_test]
+    fn test_simulate_ignores_duplicates() {
+        Spi::run("INSERT INTO plan.calendar_date (calendar_id, \"date\") VALUES (1, '2024-02-01')")
+            .unwrap();
+        let date = |month| pgrx::datum::Date::new(2024, month, 1).unwrap();
+        assert_eq!(super::kq_cx_simulate(date(1), 2, "month"), Some(date(3)));
+    }
+}
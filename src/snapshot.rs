@@ -0,0 +1,288 @@
+use std::cell::RefCell;
+use std::collections::HashMap;
+
+use kq_cx_core::{math, Calendar};
+use pgrx::prelude::*;
+
+use crate::{
+    current_namespace, ensure_cache_populated, oob, qualified_xuid, resolve_parents,
+    share_calendar, share_calendars, xuids, CalendarIdMap, ALLOW_STALE_READS, CALENDAR_CONTROL,
+    CALENDAR_ID_MAP, CALENDAR_XUID_ID_MAP, MAX_CALENDARS,
+};
+
+/// Copies of the calendars used by this backend, along with the generation they were copied
+/// from. Lookups are answered from them while another session populates the cache.
+#[derive(Default)]
+struct Snapshot {
+    calendars: HashMap<i64, (u64, Box<Calendar>)>,
+    calendar_ids: HashMap<(String, String), i64>,
+}
+
+/// Copy of every cached calendar of one generation, see `kq_cx_pin_generation`.
+struct PinnedGeneration {
+    generation: u64,
+    calendars: HashMap<i64, Box<Calendar>>,
+    calendar_ids: HashMap<(String, String), i64>,
+}
+
+thread_local! {
+    static SNAPSHOT: RefCell<Snapshot> = RefCell::new(Snapshot::default());
+    static PINNED: RefCell<Option<PinnedGeneration>> = const { RefCell::new(None) };
+}
+
+/// Generation pinned by this session, `None` when the lookups read the cache.
+pub(crate) fn pinned_generation() -> Option<u64> {
+    PINNED.with_borrow(|pinned| pinned.as_ref().map(|pinned| pinned.generation))
+}
+
+/// True when the lookups read a copy of the calendars instead of the cache: a generation is
+/// pinned, or another session fills the cache and stale reads are allowed.
+pub(crate) fn active() -> bool {
+    pinned_generation().is_some() || serve_stale()
+}
+
+/// Runs `lookup` on the calendar (or the parent) covering `date` in the pinned generation, or in
+/// the backend snapshot while stale reads are served. `None` when the lookups read the cache,
+/// `Some(None)` when the calendar was not cached when the generation was pinned.
+pub(crate) fn with_calendar<R>(
+    calendar_id: i64,
+    date: i32,
+    lookup: impl FnOnce(&Calendar) -> R,
+) -> Option<Option<R>> {
+    if pinned_generation().is_some() {
+        return Some(PINNED.with_borrow(|pinned| {
+            let pinned = pinned.as_ref()?;
+            let Some(calendar) = resolve_parents(calendar_id, date, |calendar_id| {
+                pinned.calendars.get(&calendar_id).map(Box::as_ref)
+            }) else {
+                warning!(
+                    "calendar_id = {calendar_id} not found in the pinned generation {}",
+                    pinned.generation
+                );
+                return None;
+            };
+            Some(lookup(calendar))
+        }));
+    }
+    if !serve_stale() {
+        return None;
+    }
+    SNAPSHOT.with_borrow(|snapshot| {
+        let calendar = resolve_parents(calendar_id, date, |calendar_id| {
+            snapshot
+                .calendars
+                .get(&calendar_id)
+                .map(|(_, calendar)| calendar.as_ref())
+        })?;
+        debug1!("calendar_id = {calendar_id} served from the backend snapshot");
+        Some(Some(lookup(calendar)))
+    })
+}
+
+/// Result of the lookup on the pinned generation, `None` when the calendar was not cached when
+/// it was pinned. Only called while a generation is pinned.
+pub(crate) fn pinned_add_days(calendar_id: i64, date: i32, interval: i64) -> Option<i32> {
+    with_calendar(calendar_id, date, |calendar| {
+        let lookup = math::lookup_calendar_days(calendar, date, interval);
+        oob::lookup_date(calendar_id, calendar, date, interval, &lookup)
+    })
+    .flatten()
+    .flatten()
+}
+
+/// Calendar id in the pinned generation, `None` when no generation is pinned. Warns when the
+/// calendar was not cached when it was pinned.
+pub(crate) fn pinned_calendar_id(namespace: Option<&str>, xuid: &str) -> Option<Option<i64>> {
+    PINNED.with_borrow(|pinned| {
+        let pinned = pinned.as_ref()?;
+        let namespace = namespace.map_or_else(current_namespace, str::to_string);
+        let calendar_id = pinned
+            .calendar_ids
+            .get(&(namespace.clone(), xuids::normalized(xuid).into_owned()))
+            .copied();
+        if calendar_id.is_none() {
+            warning!(
+                "calendar_xuid = {} not found in the pinned generation {}",
+                qualified_xuid(&namespace, xuid),
+                pinned.generation
+            );
+        }
+        Some(calendar_id)
+    })
+}
+
+/// Pins the current cache generation for this session: until `kq_cx_unpin_generation()`, the
+/// lookups (`kq_cx_add_days`, `kq_cx_add_days_xuid`, `kq_cx_add_days_many`, `kq_cx_add_days_grid`,
+/// `kq_cx_offset` and `kq_cx_add_days_debug`) read a copy of the calendars as they are now, even
+/// when the cache is invalidated or reloaded meanwhile. Returns the pinned generation, calling it
+/// again while pinned keeps the generation pinned first.
+#[pg_extern(parallel_unsafe)]
+fn kq_cx_pin_generation() -> i64 {
+    if let Some(generation) = pinned_generation() {
+        return generation as i64;
+    }
+    ensure_cache_populated();
+    // same lock order as the population, CALENDAR_ID_MAP first
+    let calendar_id_map = CALENDAR_ID_MAP.share();
+    let calendar_ids: HashMap<(String, String), i64> = CALENDAR_XUID_ID_MAP
+        .share()
+        .iter()
+        .map(|((namespace, xuid), calendar_id)| {
+            ((namespace.to_string(), xuid.to_string()), *calendar_id)
+        })
+        .collect();
+    // the generation changes with the contents of the cache, which CALENDAR_ID_MAP keeps still
+    let generation = CALENDAR_CONTROL.share().generation;
+    let calendars = share_calendars(&calendar_id_map)
+        .map(|calendar| (calendar.calendar_id, Box::new(calendar.clone())))
+        .collect();
+    drop(calendar_id_map);
+
+    PINNED.set(Some(PinnedGeneration {
+        generation,
+        calendars,
+        calendar_ids,
+    }));
+    debug1!("cache generation {generation} pinned");
+    generation as i64
+}
+
+/// Releases the generation pinned by `kq_cx_pin_generation()`, false when none was pinned.
+#[pg_extern(parallel_unsafe)]
+fn kq_cx_unpin_generation() -> bool {
+    PINNED.take().is_some()
+}
+
+/// True when kq.calendar.allow_stale_reads is on and another session is filling the cache, a
+/// lookup would wait until it is done.
+///
+/// `PgLwLock` has no conditional acquire, the flag of the population is checked instead of
+/// trying the lock.
+fn serve_stale() -> bool {
+    ALLOW_STALE_READS.get() && CALENDAR_CONTROL.share().cache_being_filled
+}
+
+/// Result of the lookup on the snapshot, `None` when the cache is available or the calendar
+/// was not used before by this backend.
+pub(crate) fn stale_add_days(calendar_id: i64, date: i32, interval: i64) -> Option<i32> {
+    with_calendar(calendar_id, date, |calendar| {
+        let lookup = math::lookup_calendar_days(calendar, date, interval);
+        oob::lookup_date(calendar_id, calendar, date, interval, &lookup)
+    })
+    .flatten()
+    .flatten()
+}
+
+/// Calendar id of the snapshot, see `stale_add_days`.
+pub(crate) fn stale_calendar_id(namespace: Option<&str>, xuid: &str) -> Option<i64> {
+    if !serve_stale() {
+        return None;
+    }
+    let key = (
+        namespace.map_or_else(current_namespace, str::to_string),
+        xuid.to_string(),
+    );
+    SNAPSHOT.with_borrow(|snapshot| snapshot.calendar_ids.get(&key).copied())
+}
+
+/// Copies the calendar and its parents when the snapshot is older than `generation`. The caller
+/// holds CALENDAR_ID_MAP.
+pub(crate) fn remember(calendar_id_map: &CalendarIdMap, calendar_id: i64, generation: u64) {
+    if !ALLOW_STALE_READS.get() {
+        return;
+    }
+    SNAPSHOT.with_borrow_mut(|snapshot| {
+        let mut next_id = Some(calendar_id);
+        // the depth limit stops parent cycles
+        for _ in 0..MAX_CALENDARS {
+            let Some(calendar_id) = next_id else {
+                break;
+            };
+            if matches!(snapshot.calendars.get(&calendar_id), Some((copied, _)) if *copied == generation)
+            {
+                break;
+            }
+            let Some(calendar) = share_calendar(calendar_id_map, calendar_id) else {
+                break;
+            };
+            next_id = calendar.parent_id();
+            snapshot
+                .calendars
+                .insert(calendar_id, (generation, Box::new(calendar.clone())));
+        }
+    });
+}
+
+pub(crate) fn remember_calendar_id(namespace: Option<&str>, xuid: &str, calendar_id: i64) {
+    if !ALLOW_STALE_READS.get() {
+        return;
+    }
+    let key = (
+        namespace.map_or_else(current_namespace, str::to_string),
+        xuid.to_string(),
+    );
+    SNAPSHOT.with_borrow_mut(|snapshot| snapshot.calendar_ids.insert(key, calendar_id));
+}
+
+#[cfg(any(test, feature = "pg_test"))]
+#[pg_schema]
+mod tests {
+    use pgrx::prelude::*;
+
+    #[pg_test]
+    fn test_stale_reads_during_population() {
+        Spi::run("SET LOCAL kq.calendar.allow_stale_reads = on").unwrap();
+        let date = pgrx::datum::Date::new(2024, 1, 1).unwrap();
+        let expected = crate::kq_cx_add_days_xuid(date, 1, "month");
+
+        // as seen by the other backends while a session populates the cache
+        crate::clear_cache();
+        crate::CALENDAR_CONTROL.exclusive().cache_being_filled = true;
+        let stale = crate::kq_cx_add_days_xuid(date, 1, "month");
+        crate::CALENDAR_CONTROL.exclusive().cache_being_filled = false;
+
+        assert!(expected.is_some());
+        assert_eq!(stale, expected);
+    }
+
+    #[pg_test]
+    fn test_pinned_generation() {
+        let date = pgrx::datum::Date::new(2024, 1, 1).unwrap();
+        let expected = crate::kq_cx_add_days_xuid(date, 1, "month");
+        let generation = super::kq_cx_pin_generation();
+        assert_eq!(super::kq_cx_pin_generation(), generation);
+
+        // a reload replacing the calendars while the report runs
+        crate::clear_cache();
+        assert_eq!(crate::kq_cx_add_days_xuid(date, 1, "month"), expected);
+        assert_eq!(crate::kq_cx_add_days_xuid(date, 1, "unknown"), None);
+        assert_eq!(super::pinned_generation(), Some(generation as u64));
+
+        assert!(super::kq_cx_unpin_generation());
+        assert!(!super::kq_cx_unpin_generation());
+        assert_eq!(crate::kq_cx_add_days_xuid(date, 1, "month"), expected);
+        assert!(crate::CALENDAR_CONTROL.share().generation > generation as u64);
+    }
+
+    #[pg_test]
+    fn test_pinned_generation_answers_add_days_many() {
+        let date = pgrx::datum::Date::new(2024, 1, 15).unwrap();
+        let calendar_xuids = || vec![Some("month".to_string()), Some("quarter".to_string())];
+        let expected: Vec<_> = crate::kq_cx_add_days_many(date, 1, calendar_xuids()).collect();
+        super::kq_cx_pin_generation();
+
+        // a reload with the month calendar changed meanwhile
+        Spi::run(
+            "DELETE FROM plan.calendar_date WHERE calendar_id = 1 AND \"date\" = '2024-02-01'",
+        )
+        .unwrap();
+        crate::clear_cache();
+        crate::ensure_cache_populated();
+        let pinned: Vec<_> = crate::kq_cx_add_days_many(date, 1, calendar_xuids()).collect();
+        assert_eq!(pinned, expected);
+
+        assert!(super::kq_cx_unpin_generation());
+        let reloaded: Vec<_> = crate::kq_cx_add_days_many(date, 1, calendar_xuids()).collect();
+        assert_ne!(reloaded, expected);
+    }
+}
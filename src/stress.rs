@@ -0,0 +1,277 @@
+use std::ffi::CStr;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::time::{Duration, Instant};
+
+use kq_cx_core::math;
+use kq_cx_core::synthetic::XorShift64;
+use pgrx::bgworkers::*;
+use pgrx::lwlock::PgLwLock;
+use pgrx::prelude::*;
+use pgrx::shmem::*;
+
+use crate::{access, share_calendar, CALENDAR_CONTROL, CALENDAR_ID_MAP};
+
+const MAX_STRESS_WORKERS: usize = 8;
+/// Lookups of a worker per transaction.
+const STRESS_BATCH: usize = 1000;
+/// How long the workers have to start, and to stop once the run is over, before they count as
+/// hung.
+const STRESS_GRACE: Duration = Duration::from_secs(10);
+
+#[derive(Debug, Default)]
+pub struct StressWorker {
+    started: AtomicBool,
+    finished: AtomicBool,
+    /// Stopped by an error.
+    failed: AtomicBool,
+    lookups: AtomicU64,
+    torn_reads: AtomicU64,
+}
+
+impl StressWorker {
+    fn reset(&self) {
+        self.started.store(false, Ordering::Relaxed);
+        self.finished.store(false, Ordering::Relaxed);
+        self.failed.store(false, Ordering::Relaxed);
+        self.lookups.store(0, Ordering::Relaxed);
+        self.torn_reads.store(0, Ordering::Relaxed);
+    }
+
+    fn stopped(&self) -> bool {
+        self.finished.load(Ordering::Relaxed) || self.failed.load(Ordering::Relaxed)
+    }
+}
+
+/// State of the kq_cx_stress() run, one at a time. The counters are atomics so the workers only
+/// take `STRESS_RUN` in share mode.
+#[derive(Debug, Default)]
+pub struct StressRun {
+    active: AtomicBool,
+    running: AtomicBool,
+    workers: [StressWorker; MAX_STRESS_WORKERS],
+}
+
+unsafe impl PGRXSharedMemory for StressRun {}
+
+/// Never held while taking another lock.
+pub(crate) static STRESS_RUN: PgLwLock<StressRun> = PgLwLock::new();
+
+/// Clears `active` when the run ends, stopping the workers when it is cancelled or fails.
+struct ActiveRun;
+
+impl Drop for ActiveRun {
+    fn drop(&mut self) {
+        let run = STRESS_RUN.share();
+        run.running.store(false, Ordering::Relaxed);
+        run.active.store(false, Ordering::Relaxed);
+    }
+}
+
+/// True when the calendar read under its stripe lock is whole: sorted entries matching the
+/// checksum computed when it was installed.
+fn is_consistent(dates: &[i32], checksum: u64) -> bool {
+    dates.windows(2).all(|pair| pair[0] < pair[1]) && kq_cx_core::dates_checksum(dates) == checksum
+}
+
+/// Looks up random dates of random cached calendars, returns the number of lookups and of torn
+/// reads. Calendars removed by a concurrent invalidation are skipped.
+fn stress_batch(rng: &mut XorShift64) -> (u64, u64) {
+    crate::ensure_cache_populated();
+    let calendar_ids: Vec<i64> = CALENDAR_ID_MAP.share().keys().copied().collect();
+    if calendar_ids.is_empty() {
+        return (0, 0);
+    }
+    let (mut lookups, mut torn_reads) = (0, 0);
+    for _ in 0..STRESS_BATCH {
+        check_for_interrupts!();
+        let calendar_id = calendar_ids[(rng.next_u64() % calendar_ids.len() as u64) as usize];
+        let calendar_id_map = CALENDAR_ID_MAP.share();
+        let Some(calendar) = share_calendar(&calendar_id_map, calendar_id) else {
+            continue;
+        };
+        let dates = calendar.dates();
+        let (Some(first), Some(last)) = (dates.first(), dates.last()) else {
+            continue;
+        };
+        // a day of the calendar's range, with some before and after it
+        let span = (*last as i64 - *first as i64 + 61) as u64;
+        let date = *first - 30 + (rng.next_u64() % span) as i32;
+        let interval = (rng.next_u64() % 11) as i64 - 5;
+        let lookup = math::lookup_calendar_days(&calendar, date, interval);
+        let torn = !is_consistent(dates, calendar.checksum())
+            || (lookup.out_of_bounds.is_none()
+                && dates.binary_search(&lookup.result_date).is_err());
+        drop(calendar);
+        lookups += 1;
+        torn_reads += torn as u64;
+    }
+    (lookups, torn_reads)
+}
+
+/// Entry point of the lookup workers started by kq_cx_stress(), the argument is the slot of the
+/// worker. Runs batches of lookups until the run is over.
+#[pg_guard]
+#[no_mangle]
+pub extern "C" fn kq_cx_stress_worker_main(arg: pg_sys::Datum) {
+    let slot = arg.value();
+    BackgroundWorker::attach_signal_handlers(SignalWakeFlags::SIGTERM);
+    BackgroundWorker::connect_worker_to_spi(Some(BackgroundWorker::get_extra()), None);
+    STRESS_RUN.share().workers[slot]
+        .started
+        .store(true, Ordering::Relaxed);
+
+    let mut rng = XorShift64::new(slot as u64 + 1);
+    while STRESS_RUN.share().running.load(Ordering::Relaxed)
+        && !BackgroundWorker::sigterm_received()
+    {
+        BackgroundWorker::transaction(|| {
+            PgTryBuilder::new(|| {
+                let (lookups, torn_reads) = stress_batch(&mut rng);
+                let run = STRESS_RUN.share();
+                let worker = &run.workers[slot];
+                worker.lookups.fetch_add(lookups, Ordering::Relaxed);
+                worker.torn_reads.fetch_add(torn_reads, Ordering::Relaxed);
+            })
+            .catch_others(|error| {
+                STRESS_RUN.share().workers[slot]
+                    .failed
+                    .store(true, Ordering::Relaxed);
+                error.rethrow()
+            })
+            .execute()
+        });
+    }
+    STRESS_RUN.share().workers[slot]
+        .finished
+        .store(true, Ordering::Relaxed);
+}
+
+/// Waits until `done` or the grace period is over.
+fn wait_for(done: impl Fn() -> bool) {
+    let started = Instant::now();
+    while !done() && started.elapsed() < STRESS_GRACE {
+        check_for_interrupts!();
+        std::thread::sleep(Duration::from_millis(10));
+    }
+}
+
+/// Runs `workers` background workers doing lookups for `seconds` while this backend clears and
+/// repopulates the cache in a loop, then reports what went wrong: torn reads (a calendar read
+/// half written, or a result that is not one of its entries), workers stopped by an error or
+/// that did not stop, and a population left marked as running.
+#[pg_extern]
+fn kq_cx_stress(
+    workers: default!(i32, 4),
+    seconds: default!(i32, 10),
+) -> TableIterator<
+    'static,
+    (
+        name!(workers, i32),
+        name!(repopulations, i64),
+        name!(lookups, i64),
+        name!(torn_reads, i64),
+        name!(failed_workers, i32),
+        name!(hung_workers, i32),
+        name!(cache_being_filled, bool),
+    ),
+> {
+    access::ensure_cache_admin("kq_cx_stress");
+    access::ensure_writable("kq_cx_stress");
+    if !(1..=MAX_STRESS_WORKERS as i32).contains(&workers) {
+        error!("workers must be between 1 and {MAX_STRESS_WORKERS}");
+    }
+    if seconds <= 0 {
+        error!("seconds must be greater than 0");
+    }
+    if STRESS_RUN.share().active.swap(true, Ordering::Relaxed) {
+        error!("kq_cx_stress is already running");
+    }
+    let _active = ActiveRun;
+    {
+        let run = STRESS_RUN.share();
+        run.workers.iter().for_each(StressWorker::reset);
+        run.running.store(true, Ordering::Relaxed);
+    }
+
+    let database_name = unsafe { CStr::from_ptr(pg_sys::get_database_name(pg_sys::MyDatabaseId)) }
+        .to_string_lossy()
+        .into_owned();
+    for slot in 0..workers as usize {
+        let started = BackgroundWorkerBuilder::new("kq_cx stress")
+            .set_function("kq_cx_stress_worker_main")
+            .set_library("kq_cx")
+            .set_argument(Some(pg_sys::Datum::from(slot)))
+            .set_extra(&database_name)
+            .enable_spi_access()
+            .set_restart_time(None)
+            .load_dynamic()
+            .is_ok();
+        if !started {
+            error!("cannot start {workers} stress workers, check max_worker_processes");
+        }
+    }
+    let all_workers = |predicate: fn(&StressWorker) -> bool| {
+        STRESS_RUN.share().workers[..workers as usize]
+            .iter()
+            .all(predicate)
+    };
+    wait_for(|| all_workers(|worker| worker.started.load(Ordering::Relaxed)));
+
+    let deadline = Instant::now() + Duration::from_secs(seconds as u64);
+    let mut repopulations = 0;
+    while Instant::now() < deadline {
+        check_for_interrupts!();
+        crate::clear_cache();
+        crate::ensure_cache_populated();
+        repopulations += 1;
+    }
+
+    STRESS_RUN.share().running.store(false, Ordering::Relaxed);
+    wait_for(|| all_workers(StressWorker::stopped));
+    let cache_being_filled = CALENDAR_CONTROL.share().cache_being_filled;
+    let run = STRESS_RUN.share();
+    let workers_run = &run.workers[..workers as usize];
+    let sum = |counter: fn(&StressWorker) -> &AtomicU64| {
+        workers_run
+            .iter()
+            .map(|worker| counter(worker).load(Ordering::Relaxed) as i64)
+            .sum::<i64>()
+    };
+    let count = |flag: fn(&StressWorker) -> bool| {
+        workers_run.iter().filter(|worker| flag(worker)).count() as i32
+    };
+    let row = (
+        workers,
+        repopulations,
+        sum(|worker| &worker.lookups),
+        sum(|worker| &worker.torn_reads),
+        count(|worker| worker.failed.load(Ordering::Relaxed)),
+        count(|worker| !worker.stopped()),
+        cache_being_filled,
+    );
+    drop(run);
+    TableIterator::once(row)
+}
+
+#[cfg(any(test, feature = "pg_test"))]
+#[pg_schema]
+mod tests {
+    use pgrx::prelude::*;
+
+    #[pg_test]
+    fn test_stress() {
+        let (workers, repopulations, lookups, torn_reads, failed, hung, being_filled) =
+            super::kq_cx_stress(2, 2).next().unwrap();
+        assert_eq!(workers, 2);
+        assert!(repopulations > 0);
+        assert!(lookups > 0);
+        assert_eq!((torn_reads, failed, hung), (0, 0, 0));
+        assert!(!being_filled);
+        crate::clear_cache();
+    }
+
+    #[pg_test(error = "workers must be between 1 and 8")]
+    fn test_stress_workers() {
+        super::kq_cx_stress(9, 1);
+    }
+}
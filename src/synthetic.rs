@@ -0,0 +1,174 @@
+use std::time::Instant;
+
+use kq_cx_core::synthetic::{generate_dates, Pattern};
+use kq_cx_core::{date, Calendar, MAX_ENTRIES_PER_CALENDAR};
+use pgrx::prelude::*;
+
+use crate::{
+    access, audit, calendar_key, insert_calendar, metadata, with_schema, PgDate, CALENDAR_CONTROL,
+    CALENDAR_ID_MAP, CALENDAR_XUID_ID_MAP, MAX_CALENDARS,
+};
+
+type SyntheticCalendar = (i64, String, Vec<i32>);
+
+/// Next free calendar id, above the cached calendars and (when persisting) plan.calendar.
+fn next_calendar_id(persist: bool) -> i64 {
+    let cached_max_id = CALENDAR_ID_MAP.share().keys().max().copied().unwrap_or(0);
+    if !persist {
+        return cached_max_id + 1;
+    }
+    let table_max_id = Spi::get_one::<i64>(&with_schema("SELECT MAX(id) FROM {schema}.calendar"))
+        .unwrap_or_else(|spi_error| error!("cannot get the last calendar id. {spi_error}"))
+        .unwrap_or(0);
+    cached_max_id.max(table_max_id) + 1
+}
+
+fn persist_calendars(calendars: &[SyntheticCalendar]) {
+    for (calendar_id, calendar_xuid, dates) in calendars {
+        let dates: Vec<PgDate> = dates
+            .iter()
+            .map(|date| unsafe { PgDate::from_pg_epoch_days(*date) })
+            .collect();
+        Spi::run_with_args(
+            &with_schema(r#"INSERT INTO {schema}.calendar (id, xuid, "name") VALUES ($1, $2, $2)"#),
+            Some(vec![
+                (PgBuiltInOids::INT8OID.oid(), calendar_id.into_datum()),
+                (
+                    PgBuiltInOids::TEXTOID.oid(),
+                    calendar_xuid.as_str().into_datum(),
+                ),
+            ]),
+        )
+        .and_then(|_| {
+            Spi::run_with_args(
+                &with_schema(
+                    r#"INSERT INTO {schema}.calendar_date (calendar_id, "date") SELECT $1, unnest($2)"#,
+                ),
+                Some(vec![
+                    (PgBuiltInOids::INT8OID.oid(), calendar_id.into_datum()),
+                    (PgBuiltInOids::DATEARRAYOID.oid(), dates.into_datum()),
+                ]),
+            )
+        })
+        .unwrap_or_else(|spi_error| {
+            error!("cannot persist calendar xuid = {calendar_xuid}. {spi_error}")
+        });
+    }
+}
+
+fn install_calendars(calendars: &[SyntheticCalendar], started: Instant) {
+    let mut calendar_id_map = CALENDAR_ID_MAP.exclusive();
+    let mut calendar_xuid_id_map = CALENDAR_XUID_ID_MAP.exclusive();
+    let mut entry_count = 0;
+    for (calendar_id, calendar_xuid, dates) in calendars {
+        let calendar = Calendar::from_dates(dates)
+            .unwrap_or_else(|err| error!("cannot create calendar xuid = {calendar_xuid}: {err}"));
+        if !insert_calendar(&mut calendar_id_map, *calendar_id, calendar) {
+            error!("cannot add calendar xuid = {calendar_xuid}: the cache is limited to {MAX_CALENDARS} calendars");
+        }
+        calendar_xuid_id_map
+            .insert(calendar_key("", calendar_xuid).unwrap(), *calendar_id)
+            .unwrap();
+        metadata::record_load(*calendar_id, started);
+        entry_count += dates.len();
+    }
+
+    let mut control = CALENDAR_CONTROL.exclusive();
+    control.calendar_count += calendars.len();
+    control.entry_count += entry_count;
+    control.generation = crate::next_generation(control.generation);
+}
+
+/// Fills the cache with deterministic synthetic calendars (`daily`, `weekly`, `monthly` or
+/// `random`) starting at 2020-01-01. With `persist` the calendars are also inserted in the plan
+/// tables and the cache is reloaded from them, note that entries outside the Q4 window are not
+/// loaded in that case.
+#[pg_extern]
+fn kq_cx_generate_test_calendars(
+    count: i32,
+    entries_per_calendar: i32,
+    pattern: default!(&str, "'daily'"),
+    persist: default!(bool, false),
+) -> TableIterator<
+    'static,
+    (
+        name!(calendar_id, i64),
+        name!(calendar_xuid, String),
+        name!(entries, i64),
+    ),
+> {
+    access::ensure_cache_admin("kq_cx_generate_test_calendars");
+    access::ensure_writable("kq_cx_generate_test_calendars");
+    let started = Instant::now();
+
+    let parsed_pattern = Pattern::parse(pattern).unwrap_or_else(|| {
+        error!("unknown pattern '{pattern}', expected daily, weekly, monthly or random")
+    });
+    if count <= 0 || count as usize > MAX_CALENDARS {
+        error!("count must be between 1 and {MAX_CALENDARS}");
+    }
+    if entries_per_calendar <= 0 || entries_per_calendar as usize > MAX_ENTRIES_PER_CALENDAR {
+        error!("entries_per_calendar must be between 1 and {MAX_ENTRIES_PER_CALENDAR}");
+    }
+
+    crate::ensure_cache_populated();
+
+    let first_date = date::from_ymd(2020, 1, 1);
+    let first_calendar_id = next_calendar_id(persist);
+    let calendars: Vec<SyntheticCalendar> = (0..count as i64)
+        .map(|index| {
+            let calendar_id = first_calendar_id + index;
+            (
+                calendar_id,
+                format!("synthetic_{}_{}", pattern.to_ascii_lowercase(), calendar_id),
+                generate_dates(
+                    parsed_pattern,
+                    first_date,
+                    entries_per_calendar as usize,
+                    calendar_id as u64,
+                ),
+            )
+        })
+        .collect();
+
+    if persist {
+        persist_calendars(&calendars);
+        crate::clear_cache();
+        crate::ensure_cache_populated();
+    } else {
+        install_calendars(&calendars, started);
+    }
+
+    let entry_count = calendars.iter().map(|(_, _, dates)| dates.len()).sum();
+    audit::record(audit::AuditOperation::Generate, started, entry_count);
+
+    TableIterator::new(
+        calendars
+            .into_iter()
+            .map(|(calendar_id, calendar_xuid, dates)| {
+                (calendar_id, calendar_xuid, dates.len() as i64)
+            }),
+    )
+}
+
+#[cfg(any(test, feature = "pg_test"))]
+#[pg_schema]
+mod tests {
+    use pgrx::prelude::*;
+
+    #[pg_test]
+    fn test_generate_weekly_calendars() {
+        let generated: Vec<_> = super::kq_cx_generate_test_calendars(2, 100, "weekly", false)
+            .map(|(calendar_id, _, entries)| (calendar_id, entries))
+            .collect();
+        assert_eq!(generated.len(), 2);
+
+        let (calendar_id, entries) = generated[0];
+        assert_eq!(entries, 100);
+        let first_date = crate::PgDate::new(2020, 1, 1).unwrap();
+        assert_eq!(
+            crate::kq_cx_add_days(first_date, 1, calendar_id),
+            Some(crate::PgDate::new(2020, 1, 8).unwrap())
+        );
+    }
+}
@@ -0,0 +1,166 @@
+use kq_cx_core::math::{self, OutOfBounds};
+use pgrx::prelude::*;
+
+use crate::{oob, with_schema, PgDate, CALENDAR_CONTROL, UNCACHED_FALLBACK};
+
+const Q_PREVIOUS_ENTRY: &str = r#"
+    SELECT MAX("date") FROM {schema}.calendar_date WHERE calendar_id = $1 AND "date" <= $2"#;
+const Q_HAS_ENTRIES: &str =
+    r#"SELECT EXISTS (SELECT 1 FROM {schema}.calendar_date WHERE calendar_id = $1)"#;
+const Q_ENTRY_BOUNDS: &str = r#"
+    SELECT MIN("date"), MAX("date") FROM {schema}.calendar_date WHERE calendar_id = $1"#;
+/// The cache keeps repeated dates once, the steps only count distinct dates.
+const Q_NEXT_ENTRY: &str = r#"
+    SELECT DISTINCT "date" FROM {schema}.calendar_date WHERE calendar_id = $1 AND "date" >= $2
+    ORDER BY "date" OFFSET $3 LIMIT 1"#;
+const Q_PREVIOUS_ENTRY_AT: &str = r#"
+    SELECT DISTINCT "date" FROM {schema}.calendar_date WHERE calendar_id = $1 AND "date" <= $2
+    ORDER BY "date" DESC OFFSET $3 LIMIT 1"#;
+
+/// True when kq.calendar.uncached_fallback is on and the lookups would otherwise wait for the
+/// population of another session, or fail because the calendars do not fit in the cache.
+pub(crate) fn fallback_active() -> bool {
+    if !UNCACHED_FALLBACK.get() {
+        return false;
+    }
+    let control = CALENDAR_CONTROL.share();
+    !control.cache_filled && (control.cache_being_filled || control.capacity_exceeded)
+}
+
+fn query_date(query: &str, calendar_id: i64, date: PgDate, offset: Option<i64>) -> Option<PgDate> {
+    let mut args = vec![
+        (PgBuiltInOids::INT8OID.oid(), calendar_id.into_datum()),
+        (PgBuiltInOids::DATEOID.oid(), date.into_datum()),
+    ];
+    if let Some(offset) = offset {
+        args.push((PgBuiltInOids::INT8OID.oid(), offset.into_datum()));
+    }
+    Spi::get_one_with_args::<PgDate>(&with_schema(query), args)
+        .unwrap_or_else(|spi_error| error!("cannot read calendar_id = {calendar_id}. {spi_error}"))
+}
+
+/// First and last entries of the calendar, `None` when it has no entries.
+fn entry_bounds(calendar_id: i64) -> Option<(i32, i32)> {
+    let (first, last) = Spi::get_two_with_args::<PgDate, PgDate>(
+        &with_schema(Q_ENTRY_BOUNDS),
+        vec![(PgBuiltInOids::INT8OID.oid(), calendar_id.into_datum())],
+    )
+    .unwrap_or_else(|spi_error| error!("cannot read calendar_id = {calendar_id}. {spi_error}"));
+    Some((first?.to_pg_epoch_days(), last?.to_pg_epoch_days()))
+}
+
+fn lookup(calendar_id: i64, input_date: PgDate, interval: i64) -> Result<i32, OutOfBounds> {
+    let Some(previous) = query_date(Q_PREVIOUS_ENTRY, calendar_id, input_date, None) else {
+        let has_entries = Spi::get_one_with_args::<bool>(
+            &with_schema(Q_HAS_ENTRIES),
+            vec![(PgBuiltInOids::INT8OID.oid(), calendar_id.into_datum())],
+        )
+        .unwrap_or_else(|spi_error| error!("cannot read calendar_id = {calendar_id}. {spi_error}"))
+        .unwrap_or_default();
+        return match has_entries {
+            true => Err(OutOfBounds::Past),
+            false => {
+                let lookup = math::plain_arithmetic(input_date.to_pg_epoch_days(), interval);
+                lookup.out_of_bounds.map_or(Ok(lookup.result_date), Err)
+            }
+        };
+    };
+
+    if interval >= 0 {
+        query_date(Q_NEXT_ENTRY, calendar_id, previous, Some(interval))
+            .map(|date| date.to_pg_epoch_days())
+            .ok_or(OutOfBounds::Future)
+    } else {
+        query_date(
+            Q_PREVIOUS_ENTRY_AT,
+            calendar_id,
+            previous,
+            Some(interval.saturating_neg()),
+        )
+        .map(|date| date.to_pg_epoch_days())
+        .ok_or(OutOfBounds::Past)
+    }
+}
+
+/// Same result as `kq_cx_add_days`, computed with index scans on plan.calendar_date.
+pub(crate) fn add_days(calendar_id: i64, input_date: PgDate, interval: i64) -> i32 {
+    let result_date = lookup(calendar_id, input_date, interval);
+    if oob::strict() {
+        if let Some((first, last)) = entry_bounds(calendar_id) {
+            let date = input_date.to_pg_epoch_days();
+            if result_date.is_err() || date < first || date > last {
+                oob::bounds_error(calendar_id, date, interval, (first, last));
+            }
+        }
+    }
+    result_date.unwrap_or_else(oob::sentinel)
+}
+
+pub(crate) fn calendar_id(calendar_xuid: &str) -> Option<i64> {
+    Spi::get_one_with_args::<i64>(
+        &with_schema("SELECT id FROM {schema}.calendar WHERE xuid = $1"),
+        vec![(PgBuiltInOids::TEXTOID.oid(), calendar_xuid.into_datum())],
+    )
+    .unwrap_or_else(|spi_error| error!("cannot read calendar xuid = {calendar_xuid}. {spi_error}"))
+}
+
+/// Same as kq_cx_add_days_xuid, reading plan.calendar and plan.calendar_date instead of the
+/// cache. Exceptions, defined calendars and parents are not taken into account.
+#[pg_extern(parallel_safe, stable)]
+pub(crate) fn kq_cx_add_days_uncached(
+    input_date: PgDate,
+    interval: i64,
+    calendar_xuid: &str,
+) -> Option<PgDate> {
+    crate::log_slow_lookup(
+        "kq_cx_add_days_uncached",
+        || format!("{input_date}, {interval}, {calendar_xuid}"),
+        || add_days_xuid(input_date, interval, calendar_xuid),
+    )
+}
+
+pub(crate) fn add_days_xuid(
+    input_date: PgDate,
+    interval: i64,
+    calendar_xuid: &str,
+) -> Option<PgDate> {
+    let Some(calendar_id) = calendar_id(calendar_xuid) else {
+        warning!("calendar_xuid = {calendar_xuid} not found in plan.calendar");
+        return None;
+    };
+    let result_date = add_days(calendar_id, input_date, interval);
+    Some(unsafe { PgDate::from_pg_epoch_days(result_date) })
+}
+
+#[cfg(any(test, feature = "pg_test"))]
+#[pg_schema]
+mod tests {
+    use pgrx::prelude::*;
+
+    #[pg_test]
+    fn test_uncached_matches_cache() {
+        // repeated dates are cached once
+        Spi::run(
+            "INSERT INTO plan.calendar_date (calendar_id, \"date\") \
+             VALUES (1, '2024-02-01'), (2, '2024-04-01')",
+        )
+        .unwrap();
+        crate::clear_cache();
+        let first_day = pgrx::datum::Date::new(2023, 11, 1)
+            .unwrap()
+            .to_pg_epoch_days();
+        for calendar_xuid in ["month", "quarter"] {
+            for day in (0..1400).step_by(37) {
+                let input_date = unsafe { crate::PgDate::from_pg_epoch_days(first_day + day) };
+                for interval in -3..=3 {
+                    assert_eq!(
+                        super::kq_cx_add_days_uncached(input_date, interval, calendar_xuid),
+                        crate::kq_cx_add_days_xuid(input_date, interval, calendar_xuid),
+                        "calendar_xuid = {calendar_xuid}, date = {input_date}, interval = {interval}"
+                    );
+                }
+            }
+        }
+        crate::clear_cache();
+    }
+}
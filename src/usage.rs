@@ -0,0 +1,226 @@
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicI64, AtomicU64, Ordering};
+use std::time::Instant;
+
+use pgrx::datum::TimestampWithTimeZone;
+use pgrx::lwlock::PgLwLock;
+use pgrx::prelude::*;
+use pgrx::shmem::*;
+
+use crate::{xuids, CalendarIdMap, CALENDAR_XUID_ID_MAP, MAX_CALENDARS, TRACK_LOOKUP_TIMES};
+
+/// Lookups served from the cache for one calendar. The counters are atomics so lookups only take
+/// `CALENDAR_USAGE` in share mode.
+#[derive(Debug, Default)]
+pub struct CalendarUsage {
+    lookups: AtomicU64,
+    last_used: AtomicI64,
+    /// Lookups counted while kq.calendar.track_lookup_times was on, and their total duration.
+    timed_lookups: AtomicU64,
+    lookup_ns: AtomicU64,
+}
+
+impl CalendarUsage {
+    pub(crate) fn lookups(&self) -> i64 {
+        self.lookups.load(Ordering::Relaxed) as i64
+    }
+
+    /// `None` when the calendar was never used.
+    pub(crate) fn last_used(&self) -> Option<pg_sys::TimestampTz> {
+        match self.lookups() {
+            0 => None,
+            _ => Some(self.last_used.load(Ordering::Relaxed)),
+        }
+    }
+
+    /// `None` when no lookup was timed.
+    fn average_lookup_us(&self) -> Option<f64> {
+        match self.timed_lookups.load(Ordering::Relaxed) {
+            0 => None,
+            timed_lookups => {
+                Some(self.lookup_ns.load(Ordering::Relaxed) as f64 / timed_lookups as f64 / 1000.0)
+            }
+        }
+    }
+}
+
+#[derive(Debug, Default)]
+pub struct CalendarUsageMap {
+    calendars: heapless::FnvIndexMap<i64, CalendarUsage, MAX_CALENDARS>,
+}
+
+unsafe impl PGRXSharedMemory for CalendarUsageMap {}
+
+/// Kept across populations, calendars dropped from the cache are only pruned when the map is
+/// full. Never held while taking another lock, it comes after CALENDAR_ID_MAP.
+pub(crate) static CALENDAR_USAGE: PgLwLock<CalendarUsageMap> = PgLwLock::new();
+
+/// Start of the lookups passed to `record`, only read when kq.calendar.track_lookup_times is on.
+pub(crate) fn start() -> Option<Instant> {
+    TRACK_LOOKUP_TIMES.get().then(Instant::now)
+}
+
+/// Counts `lookups` lookups of the calendar, started at `started` (see `start`). The last use is
+/// the start of the current statement to avoid reading the clock on every lookup.
+pub(crate) fn record(
+    calendar_id_map: &CalendarIdMap,
+    calendar_id: i64,
+    lookups: u64,
+    started: Option<Instant>,
+) {
+    if lookups == 0 {
+        return;
+    }
+    let now = unsafe { pg_sys::GetCurrentStatementStartTimestamp() };
+    let elapsed_ns =
+        started.map(|started| started.elapsed().as_nanos().min(u64::MAX as u128) as u64);
+    let count = |usage: &CalendarUsage| {
+        usage.lookups.fetch_add(lookups, Ordering::Relaxed);
+        usage.last_used.fetch_max(now, Ordering::Relaxed);
+        if let Some(elapsed_ns) = elapsed_ns {
+            usage.timed_lookups.fetch_add(lookups, Ordering::Relaxed);
+            usage.lookup_ns.fetch_add(elapsed_ns, Ordering::Relaxed);
+        }
+    };
+    if let Some(usage) = CALENDAR_USAGE.share().calendars.get(&calendar_id) {
+        count(usage);
+        return;
+    }
+
+    let mut usage_map = CALENDAR_USAGE.exclusive();
+    if !usage_map.calendars.contains_key(&calendar_id) {
+        if usage_map.calendars.len() == MAX_CALENDARS {
+            let dropped: Vec<i64> = usage_map
+                .calendars
+                .keys()
+                .filter(|calendar_id| !calendar_id_map.contains_key(*calendar_id))
+                .copied()
+                .collect();
+            for calendar_id in dropped {
+                usage_map.calendars.remove(&calendar_id);
+            }
+        }
+        if usage_map
+            .calendars
+            .insert(calendar_id, CalendarUsage::default())
+            .is_err()
+        {
+            return;
+        }
+    }
+    count(&usage_map.calendars[&calendar_id]);
+}
+
+/// Lookup count and last use of the calendar, (0, None) when it was never used.
+pub(crate) fn get(calendar_id: i64) -> (i64, Option<pg_sys::TimestampTz>) {
+    CALENDAR_USAGE
+        .share()
+        .calendars
+        .get(&calendar_id)
+        .map_or((0, None), |usage| (usage.lookups(), usage.last_used()))
+}
+
+/// Sets the lookup counts and times back to zero, the last uses are kept.
+pub(crate) fn reset() {
+    for usage in CALENDAR_USAGE.share().calendars.values() {
+        usage.lookups.store(0, Ordering::Relaxed);
+        usage.timed_lookups.store(0, Ordering::Relaxed);
+        usage.lookup_ns.store(0, Ordering::Relaxed);
+    }
+}
+
+/// The `n` calendars with the most lookups since the server started or `kq_cx_reset_stats()`.
+/// The average lookup time only counts the lookups made while kq.calendar.track_lookup_times was
+/// on, it is NULL when there were none.
+#[pg_extern(parallel_safe)]
+fn kq_cx_top_calendars(
+    n: default!(i32, 10),
+) -> TableIterator<
+    'static,
+    (
+        name!(calendar_id, i64),
+        name!(calendar_xuid, Option<String>),
+        name!(lookups, i64),
+        name!(avg_lookup_us, Option<f64>),
+        name!(last_used, Option<TimestampWithTimeZone>),
+    ),
+> {
+    let calendar_xuids: HashMap<i64, String> = CALENDAR_XUID_ID_MAP
+        .share()
+        .iter()
+        .map(|((namespace, xuid), calendar_id)| {
+            (
+                *calendar_id,
+                xuids::cached_xuid(namespace, xuid, *calendar_id),
+            )
+        })
+        .collect();
+    let mut rows: Vec<_> = CALENDAR_USAGE
+        .share()
+        .calendars
+        .iter()
+        .filter(|(_, usage)| usage.lookups() > 0)
+        .map(|(calendar_id, usage)| {
+            (
+                *calendar_id,
+                calendar_xuids.get(calendar_id).cloned(),
+                usage.lookups(),
+                usage.average_lookup_us(),
+                usage.last_used().and_then(|last_used| unsafe {
+                    TimestampWithTimeZone::from_datum(pg_sys::Datum::from(last_used), false)
+                }),
+            )
+        })
+        .collect();
+    rows.sort_by_key(|(calendar_id, _, lookups, ..)| (-lookups, *calendar_id));
+    rows.truncate(n.max(0) as usize);
+    TableIterator::new(rows)
+}
+
+#[cfg(any(test, feature = "pg_test"))]
+#[pg_schema]
+mod tests {
+    use pgrx::prelude::*;
+
+    #[pg_test]
+    fn test_lookups_are_counted() {
+        let date = |month| pgrx::datum::Date::new(2024, month, 1).unwrap();
+        crate::ensure_cache_populated();
+        let (lookups_before, _) = super::get(1);
+        crate::kq_cx_add_days_xuid(date(1), 1, "month");
+        crate::kq_cx_add_days_grid(
+            vec![Some(date(1)), Some(date(2))],
+            vec![Some(1), Some(2)],
+            "month",
+        )
+        .for_each(drop);
+
+        let (lookups, last_used) = super::get(1);
+        assert_eq!(lookups, lookups_before + 5);
+        assert!(last_used.is_some());
+        crate::clear_cache();
+    }
+
+    #[pg_test]
+    fn test_top_calendars() {
+        let date = pgrx::datum::Date::new(2024, 1, 1).unwrap();
+        crate::ensure_cache_populated();
+        super::reset();
+        Spi::run("SET LOCAL kq.calendar.track_lookup_times = on").unwrap();
+        crate::kq_cx_add_days_xuid(date, 1, "month");
+        crate::kq_cx_add_days_xuid(date, 1, "month");
+        crate::kq_cx_add_days_xuid(date, 1, "quarter");
+
+        let top: Vec<_> = super::kq_cx_top_calendars(1).collect();
+        assert_eq!(top.len(), 1);
+        let (calendar_id, calendar_xuid, lookups, avg_lookup_us, _) = &top[0];
+        assert_eq!(*calendar_id, 1);
+        assert_eq!(calendar_xuid.as_deref(), Some("month"));
+        assert_eq!(*lookups, 2);
+        assert!(avg_lookup_us.is_some());
+
+        super::reset();
+        assert_eq!(super::kq_cx_top_calendars(10).count(), 0);
+        crate::clear_cache();
+    }
+}
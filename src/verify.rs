@@ -0,0 +1,281 @@
+use std::collections::BTreeMap;
+
+use kq_cx_core::math;
+use kq_cx_core::synthetic::XorShift64;
+use pgrx::prelude::*;
+
+use crate::{
+    get_calendar_xuid_from_id, loader, oob, qualified_xuid, share_calendar, simulate, PgDate,
+    CALENDAR_ID_MAP, CALENDAR_XUID_ID_MAP,
+};
+
+/// Summary of one calendar, either from shared memory or from the source tables.
+struct CalendarDigest {
+    xuid: String,
+    entries: i64,
+    checksum: u64,
+}
+
+fn cached_digests() -> BTreeMap<i64, CalendarDigest> {
+    let calendar_id_map = CALENDAR_ID_MAP.share();
+    calendar_id_map
+        .keys()
+        .filter_map(|calendar_id| {
+            let xuid = get_calendar_xuid_from_id(CALENDAR_XUID_ID_MAP.share(), calendar_id);
+            let calendar = share_calendar(&calendar_id_map, *calendar_id)?;
+            Some((
+                *calendar_id,
+                CalendarDigest {
+                    xuid,
+                    entries: calendar.dates().len() as i64,
+                    checksum: calendar.checksum(),
+                },
+            ))
+        })
+        .collect()
+}
+
+fn source_digests() -> BTreeMap<i64, CalendarDigest> {
+    loader::load_calendars()
+        .into_iter()
+        .map(|loaded| {
+            (
+                loaded.calendar_id,
+                CalendarDigest {
+                    xuid: qualified_xuid(&loaded.namespace, &loaded.xuid),
+                    entries: loaded.calendar.dates().len() as i64,
+                    checksum: loaded.calendar.checksum(),
+                },
+            )
+        })
+        .collect()
+}
+
+/// Re-runs the loader queries and compares every calendar against the cached copy. Calendars
+/// missing on one side are reported with NULL entries for that side.
+#[pg_extern]
+fn kq_cx_verify_cache() -> TableIterator<
+    'static,
+    (
+        name!(calendar_id, i64),
+        name!(calendar_xuid, String),
+        name!(in_sync, bool),
+        name!(cached_entries, Option<i64>),
+        name!(db_entries, Option<i64>),
+        name!(checksum_match, bool),
+    ),
+> {
+    if !crate::validate_compatible_db() {
+        return TableIterator::new(vec![]);
+    }
+
+    let mut cached = cached_digests();
+    let source = source_digests();
+
+    let mut rows = vec![];
+    for (calendar_id, db) in source {
+        let cached_digest = cached.remove(&calendar_id);
+        let checksum_match = cached_digest
+            .as_ref()
+            .is_some_and(|cached_digest| cached_digest.checksum == db.checksum);
+        let cached_entries = cached_digest.map(|cached_digest| cached_digest.entries);
+        rows.push((
+            calendar_id,
+            db.xuid,
+            checksum_match && cached_entries == Some(db.entries),
+            cached_entries,
+            Some(db.entries),
+            checksum_match,
+        ));
+    }
+    for (calendar_id, cached_digest) in cached {
+        rows.push((
+            calendar_id,
+            cached_digest.xuid,
+            false,
+            Some(cached_digest.entries),
+            None,
+            false,
+        ));
+    }
+    rows.sort_by_key(|row| row.0);
+    TableIterator::new(rows)
+}
+
+/// Sampled lookup of a cached calendar: input date, interval and cached result.
+type SampledLookup = (i32, i64, Result<i32, math::OutOfBounds>);
+
+/// Draws `samples` lookups of the cached calendar, dates from a month before its first entry to a
+/// month after its last one and intervals from -5 to 5. The same calendar always gets the same
+/// samples.
+fn sample_lookups(calendar_id: i64, samples: usize) -> Vec<SampledLookup> {
+    let calendar_id_map = CALENDAR_ID_MAP.share();
+    let Some(calendar) = share_calendar(&calendar_id_map, calendar_id) else {
+        return vec![];
+    };
+    let (Some(first), Some(last)) = (calendar.dates().first(), calendar.dates().last()) else {
+        return vec![];
+    };
+    let span = (*last as i64 - *first as i64 + 61) as u64;
+    let mut rng = XorShift64::new(calendar_id as u64);
+    (0..samples)
+        .map(|_| {
+            let date = *first - 30 + (rng.next_u64() % span) as i32;
+            let interval = (rng.next_u64() % 11) as i64 - 5;
+            let lookup = math::lookup_calendar_days(&calendar, date, interval);
+            let result = lookup.out_of_bounds.map_or(Ok(lookup.result_date), Err);
+            (date, interval, result)
+        })
+        .collect()
+}
+
+/// Compares sampled lookups of every cached calendar with `kq_cx_simulate`, computed from
+/// plan.calendar_date, and returns the ones that differ. Defined calendars are skipped, calendars
+/// with exceptions or parents are expected to differ where those apply.
+#[pg_extern]
+fn kq_cx_verify_lookups(
+    samples: default!(i32, 100),
+) -> TableIterator<
+    'static,
+    (
+        name!(calendar_id, i64),
+        name!(calendar_xuid, String),
+        name!(input_date, PgDate),
+        name!(interval, i32),
+        name!(cached_date, PgDate),
+        name!(simulated_date, PgDate),
+    ),
+> {
+    crate::ensure_cache_populated();
+    if samples <= 0 {
+        error!("samples must be greater than 0");
+    }
+    let mut calendar_ids: Vec<i64> = CALENDAR_ID_MAP.share().keys().copied().collect();
+    calendar_ids.sort_unstable();
+
+    let to_date = |result: Result<i32, math::OutOfBounds>| unsafe {
+        PgDate::from_pg_epoch_days(result.unwrap_or_else(oob::sentinel))
+    };
+    let mut rows = vec![];
+    for calendar_id in calendar_ids
+        .into_iter()
+        .filter(|calendar_id| *calendar_id > 0)
+    {
+        // the stripe lock is released before the queries run
+        for (date, interval, cached) in sample_lookups(calendar_id, samples as usize) {
+            let simulated = simulate::simulate(calendar_id, date, interval);
+            if simulated != cached {
+                let xuid = get_calendar_xuid_from_id(CALENDAR_XUID_ID_MAP.share(), &calendar_id);
+                rows.push((
+                    calendar_id,
+                    xuid,
+                    unsafe { PgDate::from_pg_epoch_days(date) },
+                    interval as i32,
+                    to_date(cached),
+                    to_date(simulated),
+                ));
+            }
+        }
+    }
+    TableIterator::new(rows)
+}
+
+/// Runs the loader queries in checking mode and reports, per calendar, the source rows that are
+/// duplicated, have a NULL date, come out of date order or fall outside of the supported dates.
+/// The cache is not touched, so it can run right after a calendar data load.
+#[pg_extern]
+fn kq_cx_validate_source() -> TableIterator<
+    'static,
+    (
+        name!(calendar_id, i64),
+        name!(calendar_xuid, Option<String>),
+        name!(valid, bool),
+        name!(source_rows, i64),
+        name!(duplicates, i64),
+        name!(null_dates, i64),
+        name!(out_of_order, i64),
+        name!(outside_window, i64),
+    ),
+> {
+    if !crate::validate_compatible_db() {
+        return TableIterator::new(vec![]);
+    }
+
+    TableIterator::new(loader::check_source().into_iter().map(|check| {
+        let valid = check.xuid.is_some()
+            && check.duplicates == 0
+            && check.null_dates == 0
+            && check.out_of_order == 0
+            && check.outside_window == 0;
+        (
+            check.calendar_id,
+            check.xuid,
+            valid,
+            check.rows,
+            check.duplicates,
+            check.null_dates,
+            check.out_of_order,
+            check.outside_window,
+        )
+    }))
+}
+
+#[cfg(any(test, feature = "pg_test"))]
+#[pg_schema]
+mod tests {
+    use pgrx::prelude::*;
+
+    #[pg_test]
+    fn test_verify_detects_drift() {
+        crate::clear_cache();
+        crate::ensure_cache_populated();
+        assert!(super::kq_cx_verify_cache().all(|row| row.2));
+
+        Spi::run("INSERT INTO plan.calendar_date (calendar_id, \"date\") VALUES (1, '2024-07-01')")
+            .unwrap();
+        let drifted: Vec<_> = super::kq_cx_verify_cache()
+            .filter(|row| !row.2)
+            .map(|row| (row.0, row.3, row.4))
+            .collect();
+        assert_eq!(drifted, vec![(1, Some(6), Some(7))]);
+    }
+
+    #[pg_test]
+    fn test_verify_lookups_detects_drift() {
+        crate::clear_cache();
+        assert_eq!(super::kq_cx_verify_lookups(200).count(), 0);
+
+        Spi::run(
+            "DELETE FROM plan.calendar_date WHERE calendar_id = 1 AND \"date\" = '2024-03-01'",
+        )
+        .unwrap();
+        let drifted: Vec<_> = super::kq_cx_verify_lookups(200).collect();
+        assert!(!drifted.is_empty());
+        assert!(drifted.iter().all(|row| row.0 == 1 && row.4 != row.5));
+    }
+
+    #[pg_test]
+    fn test_validate_source_reports_bad_rows() {
+        assert!(super::kq_cx_validate_source().all(|row| row.2));
+
+        Spi::run(
+            "SET LOCAL kq.calendar.q3_get_calendar_entries = \
+             'SELECT * FROM (VALUES (1::int8, ''2024-02-01''::date), (1, ''2024-01-01''), \
+              (1, ''2024-01-01''), (1, NULL), (2, ''2300-01-01''), (9, ''2024-01-01'')) \
+              AS entries(calendar_id, entry_date)'",
+        )
+        .unwrap();
+        let checks: Vec<_> = super::kq_cx_validate_source()
+            .map(|row| (row.0, row.2, row.3, row.4, row.5, row.6, row.7))
+            .collect();
+        assert_eq!(
+            checks,
+            vec![
+                (1, false, 4, 1, 1, 1, 0),
+                (2, false, 1, 0, 0, 0, 1),
+                (3, true, 0, 0, 0, 0, 0),
+                (9, false, 1, 0, 0, 0, 0),
+            ]
+        );
+    }
+}
@@ -0,0 +1,312 @@
+//! Keys of the calendars whose xuid is longer than `CALENDAR_XUID_MAX_LEN` bytes, see
+//! kq.calendar.xuid_overflow. Such a calendar is stored under a key derived from its xuid, the
+//! full xuid is kept in `CALENDAR_LONG_XUIDS` so lookups can tell calendars sharing a key apart.
+//!
+//! Xuids are normalized as kq.calendar.xuid_normalization says before they are stored or looked
+//! up, so the keys never hold two forms of the same name.
+
+use std::borrow::Cow;
+use std::str::FromStr;
+
+use pgrx::lwlock::PgLwLock;
+use pgrx::prelude::*;
+use unicode_normalization::{is_nfc_quick, IsNormalized, UnicodeNormalization};
+
+use crate::{
+    get_guc_string_opt, qualified_xuid, truncated, CalendarKey, CalendarNamespace, CalendarXuid,
+    CalendarXuidIdMap, CALENDAR_XUID_MAX_LEN, GUC_XUID_NORMALIZATION, GUC_XUID_OVERFLOW,
+    MAX_CALENDARS, XUID_NORMALIZATION, XUID_OVERFLOW,
+};
+
+const LONG_XUID_MAX_LEN: usize = 256;
+/// `#` and the hash of the xuid in hex, after the prefix of the xuid.
+const HASH_SUFFIX_LEN: usize = 17;
+/// Derived keys are never shorter, a character can be cut off with up to 3 bytes left unused.
+const DERIVED_KEY_MIN_LEN: usize = CALENDAR_XUID_MAX_LEN - 3;
+
+/// What the population does with a xuid longer than `CALENDAR_XUID_MAX_LEN` bytes.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub(crate) enum XuidOverflow {
+    /// Fails, naming the calendar and the length of its xuid.
+    Error,
+    /// Stores the calendar under the longest prefix that fits, with a warning. Two xuids with the
+    /// same prefix fail the population.
+    Truncate,
+    /// Stores the calendar under a prefix followed by a hash of the whole xuid.
+    Hash,
+}
+
+fn parse_xuid_overflow(value: &str) -> Option<XuidOverflow> {
+    match value.trim().to_ascii_lowercase().as_str() {
+        "" | "error" => Some(XuidOverflow::Error),
+        "truncate" => Some(XuidOverflow::Truncate),
+        "hash" => Some(XuidOverflow::Hash),
+        _ => None,
+    }
+}
+
+pub(crate) fn xuid_overflow() -> XuidOverflow {
+    let value = get_guc_string_opt(GUC_XUID_OVERFLOW, &XUID_OVERFLOW).unwrap_or_default();
+    parse_xuid_overflow(&value).unwrap_or_else(|| {
+        ereport!(
+            ERROR,
+            PgSqlErrorCode::ERRCODE_INVALID_PARAMETER_VALUE,
+            format!("invalid value for {GUC_XUID_OVERFLOW}: \"{value}\""),
+            "Use error, truncate or hash."
+        )
+    })
+}
+
+/// How xuids are normalized, kq.calendar.xuid_normalization.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub(crate) enum XuidNormalization {
+    /// Compared byte by byte.
+    None,
+    /// Unicode NFC, the composed form most clients send.
+    Nfc,
+    /// NFC, then lower case, so `MÜNCHEN` and `münchen` are the same xuid.
+    NfcCasefold,
+}
+
+fn parse_xuid_normalization(value: &str) -> Option<XuidNormalization> {
+    match value.trim().to_ascii_lowercase().as_str() {
+        "" | "none" => Some(XuidNormalization::None),
+        "nfc" => Some(XuidNormalization::Nfc),
+        "nfc_casefold" => Some(XuidNormalization::NfcCasefold),
+        _ => None,
+    }
+}
+
+pub(crate) fn xuid_normalization() -> XuidNormalization {
+    let value = get_guc_string_opt(GUC_XUID_NORMALIZATION, &XUID_NORMALIZATION).unwrap_or_default();
+    parse_xuid_normalization(&value).unwrap_or_else(|| {
+        ereport!(
+            ERROR,
+            PgSqlErrorCode::ERRCODE_INVALID_PARAMETER_VALUE,
+            format!("invalid value for {GUC_XUID_NORMALIZATION}: \"{value}\""),
+            "Use none, nfc or nfc_casefold."
+        )
+    })
+}
+
+fn normalize(xuid: &str, normalization: XuidNormalization) -> Cow<'_, str> {
+    let nfc = match normalization {
+        XuidNormalization::None => return Cow::Borrowed(xuid),
+        _ if is_nfc_quick(xuid.chars()) == IsNormalized::Yes => Cow::Borrowed(xuid),
+        _ => Cow::Owned(xuid.nfc().collect()),
+    };
+    match normalization {
+        XuidNormalization::NfcCasefold if nfc.chars().any(char::is_uppercase) => {
+            Cow::Owned(nfc.to_lowercase())
+        }
+        _ => nfc,
+    }
+}
+
+/// `xuid` in the form the cache keys use.
+pub(crate) fn normalized(xuid: &str) -> Cow<'_, str> {
+    normalize(xuid, xuid_normalization())
+}
+
+type LongXuid = heapless::String<LONG_XUID_MAX_LEN>;
+pub(crate) type LongXuidMap = heapless::FnvIndexMap<i64, LongXuid, MAX_CALENDARS>;
+
+/// Full xuid of the calendars stored under a derived key, by calendar id.
+pub(crate) static CALENDAR_LONG_XUIDS: PgLwLock<LongXuidMap> = PgLwLock::new();
+
+/// FNV-1a, stable across builds so the keys do not change with the toolchain.
+fn fnv1a(value: &str) -> u64 {
+    value.bytes().fold(0xcbf2_9ce4_8422_2325, |hash, byte| {
+        (hash ^ byte as u64).wrapping_mul(0x0100_0000_01b3)
+    })
+}
+
+fn hashed_key(xuid: &str) -> CalendarXuid {
+    let prefix = truncated::<{ CALENDAR_XUID_MAX_LEN - HASH_SUFFIX_LEN }>(xuid);
+    let mut key = CalendarXuid::new();
+    key.push_str(&prefix).unwrap();
+    key.push_str(&format!("#{:016x}", fnv1a(xuid))).unwrap();
+    key
+}
+
+/// Key the calendar with `xuid` is stored under, the xuid itself when it fits.
+pub(crate) fn stored_xuid(xuid: &str, overflow: XuidOverflow) -> Result<CalendarXuid, String> {
+    let xuid = normalized(xuid);
+    let xuid = xuid.as_ref();
+    if let Ok(key) = CalendarXuid::from_str(xuid) {
+        return Ok(key);
+    }
+    let limit = match overflow {
+        XuidOverflow::Error => CALENDAR_XUID_MAX_LEN,
+        _ => LONG_XUID_MAX_LEN,
+    };
+    if xuid.len() > limit {
+        return Err(format!(
+            "xuid \"{xuid}\" is {} bytes long, limit is {limit}",
+            xuid.len()
+        ));
+    }
+    Ok(match overflow {
+        XuidOverflow::Hash => hashed_key(xuid),
+        _ => truncated(xuid),
+    })
+}
+
+pub(crate) fn stored_key(
+    namespace: &str,
+    xuid: &str,
+    overflow: XuidOverflow,
+) -> Result<CalendarKey, String> {
+    let namespace = CalendarNamespace::from_str(namespace)
+        .map_err(|_| format!("namespace \"{namespace}\" is too long"))?;
+    Ok((namespace, stored_xuid(xuid, overflow)?))
+}
+
+/// Records the full xuid of a calendar stored under a derived key, forgets it otherwise.
+pub(crate) fn record(calendar_id: i64, xuid: &str) {
+    let xuid = normalized(xuid);
+    let mut long_xuids = CALENDAR_LONG_XUIDS.exclusive();
+    match xuid.len() > CALENDAR_XUID_MAX_LEN {
+        true => {
+            long_xuids.insert(calendar_id, truncated(&xuid)).unwrap();
+        }
+        false => {
+            long_xuids.remove(&calendar_id);
+        }
+    }
+}
+
+pub(crate) fn clear() {
+    CALENDAR_LONG_XUIDS.exclusive().clear();
+}
+
+fn full_xuid(calendar_id: i64) -> Option<LongXuid> {
+    CALENDAR_LONG_XUIDS.share().get(&calendar_id).cloned()
+}
+
+/// Qualified xuid of a cached calendar from its key, the full xuid when the key is derived.
+pub(crate) fn cached_xuid(namespace: &str, xuid: &str, calendar_id: i64) -> String {
+    match full_xuid(calendar_id) {
+        Some(full_xuid) => qualified_xuid(namespace, &full_xuid),
+        None => qualified_xuid(namespace, xuid),
+    }
+}
+
+/// Id of the calendar with `xuid` in `calendar_xuid_id_map`, whatever kq.calendar.xuid_overflow
+/// was when it was populated.
+pub(crate) fn find(
+    calendar_xuid_id_map: &CalendarXuidIdMap,
+    namespace: &str,
+    xuid: &str,
+) -> Option<i64> {
+    let namespace = CalendarNamespace::from_str(namespace).ok()?;
+    let xuid = normalized(xuid);
+    let xuid = xuid.as_ref();
+    if let Ok(key) = CalendarXuid::from_str(xuid) {
+        let calendar_id = *calendar_xuid_id_map.get(&(namespace, key))?;
+        // a short xuid equal to the key derived from a long one is not that calendar
+        if xuid.len() >= DERIVED_KEY_MIN_LEN && full_xuid(calendar_id).is_some() {
+            return None;
+        }
+        return Some(calendar_id);
+    }
+    [hashed_key(xuid), truncated(xuid)]
+        .into_iter()
+        .find_map(|key| {
+            let calendar_id = *calendar_xuid_id_map.get(&(namespace.clone(), key))?;
+            (full_xuid(calendar_id)?.as_str() == xuid).then_some(calendar_id)
+        })
+}
+
+#[cfg(any(test, feature = "pg_test"))]
+#[pg_schema]
+mod tests {
+    use pgrx::prelude::*;
+
+    use super::XuidOverflow;
+
+    const LONG_XUID: &str = "fiscal-calendar-of-the-northern-region-2024";
+
+    #[pg_test]
+    fn test_stored_xuids() {
+        assert_eq!(super::xuid_overflow(), XuidOverflow::Error);
+        assert_eq!(
+            super::parse_xuid_overflow(" Hash"),
+            Some(XuidOverflow::Hash)
+        );
+        assert_eq!(super::parse_xuid_overflow("drop"), None);
+
+        assert_eq!(
+            super::stored_xuid("month", XuidOverflow::Error).unwrap(),
+            "month"
+        );
+        assert!(super::stored_xuid(LONG_XUID, XuidOverflow::Error).is_err());
+        assert_eq!(
+            super::stored_xuid(LONG_XUID, XuidOverflow::Truncate).unwrap(),
+            &LONG_XUID[..crate::CALENDAR_XUID_MAX_LEN]
+        );
+        let hashed = super::stored_xuid(LONG_XUID, XuidOverflow::Hash).unwrap();
+        assert_eq!(hashed.len(), crate::CALENDAR_XUID_MAX_LEN);
+        assert!(hashed.starts_with("fiscal-calendar#"));
+        assert_ne!(
+            hashed,
+            super::stored_xuid(&format!("{LONG_XUID}-b"), XuidOverflow::Hash).unwrap()
+        );
+    }
+
+    #[pg_test]
+    fn test_find_long_xuids() {
+        let mut calendar_xuid_id_map = crate::CalendarXuidIdMap::new();
+        let key = super::stored_key("", LONG_XUID, XuidOverflow::Truncate).unwrap();
+        calendar_xuid_id_map.insert(key.clone(), 42).unwrap();
+        super::record(42, LONG_XUID);
+
+        assert_eq!(super::find(&calendar_xuid_id_map, "", LONG_XUID), Some(42));
+        assert_eq!(
+            super::find(&calendar_xuid_id_map, "", &format!("{LONG_XUID}-b")),
+            None
+        );
+        assert_eq!(super::find(&calendar_xuid_id_map, "", &key.1), None);
+        assert_eq!(super::cached_xuid("", &key.1, 42), LONG_XUID);
+        super::clear();
+    }
+
+    #[pg_test]
+    fn test_normalized_xuids() {
+        use super::XuidNormalization;
+
+        let nfd = "MU\u{0308}NCHEN";
+        assert_eq!(super::normalize(nfd, XuidNormalization::None), nfd);
+        assert_eq!(
+            super::normalize(nfd, XuidNormalization::Nfc),
+            "M\u{00dc}NCHEN"
+        );
+        assert_eq!(
+            super::normalize(nfd, XuidNormalization::NfcCasefold),
+            "m\u{00fc}nchen"
+        );
+        assert_eq!(
+            super::parse_xuid_normalization("NFC_Casefold"),
+            Some(XuidNormalization::NfcCasefold)
+        );
+        assert_eq!(super::xuid_normalization(), XuidNormalization::None);
+    }
+
+    #[pg_test]
+    fn test_long_xuids_are_cached() {
+        Spi::run("SET LOCAL kq.calendar.xuid_overflow = 'hash'").unwrap();
+        Spi::run(&format!(
+            "INSERT INTO plan.calendar (id, \"name\", xuid) VALUES (99, 'long', '{LONG_XUID}'); \
+             INSERT INTO plan.calendar_date (calendar_id, \"date\") VALUES (99, '2024-01-01')"
+        ))
+        .unwrap();
+        crate::clear_cache();
+        crate::ensure_cache_populated();
+        assert_eq!(crate::find_calendar_id(None, LONG_XUID), Some(99));
+        assert_eq!(
+            crate::kq_cx_resolve_id(99, true),
+            Some(LONG_XUID.to_string())
+        );
+        crate::clear_cache();
+    }
+}